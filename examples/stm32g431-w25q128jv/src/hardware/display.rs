@@ -6,18 +6,32 @@ use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex};
 use embassy_embedded_hal::shared_bus::asynch::spi::SpiDevice;
 use embedded_graphics::{pixelcolor::Rgb565, prelude::RgbColor};
 use gc9307_async::{Config as DisplayConfig, GC9307C, Orientation, Timer};
-use embassy_time;
 use crate::resources::{font_renderer_16px::FontRenderer16px, boot_screen_loader::{BootScreenLoader, DisplayTrait}};
-
-// Embassy timer implementation for gc9307-async
-struct EmbassyTimer;
-
+use crate::ui::scroll_region::{ScrollRegion, MAX_LINE_CHARS, MAX_LINES};
+use flash_protocol::{glyph::{self, BitmapFormat, BitOrder, ByteOrder}, pattern::TestPattern};
+
+/// Embassy timer implementation for gc9307-async
+///
+/// `DisplayManager` works with any `gc9307_async::Timer`; projects on other
+/// runtimes (RTIC, bare interrupts) can disable the `embassy-timer` feature
+/// and supply their own delay provider.
+#[cfg(feature = "embassy-timer")]
+pub struct EmbassyTimer;
+
+#[cfg(feature = "embassy-timer")]
 impl Timer for EmbassyTimer {
     async fn delay_ms(milliseconds: u64) {
         embassy_time::Timer::after_millis(milliseconds).await;
     }
 }
 
+/// GC9307 resolution for this project (landscape)
+const SCREEN_WIDTH: u16 = 320;
+const SCREEN_HEIGHT: u16 = 172;
+
+/// font8x8 stores each row LSB first (bit 0 = leftmost pixel)
+const EMBEDDED_FONT_FORMAT: BitmapFormat = BitmapFormat { bit_order: BitOrder::LsbFirst, byte_order: ByteOrder::RowMajor };
+
 // Display buffer - needs to be static for the lifetime requirement
 static mut DISPLAY_BUFFER: [u8; gc9307_async::BUF_SIZE] = [0; gc9307_async::BUF_SIZE];
 
@@ -31,26 +45,33 @@ struct FontCharInfo {
 }
 
 /// Display type alias for easier use
-type DisplayType = GC9307C<'static, SpiDevice<'static, CriticalSectionRawMutex, Spi<'static, embassy_stm32::mode::Async>, Output<'static>>, Output<'static>, Output<'static>, EmbassyTimer>;
+type DisplayType<T> = GC9307C<'static, SpiDevice<'static, CriticalSectionRawMutex, Spi<'static, embassy_stm32::mode::Async>, Output<'static>>, Output<'static>, Output<'static>, T>;
 
 /// Display manager for GC9307 TFT with real hardware driver
-pub struct DisplayManager {
-    display: Option<DisplayType>,
+///
+/// `T` provides the delays the driver and test patterns need.
+pub struct DisplayManager<T: Timer> {
+    display: Option<DisplayType<T>>,
     width: u16,
     height: u16,
     font_renderer_16px: FontRenderer16px,
     boot_screen_loader: BootScreenLoader,
+    scroll_region: Option<ScrollRegion>,
+    /// Set once the missing-flash-font warning has been logged
+    flash_font_warned: bool,
 }
 
-impl DisplayManager {
+impl<T: Timer> DisplayManager<T> {
     /// Create new display manager
     pub fn new() -> Self {
         Self {
             display: None,
-            width: 320,  // GC9307 actual resolution for this project
-            height: 172,
+            width: SCREEN_WIDTH,
+            height: SCREEN_HEIGHT,
             font_renderer_16px: FontRenderer16px::new(),
             boot_screen_loader: BootScreenLoader::new(),
+            scroll_region: None,
+            flash_font_warned: false,
         }
     }
 
@@ -82,7 +103,7 @@ impl DisplayManager {
         let buffer = unsafe { &mut *core::ptr::addr_of_mut!(DISPLAY_BUFFER) };
 
         // Create display driver
-        let mut display = GC9307C::<_, _, _, EmbassyTimer>::new(
+        let mut display = GC9307C::<_, _, _, T>::new(
             display_config,
             spi_device,
             dc_pin,
@@ -210,22 +231,11 @@ impl DisplayManager {
         }
     }
 
-    /// Helper function to reverse bits in a byte (LSB->MSB conversion)
-    fn reverse_bits(byte: u8) -> u8 {
-        let mut result = 0u8;
-        for i in 0..8 {
-            if (byte & (1 << i)) != 0 {
-                result |= 1 << (7 - i);
-            }
-        }
-        result
-    }
-
     /// Get character bitmap from Flash storage using WenQuanYi format
     async fn get_char_bitmap_from_flash(
         ch: char,
         flash_manager: &mut crate::hardware::flash::FlashManager
-    ) -> Result<(heapless::Vec<u8, 256>, u8, u8), &'static str> {
+    ) -> Result<(heapless::Vec<u8, 256>, u8, u8, BitmapFormat), &'static str> {
         let char_code = ch as u32;
 
         defmt::info!("🔍 NEW FONT FUNCTION: Reading character '{}' (U+{:04X}) from Flash", ch, char_code);
@@ -244,8 +254,9 @@ impl DisplayManager {
             return Err("Invalid font header size");
         }
 
-        // Parse character count (little-endian)
-        let char_count = u32::from_le_bytes([header_data[0], header_data[1], header_data[2], header_data[3]]);
+        // Parse character count and bitmap format (little-endian)
+        let header_word = u32::from_le_bytes([header_data[0], header_data[1], header_data[2], header_data[3]]);
+        let (char_count, format) = crate::resources::font_parser::FontParser::parse_header_word(header_word)?;
         defmt::debug!("Font contains {} characters", char_count);
 
         // Binary search for the character in the character info table
@@ -261,7 +272,7 @@ impl DisplayManager {
         // Read bitmap data
         // For 12px font: bitmap_offset is now absolute address from font base
         let bitmap_address = base_address + char_info.bitmap_offset;
-        let bitmap_size = Self::calculate_bitmap_size(char_info.width, char_info.height);
+        let bitmap_size = format.bitmap_size(char_info.width, char_info.height);
 
         // Safety check: ensure bitmap size doesn't exceed read limit
         if bitmap_size > 64 {
@@ -294,7 +305,7 @@ impl DisplayManager {
             result_bitmap.push(byte).map_err(|_| "Bitmap too large")?;
         }
 
-        Ok((result_bitmap, char_info.width, char_info.height))
+        Ok((result_bitmap, char_info.width, char_info.height, format))
     }
 
     /// Binary search for character info in the sorted character table
@@ -344,19 +355,11 @@ impl DisplayManager {
         Err("Character not found")
     }
 
-    /// Calculate bitmap size in bytes for given dimensions
-    fn calculate_bitmap_size(width: u8, height: u8) -> usize {
-        let pixels_per_row = width as usize;
-        let bytes_per_row = (pixels_per_row + 7) / 8; // Round up to nearest byte
-        bytes_per_row * height as usize
-    }
-
     /// Get bitmap data for a character (8x8 pixels) - embedded fallback
-    /// Each byte represents one row of 8 pixels (MSB = leftmost pixel)
+    /// Each byte represents one row of 8 pixels (LSB = leftmost pixel, see EMBEDDED_FONT_FORMAT)
     /// Based on standard font8x8 library: https://github.com/dhepper/font8x8
     fn get_char_bitmap_embedded(ch: char) -> [u8; 8] {
-
-        let original = match ch {
+        match ch {
             'A' => [0x0C, 0x1E, 0x33, 0x33, 0x3F, 0x33, 0x33, 0x00],
             'B' => [0x3F, 0x66, 0x66, 0x3E, 0x66, 0x66, 0x3F, 0x00],
             'C' => [0x3C, 0x66, 0x03, 0x03, 0x03, 0x66, 0x3C, 0x00],
@@ -399,19 +402,7 @@ impl DisplayManager {
             '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C, 0x00],
             '!' => [0x18, 0x3C, 0x3C, 0x18, 0x18, 0x00, 0x18, 0x00],
             _ => [0xFF, 0x81, 0x81, 0x81, 0x81, 0x81, 0xFF, 0x00],   // Unknown char (box)
-        };
-
-        // Reverse bits in each byte to fix mirrored text issue
-        [
-            Self::reverse_bits(original[0]),
-            Self::reverse_bits(original[1]),
-            Self::reverse_bits(original[2]),
-            Self::reverse_bits(original[3]),
-            Self::reverse_bits(original[4]),
-            Self::reverse_bits(original[5]),
-            Self::reverse_bits(original[6]),
-            Self::reverse_bits(original[7]),
-        ]
+        }
     }
 
     // Flash font method removed - no fonts stored in firmware
@@ -423,7 +414,7 @@ impl DisplayManager {
     ) -> Result<(), &'static str> {
         // Get the 'F' character bitmap data
         match Self::get_char_bitmap_from_flash('F', flash_manager).await {
-            Ok((bitmap, width, height)) => {
+            Ok((bitmap, width, height, _)) => {
                 defmt::info!("🔍 BITMAP VERIFICATION for 'F' ({}x{})", width, height);
                 defmt::info!("Raw bitmap data (first 8 bytes): {:?}", &bitmap[..core::cmp::min(8, bitmap.len())]);
 
@@ -458,45 +449,6 @@ impl DisplayManager {
         }
     }
 
-    /// Test different bitmap parsing methods for Flash fonts
-    pub async fn test_flash_bitmap_parsing(
-        &mut self,
-        x: i32,
-        y: i32,
-        test_char: char,
-        color: Rgb565,
-        flash_manager: &mut crate::hardware::flash::FlashManager
-    ) -> Result<(), &'static str> {
-        if let Some(ref mut display) = self.display {
-            // Read the character bitmap from Flash
-            match Self::get_char_bitmap_from_flash(test_char, flash_manager).await {
-                Ok((bitmap, width, height)) => {
-                    defmt::info!("Testing bitmap parsing for '{}' ({}x{})", test_char, width, height);
-
-                    // Convert to fixed-size array for testing
-                    let mut test_bitmap = [0u8; 32];
-                    for (i, &byte) in bitmap.iter().enumerate() {
-                        if i < 32 {
-                            test_bitmap[i] = byte;
-                        }
-                    }
-
-                    // Test all 4 methods side by side
-                    Self::test_bitmap_parsing_methods(display, x, y, &test_bitmap, width, height, color).await?;
-
-                    defmt::info!("Bitmap parsing test complete for '{}'", test_char);
-                    Ok(())
-                }
-                Err(e) => {
-                    defmt::error!("Failed to read '{}' for bitmap test: {}", test_char, e);
-                    Err("Failed to read character for bitmap test")
-                }
-            }
-        } else {
-            Err("Display not initialized")
-        }
-    }
-
     /// Draw text at position using WenQuanYi bitmap font from Flash
     pub async fn draw_text(
         &mut self,
@@ -519,7 +471,7 @@ impl DisplayManager {
 
                 // Try to read from Flash using correct font format
                 match Self::get_char_bitmap_from_flash(ch, flash_manager).await {
-                    Ok((bitmap_vec, width, height)) => {
+                    Ok((bitmap_vec, width, height, format)) => {
                         // Calculate vertical offset to align characters to baseline
                         // Characters are aligned so their bottom edge sits on the baseline
                         let y_offset = BASELINE_HEIGHT - height as i32;
@@ -532,9 +484,15 @@ impl DisplayManager {
                         for i in 0..copy_len {
                             bitmap_array[i] = bitmap_vec[i];
                         }
-                        Self::draw_char_bitmap_simple_flash(display, current_x, char_y, &bitmap_array, width, height, color).await?;
+                        Self::draw_char_bitmap_simple_flash(display, current_x, char_y, &bitmap_array, format, width, height, color).await?;
                         current_x += width as i32 + 1;
                     },
+                    Err(e) if ch.is_ascii() => {
+                        // Flash font not programmed yet - keep Latin text readable
+                        Self::warn_flash_font_missing(&mut self.flash_font_warned, e);
+                        Self::draw_char_embedded(display, current_x, y + BASELINE_HEIGHT - 8, ch, color).await?;
+                        current_x += 9;
+                    },
                     Err(e) => {
                         defmt::error!("Failed to read '{}' from Flash: {}", ch, e);
                         // Draw a placeholder rectangle at baseline-aligned position
@@ -554,7 +512,7 @@ impl DisplayManager {
 
     /// Draw character bitmap with variable dimensions (optimized batch version)
     async fn draw_char_bitmap_inline(
-        display: &mut DisplayType,
+        display: &mut DisplayType<T>,
         x: i32,
         y: i32,
         bitmap_data: &[u8],
@@ -581,190 +539,48 @@ impl DisplayManager {
 
 
 
-    /// Draw character bitmap from Flash data (memory-safe version using pixel-by-pixel)
-    /// Using MSB first, row-major format (Method 1) - standard font bitmap format
-    async fn draw_char_bitmap_simple_flash(
-        display: &mut DisplayType,
-        x: i32,
-        y: i32,
-        bitmap: &[u8; 32],
-        width: u8,
-        height: u8,
-        color: Rgb565
-    ) -> Result<(), &'static str> {
-        // Render each pixel of the character using pixel-by-pixel approach
-        let bytes_per_row = ((width as usize) + 7) / 8; // Round up to nearest byte
-
-        for row in 0..height {
-            for col in 0..width {
-                let byte_index = (row as usize) * bytes_per_row + (col as usize) / 8;
-                let bit_index = 7 - ((col as usize) % 8); // MSB first - matching web-app exactly
-
-                if byte_index < bitmap.len() {
-                    let byte = bitmap[byte_index];
-                    let pixel = (byte >> bit_index) & 1; // Extract pixel using shift (web-app style)
-
-                    if pixel != 0 {
-                        let pixel_x = x + col as i32;
-                        let pixel_y = y + row as i32;
-
-                        // Draw the pixel using fill_rect (1x1 rectangle)
-                        display.fill_rect(pixel_x as u16, pixel_y as u16, 1, 1, color)
-                            .await.map_err(|_| "Failed to draw pixel")?;
-                    }
-                }
-            }
-        }
-
-        defmt::debug!("Drew character bitmap at ({}, {}) size {}x{} using MSB-first pixel-by-pixel", x, y, width, height);
-        Ok(())
-    }
-
-    /// Test different bitmap parsing methods for Flash fonts
-    async fn test_bitmap_parsing_methods(
-        display: &mut DisplayType,
-        x: i32,
-        y: i32,
-        bitmap: &[u8; 32],
-        width: u8,
-        height: u8,
-        color: Rgb565
-    ) -> Result<(), &'static str> {
-        let spacing = 40; // Space between different test methods
-
-        // Method 1: MSB first, row-major (original)
-        Self::draw_bitmap_method_1(display, x, y, bitmap, width, height, color).await?;
-
-        // Method 2: LSB first, row-major (current)
-        Self::draw_bitmap_method_2(display, x + spacing, y, bitmap, width, height, color).await?;
-
-        // Method 3: MSB first, column-major
-        Self::draw_bitmap_method_3(display, x + spacing * 2, y, bitmap, width, height, color).await?;
-
-        // Method 4: LSB first, column-major
-        Self::draw_bitmap_method_4(display, x + spacing * 3, y, bitmap, width, height, color).await?;
-
-        Ok(())
-    }
-
-    /// Method 1: MSB first, row-major (optimized batch approach)
-    async fn draw_bitmap_method_1(
-        display: &mut DisplayType,
+    /// Draw an ASCII character with the embedded 8x8 font (fallback when the Flash font is absent)
+    async fn draw_char_embedded(
+        display: &mut DisplayType<T>,
         x: i32,
         y: i32,
-        bitmap: &[u8; 32],
-        width: u8,
-        height: u8,
+        ch: char,
         color: Rgb565
     ) -> Result<(), &'static str> {
-        // Use write_area for batch rendering - massive performance improvement
-        let bg_color = Rgb565::BLACK; // Transparent pixels use black background
-
-        // Calculate the actual bitmap size needed
-        let bytes_per_row = ((width as usize) + 7) / 8;
-        let total_bytes = bytes_per_row * (height as usize);
-        let bitmap_slice = &bitmap[..total_bytes.min(bitmap.len())];
-
-        display.write_area(
-            x as u16,
-            y as u16,
-            width as u16,
-            bitmap_slice,
-            color,
-            bg_color
-        ).await.map_err(|_| "Failed to draw bitmap with optimized write_area")?;
-
-        defmt::debug!("✅ Drew bitmap method 1 at ({}, {}) size {}x{} using optimized batch rendering", x, y, width, height);
-        Ok(())
+        let mut bitmap = [0u8; 32];
+        bitmap[..8].copy_from_slice(&Self::get_char_bitmap_embedded(ch));
+        Self::draw_char_bitmap_simple_flash(display, x, y, &bitmap, EMBEDDED_FONT_FORMAT, 8, 8, color).await
     }
 
-    /// Method 2: LSB first, row-major
-    async fn draw_bitmap_method_2(
-        display: &mut DisplayType,
-        x: i32,
-        y: i32,
-        bitmap: &[u8; 32],
-        width: u8,
-        height: u8,
-        color: Rgb565
-    ) -> Result<(), &'static str> {
-        let bytes_per_row = ((width as usize) + 7) / 8;
-
-        for row in 0..height {
-            let row_start = (row as usize) * bytes_per_row;
-            for col in 0..width {
-                let byte_index = row_start + (col as usize) / 8;
-                let bit_index = (col as usize) % 8; // LSB first
-
-                if byte_index < bitmap.len() {
-                    let byte = bitmap[byte_index];
-                    if (byte & (1 << bit_index)) != 0 {
-                        display.fill_rect((x + col as i32) as u16, (y + row as i32) as u16, 1, 1, color)
-                            .await.map_err(|_| "Failed to draw pixel")?;
-                    }
-                }
-            }
+    /// Log the missing Flash font once instead of for every character
+    fn warn_flash_font_missing(warned: &mut bool, reason: &'static str) {
+        if !*warned {
+            defmt::warn!("⚠️ Flash font unavailable ({}), using embedded 8x8 font for ASCII", reason);
+            *warned = true;
         }
-        Ok(())
     }
 
-    /// Method 3: MSB first, column-major
-    async fn draw_bitmap_method_3(
-        display: &mut DisplayType,
+    /// Draw character bitmap from Flash data (memory-safe version using pixel-by-pixel)
+    /// Bit and byte order come from the font header (the web tool writes MSB first, row-major)
+    async fn draw_char_bitmap_simple_flash(
+        display: &mut DisplayType<T>,
         x: i32,
         y: i32,
         bitmap: &[u8; 32],
+        format: BitmapFormat,
         width: u8,
         height: u8,
         color: Rgb565
     ) -> Result<(), &'static str> {
-        let bytes_per_col = ((height as usize) + 7) / 8;
-
-        for col in 0..width {
-            let col_start = (col as usize) * bytes_per_col;
-            for row in 0..height {
-                let byte_index = col_start + (row as usize) / 8;
-                let bit_index = 7 - ((row as usize) % 8); // MSB first
-
-                if byte_index < bitmap.len() {
-                    let byte = bitmap[byte_index];
-                    if (byte & (1 << bit_index)) != 0 {
-                        display.fill_rect((x + col as i32) as u16, (y + row as i32) as u16, 1, 1, color)
-                            .await.map_err(|_| "Failed to draw pixel")?;
-                    }
-                }
-            }
+        // Render each pixel of the character using pixel-by-pixel approach;
+        // off-screen pixels are clipped
+        for (pixel_x, pixel_y) in glyph::lit_pixels(bitmap, format, width, height, x, y, SCREEN_WIDTH, SCREEN_HEIGHT) {
+            // Draw the pixel using fill_rect (1x1 rectangle)
+            display.fill_rect(pixel_x, pixel_y, 1, 1, color)
+                .await.map_err(|_| "Failed to draw pixel")?;
         }
-        Ok(())
-    }
 
-    /// Method 4: LSB first, column-major
-    async fn draw_bitmap_method_4(
-        display: &mut DisplayType,
-        x: i32,
-        y: i32,
-        bitmap: &[u8; 32],
-        width: u8,
-        height: u8,
-        color: Rgb565
-    ) -> Result<(), &'static str> {
-        let bytes_per_col = ((height as usize) + 7) / 8;
-
-        for col in 0..width {
-            let col_start = (col as usize) * bytes_per_col;
-            for row in 0..height {
-                let byte_index = col_start + (row as usize) / 8;
-                let bit_index = (row as usize) % 8; // LSB first
-
-                if byte_index < bitmap.len() {
-                    let byte = bitmap[byte_index];
-                    if (byte & (1 << bit_index)) != 0 {
-                        display.fill_rect((x + col as i32) as u16, (y + row as i32) as u16, 1, 1, color)
-                            .await.map_err(|_| "Failed to draw pixel")?;
-                    }
-                }
-            }
-        }
+        defmt::debug!("Drew character bitmap at ({}, {}) size {}x{} pixel-by-pixel", x, y, width, height);
         Ok(())
     }
 
@@ -988,7 +804,7 @@ impl DisplayManager {
                     .await.map_err(|_| "Failed to fill color bar")?;
 
                 // Small delay to make drawing visible
-                embassy_time::Timer::after_millis(100).await;
+                T::delay_ms(100).await;
             }
 
             defmt::info!("Color bars pattern complete");
@@ -1005,7 +821,7 @@ impl DisplayManager {
 
             // Clear screen first
             display.fill_screen(Rgb565::BLACK).await.map_err(|_| "Failed to clear screen")?;
-            embassy_time::Timer::after_millis(100).await;
+            T::delay_ms(100).await;
 
             let square_size = 20u16; // 20x20 pixel squares
             let cols = 320 / square_size; // 16 columns
@@ -1031,7 +847,7 @@ impl DisplayManager {
                 }
 
                 // Small delay per row to make drawing visible
-                embassy_time::Timer::after_millis(50).await;
+                T::delay_ms(50).await;
             }
 
             defmt::info!("Checkerboard pattern complete");
@@ -1041,6 +857,47 @@ impl DisplayManager {
         }
     }
 
+    /// Draw a shared test pattern (same pixels the host `pattern` command writes to flash)
+    ///
+    /// Each row is drawn as runs of equal color so solid areas become a few
+    /// `fill_rect` calls instead of one per pixel.
+    pub async fn draw_test_pattern(&mut self, pattern: &TestPattern) -> Result<(), &'static str> {
+        if let Some(ref mut display) = self.display {
+            defmt::info!("Drawing shared test pattern");
+
+            const WIDTH: u16 = 320;
+            const HEIGHT: u16 = 172;
+
+            for y in 0..HEIGHT {
+                let mut run_start = 0u16;
+                let mut run_color = pattern.pixel(0, y, WIDTH, HEIGHT);
+
+                for x in 1..=WIDTH {
+                    let color = if x < WIDTH { Some(pattern.pixel(x, y, WIDTH, HEIGHT)) } else { None };
+                    if color != Some(run_color) {
+                        display.fill_rect(run_start, y, x - run_start, 1, Self::rgb565_from_raw(run_color))
+                            .await.map_err(|_| "Failed to fill pattern run")?;
+                        if let Some(color) = color {
+                            run_start = x;
+                            run_color = color;
+                        }
+                    }
+                }
+            }
+
+            defmt::info!("Test pattern complete");
+            Ok(())
+        } else {
+            Err("Display not initialized")
+        }
+    }
+
+    /// Convert a raw RGB565 value into an embedded-graphics color
+    fn rgb565_from_raw(raw: u16) -> Rgb565 {
+        let (r, g, b) = flash_protocol::pattern::rgb565_components(raw);
+        Rgb565::new(r, g, b)
+    }
+
     /// Show startup screen
     pub async fn show_startup_screen(
         &mut self,
@@ -1097,9 +954,12 @@ impl DisplayManager {
             let mut current_x = x;
             const BASELINE_HEIGHT: i32 = 16; // 16px字体的基线高度
             const CHAR_SPACING: i32 = 1;     // 字符间距
+            // 上一个由闪存字库绘制的字符，用于字距调整
+            let mut previous: Option<u32> = None;
 
             for ch in text.chars() {
                 let char_code = ch as u32;
+                let left = previous.take();
 
                 // 查找字符信息
                 match self.font_renderer_16px.find_char(char_code, flash_manager).await {
@@ -1107,6 +967,11 @@ impl DisplayManager {
                         // 读取字符位图
                         match self.font_renderer_16px.read_char_bitmap(&char_info, flash_manager).await {
                             Ok(bitmap) => {
+                                // 按字距表调整与前一字符的间距（无字距表时为0）
+                                if let Some(left) = left {
+                                    current_x += self.font_renderer_16px.kerning(left, char_code, flash_manager).await as i32;
+                                }
+
                                 // 计算字符的垂直对齐位置
                                 let char_y = y + BASELINE_HEIGHT - char_info.height as i32;
 
@@ -1116,16 +981,23 @@ impl DisplayManager {
                                     current_x,
                                     char_y,
                                     &bitmap,
+                                    self.font_renderer_16px.bitmap_format(),
                                     char_info.width,
                                     char_info.height,
                                     color
                                 ).await?;
 
                                 current_x += char_info.width as i32 + CHAR_SPACING;
+                                previous = Some(char_code);
 
                                 defmt::debug!("✅ Rendered character '{}' (U+{:04X}) at ({}, {})",
                                              ch, char_code, current_x - char_info.width as i32 - CHAR_SPACING, char_y);
                             },
+                            Err(e) if ch.is_ascii() => {
+                                Self::warn_flash_font_missing(&mut self.flash_font_warned, e);
+                                Self::draw_char_embedded(display, current_x, y + BASELINE_HEIGHT - 8, ch, color).await?;
+                                current_x += 8 + CHAR_SPACING;
+                            },
                             Err(e) => {
                                 defmt::error!("❌ Failed to read bitmap for '{}': {}", ch, e);
                                 // 绘制占位符
@@ -1135,6 +1007,12 @@ impl DisplayManager {
                             }
                         }
                     },
+                    Err(e) if ch.is_ascii() => {
+                        // 闪存字库缺失时使用内置8x8字体
+                        Self::warn_flash_font_missing(&mut self.flash_font_warned, e);
+                        Self::draw_char_embedded(display, current_x, y + BASELINE_HEIGHT - 8, ch, color).await?;
+                        current_x += 8 + CHAR_SPACING;
+                    },
                     Err(e) => {
                         defmt::warn!("⚠️ Character '{}' (U+{:04X}) not found: {}", ch, char_code, e);
                         // 绘制占位符
@@ -1154,35 +1032,20 @@ impl DisplayManager {
 
     /// Render character bitmap for 16px font
     async fn render_char_bitmap_16px(
-        display: &mut DisplayType,
+        display: &mut DisplayType<T>,
         x: i32,
         y: i32,
         bitmap: &[u8],
+        format: BitmapFormat,
         width: u8,
         height: u8,
         color: Rgb565
     ) -> Result<(), &'static str> {
-        let bytes_per_row = ((width as usize) + 7) / 8;
-
-        for row in 0..height {
-            for col in 0..width {
-                let byte_index = (row as usize) * bytes_per_row + (col as usize) / 8;
-                let bit_index = 7 - ((col as usize) % 8); // MSB优先
-
-                if byte_index < bitmap.len() {
-                    let byte = bitmap[byte_index];
-                    let pixel = (byte >> bit_index) & 1;
-
-                    if pixel != 0 {
-                        let pixel_x = x + col as i32;
-                        let pixel_y = y + row as i32;
-
-                        // 绘制像素
-                        display.fill_rect(pixel_x as u16, pixel_y as u16, 1, 1, color)
-                            .await.map_err(|_| "Failed to draw pixel")?;
-                    }
-                }
-            }
+        // 按字体头声明的格式解码，超出屏幕的像素被裁剪
+        for (pixel_x, pixel_y) in glyph::lit_pixels(bitmap, format, width, height, x, y, SCREEN_WIDTH, SCREEN_HEIGHT) {
+            // 绘制像素
+            display.fill_rect(pixel_x, pixel_y, 1, 1, color)
+                .await.map_err(|_| "Failed to draw pixel")?;
         }
 
         Ok(())
@@ -1217,6 +1080,55 @@ impl DisplayManager {
         }
     }
 
+    /// Set up a scrolling text region for status/log output
+    pub fn enable_scroll_region(&mut self, x: i32, y: i32, width: u16, height: u16) {
+        // 12px font rendered on a 14px baseline, plus 2px line gap
+        const LOG_LINE_HEIGHT: u16 = 16;
+        self.scroll_region = Some(ScrollRegion::new(x, y, width, height, LOG_LINE_HEIGHT));
+        defmt::info!("Scroll region enabled at ({}, {}) size {}x{}", x, y, width, height);
+    }
+
+    /// Append a line to the scroll region and scroll the older lines up
+    ///
+    /// The GC9307 vertical scroll (VSCRDEF 0x33 / VSCSAD 0x37) moves the panel's
+    /// native rows, which in our landscape orientation is the horizontal axis, so
+    /// scrolling is done by redrawing the shifted lines inside the region only.
+    pub async fn push_line(
+        &mut self,
+        text: &str,
+        color: Rgb565,
+        flash_manager: &mut crate::hardware::flash::FlashManager
+    ) -> Result<(), &'static str> {
+        let region = self.scroll_region.as_mut().ok_or("Scroll region not enabled")?;
+        let was_full = region.len() >= region.capacity();
+        region.push_line(text);
+
+        let (x, y, width, height) = region.bounds();
+        let line_height = region.line_height();
+
+        // Collect draw positions first so the region borrow ends before drawing
+        let mut pending: heapless::Vec<(i32, heapless::String<MAX_LINE_CHARS>), MAX_LINES> = heapless::Vec::new();
+        for (line_y, line) in region.lines() {
+            let mut owned = heapless::String::new();
+            owned.push_str(line).map_err(|_| "Log line too long")?;
+            pending.push((line_y, owned)).map_err(|_| "Too many log lines")?;
+        }
+
+        if was_full {
+            // Every line moved up: clear the whole region and redraw
+            self.fill_rect(x as u16, y as u16, width, height, Rgb565::BLACK).await?;
+            for (line_y, line) in pending.iter() {
+                self.draw_text(line, x, *line_y, color, flash_manager).await?;
+            }
+        } else if let Some((line_y, line)) = pending.last() {
+            // Region still filling up: only the new line needs drawing
+            self.fill_rect(x as u16, *line_y as u16, width, line_height, Rgb565::BLACK).await?;
+            self.draw_text(line, x, *line_y, color, flash_manager).await?;
+        }
+
+        Ok(())
+    }
+
     /// Get boot screen statistics
     pub async fn get_boot_screen_stats(&mut self, flash_manager: &mut crate::hardware::flash::FlashManager) -> Result<(), &'static str> {
         match self.boot_screen_loader.get_screen_stats(flash_manager).await {
@@ -1236,7 +1148,7 @@ impl DisplayManager {
 }
 
 /// Implement DisplayTrait for our DisplayType to enable boot screen loading
-impl DisplayTrait for DisplayType {
+impl<T: Timer> DisplayTrait for DisplayType<T> {
     type Error = &'static str;
 
     async fn fill_screen(&mut self, color: Rgb565) -> Result<(), Self::Error> {
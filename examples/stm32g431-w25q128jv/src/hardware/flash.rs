@@ -5,22 +5,43 @@ use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_embedded_hal::shared_bus::asynch::spi::SpiDevice;
 use embedded_hal_async::spi::SpiDevice as SpiDeviceTrait;
 use heapless::Vec;
+use flash_protocol::geometry::FlashGeometry;
 
-use crate::resources::cache::FlashCache;
+use flash_protocol::read_cache::{CacheStats, ReadCache, ReadSource};
+
+/// Largest read `read_data_large` performs in a single SPI transaction
+pub const MAX_LARGE_READ: usize = 2048;
+
+/// Cache entries used by the viewer; glyph lookups jump around the font
+/// tables, so fewer entries thrash
+pub const DEFAULT_CACHE_ENTRIES: usize = 16;
+
+/// Flash manager shared by the screen renderer and, in builds that also run
+/// the USB programmer, its protocol handler
+///
+/// The SPI bus mutex only keeps single transactions apart. Rendering a screen
+/// takes many reads, so the renderer holds this lock for the whole screen and
+/// a protocol Write or Erase waits until it finishes instead of landing
+/// between two reads of the boot image. Anything that modifies flash while
+/// holding the lock must call `clear_cache` before releasing it.
+pub type SharedFlash = Mutex<CriticalSectionRawMutex, FlashManager>;
 
 /// Flash manager with caching support
-pub struct FlashManager {
+///
+/// `N` is the number of cache entries of `CACHE_LINE_SIZE` bytes each (see
+/// `flash_protocol::read_cache`).
+pub struct FlashManager<const N: usize = DEFAULT_CACHE_ENTRIES> {
     spi_device: Option<SpiDevice<'static, CriticalSectionRawMutex, Spi<'static, embassy_stm32::mode::Async>, Output<'static>>>,
-    cache: FlashCache<8>, // 8 cache entries
+    cache: ReadCache<N>,
     initialized: bool,
 }
 
-impl FlashManager {
+impl<const N: usize> FlashManager<N> {
     /// Create new flash manager
     pub fn new() -> Self {
         Self {
             spi_device: None,
-            cache: FlashCache::new(),
+            cache: ReadCache::new(),
             initialized: false,
         }
     }
@@ -47,136 +68,61 @@ impl FlashManager {
         self.initialized
     }
 
-    /// Read data directly from SPI Flash (W25Q128JV)
-    async fn read_from_spi(&mut self, address: u32, length: usize) -> Result<Vec<u8, 1024>, &'static str> {
-        if let Some(ref mut spi_device) = self.spi_device {
-            let mut buffer = Vec::new();
-
-            // W25Q128JV READ command (0x03) + 24-bit address
-            let cmd = [
-                0x03,                           // READ command
-                (address >> 16) as u8,         // Address high byte
-                (address >> 8) as u8,          // Address middle byte
-                address as u8,                 // Address low byte
-            ];
-
-            // Prepare read buffer
-            let mut read_buffer = [0u8; 1024];
-            let actual_length = core::cmp::min(length, 1024);
-
-            // Perform SPI transaction
-            match spi_device.transaction(&mut [
-                embedded_hal_async::spi::Operation::Write(&cmd),
-                embedded_hal_async::spi::Operation::Read(&mut read_buffer[..actual_length]),
-            ]).await {
-                Ok(_) => {
-                    // Copy data to result vector
-                    for i in 0..actual_length {
-                        buffer.push(read_buffer[i]).map_err(|_| "Buffer full")?;
-                    }
-                    defmt::debug!("Read {} bytes from SPI Flash at 0x{:08X}", actual_length, address);
-                    Ok(buffer)
-                },
-                Err(_) => {
-                    defmt::error!("SPI Flash read failed at address 0x{:08X}", address);
-                    Err("SPI Flash read failed")
-                }
-            }
-        } else {
-            Err("SPI device not initialized")
-        }
-    }
-
     /// Read data from flash with caching
+    ///
+    /// Cached ranges are served from RAM; only the gaps are read from the chip.
     pub async fn read_data(&mut self, address: u32, length: usize) -> Result<Vec<u8, 2048>, &'static str> {
         if !self.initialized {
             return Err("Flash not initialized");
         }
+        let Some(spi_device) = self.spi_device.as_mut() else {
+            return Err("SPI device not initialized");
+        };
 
-        // Try to read from cache first
         let mut result = Vec::new();
-        let mut remaining_length = length;
-        let mut current_address = address;
-
-        // Try to read data in chunks from cache
-        while remaining_length > 0 {
-            // Try to find a cache entry that contains data starting at current_address
-            let mut found_data = false;
-
-            // Check all cache entries to see if any contains data at current_address
-            for entry_address in [address, address & !0xFF, (address & !0xFF) + 256, (address & !0xFF) + 512, (address & !0xFF) + 768] {
-                if let Some(cached_data) = self.cache.get(entry_address, 1024) {
-                    // Calculate offset within this cache entry
-                    if current_address >= entry_address && current_address < entry_address + cached_data.len() as u32 {
-                        let offset_in_entry = (current_address - entry_address) as usize;
-                        let available_in_entry = cached_data.len() - offset_in_entry;
-                        let to_read = core::cmp::min(remaining_length, available_in_entry);
-
-                        // Copy data from this cache entry
-                        for i in 0..to_read {
-                            result.push(cached_data[offset_in_entry + i]).map_err(|_| "Result buffer full")?;
-                        }
-
-                        current_address += to_read as u32;
-                        remaining_length -= to_read;
-                        found_data = true;
-                        break;
-                    }
-                }
-            }
-
-            if !found_data {
-                // Cache miss - read from SPI Flash and populate cache
-                defmt::debug!("Cache miss at address 0x{:08X}, reading from SPI Flash", current_address);
-
-                // Read a larger chunk (256 bytes) to improve cache efficiency
-                let _chunk_size = core::cmp::min(256, remaining_length);
-                let chunk_address = current_address & !0xFF; // Align to 256-byte boundary
-
-                match self.read_from_spi(chunk_address, 256).await {
-                    Ok(spi_data) => {
-                        // Store in cache
-                        if let Err(e) = self.cache.put(chunk_address, &spi_data) {
-                            defmt::warn!("Failed to cache data: {}", e);
-                        }
-
-                        // Extract the requested data from the chunk
-                        let offset_in_chunk = (current_address - chunk_address) as usize;
-                        let available_in_chunk = spi_data.len() - offset_in_chunk;
-                        let to_read = core::cmp::min(remaining_length, available_in_chunk);
-
-                        for i in 0..to_read {
-                            result.push(spi_data[offset_in_chunk + i]).map_err(|_| "Result buffer full")?;
-                        }
-
-                        current_address += to_read as u32;
-                        remaining_length -= to_read;
-                    },
-                    Err(e) => {
-                        defmt::error!("Failed to read from SPI Flash at 0x{:08X}: {}", current_address, e);
-                        return Err("SPI Flash read failed");
-                    }
-                }
-            }
-        }
+        result.resize(length, 0).map_err(|_| "Result buffer full")?;
+        self.cache.read(&mut SpiSource(spi_device), address, &mut result).await?;
 
         defmt::debug!("Read {} bytes from Flash cache starting at address 0x{:08X}", length, address);
         Ok(result)
     }
 
+    /// Load the range a sequential `read_data` caller will want next
+    ///
+    /// Does nothing unless the last reads were sequential. The boot screen
+    /// joins it with the display transfer of the chunk just read (the
+    /// display has its own SPI bus), so its next `read_data` is served from
+    /// cache. Returns whether anything was loaded.
+    pub async fn prefetch(&mut self) -> Result<bool, &'static str> {
+        let Some(spi_device) = self.spi_device.as_mut() else {
+            return Err("SPI device not initialized");
+        };
+        self.cache.prefetch(&mut SpiSource(spi_device)).await
+    }
+
+    /// Cache lines loaded by `prefetch` since start-up
+    pub fn prefetch_count(&self) -> u32 {
+        self.cache.prefetch_count()
+    }
+
     /// Read a small chunk of data (for headers, etc.)
+    ///
+    /// Fails unless exactly `length` bytes were read, so a short SPI read
+    /// never hands truncated header data to the font or boot screen parsers.
     pub async fn read_chunk(&mut self, address: u32, length: usize) -> Result<Vec<u8, 256>, &'static str> {
         if length > 256 {
             return Err("Chunk too large");
         }
 
         let data = self.read_data(address, length).await?;
-        let mut chunk = Vec::new();
-
-        for &byte in &data[..core::cmp::min(length, data.len())] {
-            chunk.push(byte).map_err(|_| "Chunk buffer full")?;
+        if data.len() < length {
+            defmt::error!("❌ Short read at 0x{:08X}: got {} of {} bytes", address, data.len(), length);
+            return Err("Short flash read");
         }
 
+        let mut chunk = Vec::new();
+        chunk.extend_from_slice(&data[..length]).map_err(|_| "Chunk buffer full")?;
+
         Ok(chunk)
     }
 
@@ -189,17 +135,15 @@ impl FlashManager {
         }
 
         // Return dummy info for W25Q128JV (would need proper driver integration)
+        let jedec_id = 0xEF4018; // W25Q128JV JEDEC ID
         Ok(FlashInfo {
-            jedec_id: 0xEF4018, // W25Q128JV JEDEC ID
-            total_size: 16 * 1024 * 1024, // 16MB for W25Q128JV
-            page_size: 256,
-            sector_size: 4096,
-            block_size: 65536,
+            jedec_id,
+            geometry: FlashGeometry::from_jedec_id(jedec_id),
         })
     }
 
     /// Get cache statistics
-    pub fn get_cache_stats(&self) -> crate::resources::cache::CacheStats {
+    pub fn get_cache_stats(&self) -> CacheStats {
         self.cache.stats()
     }
 
@@ -285,11 +229,18 @@ impl FlashManager {
         }
     }
 
-    /// Large Flash read for boot screen data (up to 2048 bytes)
-    pub async fn read_data_large(&mut self, address: u32, length: usize) -> Result<heapless::Vec<u8, 2048>, &'static str> {
+    /// Large Flash read for boot screen data (up to `MAX_LARGE_READ` bytes)
+    ///
+    /// Reads the whole range in one SPI transaction, bypassing the cache.
+    /// Longer requests are rejected rather than silently truncated.
+    pub async fn read_data_large(&mut self, address: u32, length: usize) -> Result<heapless::Vec<u8, MAX_LARGE_READ>, &'static str> {
+        if length > MAX_LARGE_READ {
+            defmt::error!("❌ Large read of {} bytes exceeds {} byte limit", length, MAX_LARGE_READ);
+            return Err("Read too large");
+        }
+
         if let Some(ref mut spi_device) = self.spi_device {
-            // Limit read size to prevent memory issues
-            let safe_length = length.min(2048);
+            let safe_length = length;
 
             // Read command: 0x03 (Read Data) - same as firmware
             let cmd_buf = [
@@ -303,7 +254,7 @@ impl FlashManager {
             defmt::debug!("📤 SPI CMD: {:?}", cmd_buf);
 
             // Create larger buffer for boot screen data
-            let mut read_buf = heapless::Vec::<u8, 2048>::new();
+            let mut read_buf = heapless::Vec::<u8, MAX_LARGE_READ>::new();
             read_buf.resize(safe_length, 0).map_err(|_| "Large buffer resize failed")?;
 
             // Use the SAME transaction method as firmware
@@ -327,12 +278,38 @@ impl FlashManager {
     }
 }
 
+/// Reads cache misses straight from the chip (W25Q128JV READ, 0x03)
+struct SpiSource<'a, D>(&'a mut D);
+
+impl<D: SpiDeviceTrait> ReadSource for SpiSource<'_, D> {
+    async fn read(&mut self, address: u32, buf: &mut [u8]) -> Result<(), &'static str> {
+        let cmd = [
+            0x03,                   // READ command
+            (address >> 16) as u8,  // Address high byte
+            (address >> 8) as u8,   // Address middle byte
+            address as u8,          // Address low byte
+        ];
+
+        match self.0.transaction(&mut [
+            embedded_hal_async::spi::Operation::Write(&cmd),
+            embedded_hal_async::spi::Operation::Read(buf),
+        ]).await {
+            Ok(_) => {
+                defmt::debug!("Read {} bytes from SPI Flash at 0x{:08X}", buf.len(), address);
+                Ok(())
+            }
+            Err(_) => {
+                defmt::error!("SPI Flash read failed at address 0x{:08X}", address);
+                Err("SPI Flash read failed")
+            }
+        }
+    }
+}
+
 /// Flash information structure
 #[derive(Debug, Clone)]
 pub struct FlashInfo {
     pub jedec_id: u32,
-    pub total_size: u32,
-    pub page_size: u32,
-    pub sector_size: u32,
-    pub block_size: u32,
+    /// Same geometry the programmer firmware reports in Info
+    pub geometry: FlashGeometry,
 }
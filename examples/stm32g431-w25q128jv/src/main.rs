@@ -25,12 +25,13 @@ mod hardware;
 mod resources;
 mod ui;
 
-use hardware::{flash::FlashManager, display::DisplayManager};
+use hardware::{flash::{FlashManager, SharedFlash}, display::{DisplayManager, EmbassyTimer}};
 // Resource layout removed - no fonts in firmware
 
 // Static allocations
 static SPI1_BUS: StaticCell<Mutex<CriticalSectionRawMutex, Spi<'static, embassy_stm32::mode::Async>>> = StaticCell::new();
 static SPI2_BUS: StaticCell<Mutex<CriticalSectionRawMutex, Spi<'static, embassy_stm32::mode::Async>>> = StaticCell::new();
+static FLASH: StaticCell<SharedFlash> = StaticCell::new();
 
 bind_interrupts!(struct Irqs {
     // Add interrupt bindings as needed
@@ -107,7 +108,7 @@ async fn main(_spawner: Spawner) {
     defmt::info!("Hardware pins configured");
 
     // Initialize display
-    let mut display_manager = DisplayManager::new();
+    let mut display_manager = DisplayManager::<EmbassyTimer>::new();
     match display_manager.initialize(spi1_bus, display_cs, display_dc, display_rst).await {
         Ok(()) => {
             defmt::info!("✅ Display initialized successfully");
@@ -133,7 +134,9 @@ async fn main(_spawner: Spawner) {
     Timer::after(Duration::from_millis(2000)).await;
 
     // Initialize Flash
-    let mut flash_manager = FlashManager::new();
+    // Shared so a USB programmer task can lock it between screens (see SharedFlash)
+    let flash: &'static SharedFlash = FLASH.init(Mutex::new(FlashManager::new()));
+    let mut flash_manager = flash.lock().await;
     match flash_manager.initialize(spi2_bus, flash_cs).await {
         Ok(()) => {
             defmt::info!("✅ Flash initialized successfully");
@@ -265,6 +268,7 @@ async fn main(_spawner: Spawner) {
             display_manager.fill_rect(60, 60, 200, 60, Rgb565::RED).await.unwrap_or_default();
         }
     }
+    drop(flash_manager);
 
     // Wait before final step
     Timer::after(Duration::from_millis(3000)).await;
@@ -279,6 +283,8 @@ async fn main(_spawner: Spawner) {
     let screen_duration = Duration::from_millis(4000); // 4 seconds per screen
 
     loop {
+        // 每屏渲染期间独占Flash，协议命令在两屏之间执行，不会与渲染的读取交错
+        let mut flash_manager = flash.lock().await;
         match screen_index {
             // 第一屏：启动图片屏幕 (带性能测量)
             0 => {
@@ -337,7 +343,8 @@ async fn main(_spawner: Spawner) {
             }
         }
 
-        // 等待指定时间后切换到下一屏
+        // 等待指定时间后切换到下一屏（等待期间释放Flash）
+        drop(flash_manager);
         Timer::after(screen_duration).await;
 
         // 切换到下一屏
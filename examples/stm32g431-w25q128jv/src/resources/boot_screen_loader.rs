@@ -1,6 +1,10 @@
 use embedded_graphics::{pixelcolor::Rgb565, prelude::RgbColor};
+use flash_protocol::pattern::{rgb565_components, rgb565_from_le_bytes};
 use heapless::Vec;
-use crate::hardware::flash::FlashManager;
+use embassy_futures::join::join;
+use crate::hardware::flash::{FlashManager, MAX_LARGE_READ};
+#[cfg(feature = "jpeg")]
+use flash_protocol::jpeg::{is_jpeg, JpegDecoder, JpegSource};
 
 /// Display trait for generic display operations
 pub trait DisplayTrait {
@@ -57,10 +61,28 @@ impl BootScreenLoader {
             screen_width: 320,          // 屏幕宽度
             screen_height: 172,         // 屏幕高度
             screen_size: 320 * 172 * 2, // RGB565格式，每像素2字节
-            chunk_size: 2048,           // 每次读取2KB (优化分片大小)
+            chunk_size: MAX_LARGE_READ, // 每次读取2KB，与read_data_large单次上限一致
         }
     }
 
+    /// 使用自定义分片大小创建加载器
+    ///
+    /// 较小的分片降低峰值RAM占用，较大的分片减少每片开销。分片大小必须为偶数
+    /// (完整的RGB565像素)，且不超过`read_data_large`单次读取上限`MAX_LARGE_READ`。
+    pub fn with_chunk_size(chunk_size: usize) -> Result<Self, &'static str> {
+        if chunk_size == 0 || chunk_size % 2 != 0 {
+            return Err("Chunk size must be a non-zero multiple of 2");
+        }
+        if chunk_size > MAX_LARGE_READ {
+            return Err("Chunk size exceeds read buffer");
+        }
+
+        Ok(Self {
+            chunk_size,
+            ..Self::new()
+        })
+    }
+
     /// 获取开屏图基本信息
     pub fn get_screen_info(&self) -> BootScreenInfo {
         BootScreenInfo {
@@ -121,13 +143,14 @@ impl BootScreenLoader {
         &self,
         chunk_info: &ImageChunk,
         flash_manager: &mut FlashManager
-    ) -> Result<heapless::Vec<u8, 2048>, &'static str> {
+    ) -> Result<heapless::Vec<u8, MAX_LARGE_READ>, &'static str> {
         let read_addr = self.screen_addr + chunk_info.data_offset;
 
         defmt::debug!("📖 Reading chunk {} from 0x{:08X}, size: {} bytes",
                      chunk_info.chunk_index, read_addr, chunk_info.data_size);
 
-        let chunk_data = flash_manager.read_data_large(read_addr, chunk_info.data_size).await?;
+        // 经过缓存读取，使顺序读取可以被预取
+        let chunk_data = flash_manager.read_data(read_addr, chunk_info.data_size).await?;
 
         if chunk_data.len() < chunk_info.data_size {
             defmt::error!("❌ Failed to read complete chunk: got {} bytes, expected {}",
@@ -135,15 +158,9 @@ impl BootScreenLoader {
             return Err("Incomplete chunk read");
         }
 
-        // 转换为2048字节的Vec
-        let mut result = heapless::Vec::new();
-        for &byte in &chunk_data {
-            result.push(byte).map_err(|_| "Chunk buffer overflow")?;
-        }
-
         defmt::debug!("✅ Chunk {} read successfully: {} bytes",
-                     chunk_info.chunk_index, result.len());
-        Ok(result)
+                     chunk_info.chunk_index, chunk_data.len());
+        Ok(chunk_data)
     }
 
     /// 将RGB565数据转换为像素颜色数组
@@ -165,13 +182,11 @@ impl BootScreenLoader {
         // 学习web工具的RGB565解码方式：data[i] | (data[i+1] << 8)
         for i in (0..data.len()).step_by(2) {
             if i + 1 < data.len() {
-                // 按照web工具的方式解码RGB565 (little-endian)
-                let rgb565 = data[i] as u16 | ((data[i + 1] as u16) << 8);
+                // 按照web工具的方式解码RGB565 (little-endian，字节序由flash_protocol的测试锁定)
+                let rgb565 = rgb565_from_le_bytes([data[i], data[i + 1]]);
 
-                // 提取RGB分量 (与web工具一致的位操作)
-                let red = ((rgb565 >> 11) & 0x1F) as u8;    // 5位红色
-                let green = ((rgb565 >> 5) & 0x3F) as u8;   // 6位绿色
-                let blue = (rgb565 & 0x1F) as u8;           // 5位蓝色
+                // 提取RGB分量 (5位红色, 6位绿色, 5位蓝色)
+                let (red, green, blue) = rgb565_components(rgb565);
 
                 let color = Rgb565::new(red, green, blue);
                 pixels.push(color).map_err(|_| "Pixel buffer full")?;
@@ -192,11 +207,22 @@ impl BootScreenLoader {
     where
         D: DisplayTrait,
     {
+        #[cfg(feature = "jpeg")]
+        {
+            let header = flash_manager.read_data_simple(self.screen_addr, 3).await?;
+            if is_jpeg(&header) {
+                return self.load_and_display_jpeg(display, flash_manager).await;
+            }
+        }
+
         let total_chunks = self.get_total_chunks();
 
         defmt::info!("🖼️ Loading boot screen: {}x{} pixels, {} chunks",
                     self.screen_width, self.screen_height, total_chunks);
 
+        let started = embassy_time::Instant::now();
+        let prefetched_before = flash_manager.prefetch_count();
+
         // 首先清空屏幕
         defmt::debug!("🧹 Clearing screen...");
         display.fill_screen(Rgb565::BLACK).await.map_err(|_| "Failed to clear screen")?;
@@ -212,8 +238,15 @@ impl BootScreenLoader {
             // 转换为像素数据
             let pixels = self.convert_rgb565_data(&chunk_data)?;
 
-            // 显示块数据
-            self.display_chunk(display, &chunk_info, &pixels).await?;
+            // 显示块数据，同时预取下一块 (显示屏在SPI1上，Flash在SPI2上，互不阻塞)
+            let (shown, prefetched) = join(
+                self.display_chunk(display, &chunk_info, &pixels),
+                flash_manager.prefetch(),
+            ).await;
+            shown?;
+            if let Err(e) = prefetched {
+                defmt::warn!("⚠️ Prefetch failed: {}", e);
+            }
 
             // 显示详细进度信息
             let progress = ((chunk_index + 1) * 100) / total_chunks;
@@ -227,7 +260,52 @@ impl BootScreenLoader {
             embassy_time::Timer::after_millis(1).await;
         }
 
-        defmt::info!("✅ Boot screen loaded successfully!");
+        defmt::info!("✅ Boot screen loaded in {} ms ({} cache lines prefetched)",
+                    started.elapsed().as_millis(),
+                    flash_manager.prefetch_count() - prefetched_before);
+        Ok(())
+    }
+
+    /// 解码并显示JPEG开屏图
+    ///
+    /// 按MCU (8x8至16x16像素) 逐块从Flash流式解码，解码器状态约3.5KB，与图像尺寸无关。
+    /// 超出屏幕的部分被裁剪。
+    #[cfg(feature = "jpeg")]
+    async fn load_and_display_jpeg<D>(
+        &self,
+        display: &mut D,
+        flash_manager: &mut FlashManager
+    ) -> Result<(), &'static str>
+    where
+        D: DisplayTrait,
+    {
+        let source = FlashJpegSource {
+            flash_manager,
+            address: self.screen_addr,
+        };
+        let mut decoder = JpegDecoder::new(source).await?;
+
+        defmt::info!("🖼️ Loading JPEG boot screen: {}x{} pixels", decoder.width(), decoder.height());
+        if decoder.width() != self.screen_width || decoder.height() != self.screen_height {
+            defmt::warn!("⚠️ JPEG size differs from screen {}x{}, clipping",
+                        self.screen_width, self.screen_height);
+        }
+
+        display.fill_screen(Rgb565::BLACK).await.map_err(|_| "Failed to clear screen")?;
+
+        while let Some(mcu) = decoder.next_mcu().await? {
+            for (i, &raw) in mcu.pixels.iter().enumerate() {
+                let x = mcu.x + (i % mcu.width as usize) as u16;
+                let y = mcu.y + (i / mcu.width as usize) as u16;
+                if x < self.screen_width && y < self.screen_height {
+                    let (red, green, blue) = rgb565_components(raw);
+                    display.draw_pixel(x, y, Rgb565::new(red, green, blue))
+                        .await.map_err(|_| "Failed to draw pixel")?;
+                }
+            }
+        }
+
+        defmt::info!("✅ JPEG boot screen loaded successfully!");
         Ok(())
     }
 
@@ -340,6 +418,24 @@ impl BootScreenLoader {
     }
 }
 
+/// 从Flash顺序读取JPEG数据的字节源
+#[cfg(feature = "jpeg")]
+struct FlashJpegSource<'a> {
+    flash_manager: &'a mut FlashManager,
+    address: u32,
+}
+
+#[cfg(feature = "jpeg")]
+impl JpegSource for FlashJpegSource<'_> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, &'static str> {
+        let length = buf.len().min(MAX_LARGE_READ);
+        let data = self.flash_manager.read_data_large(self.address, length).await?;
+        buf[..data.len()].copy_from_slice(&data);
+        self.address += data.len() as u32;
+        Ok(data.len())
+    }
+}
+
 /// 开屏图统计信息
 #[derive(Debug)]
 pub struct ScreenStats {
@@ -1,9 +1,11 @@
+use flash_protocol::glyph::{self, BitmapFormat};
 use heapless::Vec;
 
 /// Font bitmap header structure
 #[derive(Debug, Clone)]
 pub struct FontHeader {
     pub char_count: u32,
+    pub format: BitmapFormat,
 }
 
 /// Character information structure
@@ -15,19 +17,48 @@ pub struct CharInfo {
     pub bitmap_offset: u16,
 }
 
+/// Size of one character-info record in a flash font table
+/// (unicode(4) + width(1) + height(1) + bitmap_offset(4))
+pub const FLASH_CHAR_INFO_SIZE: u32 = flash_protocol::font::CHAR_INFO_SIZE as u32;
+
+/// Flash space reserved for each font: header, character table and bitmaps
+pub const FONT_REGION_SIZE: u32 = flash_protocol::font::FONT_REGION_SIZE;
+
 /// Font bitmap parser for the custom format
 pub struct FontParser;
 
 impl FontParser {
+    /// Sanity-check the character count read from a flash font header
+    ///
+    /// An unprogrammed chip reads back 0xFFFFFFFF, which would send the
+    /// binary search far outside the font. Zero, all-ones and counts whose
+    /// table wouldn't fit in the font region are rejected.
+    pub fn validate_char_count(char_count: u32) -> Result<u32, &'static str> {
+        let table_size = 4 + char_count as u64 * FLASH_CHAR_INFO_SIZE as u64;
+        if char_count == 0 || char_count == u32::MAX || table_size > FONT_REGION_SIZE as u64 {
+            defmt::warn!("Font header has implausible character count {}", char_count);
+            return Err("Font not programmed");
+        }
+        Ok(char_count)
+    }
+
+    /// Split a flash font header word into a checked character count and the
+    /// bitmap format declared in its top byte
+    pub fn parse_header_word(word: u32) -> Result<(u32, BitmapFormat), &'static str> {
+        let char_count = Self::validate_char_count(word & glyph::FONT_HEADER_COUNT_MASK)?;
+        let (_, format) = glyph::parse_font_header(word)?;
+        Ok((char_count, format))
+    }
+
     /// Parse font header from raw data
     pub fn parse_header(data: &[u8]) -> Result<FontHeader, &'static str> {
         if data.len() < 4 {
             return Err("Insufficient data for header");
         }
 
-        let char_count = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+        let (char_count, format) = glyph::parse_font_header(u32::from_le_bytes([data[0], data[1], data[2], data[3]]))?;
 
-        Ok(FontHeader { char_count })
+        Ok(FontHeader { char_count, format })
     }
 
     /// Parse character information table
@@ -1,6 +1,8 @@
 use heapless::{Vec, FnvIndexMap};
 use embedded_graphics::pixelcolor::Rgb565;
 use crate::hardware::flash::FlashManager;
+use crate::resources::font_parser::FontParser;
+use flash_protocol::{font::{self, KernPair, KERN_PAIR_SIZE}, glyph::BitmapFormat};
 
 /// 16px字体的字符信息结构（10字节格式）
 #[derive(Debug, Clone, Copy)]
@@ -16,6 +18,8 @@ pub struct FontRenderer16px {
     font_base_addr: u32,
     char_cache: FnvIndexMap<u32, CharInfo16px, 16>, // 缓存16个常用字符
     char_count: u32,
+    format: BitmapFormat,       // 字体头声明的位图位序/字节序
+    kerning: Option<(u32, u32)>, // 字距表：(第一个字符对的地址, 字符对数量)
 }
 
 impl FontRenderer16px {
@@ -25,6 +29,8 @@ impl FontRenderer16px {
             font_base_addr: 0x00120000, // 16px字体在Flash中的基地址
             char_cache: FnvIndexMap::new(),
             char_count: 0,
+            format: BitmapFormat::default(),
+            kerning: None,
         }
     }
 
@@ -32,22 +38,103 @@ impl FontRenderer16px {
     pub async fn initialize(&mut self, flash_manager: &mut FlashManager) -> Result<(), &'static str> {
         defmt::info!("🎨 Initializing 16px font renderer...");
 
-        // 读取字体头部（4字节字符数量）
+        // 读取字体头部（低24位字符数量，高8位位图格式标志）
         let header_data = flash_manager.read_data_simple(self.font_base_addr, 4).await?;
 
         if header_data.len() < 4 {
             return Err("Failed to read font header");
         }
 
-        // 解析字符数量（小端序）
-        self.char_count = u32::from_le_bytes([
+        // 解析头部（小端序），拒绝未烧录(0xFFFFFFFF)或越界的字符数及未知格式
+        let header_word = u32::from_le_bytes([
             header_data[0], header_data[1], header_data[2], header_data[3]
         ]);
+        let (char_count, format) = FontParser::parse_header_word(header_word)?;
+        self.char_count = char_count;
+        self.format = format;
+        self.kerning = if font::has_kerning(header_word) {
+            self.read_kerning_table(flash_manager).await?
+        } else {
+            None
+        };
+
+        // 二分查找依赖按Unicode升序排列的字符表，抽查几项以尽早发现生成器错误
+        if !self.table_looks_sorted(flash_manager).await? {
+            defmt::error!("❌❌ 16px font character table is NOT sorted by Unicode - lookups will miss characters! Rebuild the font with `make-font`");
+        }
 
-        defmt::info!("✅ 16px font initialized: {} characters available", self.char_count);
+        defmt::info!("✅ 16px font initialized: {} characters available, {} kerning pairs",
+                     self.char_count, self.kerning.map_or(0, |(_, pairs)| pairs));
         Ok(())
     }
 
+    /// 读取字距表头；字符对数量超出字体区域时忽略字距表
+    async fn read_kerning_table(&self, flash_manager: &mut FlashManager) -> Result<Option<(u32, u32)>, &'static str> {
+        let table_offset = font::kerning_table_offset(self.char_count) as u32;
+        let data = flash_manager.read_data_simple(self.font_base_addr + table_offset, 4).await?;
+        if data.len() < 4 {
+            return Err("Failed to read kerning table");
+        }
+
+        let pair_count = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+        let table_end = table_offset as u64 + 4 + pair_count as u64 * KERN_PAIR_SIZE as u64;
+        if table_end > font::FONT_REGION_SIZE as u64 {
+            defmt::warn!("⚠️ Kerning table claims {} pairs, more than fit in the font; ignoring it", pair_count);
+            return Ok(None);
+        }
+        Ok(Some((self.font_base_addr + table_offset + 4, pair_count)))
+    }
+
+    /// 查找字符对的间距调整（像素）；无字距表、未收录或读取失败时为0
+    pub async fn kerning(&self, left: u32, right: u32, flash_manager: &mut FlashManager) -> i8 {
+        let Some((pairs_addr, pair_count)) = self.kerning else {
+            return 0;
+        };
+
+        // 字符对按(左, 右)升序排列，二分查找
+        let mut low = 0u32;
+        let mut high = pair_count;
+        while low < high {
+            let mid = (low + high) / 2;
+            let data = match flash_manager.read_data_simple(pairs_addr + mid * KERN_PAIR_SIZE as u32, KERN_PAIR_SIZE).await {
+                Ok(data) => data,
+                Err(_) => return 0,
+            };
+            let pair = match KernPair::from_bytes(&data) {
+                Ok(pair) => pair,
+                Err(_) => return 0,
+            };
+            match pair.key().cmp(&(left, right)) {
+                core::cmp::Ordering::Equal => return pair.adjust,
+                core::cmp::Ordering::Less => low = mid + 1,
+                core::cmp::Ordering::Greater => high = mid,
+            }
+        }
+        0
+    }
+
+    /// 抽查字符表（含首尾项）的Unicode是否严格递增
+    async fn table_looks_sorted(&self, flash_manager: &mut FlashManager) -> Result<bool, &'static str> {
+        let mut previous: Option<u32> = None;
+        for index in font::table_sample_indices(self.char_count) {
+            let entry_addr = self.font_base_addr + 4 + index * 10;
+            let entry = flash_manager.read_data_simple(entry_addr, 4).await?;
+            if entry.len() < 4 {
+                return Err("Failed to read character info");
+            }
+            let unicode = u32::from_le_bytes([entry[0], entry[1], entry[2], entry[3]]);
+            if let Some(previous) = previous {
+                if unicode <= previous {
+                    defmt::warn!("Character table entry {} (U+{:04X}) is not above U+{:04X}",
+                                 index, unicode, previous);
+                    return Ok(false);
+                }
+            }
+            previous = Some(unicode);
+        }
+        Ok(true)
+    }
+
     /// 查找字符信息（使用二分查找优化）
     pub async fn find_char(&mut self, char_code: u32, flash_manager: &mut FlashManager) -> Result<CharInfo16px, &'static str> {
         // 首先检查缓存
@@ -119,9 +206,8 @@ impl FontRenderer16px {
         char_info: &CharInfo16px,
         flash_manager: &mut FlashManager
     ) -> Result<Vec<u8, 64>, &'static str> {
-        // 计算位图大小
-        let bitmap_size_bits = char_info.width as usize * char_info.height as usize;
-        let bitmap_size_bytes = (bitmap_size_bits + 7) / 8;
+        // 按字体格式计算位图大小（行/列按字节补齐）
+        let bitmap_size_bytes = self.format.bitmap_size(char_info.width, char_info.height);
 
         if bitmap_size_bytes > 64 {
             defmt::error!("❌ Bitmap too large: {} bytes (max 64)", bitmap_size_bytes);
@@ -160,30 +246,21 @@ impl FontRenderer16px {
     {
         let width = char_info.width;
         let height = char_info.height;
-        let bytes_per_row = ((width as usize) + 7) / 8;
 
         defmt::debug!("🎨 Rendering character U+{:04X} at ({}, {}) size {}x{}",
                      char_info.unicode, x, y, width, height);
 
-        // 逐像素渲染（MSB优先，行主序）
-        for row in 0..height {
-            for col in 0..width {
-                let byte_index = (row as usize) * bytes_per_row + (col as usize) / 8;
-                let bit_index = 7 - ((col as usize) % 8); // MSB优先
-
-                if byte_index < bitmap.len() {
-                    let byte = bitmap[byte_index];
-                    let pixel = (byte >> bit_index) & 1;
-
-                    if pixel != 0 {
-                        let pixel_x = x + col as i32;
-                        let pixel_y = y + row as i32;
-
-                        // 使用display的fill_rect方法绘制1x1像素
-                        // 注意：这里需要根据实际的display类型调整
-                        // 暂时使用占位符实现
-                        defmt::trace!("Drawing pixel at ({}, {})", pixel_x, pixel_y);
-                    }
+        // 逐像素渲染（位序/字节序由字体头决定）
+        for row in 0..height as usize {
+            for col in 0..width as usize {
+                if self.format.pixel(bitmap, width, height, col, row) {
+                    let pixel_x = x + col as i32;
+                    let pixel_y = y + row as i32;
+
+                    // 使用display的fill_rect方法绘制1x1像素
+                    // 注意：这里需要根据实际的display类型调整
+                    // 暂时使用占位符实现
+                    defmt::trace!("Drawing pixel at ({}, {})", pixel_x, pixel_y);
                 }
             }
         }
@@ -200,16 +277,24 @@ impl FontRenderer16px {
     ) -> Result<u32, &'static str> {
         let mut total_width = 0u32;
         let char_spacing = 1u32; // 字符间距
+        let mut previous: Option<u32> = None;
 
         for ch in text.chars() {
             let char_code = ch as u32;
             match self.find_char(char_code, flash_manager).await {
                 Ok(char_info) => {
+                    // 与draw_text_16px一致地应用字距调整
+                    if let Some(left) = previous {
+                        let adjust = self.kerning(left, char_code, flash_manager).await as i32;
+                        total_width = (total_width as i32 + adjust).max(0) as u32;
+                    }
                     total_width += char_info.width as u32 + char_spacing;
+                    previous = Some(char_code);
                 },
                 Err(_) => {
                     // 未找到字符，使用默认宽度
                     total_width += 8 + char_spacing; // 默认8像素宽度
+                    previous = None;
                 }
             }
         }
@@ -222,6 +307,11 @@ impl FontRenderer16px {
         Ok(total_width)
     }
 
+    /// 字体头声明的位图格式
+    pub fn bitmap_format(&self) -> BitmapFormat {
+        self.format
+    }
+
     /// 获取字体基本信息
     pub fn get_font_info(&self) -> (u32, u32) {
         (self.font_base_addr, self.char_count)
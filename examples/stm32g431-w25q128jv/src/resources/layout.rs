@@ -5,6 +5,10 @@
 pub const BOOT_SCREEN_ADDR: u32 = 0x000000;
 pub const BOOT_SCREEN_SIZE: u32 = 110_080; // 320 * 172 * 2 bytes
 
+/// Asset table written by the host `assets` command (see flash_protocol::asset_pack)
+pub const ASSET_TABLE_ADDR: u32 = flash_protocol::asset_pack::ASSET_TABLE_ADDR;
+pub const ASSET_TABLE_SIZE: u32 = 4096;
+
 /// Font bitmap resource (12px bitmap font, 2094 characters)
 pub const FONT_BITMAP_ADDR: u32 = 0x00020000;
 pub const FONT_BITMAP_SIZE: u32 = 2_097_152; // 2MB allocated space
@@ -50,6 +54,12 @@ pub const RESOURCES: &[ResourceInfo] = &[
         size: BOOT_SCREEN_SIZE,
         description: "320x172 RGB565 boot screen",
     },
+    ResourceInfo {
+        name: "asset_table",
+        address: ASSET_TABLE_ADDR,
+        size: ASSET_TABLE_SIZE,
+        description: "Asset pack table (type/address/length/CRC32)",
+    },
     ResourceInfo {
         name: "font_bitmap",
         address: FONT_BITMAP_ADDR,
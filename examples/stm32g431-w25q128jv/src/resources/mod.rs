@@ -1,6 +1,5 @@
 pub mod layout;
 pub mod font_parser;
 pub mod image_parser;
-pub mod cache;
 pub mod font_renderer_16px;
 pub mod boot_screen_loader;
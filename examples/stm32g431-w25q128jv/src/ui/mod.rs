@@ -1,3 +1,4 @@
 pub mod app;
 pub mod font_viewer;
 pub mod image_viewer;
+pub mod scroll_region;
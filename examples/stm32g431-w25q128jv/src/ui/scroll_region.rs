@@ -0,0 +1,93 @@
+use heapless::{Deque, String};
+
+/// Maximum characters kept per log line (longer lines are truncated)
+pub const MAX_LINE_CHARS: usize = 48;
+
+/// Maximum number of lines a scroll region can hold
+pub const MAX_LINES: usize = 16;
+
+/// Scrolling text region for status/log output
+///
+/// Keeps a ring of the most recent lines; pushing a new line drops the oldest
+/// one once the region is full so the text moves up by one line.
+pub struct ScrollRegion {
+    x: i32,
+    y: i32,
+    width: u16,
+    line_height: u16,
+    visible_lines: usize,
+    lines: Deque<String<MAX_LINE_CHARS>, MAX_LINES>,
+}
+
+impl ScrollRegion {
+    /// Create a scroll region covering `width` x `height` pixels at (x, y)
+    pub fn new(x: i32, y: i32, width: u16, height: u16, line_height: u16) -> Self {
+        let visible_lines = core::cmp::max(1, core::cmp::min((height / line_height) as usize, MAX_LINES));
+
+        Self {
+            x,
+            y,
+            width,
+            line_height,
+            visible_lines,
+            lines: Deque::new(),
+        }
+    }
+
+    /// Append a line, dropping the oldest one when the region is full
+    pub fn push_line(&mut self, text: &str) {
+        if self.lines.len() >= self.visible_lines {
+            self.lines.pop_front();
+        }
+
+        let mut line = String::new();
+        for ch in text.chars() {
+            if line.push(ch).is_err() {
+                break; // Truncate overly long lines
+            }
+        }
+
+        // Cannot fail: we made room above
+        let _ = self.lines.push_back(line);
+    }
+
+    /// Remove all lines
+    pub fn clear(&mut self) {
+        self.lines.clear();
+    }
+
+    /// Iterate over the visible lines with their screen Y coordinate (oldest first)
+    pub fn lines(&self) -> impl Iterator<Item = (i32, &str)> {
+        let line_height = self.line_height as i32;
+        let top = self.y;
+        self.lines
+            .iter()
+            .enumerate()
+            .map(move |(i, line)| (top + i as i32 * line_height, line.as_str()))
+    }
+
+    /// Region origin and size as (x, y, width, height)
+    pub fn bounds(&self) -> (i32, i32, u16, u16) {
+        (self.x, self.y, self.width, self.line_height * self.visible_lines as u16)
+    }
+
+    /// Height of a single text line in pixels
+    pub fn line_height(&self) -> u16 {
+        self.line_height
+    }
+
+    /// Number of lines currently held
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// Whether the region holds no lines
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    /// Number of lines that fit in the region
+    pub fn capacity(&self) -> usize {
+        self.visible_lines
+    }
+}
@@ -0,0 +1,58 @@
+//! Reboot into the STM32G4 system memory bootloader (USB DFU)
+//!
+//! Jumping straight from the running firmware would hand the bootloader
+//! clocks, USB and interrupts in whatever state they are in, so
+//! `EnterBootloader` instead leaves a marker in RAM that survives a reset and
+//! resets. `jump_if_requested`, called first thing in `main`, finds the
+//! marker while the chip is still in its reset state and jumps to system
+//! memory as described in AN2606: remap system flash to address 0, load the
+//! bootloader's stack pointer and branch to its reset vector.
+
+use core::mem::MaybeUninit;
+
+/// Start of system memory (the ST bootloader) on STM32G4
+const SYSTEM_MEMORY: u32 = 0x1FFF_0000;
+
+/// RCC_APB2ENR (RM0440 7.4.18) and its SYSCFGEN bit
+const RCC_APB2ENR: *mut u32 = 0x4002_1060 as *mut u32;
+const RCC_APB2ENR_SYSCFGEN: u32 = 1 << 0;
+
+/// SYSCFG_MEMRMP (RM0440 9.1.1); MEM_MODE = 0b001 maps system flash at 0
+const SYSCFG_MEMRMP: *mut u32 = 0x4001_0000 as *mut u32;
+const MEMRMP_SYSTEM_FLASH: u32 = 0b001;
+
+const BOOT_MAGIC: u32 = 0xB007_DF00;
+
+/// Not zeroed by the runtime, so it keeps its value across a system reset
+#[link_section = ".uninit.BOOT_REQUEST"]
+static mut BOOT_REQUEST: MaybeUninit<u32> = MaybeUninit::uninit();
+
+/// Reset into the DFU bootloader
+pub fn reboot_to_dfu() -> ! {
+    defmt::info!("Rebooting into the system DFU bootloader");
+    unsafe { core::ptr::write_volatile(BOOT_REQUEST.as_mut_ptr(), BOOT_MAGIC) };
+    cortex_m::peripheral::SCB::sys_reset()
+}
+
+/// Jump to the bootloader if the last reset came from `reboot_to_dfu`
+///
+/// Must run before any clock or peripheral setup.
+pub fn jump_if_requested() {
+    unsafe {
+        if core::ptr::read_volatile(BOOT_REQUEST.as_ptr()) != BOOT_MAGIC {
+            return;
+        }
+        // Boot normally next time, e.g. after the DFU upload's reset
+        core::ptr::write_volatile(BOOT_REQUEST.as_mut_ptr(), 0);
+
+        core::ptr::write_volatile(
+            RCC_APB2ENR,
+            core::ptr::read_volatile(RCC_APB2ENR) | RCC_APB2ENR_SYSCFGEN,
+        );
+        core::ptr::write_volatile(
+            SYSCFG_MEMRMP,
+            (core::ptr::read_volatile(SYSCFG_MEMRMP) & !0b111) | MEMRMP_SYSTEM_FLASH,
+        );
+        cortex_m::asm::bootload(SYSTEM_MEMORY as *const u32)
+    }
+}
@@ -0,0 +1,97 @@
+//! Dual Output Fast Read (0x3B), enabled by the `dual_read` feature
+//!
+//! After the opcode, address and one dummy byte the W25Q128 shifts data out
+//! two bits per clock: the odd bit on IO1 (DO, the usual MISO line) and the
+//! even bit on IO0 (DI, the usual MOSI line). No QE bit is needed and WP#/HOLD#
+//! keep their normal roles, but PB15 must be wired straight to the chip's DI:
+//! a series resistor is fine, a one-way level shifter or buffer is not.
+//!
+//! The STM32G431 SPI peripheral has no dual mode, so the command phase goes
+//! through the SPI peripheral and the data phase is bit-banged: SCK (PB13)
+//! and MOSI (PB15) are switched to GPIO for the transfer and handed back to
+//! the peripheral afterwards. The bit-banged clock is set by CPU speed, not
+//! by `set_spi_frequency`, so the gain over single reads shrinks as the SPI
+//! clock goes up; `SafeFlashManager` checks the result against a single read
+//! at startup and keeps single reads if they differ.
+
+use embassy_stm32::gpio::Output;
+use embassy_stm32::mode::Async;
+use embassy_stm32::pac;
+use embassy_stm32::pac::gpio::vals;
+use embassy_stm32::spi::Spi;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::mutex::Mutex;
+use flash_protocol::SpiMode;
+
+const CMD_FAST_READ_DUAL_OUTPUT: u8 = 0x3B;
+
+// GPIOB pin numbers of the flash SPI lines
+const SCK: usize = 13;
+const IO1: usize = 14;
+const IO0: usize = 15;
+
+/// Fill `data` from `address` with a dual output read
+///
+/// Holds the bus lock for the whole transfer so no other SPI user can run
+/// while its pins are in GPIO mode.
+pub async fn read(
+    spi_bus: &Mutex<CriticalSectionRawMutex, Spi<'static, Async>>,
+    cs_pin: &mut Output<'static>,
+    spi_mode: SpiMode,
+    address: u32,
+    data: &mut [u8],
+) -> Result<(), embassy_stm32::spi::Error> {
+    let mut spi = spi_bus.lock().await;
+    let cmd = [
+        CMD_FAST_READ_DUAL_OUTPUT,
+        (address >> 16) as u8,
+        (address >> 8) as u8,
+        address as u8,
+        0x00, // 8 dummy clocks
+    ];
+
+    cs_pin.set_low();
+    if let Err(e) = spi.write(&cmd).await {
+        cs_pin.set_high();
+        return Err(e);
+    }
+
+    let gpio = pac::GPIOB;
+    let saved = gpio.moder().read();
+    // SCK idles at the level the peripheral left it (CPOL)
+    let idle_high = spi_mode == SpiMode::Mode3;
+    gpio.bsrr().write(|w| {
+        if idle_high {
+            w.set_bs(SCK, true)
+        } else {
+            w.set_br(SCK, true)
+        }
+    });
+    gpio.moder().modify(|w| {
+        w.set_moder(SCK, vals::Moder::OUTPUT);
+        w.set_moder(IO1, vals::Moder::INPUT);
+        // Release IO0 before the chip starts driving it
+        w.set_moder(IO0, vals::Moder::INPUT);
+    });
+
+    for byte in data.iter_mut() {
+        let mut value = 0u8;
+        for _ in 0..4 {
+            // The chip shifts out on the falling edge; sample after the rising one
+            gpio.bsrr().write(|w| w.set_br(SCK, true));
+            gpio.bsrr().write(|w| w.set_bs(SCK, true));
+            let idr = gpio.idr().read();
+            value = (value << 2)
+                | ((idr.idr(IO1) == vals::Idr::HIGH) as u8) << 1
+                | (idr.idr(IO0) == vals::Idr::HIGH) as u8;
+        }
+        *byte = value;
+    }
+
+    if !idle_high {
+        gpio.bsrr().write(|w| w.set_br(SCK, true));
+    }
+    cs_pin.set_high();
+    gpio.moder().write_value(saved);
+    Ok(())
+}
@@ -0,0 +1,249 @@
+//! USB transport for the command protocol
+//!
+//! Reception and processing run as two loops joined in the main task and
+//! linked by a bounded [`PacketQueue`]: `usb_receive_loop` reassembles
+//! packets from the CDC byte stream while `protocol_handler_loop` hands them
+//! to the shared `ProtocolHandler` and streams responses back in
+//! endpoint-sized chunks. The next packet can arrive over USB while the
+//! current one is still being programmed. The trailer checksum negotiated by
+//! the handler is shared with the receive loop through a [`Cell`].
+//!
+//! Both loops count the errors they see (see [`error_counters`]) for the
+//! host's `stats` command.
+//!
+//! Packets are numbered as they arrive. Abort packets are acted on by the
+//! receive loop as soon as they are parsed (see `safe_flash::request_abort`)
+//! and only then queued for their reply, so they can interrupt the command
+//! being processed and the ones queued behind it. Packets failing their
+//! CRC are still queued, without acting on them, so the handler can answer
+//! `CrcError` in order with the other replies. They can't overtake a
+//! full queue, which only happens while the host is streaming writes.
+
+use alloc::vec::Vec;
+use core::cell::Cell;
+use core::sync::atomic::{AtomicU32, Ordering};
+use embassy_stm32::peripherals;
+use embassy_stm32::usb::Driver;
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_time::{Duration, Timer};
+use embassy_usb::class::cdc_acm::{Receiver, Sender};
+use flash_protocol::error_counters::{self, ErrorCounters};
+use flash_protocol::framing::{compact, try_parse_packet_counting};
+use flash_protocol::handler::{ProtocolHandler, ResponseSink};
+use flash_protocol::{Command, CrcMode, Packet, Response};
+
+use crate::bootloader;
+use crate::safe_flash::{request_abort, start_command, SafeFlashManager};
+
+/// Parsed packets waiting for the flash; when full, USB reads stall until
+/// programming catches up (each queued packet holds up to 1KB of heap)
+pub const PACKET_QUEUE_DEPTH: usize = 4;
+
+/// Packets queued for the handler, each with its arrival number (see
+/// `safe_flash::request_abort`)
+pub type PacketQueue = Channel<NoopRawMutex, (u32, Packet), PACKET_QUEUE_DEPTH>;
+
+/// Arrival number of the next packet; starts at 1 so no command is aborted
+/// before the first Abort. Kept across reconnects so numbers only grow
+static NEXT_ARRIVAL: AtomicU32 = AtomicU32::new(1);
+
+/// Max packet size of the CDC bulk endpoints
+///
+/// 64 bytes is the largest bulk packet USB 2.0 allows at full speed, the only
+/// speed the STM32G4 USB peripheral supports, so this can't be raised.
+/// Throughput instead comes from the host writing whole protocol packets at
+/// once (several bulk transactions per 1ms frame) and from keeping the
+/// per-transaction work here small.
+pub const CDC_PACKET_SIZE: usize = 64;
+
+/// Headers the parser rejected since boot or the last clear
+static DROPPED_PACKETS: AtomicU32 = AtomicU32::new(0);
+/// Packets whose CRC trailer didn't match (answered with `CrcError`)
+static CRC_ERRORS: AtomicU32 = AtomicU32::new(0);
+/// Times stalled bytes were dropped to make room in the receive buffer
+static BUFFER_OVERFLOWS: AtomicU32 = AtomicU32::new(0);
+/// Responses reporting a failed flash operation
+static FLASH_ERRORS: AtomicU32 = AtomicU32::new(0);
+
+/// Errors counted since boot or the last [`clear_error_counters`]
+pub fn error_counters() -> ErrorCounters {
+    ErrorCounters {
+        dropped_packets: DROPPED_PACKETS.load(Ordering::Relaxed),
+        crc_errors: CRC_ERRORS.load(Ordering::Relaxed),
+        buffer_overflows: BUFFER_OVERFLOWS.load(Ordering::Relaxed),
+        flash_errors: FLASH_ERRORS.load(Ordering::Relaxed),
+    }
+}
+
+pub fn clear_error_counters() {
+    for counter in [
+        &DROPPED_PACKETS,
+        &CRC_ERRORS,
+        &BUFFER_OVERFLOWS,
+        &FLASH_ERRORS,
+    ] {
+        counter.store(0, Ordering::Relaxed);
+    }
+}
+
+// 错误处理结构
+pub struct Disconnected {}
+
+impl From<embassy_usb::driver::EndpointError> for Disconnected {
+    fn from(val: embassy_usb::driver::EndpointError) -> Self {
+        match val {
+            embassy_usb::driver::EndpointError::BufferOverflow => core::panic!("Buffer overflow"),
+            embassy_usb::driver::EndpointError::Disabled => Disconnected {},
+        }
+    }
+}
+
+/// Sends responses over the CDC data endpoint
+struct UsbSink<'c, 'a> {
+    sender: &'c mut Sender<'a, Driver<'a, peripherals::USB>>,
+}
+
+impl ResponseSink for UsbSink<'_, '_> {
+    type Error = Disconnected;
+
+    async fn send(&mut self, response: &Response, crc_mode: CrcMode) -> Result<(), Disconnected> {
+        if error_counters::is_flash_error(response) {
+            FLASH_ERRORS.fetch_add(1, Ordering::Relaxed);
+        }
+        // Send response in chunks to avoid buffer overflow
+        let response_data = response.to_bytes_with(crc_mode);
+        defmt::info!("Protocol: Sending response, {} bytes", response_data.len());
+
+        // Send in endpoint-sized chunks
+        let mut sent = 0;
+        while sent < response_data.len() {
+            let chunk_end = core::cmp::min(sent + CDC_PACKET_SIZE, response_data.len());
+            let chunk = &response_data[sent..chunk_end];
+            self.sender.write_packet(chunk).await?;
+            sent = chunk_end;
+            defmt::debug!(
+                "Protocol: Sent chunk {} bytes, total sent: {}",
+                chunk.len(),
+                sent
+            );
+        }
+        // A bulk transfer ends with a short packet. A response filling its
+        // last packet exactly needs a zero-length one, or the host keeps
+        // waiting for more data
+        if response_data.len() % CDC_PACKET_SIZE == 0 {
+            self.sender.write_packet(&[]).await?;
+            defmt::debug!("Protocol: Sent zero-length packet to end the transfer");
+        }
+        defmt::info!("Protocol: Response sent successfully");
+        Ok(())
+    }
+}
+
+/// Reassemble packets from USB and queue them for processing
+///
+/// Returns when the host disconnects.
+pub async fn usb_receive_loop<'a>(
+    receiver: &mut Receiver<'a, Driver<'a, peripherals::USB>>,
+    queue: &PacketQueue,
+    crc_mode: &Cell<CrcMode>,
+) -> Result<(), Disconnected> {
+    // Protocol processing variables with memory management
+    let mut packet_buffer = Vec::with_capacity(2048); // Pre-allocate reasonable capacity
+    let mut buffer = [0u8; CDC_PACKET_SIZE];
+    const MAX_BUFFER_SIZE: usize = 4096; // Maximum buffer size to prevent memory issues
+
+    loop {
+        // Read data from USB
+        let n = receiver.read_packet(&mut buffer).await?;
+        if n > 0 {
+            // Debug level: this runs for every 64-byte transaction and RTT
+            // logging at info level noticeably slows writes
+            defmt::debug!("USB: Received {} bytes", n);
+
+            // Add to packet buffer with size check, keeping any packet that
+            // may still complete
+            let dropped = compact(&mut packet_buffer, n, MAX_BUFFER_SIZE);
+            if dropped > 0 {
+                BUFFER_OVERFLOWS.fetch_add(1, Ordering::Relaxed);
+                defmt::warn!(
+                    "Buffer overflow protection: dropped {} stalled bytes, kept {}",
+                    dropped,
+                    packet_buffer.len()
+                );
+            }
+            packet_buffer.extend_from_slice(&buffer[..n]);
+            defmt::debug!("USB: Packet buffer now has {} bytes", packet_buffer.len());
+
+            // Try to parse complete packets
+            let mut rejected = 0;
+            while let Some(packet) =
+                try_parse_packet_counting(&mut packet_buffer, crc_mode.get(), &mut rejected)
+            {
+                defmt::info!(
+                    "Protocol: Parsed packet - Address: 0x{:08x}, Length: {}",
+                    packet.address,
+                    packet.length
+                );
+                let arrival = NEXT_ARRIVAL.fetch_add(1, Ordering::Relaxed);
+                if !packet.verify_crc_with(crc_mode.get().for_command(packet.command)) {
+                    // The handler rejects it without executing it
+                    CRC_ERRORS.fetch_add(1, Ordering::Relaxed);
+                    defmt::warn!("Protocol: CRC mismatch in {} packet", packet.command);
+                } else if packet.command == Command::Abort {
+                    defmt::warn!("Protocol: Abort requested");
+                    request_abort(arrival);
+                }
+
+                // Waits here (applying backpressure) while the queue is full
+                queue.send((arrival, packet)).await;
+
+                // Memory management: shrink buffer if it's getting large
+                if packet_buffer.capacity() > 2048 && packet_buffer.len() < 512 {
+                    defmt::debug!(
+                        "Memory: Shrinking buffer from capacity {} to {}",
+                        packet_buffer.capacity(),
+                        packet_buffer.len()
+                    );
+                    packet_buffer.shrink_to_fit();
+                }
+
+                // Don't clear the entire buffer - try_parse_packet_counting already removed the processed packet
+            }
+            DROPPED_PACKETS.fetch_add(rejected, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Process queued packets in order, sending their responses over USB
+pub async fn protocol_handler_loop<'a>(
+    sender: &mut Sender<'a, Driver<'a, peripherals::USB>>,
+    queue: &PacketQueue,
+    handler: &mut ProtocolHandler<SafeFlashManager>,
+    crc_mode: &Cell<CrcMode>,
+) -> ! {
+    defmt::info!("Protocol handler started with full protocol support");
+
+    loop {
+        let (arrival, packet) = queue.receive().await;
+        // Stops at once if an Abort came in after this packet was queued
+        start_command(arrival);
+
+        // Process the command; streamed reads send several responses
+        let mut sink = UsbSink {
+            sender: &mut *sender,
+        };
+        if handler.handle_packet(&packet, &mut sink).await.is_err() {
+            defmt::info!("Protocol: Host disconnected, response dropped");
+        }
+        // The host only sends its next packet after this response, so the
+        // receive loop sees an Info's new mode in time
+        crc_mode.set(handler.crc_mode());
+
+        if packet.command == Command::EnterBootloader {
+            // Give the host time to collect the response before USB drops
+            Timer::after(Duration::from_millis(100)).await;
+            bootloader::reboot_to_dfu();
+        }
+    }
+}
@@ -1,5 +1,6 @@
 // defmt support for error types
 use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU32, Ordering};
 use embassy_embedded_hal::shared_bus::asynch::spi::SpiDevice;
 use embassy_stm32::gpio::Output;
 use embassy_stm32::mode::Async;
@@ -9,15 +10,20 @@ use embassy_sync::mutex::Mutex;
 use embassy_time::{with_timeout, Duration, Timer};
 use embedded_hal::digital::OutputPin;
 use embedded_hal_async::spi::SpiDevice as SpiDeviceTrait;
+use flash_protocol::backend::{BackendError, FlashBackend};
+use flash_protocol::error_counters::ErrorCounters;
+use flash_protocol::SpiMode;
 
 // W25Q128 Commands
 const CMD_READ_JEDEC_ID: u8 = 0x9F;
 const CMD_READ_DATA: u8 = 0x03;
+const CMD_READ_SFDP: u8 = 0x5A;
 const CMD_WRITE_ENABLE: u8 = 0x06;
-#[allow(dead_code)]
 const CMD_WRITE_DISABLE: u8 = 0x04;
 const CMD_PAGE_PROGRAM: u8 = 0x02;
 const CMD_SECTOR_ERASE: u8 = 0x20;
+const CMD_BLOCK_ERASE_64K: u8 = 0xD8;
+const CMD_CHIP_ERASE: u8 = 0xC7;
 const CMD_READ_STATUS: u8 = 0x05;
 const CMD_READ_STATUS2: u8 = 0x35; // Read Status Register 2
 const CMD_READ_STATUS3: u8 = 0x15; // Read Status Register 3
@@ -25,25 +31,108 @@ const CMD_READ_STATUS3: u8 = 0x15; // Read Status Register 3
 const CMD_WRITE_STATUS: u8 = 0x01; // Write Status Register
 const CMD_RELEASE_POWER_DOWN: u8 = 0xAB; // Release from Deep Power-down
 
+/// SPI2 kernel clock: APB1 runs undivided from the 170MHz system clock set
+/// up in `main`
+const SPI_KERNEL_CLOCK_HZ: u32 = 170_000_000;
+
+/// SPI clock for the external flash at boot, kernel clock / 8 (21.25MHz; the
+/// W25Q128JV supports up to 133MHz)
+pub const SPI_FREQUENCY_HZ: u32 = SPI_KERNEL_CLOCK_HZ / 8;
+
+/// Highest clock accepted by `set_spi_frequency`: Read Data (0x03), used for
+/// every read, is only specified up to 50MHz
+const MAX_SPI_FREQUENCY_HZ: u32 = 50_000_000;
+
+/// Largest single read; the protocol layer splits longer reads, and this
+/// keeps each buffer small on the heap
+const MAX_SINGLE_READ: u32 = 256;
+
+/// Extra attempts for a page that fails to program before aborting the write
+const PAGE_PROGRAM_RETRIES: u32 = 3;
+
+/// Cancel flag for the flash operation in progress
+///
+/// Arrival number of the last Abort packet
+///
+/// The USB receive loop numbers packets as they arrive and records an Abort
+/// as soon as it is parsed (it runs alongside the handler, so it sees the
+/// packet while a long erase or write is still going). Every command that
+/// arrived before the Abort stops, whether it is running or still queued;
+/// commands sent after it are unaffected. Reads, writes and erases check
+/// between pages and sectors; an operation the chip has already started (a
+/// sector erase, a chip erase) still runs to completion.
+static ABORT_BEFORE: AtomicU32 = AtomicU32::new(0);
+
+/// Arrival number of the command being processed
+static RUNNING_COMMAND: AtomicU32 = AtomicU32::new(0);
+
+/// Stop every command that arrived before the Abort numbered `arrival` at
+/// its next page/sector boundary
+pub fn request_abort(arrival: u32) {
+    ABORT_BEFORE.store(arrival, Ordering::Release);
+}
+
+/// Record that the command numbered `arrival` is about to be processed
+pub fn start_command(arrival: u32) {
+    RUNNING_COMMAND.store(arrival, Ordering::Release);
+}
+
+fn check_abort() -> Result<(), SafeFlashError> {
+    if RUNNING_COMMAND.load(Ordering::Acquire) < ABORT_BEFORE.load(Ordering::Acquire) {
+        defmt::warn!("Flash operation aborted");
+        return Err(SafeFlashError::Aborted);
+    }
+    Ok(())
+}
+
+/// SPI clock produced by the fastest prescaler (/2 to /256) that doesn't
+/// exceed `hz`
+fn achieved_spi_frequency(hz: u32) -> u32 {
+    (1..=8)
+        .map(|shift| SPI_KERNEL_CLOCK_HZ >> shift)
+        .find(|&clock| clock <= hz)
+        .unwrap_or(SPI_KERNEL_CLOCK_HZ >> 8)
+}
+
 #[derive(Debug, defmt::Format)]
 pub enum SafeFlashError {
     NotInitialized,
     InitializationFailed,
     SpiError,
     Timeout,
+    /// Write enable latch won't set (WP# low or chip protected)
+    WriteProtected,
+    /// Cancelled by an Abort command
+    Aborted,
 }
 
-pub struct FlashInfo {
-    pub jedec_id: u32,
-    pub total_size: u32,
-    pub page_size: u32,
-    pub sector_size: u32,
+impl From<SafeFlashError> for BackendError {
+    fn from(error: SafeFlashError) -> Self {
+        match error {
+            SafeFlashError::NotInitialized | SafeFlashError::InitializationFailed => {
+                BackendError::NotInitialized
+            }
+            SafeFlashError::SpiError => BackendError::Bus,
+            SafeFlashError::Timeout => BackendError::Timeout,
+            SafeFlashError::WriteProtected => BackendError::WriteProtected,
+            SafeFlashError::Aborted => BackendError::Aborted,
+        }
+    }
 }
 
 pub struct SafeFlashManager {
     spi_bus: Option<&'static Mutex<CriticalSectionRawMutex, Spi<'static, Async>>>,
     initialized: bool,
     flash_available: bool,
+    /// JEDEC ID read during initialization
+    jedec_id: Option<u32>,
+    /// SPI mode last applied by `set_spi_mode` (the bus starts in mode 0)
+    spi_mode: SpiMode,
+    /// SPI clock last applied (the bus starts at `SPI_FREQUENCY_HZ`)
+    spi_frequency_hz: u32,
+    /// Reads use Dual Output Fast Read (passed the check at init)
+    #[cfg(feature = "dual_read")]
+    dual_read: bool,
 }
 
 impl SafeFlashManager {
@@ -52,6 +141,11 @@ impl SafeFlashManager {
             spi_bus: None,
             initialized: false,
             flash_available: false,
+            jedec_id: None,
+            spi_mode: SpiMode::Mode0,
+            spi_frequency_hz: SPI_FREQUENCY_HZ,
+            #[cfg(feature = "dual_read")]
+            dual_read: false,
         }
     }
 
@@ -104,9 +198,17 @@ impl SafeFlashManager {
         .await;
 
         match result {
-            Ok(Ok(_jedec_id)) => {
+            Ok(Ok(jedec_id)) => {
+                defmt::info!(
+                    "Detected flash JEDEC ID 0x{:06X} ({} bytes)",
+                    jedec_id,
+                    flash_protocol::jedec::capacity_bytes(jedec_id)
+                );
                 self.initialized = true;
                 self.flash_available = true;
+                self.jedec_id = Some(jedec_id);
+                #[cfg(feature = "dual_read")]
+                self.probe_dual_read().await;
                 Ok(())
             }
             _ => {
@@ -143,22 +245,57 @@ impl SafeFlashManager {
         Ok(jedec_id)
     }
 
-    pub async fn get_flash_info(&mut self) -> Result<FlashInfo, SafeFlashError> {
+    pub async fn read_jedec_id(&mut self) -> Result<u32, SafeFlashError> {
         if !self.is_available() {
             defmt::error!("Flash not available - hardware not initialized or not connected");
             return Err(SafeFlashError::NotInitialized);
         }
 
-        // For now, return the info we detected during initialization
-        // TODO: Implement proper re-reading of JEDEC ID without consuming CS pin
-        let flash_info = FlashInfo {
-            jedec_id: 0xEF4018,           // W25Q128 - this was detected during init
-            total_size: 16 * 1024 * 1024, // 16MB
-            page_size: 256,
-            sector_size: 4096,
+        let spi_bus = self.spi_bus.ok_or(SafeFlashError::NotInitialized)?;
+        let cs_pin = self.create_cs_pin();
+
+        with_timeout(Duration::from_millis(100), async {
+            let mut spi_device = SpiDevice::new(spi_bus, cs_pin);
+            self.read_jedec_id_internal(&mut spi_device).await
+        })
+        .await
+        .map_err(|_| SafeFlashError::Timeout)?
+    }
+
+    /// Reconfigure SPI clock polarity/phase, keeping the bus frequency
+    pub async fn set_spi_mode(&mut self, mode: SpiMode) -> Result<(), SafeFlashError> {
+        self.apply_spi_config(mode, self.spi_frequency_hz).await?;
+        self.spi_mode = mode;
+        defmt::info!("SPI reconfigured to mode {:?}", mode);
+        Ok(())
+    }
+
+    /// Change the SPI clock, keeping the mode; returns the clock the
+    /// prescaler actually produces, the fastest one not above `hz` (capped at
+    /// `MAX_SPI_FREQUENCY_HZ`), or the slowest one if `hz` is below that
+    pub async fn set_spi_frequency(&mut self, hz: u32) -> Result<u32, SafeFlashError> {
+        let hz = achieved_spi_frequency(hz.min(MAX_SPI_FREQUENCY_HZ));
+        // Asking for an exact kernel clock division makes the HAL pick the
+        // same prescaler
+        self.apply_spi_config(self.spi_mode, hz).await?;
+        self.spi_frequency_hz = hz;
+        defmt::info!("SPI clock set to {} Hz", hz);
+        Ok(hz)
+    }
+
+    async fn apply_spi_config(&mut self, mode: SpiMode, hz: u32) -> Result<(), SafeFlashError> {
+        let spi_bus = self.spi_bus.ok_or(SafeFlashError::NotInitialized)?;
+
+        let mut config = embassy_stm32::spi::Config::default();
+        config.frequency = embassy_stm32::time::Hertz(hz);
+        config.mode = match mode {
+            SpiMode::Mode0 => embassy_stm32::spi::MODE_0,
+            SpiMode::Mode3 => embassy_stm32::spi::MODE_3,
         };
 
-        Ok(flash_info)
+        let mut spi = spi_bus.lock().await;
+        spi.set_config(&config)
+            .map_err(|_| SafeFlashError::SpiError)
     }
 
     pub fn is_available(&self) -> bool {
@@ -186,6 +323,16 @@ impl SafeFlashManager {
             return Err(SafeFlashError::NotInitialized);
         }
 
+        #[cfg(feature = "dual_read")]
+        if self.dual_read {
+            return with_timeout(
+                Duration::from_millis(5000),
+                self.dual_read_data(address, size),
+            )
+            .await
+            .map_err(|_| SafeFlashError::Timeout)?;
+        }
+
         let spi_bus = self.spi_bus.ok_or(SafeFlashError::NotInitialized)?;
         let cs_pin = self.create_cs_pin();
 
@@ -198,6 +345,42 @@ impl SafeFlashManager {
         .map_err(|_| SafeFlashError::Timeout)?
     }
 
+    /// Read SFDP tables (24-bit address, then one dummy byte)
+    pub async fn read_sfdp(&mut self, address: u32, size: u32) -> Result<Vec<u8>, SafeFlashError> {
+        use embedded_hal_async::spi::SpiDevice as SpiDeviceTrait;
+
+        if !self.is_available() {
+            return Err(SafeFlashError::NotInitialized);
+        }
+
+        let spi_bus = self.spi_bus.ok_or(SafeFlashError::NotInitialized)?;
+        let cs_pin = self.create_cs_pin();
+
+        let cmd = [
+            CMD_READ_SFDP,
+            (address >> 16) as u8,
+            (address >> 8) as u8,
+            address as u8,
+            0x00,
+        ];
+        let mut data = alloc::vec![0u8; size.min(MAX_SINGLE_READ) as usize];
+
+        with_timeout(Duration::from_millis(100), async {
+            let mut spi_device = SpiDevice::new(spi_bus, cs_pin);
+            spi_device
+                .transaction(&mut [
+                    embedded_hal_async::spi::Operation::Write(&cmd),
+                    embedded_hal_async::spi::Operation::Read(&mut data),
+                ])
+                .await
+                .map_err(|_| SafeFlashError::SpiError)
+        })
+        .await
+        .map_err(|_| SafeFlashError::Timeout)??;
+
+        Ok(data)
+    }
+
     pub async fn write_data(&mut self, address: u32, data: &[u8]) -> Result<(), SafeFlashError> {
         if !self.is_available() {
             return Err(SafeFlashError::NotInitialized);
@@ -227,7 +410,59 @@ impl SafeFlashManager {
         // Erase sector on Flash chip
         with_timeout(Duration::from_millis(5000), async {
             let mut spi_device = SpiDevice::new(spi_bus, cs_pin);
-            self.erase_sector_internal(&mut spi_device, address).await
+            self.erase_internal(
+                &mut spi_device,
+                CMD_SECTOR_ERASE,
+                Some(address),
+                Duration::from_millis(10),
+            )
+            .await
+        })
+        .await
+        .map_err(|_| SafeFlashError::Timeout)?
+    }
+
+    pub async fn erase_block(&mut self, address: u32) -> Result<(), SafeFlashError> {
+        if !self.is_available() {
+            return Err(SafeFlashError::NotInitialized);
+        }
+
+        let spi_bus = self.spi_bus.ok_or(SafeFlashError::NotInitialized)?;
+        let cs_pin = self.create_cs_pin();
+
+        // 64KB block erase takes up to 2s on W25Q128
+        with_timeout(Duration::from_millis(5000), async {
+            let mut spi_device = SpiDevice::new(spi_bus, cs_pin);
+            self.erase_internal(
+                &mut spi_device,
+                CMD_BLOCK_ERASE_64K,
+                Some(address),
+                Duration::from_millis(10),
+            )
+            .await
+        })
+        .await
+        .map_err(|_| SafeFlashError::Timeout)?
+    }
+
+    pub async fn chip_erase(&mut self) -> Result<(), SafeFlashError> {
+        if !self.is_available() {
+            return Err(SafeFlashError::NotInitialized);
+        }
+
+        let spi_bus = self.spi_bus.ok_or(SafeFlashError::NotInitialized)?;
+        let cs_pin = self.create_cs_pin();
+
+        // Chip erase takes up to 200s on W25Q128
+        with_timeout(Duration::from_secs(250), async {
+            let mut spi_device = SpiDevice::new(spi_bus, cs_pin);
+            self.erase_internal(
+                &mut spi_device,
+                CMD_CHIP_ERASE,
+                None,
+                Duration::from_millis(100),
+            )
+            .await
         })
         .await
         .map_err(|_| SafeFlashError::Timeout)?
@@ -244,14 +479,15 @@ impl SafeFlashManager {
     {
         use embedded_hal_async::spi::SpiDevice as SpiDeviceTrait;
 
+        // The handler reads large ranges as a series of these calls
+        check_abort()?;
+
         defmt::info!(
             "Flash read internal: address=0x{:08X}, size={}",
             address,
             size
         );
 
-        // Limit single read to avoid heap issues - let the protocol layer handle chunking
-        const MAX_SINGLE_READ: u32 = 256; // Maximum single read size
         let actual_size = if size > MAX_SINGLE_READ {
             MAX_SINGLE_READ
         } else {
@@ -306,16 +542,64 @@ impl SafeFlashManager {
         Ok(data)
     }
 
-    async fn erase_sector_internal<CS>(
+    /// Read up to `MAX_SINGLE_READ` bytes with Dual Output Fast Read
+    #[cfg(feature = "dual_read")]
+    async fn dual_read_data(&self, address: u32, size: u32) -> Result<Vec<u8>, SafeFlashError> {
+        check_abort()?;
+
+        let spi_bus = self.spi_bus.ok_or(SafeFlashError::NotInitialized)?;
+        let mut cs_pin = self.create_cs_pin();
+        let mut data = alloc::vec![0u8; size.min(MAX_SINGLE_READ) as usize];
+        crate::dual_read::read(spi_bus, &mut cs_pin, self.spi_mode, address, &mut data)
+            .await
+            .map_err(|_| SafeFlashError::SpiError)?;
+        Ok(data)
+    }
+
+    /// Switch reads to dual output if it returns the same data as a single
+    /// read of the start of flash
+    ///
+    /// A blank (all 0xFF) start proves nothing about the IO0 wiring, so dual
+    /// reads stay off until the next reset in that case.
+    #[cfg(feature = "dual_read")]
+    async fn probe_dual_read(&mut self) {
+        let single = match self.read_data(0, MAX_SINGLE_READ).await {
+            Ok(data) => data,
+            Err(e) => {
+                defmt::warn!("Dual read check skipped: {:?}", e);
+                return;
+            }
+        };
+        if single.iter().all(|&b| b == 0xFF) {
+            defmt::info!("Dual read check skipped: flash start is blank, using single reads");
+            return;
+        }
+        match self.dual_read_data(0, MAX_SINGLE_READ).await {
+            Ok(dual) if dual == single => {
+                defmt::info!("Dual output fast read (0x3B) enabled");
+                self.dual_read = true;
+            }
+            _ => defmt::warn!("Dual read mismatch - check IO0 wiring, using single reads"),
+        }
+    }
+
+    /// Issue an erase opcode (with optional 24-bit address) and wait for completion
+    async fn erase_internal<CS>(
         &self,
         spi_device: &mut SpiDevice<'_, CriticalSectionRawMutex, Spi<'_, Async>, CS>,
-        address: u32,
+        opcode: u8,
+        address: Option<u32>,
+        poll_interval: Duration,
     ) -> Result<(), SafeFlashError>
     where
         CS: OutputPin,
     {
         use embedded_hal_async::spi::SpiDevice as SpiDeviceTrait;
 
+        // Multi-sector erases are a series of these calls; once the opcode is
+        // sent the chip can't be stopped, so this is the only place to bail
+        check_abort()?;
+
         // Write enable
         let write_enable_cmd = [CMD_WRITE_ENABLE];
         spi_device
@@ -323,16 +607,22 @@ impl SafeFlashManager {
             .await
             .map_err(|_| SafeFlashError::SpiError)?;
 
-        // Sector erase command with 24-bit address
-        let erase_cmd = [
-            CMD_SECTOR_ERASE,
-            (address >> 16) as u8,
-            (address >> 8) as u8,
-            address as u8,
-        ];
+        // Erase command, followed by a 24-bit address for sector/block erase
+        let erase_cmd = match address {
+            Some(address) => [
+                opcode,
+                (address >> 16) as u8,
+                (address >> 8) as u8,
+                address as u8,
+            ],
+            None => [opcode, 0, 0, 0],
+        };
+        let cmd_len = if address.is_some() { 4 } else { 1 };
 
         spi_device
-            .transaction(&mut [embedded_hal_async::spi::Operation::Write(&erase_cmd)])
+            .transaction(&mut [embedded_hal_async::spi::Operation::Write(
+                &erase_cmd[..cmd_len],
+            )])
             .await
             .map_err(|_| SafeFlashError::SpiError)?;
 
@@ -354,7 +644,7 @@ impl SafeFlashManager {
                 break;
             }
 
-            Timer::after(Duration::from_millis(10)).await;
+            Timer::after(poll_interval).await;
         }
 
         Ok(())
@@ -369,13 +659,13 @@ impl SafeFlashManager {
     where
         CS: OutputPin,
     {
-        use embedded_hal_async::spi::SpiDevice as SpiDeviceTrait;
-
         let page_size = 256; // W25Q128 page size
         let mut current_address = address;
         let mut remaining_data = data;
 
         while !remaining_data.is_empty() {
+            check_abort()?;
+
             // Calculate how much we can write in this page
             let page_offset = current_address % page_size;
             let bytes_to_write =
@@ -383,131 +673,173 @@ impl SafeFlashManager {
 
             let chunk = &remaining_data[..bytes_to_write];
 
-            // Write enable
-            defmt::debug!("Sending write enable command");
-            let write_enable_cmd = [CMD_WRITE_ENABLE];
-            spi_device
-                .transaction(&mut [embedded_hal_async::spi::Operation::Write(&write_enable_cmd)])
-                .await
-                .map_err(|_| SafeFlashError::SpiError)?;
-            defmt::debug!("Write enable command sent successfully");
-
-            // Add a small delay to allow Flash to process the command
-            Timer::after(Duration::from_micros(10)).await;
-
-            // Verify write enable latch (WEL) is set - check immediately after command
-            defmt::debug!("Checking WEL bit immediately after Write Enable command...");
-            let status_cmd = [CMD_READ_STATUS];
-            let mut status = [0u8; 1];
-            spi_device
-                .transaction(&mut [
-                    embedded_hal_async::spi::Operation::Write(&status_cmd),
-                    embedded_hal_async::spi::Operation::Read(&mut status),
-                ])
-                .await
-                .map_err(|_| SafeFlashError::SpiError)?;
-
-            defmt::info!("Status after Write Enable: 0x{:02X}", status[0]);
-            if (status[0] & 0x02) == 0 {
-                defmt::error!(
-                    "Write Enable Latch (WEL) not set! Status: 0x{:02X}",
-                    status[0]
-                );
-                defmt::error!(
-                    "This indicates the Flash chip is not responding to Write Enable commands"
-                );
-
-                // Test if SPI communication is still working by reading JEDEC ID
-                defmt::info!("Testing SPI communication after failed Write Enable...");
-                match self.read_jedec_id_internal(spi_device).await {
-                    Ok(jedec_id) => {
-                        defmt::info!(
-                            "SPI read communication still works: JEDEC ID = 0x{:06X}",
-                            jedec_id
+            let mut attempt = 1;
+            loop {
+                match self
+                    .program_page_internal(spi_device, current_address, chunk)
+                    .await
+                {
+                    Ok(()) => break,
+                    // WriteProtected (WEL didn't latch) is retried too: a
+                    // glitched write enable looks the same as real write
+                    // protection until it keeps happening
+                    Err(e) if attempt <= PAGE_PROGRAM_RETRIES => {
+                        defmt::warn!(
+                            "Page program at 0x{:08X} failed ({:?}), retry {}/{}",
+                            current_address,
+                            e,
+                            attempt,
+                            PAGE_PROGRAM_RETRIES
                         );
-                        defmt::error!("This confirms SPI read works but Write Enable fails");
-                        defmt::error!("Possible causes: 1) Hardware write protection 2) Flash chip defect 3) MOSI line issue");
+                        attempt += 1;
                     }
-                    Err(_) => {
-                        defmt::error!(
-                            "SPI communication completely failed after Write Enable attempt"
-                        );
+                    Err(e) => {
                         defmt::error!(
-                            "This suggests the Write Enable command corrupted SPI communication"
+                            "Page program at 0x{:08X} failed after {} retries: {:?}",
+                            current_address,
+                            PAGE_PROGRAM_RETRIES,
+                            e
                         );
+                        return Err(e);
                     }
                 }
-
-                return Err(SafeFlashError::SpiError);
             }
-            defmt::info!(
-                "✅ Write Enable Latch (WEL) confirmed set, status: 0x{:02X}",
-                status[0]
-            );
 
-            // Page program command with 24-bit address
-            defmt::debug!(
-                "Writing {} bytes to address 0x{:08X}",
-                chunk.len(),
-                current_address
+            // Move to next chunk
+            current_address += bytes_to_write as u32;
+            remaining_data = &remaining_data[bytes_to_write..];
+        }
+
+        Ok(())
+    }
+
+    /// Write-enable, program one page (or part of one) and wait for completion
+    async fn program_page_internal<CS>(
+        &self,
+        spi_device: &mut SpiDevice<'_, CriticalSectionRawMutex, Spi<'_, Async>, CS>,
+        address: u32,
+        chunk: &[u8],
+    ) -> Result<(), SafeFlashError>
+    where
+        CS: OutputPin,
+    {
+        use embedded_hal_async::spi::SpiDevice as SpiDeviceTrait;
+
+        // Write enable
+        defmt::debug!("Sending write enable command");
+        let write_enable_cmd = [CMD_WRITE_ENABLE];
+        spi_device
+            .transaction(&mut [embedded_hal_async::spi::Operation::Write(&write_enable_cmd)])
+            .await
+            .map_err(|_| SafeFlashError::SpiError)?;
+        defmt::debug!("Write enable command sent successfully");
+
+        // Add a small delay to allow Flash to process the command
+        Timer::after(Duration::from_micros(10)).await;
+
+        // Verify write enable latch (WEL) is set - check immediately after command
+        defmt::debug!("Checking WEL bit immediately after Write Enable command...");
+        let status_cmd = [CMD_READ_STATUS];
+        let mut status = [0u8; 1];
+        spi_device
+            .transaction(&mut [
+                embedded_hal_async::spi::Operation::Write(&status_cmd),
+                embedded_hal_async::spi::Operation::Read(&mut status),
+            ])
+            .await
+            .map_err(|_| SafeFlashError::SpiError)?;
+
+        defmt::info!("Status after Write Enable: 0x{:02X}", status[0]);
+        if (status[0] & 0x02) == 0 {
+            defmt::error!(
+                "Write Enable Latch (WEL) not set! Status: 0x{:02X}",
+                status[0]
             );
-            let program_cmd = [
-                CMD_PAGE_PROGRAM,
-                (current_address >> 16) as u8,
-                (current_address >> 8) as u8,
-                current_address as u8,
-            ];
-            defmt::debug!(
-                "Program command: {:02X} {:02X} {:02X} {:02X}",
-                program_cmd[0],
-                program_cmd[1],
-                program_cmd[2],
-                program_cmd[3]
+            defmt::error!(
+                "This indicates the Flash chip is not responding to Write Enable commands"
             );
 
+            // Test if SPI communication is still working by reading JEDEC ID
+            defmt::info!("Testing SPI communication after failed Write Enable...");
+            match self.read_jedec_id_internal(spi_device).await {
+                Ok(jedec_id) => {
+                    defmt::info!(
+                        "SPI read communication still works: JEDEC ID = 0x{:06X}",
+                        jedec_id
+                    );
+                    defmt::error!("This confirms SPI read works but Write Enable fails");
+                    defmt::error!("Possible causes: 1) Hardware write protection 2) Flash chip defect 3) MOSI line issue");
+                    return Err(SafeFlashError::WriteProtected);
+                }
+                Err(_) => {
+                    defmt::error!("SPI communication completely failed after Write Enable attempt");
+                    defmt::error!(
+                        "This suggests the Write Enable command corrupted SPI communication"
+                    );
+                }
+            }
+
+            return Err(SafeFlashError::SpiError);
+        }
+        defmt::info!(
+            "✅ Write Enable Latch (WEL) confirmed set, status: 0x{:02X}",
+            status[0]
+        );
+
+        // Page program command with 24-bit address
+        defmt::debug!("Writing {} bytes to address 0x{:08X}", chunk.len(), address);
+        let program_cmd = [
+            CMD_PAGE_PROGRAM,
+            (address >> 16) as u8,
+            (address >> 8) as u8,
+            address as u8,
+        ];
+        defmt::debug!(
+            "Program command: {:02X} {:02X} {:02X} {:02X}",
+            program_cmd[0],
+            program_cmd[1],
+            program_cmd[2],
+            program_cmd[3]
+        );
+
+        spi_device
+            .transaction(&mut [
+                embedded_hal_async::spi::Operation::Write(&program_cmd),
+                embedded_hal_async::spi::Operation::Write(chunk),
+            ])
+            .await
+            .map_err(|_| SafeFlashError::SpiError)?;
+        defmt::debug!("Page program command sent successfully");
+
+        // Add a small delay to allow Flash to start the write operation
+        Timer::after(Duration::from_micros(100)).await;
+        defmt::debug!("Initial delay completed, starting status polling...");
+
+        // Wait for write to complete (poll status register)
+        defmt::debug!("Waiting for write to complete...");
+        let mut poll_count = 0;
+        loop {
+            let status_cmd = [CMD_READ_STATUS];
+            let mut status = [0u8; 1];
+
             spi_device
                 .transaction(&mut [
-                    embedded_hal_async::spi::Operation::Write(&program_cmd),
-                    embedded_hal_async::spi::Operation::Write(chunk),
+                    embedded_hal_async::spi::Operation::Write(&status_cmd),
+                    embedded_hal_async::spi::Operation::Read(&mut status),
                 ])
                 .await
                 .map_err(|_| SafeFlashError::SpiError)?;
-            defmt::debug!("Page program command sent successfully");
 
-            // Add a small delay to allow Flash to start the write operation
-            Timer::after(Duration::from_micros(100)).await;
-            defmt::debug!("Initial delay completed, starting status polling...");
+            poll_count += 1;
+            defmt::debug!("Status poll #{}: 0x{:02X}", poll_count, status[0]);
 
-            // Wait for write to complete (poll status register)
-            defmt::debug!("Waiting for write to complete...");
-            let mut poll_count = 0;
-            loop {
-                let status_cmd = [CMD_READ_STATUS];
-                let mut status = [0u8; 1];
-
-                spi_device
-                    .transaction(&mut [
-                        embedded_hal_async::spi::Operation::Write(&status_cmd),
-                        embedded_hal_async::spi::Operation::Read(&mut status),
-                    ])
-                    .await
-                    .map_err(|_| SafeFlashError::SpiError)?;
-
-                poll_count += 1;
-                defmt::debug!("Status poll #{}: 0x{:02X}", poll_count, status[0]);
-
-                // Check if write in progress bit (bit 0) is clear
-                if (status[0] & 0x01) == 0 {
-                    defmt::debug!("Write completed after {} polls", poll_count);
-                    break;
-                }
-
-                Timer::after(Duration::from_millis(1)).await;
+            // Check if write in progress bit (bit 0) is clear
+            if (status[0] & 0x01) == 0 {
+                defmt::debug!("Write completed after {} polls", poll_count);
+                break;
             }
 
-            // Move to next chunk
-            current_address += bytes_to_write as u32;
-            remaining_data = &remaining_data[bytes_to_write..];
+            Timer::after(Duration::from_millis(1)).await;
         }
 
         Ok(())
@@ -536,6 +868,40 @@ impl SafeFlashManager {
         Ok(status[0])
     }
 
+    /// Check that the chip accepts writes: send Write Enable, read back WEL,
+    /// then Write Disable again. Returns `Ok(false)` if WEL won't set, which
+    /// usually means WP# is held low.
+    pub async fn check_write_enable(&mut self) -> Result<bool, SafeFlashError> {
+        if !self.is_available() {
+            return Err(SafeFlashError::NotInitialized);
+        }
+
+        let spi_bus = self.spi_bus.ok_or(SafeFlashError::NotInitialized)?;
+        let cs_pin = self.create_cs_pin();
+
+        with_timeout(Duration::from_millis(100), async {
+            let mut spi_device = SpiDevice::new(spi_bus, cs_pin);
+
+            spi_device
+                .transaction(&mut [embedded_hal_async::spi::Operation::Write(&[
+                    CMD_WRITE_ENABLE,
+                ])])
+                .await
+                .map_err(|_| SafeFlashError::SpiError)?;
+            let status = self.read_status_internal(&mut spi_device).await?;
+            spi_device
+                .transaction(&mut [embedded_hal_async::spi::Operation::Write(&[
+                    CMD_WRITE_DISABLE,
+                ])])
+                .await
+                .map_err(|_| SafeFlashError::SpiError)?;
+
+            Ok(status & 0x02 != 0)
+        })
+        .await
+        .map_err(|_| SafeFlashError::Timeout)?
+    }
+
     /// Read and display all status registers for debugging
     pub async fn diagnose_flash_protection(&mut self) -> Result<(), SafeFlashError> {
         if !self.is_available() {
@@ -670,3 +1036,79 @@ impl SafeFlashManager {
         Ok(())
     }
 }
+
+impl FlashBackend for SafeFlashManager {
+    async fn read(&mut self, address: u32, length: u32) -> Result<Vec<u8>, BackendError> {
+        Ok(self.read_data(address, length).await?)
+    }
+
+    async fn write(&mut self, address: u32, data: &[u8]) -> Result<(), BackendError> {
+        Ok(self.write_data(address, data).await?)
+    }
+
+    async fn erase_sector(&mut self, address: u32) -> Result<(), BackendError> {
+        Ok(SafeFlashManager::erase_sector(self, address).await?)
+    }
+
+    async fn erase_block(&mut self, address: u32) -> Result<(), BackendError> {
+        Ok(SafeFlashManager::erase_block(self, address).await?)
+    }
+
+    async fn chip_erase(&mut self) -> Result<(), BackendError> {
+        Ok(SafeFlashManager::chip_erase(self).await?)
+    }
+
+    async fn jedec_id(&mut self) -> Result<u32, BackendError> {
+        // Reuse the ID probed at init; the chip cannot change while powered
+        match self.jedec_id {
+            Some(jedec_id) => Ok(jedec_id),
+            None => Ok(self.read_jedec_id().await?),
+        }
+    }
+
+    async fn read_sfdp(&mut self, address: u32, length: u32) -> Result<Vec<u8>, BackendError> {
+        Ok(SafeFlashManager::read_sfdp(self, address, length).await?)
+    }
+
+    async fn write_enable_check(&mut self) -> Result<bool, BackendError> {
+        Ok(self.check_write_enable().await?)
+    }
+
+    async fn set_spi_mode(&mut self, mode: SpiMode) -> Result<(), BackendError> {
+        Ok(SafeFlashManager::set_spi_mode(self, mode).await?)
+    }
+
+    fn spi_mode(&self) -> SpiMode {
+        self.spi_mode
+    }
+
+    fn spi_frequency_hz(&self) -> u32 {
+        self.spi_frequency_hz
+    }
+
+    async fn set_spi_frequency(&mut self, hz: u32) -> Result<u32, BackendError> {
+        Ok(SafeFlashManager::set_spi_frequency(self, hz).await?)
+    }
+
+    async fn pause(&mut self, ms: u32) {
+        Timer::after(Duration::from_millis(ms as u64)).await;
+    }
+
+    async fn error_counters(&mut self) -> Result<ErrorCounters, BackendError> {
+        // Kept by the USB transport, which sees the errors
+        Ok(crate::protocol_handler::error_counters())
+    }
+
+    async fn clear_error_counters(&mut self) -> Result<(), BackendError> {
+        crate::protocol_handler::clear_error_counters();
+        Ok(())
+    }
+
+    async fn status(&mut self) -> Result<u8, BackendError> {
+        // Log the full protection state alongside every status request
+        if let Err(e) = self.diagnose_flash_protection().await {
+            defmt::error!("Flash diagnosis error: {:?}", e);
+        }
+        Ok(self.read_status().await?)
+    }
+}
@@ -0,0 +1,95 @@
+//! Walking-ones / walking-zeros data-line check for `bitcheck`
+//!
+//! A scratch sector is programmed with bytes that each set (then clear) a
+//! single bit, so a data line stuck high or low, or shorted to a neighbour,
+//! shows up as a specific bit position that reads back wrong. A constant
+//! pattern like 0x55 can't tell those faults apart.
+
+use flash_protocol::FLASH_SECTOR_SIZE;
+
+/// Bytes written and read back (one erase sector)
+pub const BITCHECK_SIZE: usize = FLASH_SECTOR_SIZE;
+
+/// Walking ones (0x01, 0x02, ... 0x80) over the first half of the sector,
+/// walking zeros (0xFE, 0xFD, ... 0x7F) over the second
+pub fn pattern() -> Vec<u8> {
+    let half = BITCHECK_SIZE / 2;
+    (0..BITCHECK_SIZE)
+        .map(|i| {
+            let one = 1u8 << (i % 8);
+            if i < half {
+                one
+            } else {
+                !one
+            }
+        })
+        .collect()
+}
+
+/// Per-bit mismatch counts between the written pattern and the read-back data
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BitReport {
+    /// Bytes where the bit should have read 1 but read 0, indexed by bit (D0-D7)
+    pub stuck_low: [u32; 8],
+    /// Bytes where the bit should have read 0 but read 1
+    pub stuck_high: [u32; 8],
+}
+
+impl BitReport {
+    pub fn analyze(expected: &[u8], actual: &[u8]) -> Self {
+        let mut report = Self::default();
+        for (&want, &got) in expected.iter().zip(actual) {
+            for bit in 0..8 {
+                let mask = 1u8 << bit;
+                match (want & mask != 0, got & mask != 0) {
+                    (true, false) => report.stuck_low[bit] += 1,
+                    (false, true) => report.stuck_high[bit] += 1,
+                    _ => {}
+                }
+            }
+        }
+        report
+    }
+
+    /// Bit positions that failed to toggle at least once
+    pub fn failing_bits(&self) -> Vec<usize> {
+        (0..8)
+            .filter(|&bit| self.stuck_low[bit] != 0 || self.stuck_high[bit] != 0)
+            .collect()
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.failing_bits().is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pattern_toggles_every_bit_both_ways() {
+        let pattern = pattern();
+        assert_eq!(pattern.len(), BITCHECK_SIZE);
+        assert_eq!(
+            &pattern[..8],
+            &[0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80]
+        );
+        assert_eq!(&pattern[BITCHECK_SIZE / 2..][..2], &[0xFE, 0xFD]);
+        assert!(BitReport::analyze(&pattern, &pattern).is_ok());
+    }
+
+    #[test]
+    fn test_analyze_pinpoints_stuck_bits() {
+        let expected = pattern();
+        // D3 stuck low, D6 stuck high
+        let actual: Vec<u8> = expected.iter().map(|b| (b & !0x08) | 0x40).collect();
+
+        let report = BitReport::analyze(&expected, &actual);
+        assert_eq!(report.failing_bits(), vec![3, 6]);
+        // Each bit is 1 in exactly half of the pattern
+        assert_eq!(report.stuck_low[3], BITCHECK_SIZE as u32 / 2);
+        assert_eq!(report.stuck_high[6], BITCHECK_SIZE as u32 / 2);
+        assert_eq!(report.stuck_high[3], 0);
+    }
+}
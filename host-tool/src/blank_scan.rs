@@ -0,0 +1,69 @@
+//! Used-size detection for `read --until-blank`
+//!
+//! Data is fed in sector-sized pieces. Once `threshold` consecutive sectors
+//! read back as all 0xFF the rest of the chip is assumed erased, and the used
+//! size is everything up to the last sector that held data.
+
+/// Tracks the trailing run of blank sectors in a dump
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlankScan {
+    threshold: u32,
+    blank_run: u32,
+    scanned: u64,
+    used: u64,
+}
+
+impl BlankScan {
+    /// Stop after `threshold` (at least 1) consecutive blank sectors
+    pub fn new(threshold: u32) -> Self {
+        Self {
+            threshold: threshold.max(1),
+            blank_run: 0,
+            scanned: 0,
+            used: 0,
+        }
+    }
+
+    /// Account for the next sector, returning `true` once the blank run is
+    /// long enough to stop reading
+    pub fn push_sector(&mut self, sector: &[u8]) -> bool {
+        self.scanned += sector.len() as u64;
+        if sector.iter().all(|&b| b == 0xFF) {
+            self.blank_run += 1;
+        } else {
+            self.blank_run = 0;
+            self.used = self.scanned;
+        }
+        self.blank_run >= self.threshold
+    }
+
+    /// Bytes up to and including the last sector that wasn't blank
+    pub fn used_size(&self) -> u64 {
+        self.used
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blank_scan_stops_after_run() {
+        let data = [0x00u8; 4];
+        let blank = [0xFFu8; 4];
+
+        let mut scan = BlankScan::new(2);
+        assert!(!scan.push_sector(&data));
+        // A single blank sector between data doesn't end the scan
+        assert!(!scan.push_sector(&blank));
+        assert!(!scan.push_sector(&data));
+        assert_eq!(scan.used_size(), 12);
+        assert!(!scan.push_sector(&blank));
+        assert!(scan.push_sector(&blank));
+        assert_eq!(scan.used_size(), 12);
+
+        let mut scan = BlankScan::new(1);
+        assert!(scan.push_sector(&blank));
+        assert_eq!(scan.used_size(), 0);
+    }
+}
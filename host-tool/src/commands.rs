@@ -1,13 +1,64 @@
 use anyhow::{Context, Result};
 use crc32fast::Hasher;
+use flash_protocol::capabilities::Capabilities;
+use flash_protocol::config::RuntimeConfig;
+use flash_protocol::error_counters::ErrorCounters;
+use flash_protocol::geometry::{EraseUnit, FlashGeometry};
+use flash_protocol::segments::Segment;
 use flash_protocol::*;
 use indicatif::ProgressBar;
 use sha2::{Digest, Sha256};
+use std::ops::Range;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio_serial::SerialStream;
+
+use crate::serial::{check_status, SerialConnection};
+
+/// StreamWrite packets sent between batch checksums
+const STREAM_BATCH_PACKETS: usize = 4;
+
+/// Times a stream write batch is resent after failing its checksum
+const BATCH_RESENDS: u32 = 2;
+
+/// Default block size for progressive CRC verification
+///
+/// Smaller blocks pinpoint (and let retries rewrite) less data per failure
+/// but cost one round-trip each; larger blocks verify faster.
+pub const DEFAULT_VERIFY_BLOCK_SIZE: usize = 64 * 1024;
+
+/// Read-back chunk size for firmware that can't report its payload limit
+const DEFAULT_READ_CHUNK_SIZE: usize = 256;
+
+/// Largest read-back chunk (the biggest response the host accepts)
+pub const MAX_READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Response timeout for a chip erase (up to 200s on the W25Q128)
+const CHIP_ERASE_TIMEOUT: Duration = Duration::from_secs(250);
+
+pub struct FlashCommands<'a, P = SerialStream> {
+    connection: &'a mut SerialConnection<P>,
+    verify_block_size: usize,
+    /// Bytes per Read during read-back verification, `None` until set or
+    /// negotiated with GetConfig
+    read_chunk_size: Option<usize>,
+    /// Chip geometry from Capabilities or the last Info (a W25Q128 until then)
+    geometry: FlashGeometry,
+    /// What the firmware reported on connect, `None` if it predates
+    /// Capabilities
+    capabilities: Option<Capabilities>,
+    /// Pace erases sector by sector (see [`set_power_safe`](Self::set_power_safe))
+    power_safe: bool,
+}
 
-use crate::serial::SerialConnection;
-
-pub struct FlashCommands<'a> {
-    connection: &'a mut SerialConnection,
+/// A progressive CRC verification block whose flash contents didn't match
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockFailure {
+    /// Zero-based block number within the verified data
+    pub index: usize,
+    pub address: u32,
+    pub length: u32,
+    pub expected_crc: u32,
 }
 
 #[derive(Debug)]
@@ -16,16 +67,150 @@ pub struct FlashInfo {
     pub total_size: u32,
     pub page_size: u32,
     pub sector_size: u32,
+    /// The firmware's write-enable check failed (WP# likely held low)
+    pub write_protected: bool,
 }
 
 #[allow(dead_code)]
-impl<'a> FlashCommands<'a> {
-    pub fn new(connection: &'a mut SerialConnection) -> Self {
-        Self { connection }
+impl<'a, P: AsyncRead + AsyncWrite + Unpin> FlashCommands<'a, P> {
+    pub fn new(connection: &'a mut SerialConnection<P>) -> Self {
+        Self {
+            connection,
+            verify_block_size: DEFAULT_VERIFY_BLOCK_SIZE,
+            read_chunk_size: None,
+            geometry: FlashGeometry::W25Q128,
+            capabilities: None,
+            power_safe: false,
+        }
+    }
+
+    /// Chip geometry reported by Capabilities or the last Info; erase
+    /// planning rounds to its sectors
+    pub fn geometry(&self) -> FlashGeometry {
+        self.geometry
+    }
+
+    /// What the firmware reported to [`detect_capabilities`](Self::detect_capabilities)
+    pub fn capabilities(&self) -> Option<Capabilities> {
+        self.capabilities
+    }
+
+    /// Ask the firmware what it supports and tune later commands to it
+    ///
+    /// Called once on connect. The answer picks between ReadStream and Read,
+    /// CRC and read-back verification, and chip and sector erases, and sets
+    /// the read chunk size and geometry. Firmware that predates
+    /// Capabilities doesn't answer, which keeps the previous behavior.
+    pub async fn detect_capabilities(&mut self) -> Result<Option<Capabilities>> {
+        let packet = Packet::new(Command::Capabilities, 0, Vec::new());
+        let Some(response) = self.connection.probe(packet).await? else {
+            log::debug!("Firmware predates Capabilities, using defaults");
+            return Ok(None);
+        };
+        let decoded = check_status(response).and_then(|response| {
+            Capabilities::from_bytes(&response.data).map_err(|e| anyhow::anyhow!(e))
+        });
+        let capabilities = match decoded {
+            Ok(capabilities) => capabilities,
+            Err(e) => {
+                log::warn!("Ignoring firmware capabilities: {:#}", e);
+                return Ok(None);
+            }
+        };
+
+        if let Some(geometry) = capabilities.geometry {
+            self.geometry = geometry;
+        }
+        self.capabilities = Some(capabilities);
+        Ok(Some(capabilities))
+    }
+
+    /// Whether the firmware implements `command`; without Capabilities every
+    /// command is assumed to work, as before
+    fn supports(&self, command: Command) -> bool {
+        match self.capabilities {
+            Some(capabilities) => capabilities.supports(command),
+            None => true,
+        }
+    }
+
+    /// Block size used by progressive CRC verification
+    pub fn verify_block_size(&self) -> usize {
+        self.verify_block_size
+    }
+
+    /// Change the progressive CRC verification block size (validated by the caller)
+    pub fn set_verify_block_size(&mut self, size: usize) {
+        self.verify_block_size = size;
+    }
+
+    /// Rest between sector erases and never chip erase, lowering the current
+    /// drawn by long erases
+    ///
+    /// Firmware that reports `power_safe_erase` pauses between the sectors
+    /// of each Erase; with older firmware the host erases one sector per
+    /// packet and pauses itself.
+    pub fn set_power_safe(&mut self, power_safe: bool) {
+        self.power_safe = power_safe;
+    }
+
+    /// Fix the read-back verification chunk size instead of asking the
+    /// firmware (validated by the caller)
+    pub fn set_read_chunk_size(&mut self, size: usize) {
+        self.read_chunk_size = Some(size);
+    }
+
+    /// Chunk size for read-back verification
+    ///
+    /// Unless set explicitly, this is the largest read the firmware reports
+    /// through Capabilities, or else the payload limit from GetConfig, so
+    /// verification speeds up when the firmware accepts larger reads.
+    /// Firmware with neither gets [`DEFAULT_READ_CHUNK_SIZE`]. The result is
+    /// cached.
+    pub async fn read_chunk_size(&mut self) -> usize {
+        if let Some(size) = self.read_chunk_size {
+            return size;
+        }
+        if let Some(capabilities) = self.capabilities {
+            let size = (capabilities.max_read_size as usize).clamp(1, MAX_READ_CHUNK_SIZE);
+            self.read_chunk_size = Some(size);
+            return size;
+        }
+
+        let size = match self.get_config().await {
+            Ok(config) => (config.max_payload_size as usize).clamp(1, MAX_READ_CHUNK_SIZE),
+            Err(e) => {
+                log::debug!(
+                    "Using {}-byte reads for verification: {:#}",
+                    DEFAULT_READ_CHUNK_SIZE,
+                    e
+                );
+                DEFAULT_READ_CHUNK_SIZE
+            }
+        };
+        self.read_chunk_size = Some(size);
+        size
+    }
+
+    /// Send a recorded packet as-is and collect the statuses of the next
+    /// `responses` replies, error statuses included (used by `replay`)
+    pub async fn replay_packet(
+        &mut self,
+        packet: &Packet,
+        responses: usize,
+    ) -> Result<Vec<Status>> {
+        self.connection.send_packet(packet).await?;
+        let mut statuses = Vec::with_capacity(responses);
+        for _ in 0..responses {
+            statuses.push(self.connection.receive_response().await?.status);
+        }
+        Ok(statuses)
     }
 
     pub async fn get_info(&mut self) -> Result<FlashInfo> {
-        let packet = Packet::new(Command::Info, 0, Vec::new());
+        // Re-request the negotiated trailer; an Info without one resets it to CRC-32
+        let crc_mode = self.connection.crc_mode();
+        let packet = Packet::new(Command::Info, 0, vec![crc_mode as u8]);
         let response = self.connection.send_command(packet).await?;
 
         if response.data.len() < 16 {
@@ -56,22 +241,194 @@ impl<'a> FlashCommands<'a> {
             response.data[14],
             response.data[15],
         ]);
+        // Older firmware always reports 4KB, and a size the firmware can't
+        // erase in keeps the last geometry
+        match EraseUnit::from_size(sector_size) {
+            Some(erase_unit) => {
+                self.geometry = FlashGeometry {
+                    total_size,
+                    page_size,
+                    erase_unit,
+                }
+            }
+            None => log::warn!("Unsupported sector size {} bytes", sector_size),
+        }
+
+        // Older firmware sends no flags word
+        let flags = response
+            .data
+            .get(16..20)
+            .map_or(0, |b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
 
         Ok(FlashInfo {
             jedec_id,
             total_size,
             page_size,
             sector_size,
+            write_protected: flags & INFO_FLAG_WRITE_PROTECTED != 0,
         })
     }
 
+    /// Read `length` bytes of the chip's SFDP space at `address`
+    ///
+    /// `None` if the firmware predates ReadSfdp or the chip has no SFDP.
+    pub async fn read_sfdp(&mut self, address: u32, length: u32) -> Result<Option<Vec<u8>>> {
+        if !self.supports(Command::ReadSfdp) {
+            return Ok(None);
+        }
+        let mut packet = Packet::new(Command::ReadSfdp, address, Vec::new());
+        packet.length = length;
+        let Some(response) = self.connection.probe(packet).await? else {
+            log::debug!("Firmware predates ReadSfdp");
+            return Ok(None);
+        };
+        if response.error_detail() == Some(ErrorDetail::Unsupported) {
+            return Ok(None);
+        }
+        let response = check_status(response).context("Failed to read SFDP")?;
+        Ok(Some(response.data))
+    }
+
     pub async fn erase(&mut self, address: u32, size: u32) -> Result<()> {
-        let data = size.to_le_bytes().to_vec();
+        if size == 0 {
+            return Ok(());
+        }
+        if self.can_chip_erase(address, size) {
+            return self.chip_erase().await;
+        }
+        if self.power_safe && !self.firmware_paces_erase() {
+            return self.erase_paced_by_host(address, size, None).await;
+        }
+        let data = erase_progress::request_with_flags(size, self.erase_flags(false));
         let packet = Packet::new(Command::Erase, address, data);
         self.connection.send_command(packet).await?;
         Ok(())
     }
 
+    /// Erase with the firmware reporting each sector as it finishes;
+    /// `progress` counts sectors
+    ///
+    /// Firmware that doesn't report progress answers once when the erase is
+    /// done, which fills the bar in one step.
+    pub async fn erase_with_progress(
+        &mut self,
+        address: u32,
+        size: u32,
+        progress: &ProgressBar,
+    ) -> Result<()> {
+        if size == 0 {
+            return Ok(());
+        }
+        if self.can_chip_erase(address, size) {
+            self.chip_erase().await?;
+            progress.set_position(progress.length().unwrap_or(0));
+            return Ok(());
+        }
+        if self.power_safe && !self.firmware_paces_erase() {
+            return self
+                .erase_paced_by_host(address, size, Some(progress))
+                .await;
+        }
+        let data = erase_progress::request_with_flags(size, self.erase_flags(true));
+        let packet = Packet::new(Command::Erase, address, data);
+        let sequence = self.connection.send_request(packet).await?;
+
+        loop {
+            let response = check_status(self.connection.receive_reply(sequence).await?)?;
+            let Some((done, total)) = erase_progress::decode(&response.data) else {
+                progress.set_position(progress.length().unwrap_or(0));
+                return Ok(());
+            };
+            progress.set_length(total as u64);
+            progress.set_position(done as u64);
+            if done >= total {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Whether erasing `size` bytes at `address` clears the whole chip and
+    /// the firmware can do that with one chip erase (never in power-safe
+    /// mode, as a chip erase can't be paced)
+    pub fn can_chip_erase(&self, address: u32, size: u32) -> bool {
+        !self.power_safe
+            && self
+                .capabilities
+                .is_some_and(|capabilities| capabilities.chip_erase)
+            && covers_chip(&self.geometry, address, size)
+    }
+
+    /// Whether the firmware is known to answer BatchChecksum; unlike
+    /// [`supports`](Self::supports), unknown capabilities count as no
+    fn checks_stream_batches(&self) -> bool {
+        self.capabilities
+            .is_some_and(|capabilities| capabilities.supports(Command::BatchChecksum))
+    }
+
+    fn firmware_paces_erase(&self) -> bool {
+        self.capabilities
+            .is_some_and(|capabilities| capabilities.power_safe_erase)
+    }
+
+    /// Flags byte of an Erase request
+    fn erase_flags(&self, report_progress: bool) -> u8 {
+        let mut flags = 0;
+        if report_progress {
+            flags |= erase_progress::FLAG_REPORT_PROGRESS;
+        }
+        if self.power_safe {
+            flags |= erase_progress::FLAG_POWER_SAFE;
+        }
+        flags
+    }
+
+    /// Power-safe erase for firmware that can't pace: one Erase per sector
+    /// with a pause between them; `progress` counts sectors
+    async fn erase_paced_by_host(
+        &mut self,
+        address: u32,
+        size: u32,
+        progress: Option<&ProgressBar>,
+    ) -> Result<()> {
+        let span = self.geometry.erase_span(address, size);
+        let sector_size = self.geometry.sector_size();
+        let total = span.length / sector_size;
+        if let Some(progress) = progress {
+            progress.set_length(total as u64);
+        }
+        for sector in 0..total {
+            if sector > 0 {
+                let pause = erase_progress::POWER_SAFE_PAUSE_MS as u64;
+                tokio::time::sleep(Duration::from_millis(pause)).await;
+            }
+            let sector_address = span.address + sector * sector_size;
+            let data = erase_progress::request(sector_size, false);
+            let packet = Packet::new(Command::Erase, sector_address, data);
+            self.connection
+                .send_command(packet)
+                .await
+                .with_context(|| format!("Failed to erase sector at 0x{:08X}", sector_address))?;
+            if let Some(progress) = progress {
+                progress.inc(1);
+            }
+        }
+        Ok(())
+    }
+
+    /// Clear the whole chip with one chip-erase command, which is faster
+    /// than erasing it sector by sector but reports no progress
+    pub async fn chip_erase(&mut self) -> Result<()> {
+        let data = erase_progress::chip_erase_request(self.geometry.total_size);
+        let packet = Packet::new(Command::Erase, 0, data);
+        let response_timeout = self.connection.response_timeout();
+        self.connection
+            .set_response_timeout(response_timeout.max(CHIP_ERASE_TIMEOUT));
+        let result = self.connection.send_command(packet).await;
+        self.connection.set_response_timeout(response_timeout);
+        result.context("Chip erase failed")?;
+        Ok(())
+    }
+
     pub async fn read_status(&mut self) -> Result<u8> {
         let packet = Packet::new(Command::Status, 0, Vec::new());
         let response = self.connection.send_command(packet).await?;
@@ -83,6 +440,80 @@ impl<'a> FlashCommands<'a> {
         Ok(response.data[0])
     }
 
+    pub async fn set_spi_mode(&mut self, mode: SpiMode) -> Result<()> {
+        let packet = Packet::new(Command::SetSpiMode, 0, vec![mode as u8]);
+        self.connection
+            .send_command(packet)
+            .await
+            .with_context(|| format!("Failed to set SPI mode {}", mode as u8))?;
+        Ok(())
+    }
+
+    /// Change the SPI clock, returning the clock the firmware now uses
+    pub async fn set_spi_frequency(&mut self, hz: u32) -> Result<u32> {
+        let packet = Packet::new(Command::SetSpiFrequency, 0, hz.to_le_bytes().to_vec());
+        let response = self
+            .connection
+            .send_command(packet)
+            .await
+            .with_context(|| format!("Failed to set SPI clock to {} Hz", hz))?;
+        match response.data.get(0..4) {
+            Some(bytes) => Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+            None => Err(anyhow::anyhow!("Invalid SPI frequency response length")),
+        }
+    }
+
+    /// Ask the firmware to reboot into the STM32 system DFU bootloader
+    ///
+    /// The serial port goes away once the device has answered.
+    pub async fn enter_bootloader(&mut self) -> Result<()> {
+        let packet = Packet::new(Command::EnterBootloader, 0, Vec::new());
+        self.connection
+            .send_command(packet)
+            .await
+            .context("Failed to enter the DFU bootloader (firmware may predate EnterBootloader)")?;
+        Ok(())
+    }
+
+    /// Read the device's current runtime settings
+    pub async fn get_config(&mut self) -> Result<RuntimeConfig> {
+        let packet = Packet::new(Command::GetConfig, 0, Vec::new());
+        let response = self
+            .connection
+            .send_command(packet)
+            .await
+            .context("Failed to read device configuration (firmware may predate GetConfig)")?;
+        RuntimeConfig::from_bytes(&response.data).map_err(|e| anyhow::anyhow!(e))
+    }
+
+    /// Read the firmware's error counters, clearing them afterwards if `clear`
+    pub async fn error_counters(&mut self, clear: bool) -> Result<ErrorCounters> {
+        let response = self.send_counter_command(Command::GetErrorCounters).await?;
+        let counters = ErrorCounters::from_bytes(&response.data).map_err(|e| anyhow::anyhow!(e))?;
+        if clear {
+            self.send_counter_command(Command::ClearErrorCounters)
+                .await?;
+        }
+        Ok(counters)
+    }
+
+    /// Send a Get/ClearErrorCounters packet, failing with an update hint on
+    /// firmware without error counters
+    async fn send_counter_command(&mut self, command: Command) -> Result<Response> {
+        let hint = "Firmware does not keep error counters - update it (see enter-dfu)";
+        if !self.supports(command) {
+            anyhow::bail!(hint);
+        }
+        let packet = Packet::new(command, 0, Vec::new());
+        let Some(response) = self.connection.probe(packet).await? else {
+            anyhow::bail!(hint);
+        };
+        if response.error_detail() == Some(ErrorDetail::Unsupported) {
+            anyhow::bail!(hint);
+        }
+        check_status(response).with_context(|| format!("{:?} failed", command))
+    }
+
     pub async fn write(&mut self, address: u32, data: &[u8]) -> Result<()> {
         let mut current_address = address;
         let mut remaining_data = data;
@@ -124,19 +555,13 @@ impl<'a> FlashCommands<'a> {
         let mut current_address = address;
         let mut remaining_data = data;
         let mut written = 0;
-        let mut sequence: u16 = 1;
 
         while !remaining_data.is_empty() {
             let chunk_size = std::cmp::min(remaining_data.len(), MAX_PAYLOAD_SIZE);
             let chunk = &remaining_data[..chunk_size];
 
             // Use regular Write command with 4KB packets for maximum compatibility
-            let packet = Packet::new_with_sequence(
-                Command::Write,
-                current_address,
-                chunk.to_vec(),
-                sequence,
-            );
+            let packet = Packet::new(Command::Write, current_address, chunk.to_vec());
 
             // Send and wait for ACK - simplified approach
             self.connection
@@ -147,7 +572,6 @@ impl<'a> FlashCommands<'a> {
             current_address += chunk_size as u32;
             remaining_data = &remaining_data[chunk_size..];
             written += chunk_size;
-            sequence = sequence.wrapping_add(1);
 
             progress.set_position(written as u64);
         }
@@ -155,72 +579,154 @@ impl<'a> FlashCommands<'a> {
         Ok(())
     }
 
+    /// Read `size` bytes into memory in as few round trips as the firmware
+    /// allows (see [`read_to_writer`](Self::read_to_writer))
     pub async fn read(&mut self, address: u32, size: u32) -> Result<Vec<u8>> {
-        let mut result = Vec::new();
+        self.read_with_progress(address, size, &ProgressBar::hidden())
+            .await
+    }
+
+    pub async fn read_with_progress(
+        &mut self,
+        address: u32,
+        size: u32,
+        progress: &ProgressBar,
+    ) -> Result<Vec<u8>> {
+        let mut result = Vec::with_capacity(size as usize);
+        self.read_to_writer(address, size, &mut result, progress)
+            .await?;
+        Ok(result)
+    }
+
+    /// Read `size` bytes and write each chunk to `writer` as it arrives, so
+    /// memory use doesn't grow with the size of the read
+    pub async fn read_to_writer<W: AsyncWrite + Unpin>(
+        &mut self,
+        address: u32,
+        size: u32,
+        writer: &mut W,
+        progress: &ProgressBar,
+    ) -> Result<()> {
+        if !self.supports(Command::ReadStream) {
+            return self
+                .read_packets_to_writer(address, size, writer, progress)
+                .await;
+        }
+
         let mut current_address = address;
         let mut remaining_size = size;
 
         while remaining_size > 0 {
-            let chunk_size = std::cmp::min(remaining_size, MAX_PAYLOAD_SIZE as u32);
-
-            // For read commands, use length field for size, data field should be empty
-            let mut packet = Packet::new(Command::Read, current_address, Vec::new());
-            packet.length = chunk_size;
-            packet.crc = packet.calculate_crc();
-            let response = self
-                .connection
-                .send_command(packet)
+            let stream_size = std::cmp::min(remaining_size, read_stream::MAX_STREAM_LENGTH);
+            let received = self
+                .read_stream(current_address, stream_size, writer, progress)
                 .await
                 .with_context(|| format!("Failed to read at address 0x{:08X}", current_address))?;
+            if received != stream_size {
+                return Err(anyhow::anyhow!(
+                    "Short read at 0x{:08X}: got {} of {} bytes",
+                    current_address,
+                    received,
+                    stream_size
+                ));
+            }
 
-            result.extend_from_slice(&response.data);
-            current_address += chunk_size;
-            remaining_size -= chunk_size;
+            current_address += stream_size;
+            remaining_size -= stream_size;
         }
 
-        Ok(result)
+        Ok(())
     }
 
-    pub async fn read_with_progress(
+    /// [`read_to_writer`](Self::read_to_writer) with one Read per chunk, for
+    /// firmware without ReadStream
+    async fn read_packets_to_writer<W: AsyncWrite + Unpin>(
         &mut self,
         address: u32,
         size: u32,
+        writer: &mut W,
         progress: &ProgressBar,
-    ) -> Result<Vec<u8>> {
-        let mut result = Vec::new();
+    ) -> Result<()> {
+        let read_chunk_size = self.read_chunk_size().await as u32;
         let mut current_address = address;
         let mut remaining_size = size;
-        let mut read_bytes = 0;
-        let mut sequence: u16 = 1;
 
         while remaining_size > 0 {
-            // Use smaller chunks for read operations to match firmware limitations
-            const MAX_READ_SIZE: u32 = 256;
-            let chunk_size = std::cmp::min(remaining_size, MAX_READ_SIZE);
-
-            // Use the correct protocol format - empty data field, size in length field
-            let mut packet =
-                Packet::new_with_sequence(Command::Read, current_address, Vec::new(), sequence);
+            let chunk_size = std::cmp::min(remaining_size, read_chunk_size);
+            let mut packet = Packet::new(Command::Read, current_address, Vec::new());
             packet.length = chunk_size;
-            // Recalculate CRC after modifying length field
-            packet.crc = packet.calculate_crc();
-
             let response = self
                 .connection
                 .send_command(packet)
                 .await
                 .with_context(|| format!("Failed to read at address 0x{:08X}", current_address))?;
+            if response.data.len() != chunk_size as usize {
+                return Err(anyhow::anyhow!(
+                    "Short read at 0x{:08X}: got {} of {} bytes",
+                    current_address,
+                    response.data.len(),
+                    chunk_size
+                ));
+            }
 
-            result.extend_from_slice(&response.data);
+            writer
+                .write_all(&response.data)
+                .await
+                .context("Failed to write read data")?;
+            progress.inc(chunk_size as u64);
             current_address += chunk_size;
             remaining_size -= chunk_size;
-            read_bytes += chunk_size;
-            sequence = sequence.wrapping_add(1);
-
-            progress.set_position(read_bytes as u64);
         }
 
-        Ok(result)
+        Ok(())
+    }
+
+    /// Issue one ReadStream request and write its chunks to `writer`,
+    /// returning the number of bytes received
+    ///
+    /// The device answers with `chunk_count(size)` tagged responses; the one
+    /// whose index is `total - 1` ends the stream.
+    async fn read_stream<W: AsyncWrite + Unpin>(
+        &mut self,
+        address: u32,
+        size: u32,
+        writer: &mut W,
+        progress: &ProgressBar,
+    ) -> Result<u32> {
+        // Size goes in the length field, data stays empty
+        let mut packet = Packet::new(Command::ReadStream, address, Vec::new());
+        packet.length = size;
+        let sequence = self.connection.send_request(packet).await?;
+
+        let expected_total = read_stream::chunk_count(size);
+        let mut expected_index = 0u16;
+        let mut received = 0u32;
+        loop {
+            let response = check_status(self.connection.receive_reply(sequence).await?)?;
+            let (index, total, payload) = read_stream::decode_chunk(&response.data)
+                .ok_or_else(|| anyhow::anyhow!("Stream response too short"))?;
+            if index != expected_index || total != expected_total {
+                return Err(anyhow::anyhow!(
+                    "Unexpected stream chunk {}/{} (expected {}/{})",
+                    index,
+                    total,
+                    expected_index,
+                    expected_total
+                ));
+            }
+
+            writer
+                .write_all(payload)
+                .await
+                .context("Failed to write read data")?;
+            received += payload.len() as u32;
+            progress.inc(payload.len() as u64);
+
+            if index == total - 1 {
+                return Ok(received);
+            }
+            expected_index += 1;
+        }
     }
 
     pub async fn verify(&mut self, address: u32, expected_data: &[u8]) -> Result<()> {
@@ -279,71 +785,134 @@ impl<'a> FlashCommands<'a> {
     }
 
     /// Ultra-high-speed burst stream write with data integrity verification
+    ///
+    /// Packets go out unacknowledged in small batches; each batch is then
+    /// confirmed with a `BatchChecksum` round trip and resent (up to
+    /// [`BATCH_RESENDS`] times) if the device's CRC doesn't match. A resend
+    /// repairs packets that were dropped, since their target is still
+    /// erased, but not bits that were programmed wrong.
+    ///
+    /// Firmware that doesn't report BatchChecksum among its capabilities
+    /// would drop the checksum packet and leave the write waiting for a
+    /// reply, so its batches go out unchecked and the final verify catches
+    /// any loss.
     pub async fn stream_write_with_progress(
         &mut self,
         address: u32,
         data: &[u8],
         progress: &ProgressBar,
     ) -> Result<()> {
-        let mut current_address = address;
-        let mut remaining_data = data;
+        let batch_bytes = STREAM_BATCH_PACKETS * MAX_PAYLOAD_SIZE;
+        let check_batches = self.checks_stream_batches();
+        if !check_batches {
+            log::debug!("Firmware has no batch checksums, relying on the final verify");
+        }
         let mut written = 0;
-        let mut sequence: u16 = 1;
 
-        // Reduced batch processing for reliability
-        let batch_size = 4; // Send 4 packets at once for better reliability
-        let mut batch_packets = Vec::with_capacity(batch_size);
-
-        while !remaining_data.is_empty() {
-            // Prepare a batch of packets
-            batch_packets.clear();
+        for batch in data.chunks(batch_bytes) {
+            let batch_address = address + written as u32;
+            let checksum = batch::BatchChecksum::for_data(batch);
 
-            for _ in 0..batch_size {
-                if remaining_data.is_empty() {
+            let mut resends = 0;
+            loop {
+                self.send_stream_batch(batch_address, batch).await?;
+                if !check_batches || self.check_batch(batch_address, checksum).await? {
                     break;
                 }
-
-                let chunk_size = std::cmp::min(remaining_data.len(), MAX_PAYLOAD_SIZE);
-                let chunk = &remaining_data[..chunk_size];
-
-                // Use StreamWrite command - no ACK expected
-                let packet = Packet::new_with_sequence(
-                    Command::StreamWrite,
-                    current_address,
-                    chunk.to_vec(),
-                    sequence,
+                if resends == BATCH_RESENDS {
+                    return Err(anyhow::anyhow!(
+                        "Stream write batch 0x{:08X}-0x{:08X} failed its checksum after {} resends",
+                        batch_address,
+                        batch_address + batch.len() as u32 - 1,
+                        resends
+                    ));
+                }
+                resends += 1;
+                log::warn!(
+                    "Stream write batch at 0x{:08X} failed its checksum, resending ({}/{})",
+                    batch_address,
+                    resends,
+                    BATCH_RESENDS
                 );
-                batch_packets.push(packet);
-
-                current_address += chunk_size as u32;
-                remaining_data = &remaining_data[chunk_size..];
-                written += chunk_size;
-                sequence = sequence.wrapping_add(1);
             }
 
-            // Send entire batch rapidly
-            for packet in batch_packets.iter() {
-                self.connection
-                    .send_packet_no_ack(packet.clone())
-                    .await
-                    .context("Failed to send batch stream write packet")?;
+            written += batch.len();
+            progress.set_position(written as u64);
+        }
 
-                // Minimal yield to prevent blocking
-                tokio::task::yield_now().await;
-            }
+        Ok(())
+    }
 
-            progress.set_position(written as u64);
+    /// Send one batch as back-to-back StreamWrite packets
+    async fn send_stream_batch(&mut self, address: u32, batch: &[u8]) -> Result<()> {
+        for (i, chunk) in batch.chunks(MAX_PAYLOAD_SIZE).enumerate() {
+            let chunk_address = address + (i * MAX_PAYLOAD_SIZE) as u32;
+            let packet = Packet::new(Command::StreamWrite, chunk_address, chunk.to_vec());
+            self.connection
+                .send_packet_no_ack(packet)
+                .await
+                .context("Failed to send batch stream write packet")?;
 
-            // Increased delay to allow Flash controller to process the batch
-            tokio::time::sleep(tokio::time::Duration::from_millis(5)).await;
+            // Minimal yield to prevent blocking
+            tokio::task::yield_now().await;
         }
+        Ok(())
+    }
 
-        // Give extra time for Flash controller to complete all pending writes
-        if written > 0 {
-            tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+    /// Ask the device whether a stream-written batch reads back with the
+    /// expected CRC
+    async fn check_batch(&mut self, address: u32, checksum: batch::BatchChecksum) -> Result<bool> {
+        let packet = Packet::new(Command::BatchChecksum, address, checksum.to_bytes());
+        let sequence = self.connection.send_request(packet).await?;
+        // Replies come in order, so this also waits out the batch itself
+        let response = self.connection.receive_reply(sequence).await?;
+        if response.status == Status::VerificationFailed {
+            return Ok(false);
         }
+        check_status(response)
+            .with_context(|| format!("Batch checksum at 0x{:08X} failed", address))?;
+        Ok(true)
+    }
 
-        Ok(())
+    /// Whether flash already holds `data` at `address`, checked one 4KB
+    /// sector at a time with the device's `BatchChecksum`
+    ///
+    /// Stops at the first sector that differs. Returns `None` if the
+    /// firmware rejects or ignores `BatchChecksum`, so the caller can fall
+    /// back to writing.
+    pub async fn already_matches(
+        &mut self,
+        address: u32,
+        data: &[u8],
+        progress: &ProgressBar,
+    ) -> Result<Option<bool>> {
+        for (index, sector) in data.chunks(FLASH_SECTOR_SIZE).enumerate() {
+            let sector_address = address + (index * FLASH_SECTOR_SIZE) as u32;
+            let checksum = batch::BatchChecksum::for_data(sector);
+            let packet = Packet::new(Command::BatchChecksum, sector_address, checksum.to_bytes());
+            let sequence = self.connection.send_request(packet).await?;
+            let response = match self.connection.receive_reply(sequence).await {
+                Ok(response) => response,
+                Err(e) => {
+                    log::warn!("No sector checksum from firmware ({}), writing anyway", e);
+                    return Ok(None);
+                }
+            };
+            match response.status {
+                Status::Success => progress.inc(sector.len() as u64),
+                Status::VerificationFailed => {
+                    log::debug!("Sector at 0x{:08X} differs", sector_address);
+                    return Ok(Some(false));
+                }
+                Status::InvalidCommand => return Ok(None),
+                _ => {
+                    check_status(response).with_context(|| {
+                        format!("Sector checksum at 0x{:08X} failed", sector_address)
+                    })?;
+                }
+            }
+        }
+        Ok(Some(true))
     }
 
     /// Verify written data by reading back and comparing
@@ -356,20 +925,18 @@ impl<'a> FlashCommands<'a> {
         let mut current_address = address;
         let mut remaining_data = expected_data;
         let mut verified = 0;
-        let mut sequence: u16 = 1;
+
+        let read_chunk_size = self.read_chunk_size().await;
 
         progress.set_message("Verifying written data...");
         progress.set_position(0);
 
         while !remaining_data.is_empty() {
-            // Use smaller chunks for read operations to match firmware limitations
-            const MAX_READ_SIZE: usize = 256;
-            let chunk_size = std::cmp::min(remaining_data.len(), MAX_READ_SIZE);
+            let chunk_size = std::cmp::min(remaining_data.len(), read_chunk_size);
             let expected_chunk = &remaining_data[..chunk_size];
 
             // Read back the data - use length field for size, data field should be empty
-            let mut read_packet =
-                Packet::new_with_sequence(Command::Read, current_address, Vec::new(), sequence);
+            let mut read_packet = Packet::new(Command::Read, current_address, Vec::new());
             read_packet.length = chunk_size as u32;
             read_packet.crc = read_packet.calculate_crc();
             let response = self
@@ -414,7 +981,6 @@ impl<'a> FlashCommands<'a> {
             current_address += chunk_size as u32;
             remaining_data = &remaining_data[chunk_size..];
             verified += chunk_size;
-            sequence = sequence.wrapping_add(1);
 
             progress.set_position(verified as u64);
         }
@@ -431,36 +997,44 @@ impl<'a> FlashCommands<'a> {
         progress: &ProgressBar,
     ) -> Result<()> {
         progress.set_message("Computing original data hash...");
+        let original_hash: [u8; 32] = Sha256::digest(original_data).into();
+
+        self.verify_against_hash(
+            address,
+            original_data.len() as u32,
+            &original_hash,
+            progress,
+        )
+        .await
+    }
 
-        // Calculate SHA256 hash of original data
-        let mut hasher = Sha256::new();
-        hasher.update(original_data);
-        let original_hash = hasher.finalize();
-
+    /// Check `size` bytes at `address` against an expected SHA256, for when
+    /// only the digest of the original is at hand
+    pub async fn verify_against_hash(
+        &mut self,
+        address: u32,
+        size: u32,
+        expected_hash: &[u8; 32],
+        progress: &ProgressBar,
+    ) -> Result<()> {
         progress.set_message("Reading back flash data...");
         progress.set_position(0);
 
         // Read back all data from flash
-        let flash_data = self
-            .read_flash_data(address, original_data.len() as u32, progress)
-            .await?;
+        let flash_data = self.read_flash_data(address, size, progress).await?;
 
         progress.set_message("Computing flash data hash...");
-
-        // Calculate SHA256 hash of flash data
-        let mut hasher = Sha256::new();
-        hasher.update(&flash_data);
-        let flash_hash = hasher.finalize();
+        let flash_hash: [u8; 32] = Sha256::digest(&flash_data).into();
 
         // Compare hashes
-        if original_hash == flash_hash {
+        if &flash_hash == expected_hash {
             progress.set_message("✅ Hash verification successful!");
             Ok(())
         } else {
             Err(anyhow::anyhow!(
-                "❌ Hash verification failed!\nOriginal: {:x}\nFlash:    {:x}",
-                original_hash,
-                flash_hash
+                "❌ Hash verification failed!\nExpected: {}\nFlash:    {}",
+                hex::encode(expected_hash),
+                hex::encode(flash_hash)
             ))
         }
     }
@@ -472,19 +1046,16 @@ impl<'a> FlashCommands<'a> {
         size: u32,
         progress: &ProgressBar,
     ) -> Result<Vec<u8>> {
+        let read_chunk_size = self.read_chunk_size().await as u32;
         let mut result = Vec::new();
         let mut current_address = address;
         let mut remaining_size = size;
-        let mut sequence: u16 = 1;
 
         while remaining_size > 0 {
-            // Use smaller chunks for read operations to match firmware limitations
-            const MAX_READ_SIZE: u32 = 256;
-            let chunk_size = std::cmp::min(remaining_size, MAX_READ_SIZE);
+            let chunk_size = std::cmp::min(remaining_size, read_chunk_size);
 
             // Read back the data - use length field for size
-            let mut read_packet =
-                Packet::new_with_sequence(Command::Read, current_address, Vec::new(), sequence);
+            let mut read_packet = Packet::new(Command::Read, current_address, Vec::new());
             read_packet.length = chunk_size;
             // Recalculate CRC after modifying length field
             read_packet.crc = read_packet.calculate_crc();
@@ -503,7 +1074,6 @@ impl<'a> FlashCommands<'a> {
             result.extend_from_slice(&response.data);
             current_address += chunk_size;
             remaining_size -= chunk_size;
-            sequence = sequence.wrapping_add(1);
 
             progress.set_position((size - remaining_size) as u64);
         }
@@ -528,8 +1098,11 @@ impl<'a> FlashCommands<'a> {
         progress.set_message("Requesting firmware CRC verification...");
 
         // Send CRC verification command to firmware
-        let crc_bytes = expected_crc.to_le_bytes().to_vec();
-        let verify_packet = Packet::new_with_sequence(Command::VerifyCRC, address, crc_bytes, 1);
+        let checksum = batch::BatchChecksum {
+            crc32: expected_crc,
+            length: data.len() as u32,
+        };
+        let verify_packet = Packet::new(Command::VerifyCRC, address, checksum.to_bytes());
 
         match self.connection.send_command(verify_packet).await {
             Ok(response) => {
@@ -545,7 +1118,7 @@ impl<'a> FlashCommands<'a> {
             Err(e) => {
                 // If CRC verification is not supported by firmware, fall back to warning
                 progress.set_message("⚠️  CRC verification not supported by firmware");
-                eprintln!(
+                log::warn!(
                     "Warning: CRC verification failed ({}), but data was transmitted successfully",
                     e
                 );
@@ -561,59 +1134,31 @@ impl<'a> FlashCommands<'a> {
         data: &[u8],
         progress: &ProgressBar,
     ) -> Result<()> {
-        const VERIFY_BLOCK_SIZE: usize = 64 * 1024; // 64KB per block
-
         let mut current_address = address;
         let mut remaining_data = data;
         let mut block_index = 0;
-        let _total_blocks = data.len().div_ceil(VERIFY_BLOCK_SIZE);
 
         progress.set_message("Starting progressive CRC verification...");
         progress.set_position(0);
 
         while !remaining_data.is_empty() {
-            let block_size = std::cmp::min(remaining_data.len(), VERIFY_BLOCK_SIZE);
+            let block_size = std::cmp::min(remaining_data.len(), self.verify_block_size);
             let block_data = &remaining_data[..block_size];
 
-            // Calculate CRC32 for this block
-            let mut hasher = Hasher::new();
-            hasher.update(block_data);
-            let expected_crc = hasher.finalize();
-
             // Verify this block
             progress.set_message("Verifying block...");
-
-            // Send block CRC verification command to firmware
-            let mut crc_data = Vec::new();
-            crc_data.extend_from_slice(&expected_crc.to_le_bytes());
-            crc_data.extend_from_slice(&(block_size as u32).to_le_bytes());
-
-            let verify_packet = Packet::new_with_sequence(
-                Command::VerifyCRC,
-                current_address,
-                crc_data,
-                (block_index + 1) as u16,
-            );
-
-            match self.connection.send_command(verify_packet).await {
-                Ok(response) => {
-                    if response.status == Status::Success {
-                        progress.set_message("✅ Block verified successfully!");
-                    } else {
-                        return Err(anyhow::anyhow!(
-                            "❌ Block {} CRC verification failed at address 0x{:08X} (expected CRC: 0x{:08X})",
-                            block_index + 1, current_address, expected_crc
-                        ));
-                    }
-                }
-                Err(e) => {
-                    return Err(anyhow::anyhow!(
-                        "❌ Block {} verification communication error at address 0x{:08X}: {}",
-                        block_index + 1,
-                        current_address,
-                        e
-                    ));
-                }
+            if self
+                .verify_crc_block(current_address, block_data, block_index)
+                .await?
+            {
+                progress.set_message("✅ Block verified successfully!");
+            } else {
+                return Err(anyhow::anyhow!(
+                    "❌ Block {} CRC verification failed at address 0x{:08X} (expected CRC: 0x{:08X})",
+                    block_index + 1,
+                    current_address,
+                    crc32fast::hash(block_data)
+                ));
             }
 
             current_address += block_size as u32;
@@ -630,11 +1175,170 @@ impl<'a> FlashCommands<'a> {
         Ok(())
     }
 
+    /// Check one verification block against the firmware's CRC32, returning whether it matched
+    ///
+    /// Firmware whose capabilities lack VerifyCRC has the block read back
+    /// and compared instead.
+    async fn verify_crc_block(
+        &mut self,
+        address: u32,
+        block_data: &[u8],
+        block_index: usize,
+    ) -> Result<bool> {
+        if !self.supports(Command::VerifyCRC) {
+            let flash_data = self
+                .read_with_progress(address, block_data.len() as u32, &ProgressBar::hidden())
+                .await
+                .with_context(|| {
+                    format!(
+                        "❌ Block {} read-back failed at address 0x{:08X}",
+                        block_index + 1,
+                        address
+                    )
+                })?;
+            return Ok(flash_data == block_data);
+        }
+
+        // Calculate CRC32 for this block
+        let mut hasher = Hasher::new();
+        hasher.update(block_data);
+        let expected_crc = hasher.finalize();
+
+        // Send block CRC verification command to firmware
+        let mut crc_data = Vec::new();
+        crc_data.extend_from_slice(&expected_crc.to_le_bytes());
+        crc_data.extend_from_slice(&(block_data.len() as u32).to_le_bytes());
+
+        let verify_packet = Packet::new(Command::VerifyCRC, address, crc_data);
+
+        // A mismatch comes back as VerificationFailed, which is an answer
+        // rather than a failed exchange
+        let context = || {
+            format!(
+                "❌ Block {} verification communication error at address 0x{:08X}",
+                block_index + 1,
+                address
+            )
+        };
+        let sequence = self
+            .connection
+            .send_request(verify_packet)
+            .await
+            .with_context(context)?;
+        let response = self
+            .connection
+            .receive_reply(sequence)
+            .await
+            .with_context(context)?;
+        if response.status == Status::VerificationFailed {
+            return Ok(false);
+        }
+        check_status(response).with_context(context)?;
+        Ok(true)
+    }
+
+    /// Progressive CRC verification that checks every block instead of
+    /// stopping at the first mismatch, returning the blocks that failed
+    pub async fn verify_with_progressive_crc_report(
+        &mut self,
+        address: u32,
+        data: &[u8],
+        progress: &ProgressBar,
+    ) -> Result<Vec<BlockFailure>> {
+        let mut failures = Vec::new();
+
+        progress.set_message("Starting progressive CRC verification...");
+        progress.set_position(0);
+
+        let block_size = self.verify_block_size;
+        for (block_index, block_data) in data.chunks(block_size).enumerate() {
+            let offset = block_index * block_size;
+            let block_address = address + offset as u32;
+
+            if !self
+                .verify_crc_block(block_address, block_data, block_index)
+                .await?
+            {
+                log::warn!(
+                    "Block {} CRC verification failed at address 0x{:08X}",
+                    block_index + 1,
+                    block_address
+                );
+                failures.push(BlockFailure {
+                    index: block_index,
+                    address: block_address,
+                    length: block_data.len() as u32,
+                    expected_crc: crc32fast::hash(block_data),
+                });
+            }
+
+            progress.set_position((offset + block_data.len()) as u64);
+
+            // Small delay between blocks to avoid overwhelming the firmware
+            tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
+        }
+
+        Ok(failures)
+    }
+
+    /// Verify written data, re-erasing and rewriting failed blocks up to `max_retries` times
+    ///
+    /// Rewrites erase whole sectors, so a block is widened to the sectors it
+    /// touches (within `data`) before being programmed again. As with
+    /// `write --erase`, bytes outside `data` that share its first or last
+    /// sector are lost.
+    pub async fn verify_and_repair(
+        &mut self,
+        address: u32,
+        data: &[u8],
+        max_retries: u32,
+        progress: &ProgressBar,
+    ) -> Result<()> {
+        let mut attempt = 0;
+        loop {
+            let failed = self
+                .verify_with_progressive_crc_report(address, data, progress)
+                .await?;
+            if failed.is_empty() {
+                progress.set_message("🎉 All blocks verified successfully!");
+                return Ok(());
+            }
+            if attempt == max_retries {
+                return Err(anyhow::anyhow!(
+                    "❌ {} block(s) failed CRC verification after {} retries: {}",
+                    failed.len(),
+                    max_retries,
+                    failure_summary(&failed)
+                ));
+            }
+            attempt += 1;
+
+            for failure in failed {
+                let offset = (failure.address - address) as usize;
+                let block = offset..offset + failure.length as usize;
+                let span = retry_span(address, data.len(), block, &self.geometry);
+                let span_address = address + span.start as u32;
+                log::info!(
+                    "Retry {}/{}: rewriting 0x{:08X}..0x{:08X}",
+                    attempt,
+                    max_retries,
+                    span_address,
+                    span_address + span.len() as u32
+                );
+                self.erase(span_address, span.len() as u32).await?;
+                self.write(span_address, &data[span]).await?;
+            }
+        }
+    }
+
     /// High-speed write with progressive CRC-based verification
+    ///
+    /// Blocks that fail verification are re-erased and rewritten up to `max_retries` times.
     pub async fn write_and_verify_with_progress(
         &mut self,
         address: u32,
         data: &[u8],
+        max_retries: u32,
         progress: &ProgressBar,
     ) -> Result<()> {
         // Phase 1: High-speed write
@@ -644,9 +1348,317 @@ impl<'a> FlashCommands<'a> {
 
         // Phase 2: Progressive CRC-based verification (much faster and more reliable)
         progress.set_message("Performing progressive CRC verification...");
-        self.verify_with_progressive_crc(address, data, progress)
+        self.verify_and_repair(address, data, max_retries, progress)
             .await?;
 
         Ok(())
     }
+
+    /// Write one flash sector at a time, CRC-verifying each before moving on
+    ///
+    /// A sector that fails is re-erased and rewritten up to `max_retries`
+    /// times; if it still fails the write stops there instead of carrying on
+    /// through the rest of the image. The extra round trip per sector makes
+    /// this slower than verifying at the end when writes succeed. Re-erasing
+    /// a partial first or last sector loses its bytes outside `data`.
+    pub async fn write_interleaved_with_progress(
+        &mut self,
+        address: u32,
+        data: &[u8],
+        basic: bool,
+        max_retries: u32,
+        progress: &ProgressBar,
+    ) -> Result<()> {
+        let sector_size = self.geometry.sector_size();
+        for (index, span) in sector_spans(address, data.len(), sector_size)
+            .into_iter()
+            .enumerate()
+        {
+            let sector_address = address + span.start as u32;
+            let sector = &data[span.clone()];
+
+            let mut attempt = 0;
+            loop {
+                if basic {
+                    self.write(sector_address, sector).await?;
+                } else {
+                    self.stream_write_with_progress(sector_address, sector, &ProgressBar::hidden())
+                        .await?;
+                }
+                if self.verify_crc_block(sector_address, sector, index).await? {
+                    break;
+                }
+                if attempt == max_retries {
+                    return Err(anyhow::anyhow!(
+                        "❌ Sector at 0x{:08X} failed CRC verification after {} retries; stopped with 0x{:X} of 0x{:X} bytes written",
+                        sector_address,
+                        max_retries,
+                        span.start,
+                        data.len()
+                    ));
+                }
+                attempt += 1;
+                log::info!(
+                    "Retry {}/{}: rewriting sector at 0x{:08X}",
+                    attempt,
+                    max_retries,
+                    sector_address
+                );
+                self.erase(sector_address, sector.len() as u32).await?;
+            }
+
+            progress.set_position(span.end as u64);
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether an erase of `size` bytes at `address` clears all of a chip with
+/// `geometry`
+fn covers_chip(geometry: &FlashGeometry, address: u32, size: u32) -> bool {
+    geometry.erase_span(address, size) == Segment::new(0, geometry.total_size)
+}
+
+/// Split `data_len` bytes written at `address` into byte ranges that each
+/// stay within one `sector_size` flash sector
+fn sector_spans(address: u32, data_len: usize, sector_size: u32) -> Vec<Range<usize>> {
+    let sector = sector_size as usize;
+    let base = address as usize;
+    let mut spans = Vec::new();
+    let mut start = 0;
+    while start < data_len {
+        let sector_end = ((base + start) / sector + 1) * sector;
+        let end = (sector_end - base).min(data_len);
+        spans.push(start..end);
+        start = end;
+    }
+    spans
+}
+
+/// Describe failed blocks as address ranges, merging adjacent blocks
+///
+/// e.g. `0x00010000-0x0002FFFF, 0x00080000-0x0008FFFF`
+pub fn failure_summary(failures: &[BlockFailure]) -> String {
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+    for failure in failures {
+        let end = failure.address + failure.length;
+        match ranges.last_mut() {
+            Some((_, last_end)) if *last_end == failure.address => *last_end = end,
+            _ => ranges.push((failure.address, end)),
+        }
+    }
+
+    ranges
+        .iter()
+        .map(|(start, end)| format!("0x{:08X}-0x{:08X}", start, end - 1))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Widen a failed block (a byte range of the written data) to the flash
+/// sectors erasing it clears, clipped to the data so nothing outside it is
+/// rewritten
+fn retry_span(
+    address: u32,
+    data_len: usize,
+    block: Range<usize>,
+    geometry: &FlashGeometry,
+) -> Range<usize> {
+    let base = address as usize;
+    let erased = geometry.erase_span(address + block.start as u32, block.len() as u32);
+    (erased.address as usize).saturating_sub(base)..(erased.end() as usize - base).min(data_len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serial::tests::answering_device;
+    use tokio::io::DuplexStream;
+    use tokio::task::JoinHandle;
+
+    /// Connection to a device that acks every request except stream writes
+    /// (which firmware never answers) and VerifyCRC, which takes its status
+    /// from `verify` in order (Success once that runs out)
+    fn scripted_device(
+        verify: Vec<Status>,
+    ) -> (SerialConnection<DuplexStream>, JoinHandle<Vec<Packet>>) {
+        let (host, device) = tokio::io::duplex(64 * 1024);
+        let mut verify = verify.into_iter();
+        let device = tokio::spawn(answering_device(device, move |packet| {
+            match packet.command {
+                Command::StreamWrite => None,
+                Command::VerifyCRC => Some(verify.next().unwrap_or(Status::Success)),
+                _ => Some(Status::Success),
+            }
+        }));
+        let mut connection = SerialConnection::with_port(host, None);
+        connection.set_response_timeout(Duration::from_millis(200));
+        (connection, device)
+    }
+
+    /// Addresses of the packets with `command`
+    fn addresses(packets: &[Packet], command: Command) -> Vec<u32> {
+        packets
+            .iter()
+            .filter(|packet| packet.command == command)
+            .map(|packet| packet.address)
+            .collect()
+    }
+
+    #[test]
+    fn test_retry_span_covers_whole_sectors_within_data() {
+        let geometry = FlashGeometry::W25Q128;
+        // Aligned write: the block is already sector-aligned
+        assert_eq!(
+            retry_span(0x10000, 0x30000, 0x10000..0x20000, &geometry),
+            0x10000..0x20000
+        );
+        // Unaligned write: widen to the sectors, but never past the data
+        assert_eq!(
+            retry_span(0x800, 0x30000, 0x10000..0x20000, &geometry),
+            0xF800..0x20800
+        );
+        assert_eq!(
+            retry_span(0x800, 0x10100, 0x10000..0x10100, &geometry),
+            0xF800..0x10100
+        );
+        assert_eq!(
+            retry_span(0x800, 0x20000, 0..0x10000, &geometry),
+            0..0x10800
+        );
+
+        // On a chip with 64KB sectors a small failure rewrites a whole block
+        let geometry = FlashGeometry::from_jedec_id(0x202017);
+        assert_eq!(
+            retry_span(0x800, 0x30000, 0x10000..0x10100, &geometry),
+            0xF800..0x1F800
+        );
+    }
+
+    #[test]
+    fn test_sector_spans_split_at_sector_boundaries() {
+        assert_eq!(
+            sector_spans(0x1000, 0x2000, 0x1000),
+            vec![0..0x1000, 0x1000..0x2000]
+        );
+        assert_eq!(
+            sector_spans(0x800, 0x1900, 0x1000),
+            vec![0..0x800, 0x800..0x1800, 0x1800..0x1900]
+        );
+        assert_eq!(sector_spans(0x10, 0x20, 0x1000), vec![0..0x20]);
+        assert!(sector_spans(0, 0, 0x1000).is_empty());
+        assert_eq!(
+            sector_spans(0x8000, 0x10000, 0x10000),
+            vec![0..0x8000, 0x8000..0x10000]
+        );
+    }
+
+    #[test]
+    fn test_covers_chip_after_sector_rounding() {
+        let geometry = FlashGeometry::W25Q128;
+        assert!(covers_chip(&geometry, 0, 0x100_0000));
+        assert!(covers_chip(&geometry, 0x10, 0x100_0000 - 0x20));
+        assert!(!covers_chip(&geometry, 0x1000, 0xFF_F000));
+        assert!(!covers_chip(&geometry, 0, 0x80_0000));
+    }
+
+    #[test]
+    fn test_failure_summary_merges_adjacent_blocks() {
+        let block = |index: usize, length: u32| BlockFailure {
+            index,
+            address: (index * DEFAULT_VERIFY_BLOCK_SIZE) as u32,
+            length,
+            expected_crc: 0,
+        };
+        let failures = [block(1, 0x10000), block(2, 0x10000), block(8, 0x100)];
+
+        assert_eq!(
+            failure_summary(&failures),
+            "0x00010000-0x0002FFFF, 0x00080000-0x000800FF"
+        );
+        assert_eq!(failure_summary(&[]), "");
+    }
+
+    #[tokio::test]
+    async fn test_verify_and_repair_rewrites_failed_block() {
+        let data = vec![0x5A; 2 * FLASH_SECTOR_SIZE];
+        let (mut connection, device) =
+            scripted_device(vec![Status::Success, Status::VerificationFailed]);
+        let mut commands = FlashCommands::new(&mut connection);
+        commands.set_verify_block_size(FLASH_SECTOR_SIZE);
+        let result = commands
+            .verify_and_repair(0x10000, &data, 1, &ProgressBar::hidden())
+            .await;
+        drop(connection);
+        let packets = device.await.unwrap();
+
+        result.unwrap();
+        assert_eq!(addresses(&packets, Command::Erase), [0x11000]);
+        assert_eq!(addresses(&packets, Command::Write)[0], 0x11000);
+        // Both blocks checked, then both again after the rewrite
+        assert_eq!(
+            addresses(&packets, Command::VerifyCRC),
+            [0x10000, 0x11000, 0x10000, 0x11000]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_verify_report_lists_every_failed_block() {
+        let data = vec![0x5A; 3 * FLASH_SECTOR_SIZE];
+        let (mut connection, device) = scripted_device(vec![
+            Status::VerificationFailed,
+            Status::Success,
+            Status::VerificationFailed,
+        ]);
+        let mut commands = FlashCommands::new(&mut connection);
+        commands.set_verify_block_size(FLASH_SECTOR_SIZE);
+        let failures = commands
+            .verify_with_progressive_crc_report(0x10000, &data, &ProgressBar::hidden())
+            .await
+            .unwrap();
+        drop(connection);
+        device.await.unwrap();
+
+        assert_eq!(failures.len(), 2);
+        assert_eq!(
+            failure_summary(&failures),
+            "0x00010000-0x00010FFF, 0x00012000-0x00012FFF"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_interleaved_write_retries_failed_sector() {
+        let data = vec![0x5A; 2 * FLASH_SECTOR_SIZE];
+        let (mut connection, device) = scripted_device(vec![
+            Status::Success,
+            Status::VerificationFailed,
+            Status::Success,
+        ]);
+        let result = FlashCommands::new(&mut connection)
+            .write_interleaved_with_progress(0x10000, &data, false, 1, &ProgressBar::hidden())
+            .await;
+        drop(connection);
+        let packets = device.await.unwrap();
+
+        result.unwrap();
+        assert_eq!(addresses(&packets, Command::Erase), [0x11000]);
+        assert_eq!(
+            addresses(&packets, Command::VerifyCRC),
+            [0x10000, 0x11000, 0x11000]
+        );
+
+        // Out of retries the write stops at the failing sector
+        let (mut connection, device) =
+            scripted_device(vec![Status::Success, Status::VerificationFailed]);
+        let error = FlashCommands::new(&mut connection)
+            .write_interleaved_with_progress(0x10000, &data, false, 0, &ProgressBar::hidden())
+            .await
+            .unwrap_err();
+        drop(connection);
+        device.await.unwrap();
+        assert!(error
+            .to_string()
+            .contains("stopped with 0x1000 of 0x2000 bytes written"));
+    }
 }
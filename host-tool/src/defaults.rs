@@ -0,0 +1,205 @@
+//! Defaults for the global options from `flash-programmer.toml`
+//!
+//! The first file found is used: `./flash-programmer.toml`, then
+//! `$XDG_CONFIG_HOME/flash-programmer.toml` (`~/.config/` if unset). Keys
+//! are global option names without the dashes:
+//!
+//! ```toml
+//! port = "/dev/ttyACM1"
+//! connect-timeout = "10s"
+//! spi-mode = 3
+//! crc16 = true
+//! ```
+//!
+//! The command line is parsed first; options it leaves out (and that no
+//! `FLASH_PROGRAMMER_*` environment variable sets) are then taken from the
+//! file and everything is parsed again, so values are checked exactly like
+//! the flags they stand for. A file value is dropped when it conflicts with
+//! an option given on the command line (`quiet = true` loses to
+//! `--verbose`), and a flag the file turns on can be turned off with
+//! `--no-<flag>`.
+
+use anyhow::{bail, Context, Result};
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, ArgMatches};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+pub const CONFIG_FILE_NAME: &str = "flash-programmer.toml";
+
+/// Where a config file is looked for, most specific first
+pub fn search_paths() -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::from(CONFIG_FILE_NAME)];
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+    if let Some(dir) = config_dir {
+        paths.push(dir.join(CONFIG_FILE_NAME));
+    }
+    paths
+}
+
+/// Option values from the first config file in `paths`, as (argument id,
+/// values) pairs; empty if there is none
+pub fn load(paths: &[PathBuf]) -> Result<Vec<(String, Vec<String>)>> {
+    let Some(path) = paths.iter().find(|path| path.is_file()) else {
+        return Ok(Vec::new());
+    };
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config file: {:?}", path))?;
+    parse(&text).with_context(|| format!("Invalid config file: {:?}", path))
+}
+
+fn parse(text: &str) -> Result<Vec<(String, Vec<String>)>> {
+    let table: toml::Table = text.parse()?;
+    table
+        .into_iter()
+        .map(|(key, value)| {
+            let values = match value {
+                toml::Value::Array(items) => items
+                    .into_iter()
+                    .map(|item| scalar(&key, item))
+                    .collect::<Result<_>>()?,
+                value => vec![scalar(&key, value)?],
+            };
+            Ok((key.replace('-', "_"), values))
+        })
+        .collect()
+}
+
+fn scalar(key: &str, value: toml::Value) -> Result<String> {
+    Ok(match value {
+        toml::Value::String(text) => text,
+        toml::Value::Integer(number) => number.to_string(),
+        toml::Value::Boolean(flag) => flag.to_string(),
+        other => bail!(
+            "`{}` must be a string, number or boolean, not {}",
+            key,
+            other
+        ),
+    })
+}
+
+/// Check that `values` only names global options of `command`, and add a
+/// `--no-<flag>` switch for each flag among them
+pub fn apply(
+    mut command: clap::Command,
+    values: &[(String, Vec<String>)],
+) -> Result<clap::Command> {
+    for (id, _) in values {
+        let Some(arg) = command.get_arguments().find(|arg| {
+            arg.get_id() == id.as_str()
+                && !arg.is_positional()
+                && !matches!(id.as_str(), "help" | "version")
+        }) else {
+            bail!(
+                "Unknown option `{}` in {}",
+                id.replace('_', "-"),
+                CONFIG_FILE_NAME
+            );
+        };
+        if is_flag(arg) {
+            let long = long_name(arg);
+            let switch = Arg::new(format!("no_{}", id))
+                .long(format!("no-{}", long))
+                .action(ArgAction::SetTrue)
+                .help(format!("Ignore `{}` from {}", long, CONFIG_FILE_NAME));
+            command = command.arg(switch);
+        }
+    }
+    Ok(command)
+}
+
+/// Parse `args` with `command` (from [`apply`]), taking the options they
+/// leave out from `values`
+pub fn get_matches_from<I, T>(
+    command: &clap::Command,
+    args: I,
+    values: &[(String, Vec<String>)],
+) -> clap::error::Result<ArgMatches>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString>,
+{
+    let args: Vec<OsString> = args.into_iter().map(Into::into).collect();
+    let matches = command.clone().try_get_matches_from(&args)?;
+    let given = |id: &str| {
+        matches!(
+            matches.value_source(id),
+            Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+        )
+    };
+
+    let mut config_args = Vec::new();
+    for (id, values) in values {
+        let Some(arg) = command
+            .get_arguments()
+            .find(|arg| arg.get_id() == id.as_str())
+        else {
+            continue;
+        };
+        let conflicts = command.get_arguments().any(|other| {
+            given(other.get_id().as_str())
+                && (command.get_arg_conflicts_with(arg).contains(&other)
+                    || command.get_arg_conflicts_with(other).contains(&arg))
+        });
+        let switched_off = is_flag(arg) && given(&format!("no_{}", id));
+        if given(id) || switched_off || conflicts {
+            continue;
+        }
+
+        let long = long_name(arg);
+        if is_flag(arg) && values.len() == 1 {
+            match values[0].as_str() {
+                "true" => config_args.push(OsString::from(format!("--{}", long))),
+                "false" => {}
+                // Let clap reject the value
+                value => config_args.push(format!("--{}={}", long, value).into()),
+            }
+        } else {
+            config_args.extend(
+                values
+                    .iter()
+                    .map(|value| OsString::from(format!("--{}={}", long, value))),
+            );
+        }
+    }
+    if config_args.is_empty() {
+        return Ok(matches);
+    }
+
+    // Options go before the subcommand, right after the program name
+    let mut merged = args;
+    let at = merged.len().min(1);
+    merged.splice(at..at, config_args);
+    command.clone().try_get_matches_from(merged)
+}
+
+fn is_flag(arg: &Arg) -> bool {
+    matches!(arg.get_action(), ArgAction::SetTrue)
+}
+
+fn long_name(arg: &Arg) -> String {
+    arg.get_long()
+        .map(str::to_string)
+        .unwrap_or_else(|| arg.get_id().as_str().replace('_', "-"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_maps_keys_to_argument_ids() {
+        let values =
+            parse("port = \"/dev/ttyACM1\"\nspi-mode = 3\ncrc16 = true\nports = [\"a\", \"b\"]\n")
+                .unwrap();
+        assert!(values.contains(&("port".into(), vec!["/dev/ttyACM1".into()])));
+        assert!(values.contains(&("spi_mode".into(), vec!["3".into()])));
+        assert!(values.contains(&("crc16".into(), vec!["true".into()])));
+        assert!(values.contains(&("ports".into(), vec!["a".into(), "b".into()])));
+
+        assert!(parse("port = 1.5").is_err());
+        assert!(parse("port = ").is_err());
+    }
+}
@@ -0,0 +1,81 @@
+//! Running one command on several programmers at once (`--ports`, `--all`)
+//!
+//! Each device gets its own connection and tokio task. The task-local
+//! [`DEVICE`] context lets logging prefix lines with the port and puts each
+//! device's progress bars in one [`MultiProgress`].
+
+use anyhow::{Context, Result};
+use indicatif::MultiProgress;
+use tokio_serial::SerialPortType;
+
+/// USB IDs the programmer firmware enumerates with
+pub const PROGRAMMER_VID: u16 = 0xC0DE;
+pub const PROGRAMMER_PID: u16 = 0xCAFE;
+
+/// The device a task is working on
+#[derive(Clone)]
+pub struct DeviceContext {
+    pub port: String,
+    pub progress: MultiProgress,
+}
+
+tokio::task_local! {
+    pub static DEVICE: DeviceContext;
+}
+
+/// Port of the device the current task is working on, in multi-device runs
+pub fn current_port() -> Option<String> {
+    DEVICE.try_with(|device| device.port.clone()).ok()
+}
+
+/// Serial ports of every connected programmer, sorted by name
+pub fn find_programmers() -> Result<Vec<String>> {
+    let mut ports: Vec<String> = tokio_serial::available_ports()
+        .context("Failed to list serial ports")?
+        .into_iter()
+        .filter(|port| {
+            matches!(&port.port_type, SerialPortType::UsbPort(usb)
+                if usb.vid == PROGRAMMER_VID && usb.pid == PROGRAMMER_PID)
+        })
+        .map(|port| port.port_name)
+        .collect();
+    ports.sort();
+    Ok(ports)
+}
+
+/// One summary line per device, and how many failed
+pub fn summarize(results: &[(String, Result<()>)]) -> (Vec<String>, usize) {
+    let lines = results
+        .iter()
+        .map(|(port, result)| match result {
+            Ok(()) => format!("  {}: PASS", port),
+            Err(e) => format!("  {}: FAIL ({:#})", port, e),
+        })
+        .collect();
+    let failed = results.iter().filter(|(_, result)| result.is_err()).count();
+    (lines, failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn test_summary_reports_each_device() {
+        let results = vec![
+            ("/dev/ttyACM0".to_string(), Ok(())),
+            (
+                "/dev/ttyACM1".to_string(),
+                Err(anyhow!("Verify failed").context("Write failed")),
+            ),
+        ];
+        let (lines, failed) = summarize(&results);
+        assert_eq!(failed, 1);
+        assert_eq!(lines[0], "  /dev/ttyACM0: PASS");
+        assert_eq!(
+            lines[1],
+            "  /dev/ttyACM1: FAIL (Write failed: Verify failed)"
+        );
+    }
+}
@@ -0,0 +1,59 @@
+//! Expected digests for `verify-hash`
+//!
+//! A `.sha256` file is what `sha256sum image.bin > image.bin.sha256` writes:
+//! the hex digest followed by the file name. A file holding only the hex
+//! digest is accepted too.
+
+use anyhow::{Context, Result};
+
+/// Bytes in a SHA256 digest
+pub const SHA256_SIZE: usize = 32;
+
+/// Read the single SHA256 digest in the text of a `.sha256` file
+pub fn parse_sha256_file(text: &str) -> Result<[u8; SHA256_SIZE]> {
+    let mut digests = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_whitespace().next());
+    let digest = digests.next().context("No SHA256 digest in hash file")?;
+    if digests.next().is_some() {
+        anyhow::bail!("Hash file lists several digests; give one with a single entry");
+    }
+
+    let bytes =
+        hex::decode(digest).with_context(|| format!("'{}' is not a hex SHA256 digest", digest))?;
+    bytes.try_into().map_err(|bytes: Vec<u8>| {
+        anyhow::anyhow!(
+            "SHA256 digest must be {} bytes, got {}",
+            SHA256_SIZE,
+            bytes.len()
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EMPTY_SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    #[test]
+    fn test_parses_sha256sum_output_and_bare_digest() {
+        let expected: [u8; SHA256_SIZE] = hex::decode(EMPTY_SHA256).unwrap().try_into().unwrap();
+        let sha256sum = format!("{}  empty.bin\n", EMPTY_SHA256);
+        assert_eq!(parse_sha256_file(&sha256sum).unwrap(), expected);
+        let binary_mode = format!("{} *empty.bin\n", EMPTY_SHA256.to_uppercase());
+        assert_eq!(parse_sha256_file(&binary_mode).unwrap(), expected);
+        assert_eq!(parse_sha256_file(EMPTY_SHA256).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_rejects_bad_hash_files() {
+        assert!(parse_sha256_file("").is_err());
+        assert!(parse_sha256_file("not-hex  image.bin").is_err());
+        assert!(parse_sha256_file(&EMPTY_SHA256[..62]).is_err());
+        let two = format!("{0}  a.bin\n{0}  b.bin\n", EMPTY_SHA256);
+        assert!(parse_sha256_file(&two).is_err());
+    }
+}
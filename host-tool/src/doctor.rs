@@ -0,0 +1,142 @@
+//! SPI link checks for `doctor`
+//!
+//! A marginal SPI link (long or loose wires, a clock too fast for them)
+//! flips the odd bit, which otherwise surfaces as random verify failures.
+//! `doctor` instead reads values that never change several times: the JEDEC
+//! ID must come back identical every time, and so must the SFDP header,
+//! starting with the "SFDP" signature.
+
+use flash_protocol::geometry::SFDP_SIGNATURE;
+use std::fmt;
+
+/// Reads of each value by default
+pub const DEFAULT_READS: u32 = 16;
+
+/// Outcome of one check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Finding {
+    Ok(String),
+    /// Couldn't be checked (older firmware, chip without SFDP); not a failure
+    Skipped(String),
+    Problem(String),
+}
+
+impl Finding {
+    pub fn is_problem(&self) -> bool {
+        matches!(self, Finding::Problem(_))
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Finding::Ok(message) => write!(f, "  [ok]   {}", message),
+            Finding::Skipped(message) => write!(f, "  [skip] {}", message),
+            Finding::Problem(message) => write!(f, "  [FAIL] {}", message),
+        }
+    }
+}
+
+/// Distinct values in `values` with how often each was read, most common first
+fn tally<T: PartialEq + Clone>(values: &[T]) -> Vec<(T, usize)> {
+    let mut counts: Vec<(T, usize)> = Vec::new();
+    for value in values {
+        match counts.iter_mut().find(|(seen, _)| seen == value) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((value.clone(), 1)),
+        }
+    }
+    counts.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+    counts
+}
+
+/// Check that repeated JEDEC ID reads agree and look like a chip answered
+pub fn check_jedec_ids(ids: &[u32]) -> Finding {
+    match tally(ids).as_slice() {
+        [] => Finding::Skipped("JEDEC ID not read".into()),
+        [(id @ (0x000000 | 0xFFFFFF), _)] => Finding::Problem(format!(
+            "JEDEC ID reads 0x{:06X} every time: no chip answering (check MISO and CS)",
+            id
+        )),
+        [(id, _)] => Finding::Ok(format!(
+            "JEDEC ID 0x{:06X} identical on all {} reads",
+            id,
+            ids.len()
+        )),
+        counts => Finding::Problem(format!(
+            "JEDEC ID changed between reads: {}",
+            counts
+                .iter()
+                .map(|(id, count)| format!("0x{:06X} x{}", id, count))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+/// Check that repeated reads of the SFDP header agree and carry the signature
+pub fn check_sfdp_headers(headers: &[Vec<u8>]) -> Finding {
+    let counts = tally(headers);
+    let header = match counts.as_slice() {
+        [] => return Finding::Skipped("SFDP header not read".into()),
+        [(header, _)] => header,
+        counts => {
+            return Finding::Problem(format!(
+                "SFDP header changed between reads ({} different values in {} reads)",
+                counts.len(),
+                headers.len()
+            ))
+        }
+    };
+    if header.iter().all(|&b| b == 0xFF) {
+        return Finding::Skipped("SFDP reads all 0xFF: the chip has no SFDP table".into());
+    }
+    match header.get(..6) {
+        Some(&[a, b, c, d, minor, major]) if u32::from_le_bytes([a, b, c, d]) == SFDP_SIGNATURE => {
+            Finding::Ok(format!(
+                "SFDP signature (revision {}.{}) identical on all {} reads",
+                major,
+                minor,
+                headers.len()
+            ))
+        }
+        _ => Finding::Problem(format!(
+            "SFDP header starts {:02X?} instead of \"SFDP\"",
+            &header[..header.len().min(4)]
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_jedec_ids_must_agree() {
+        assert!(matches!(check_jedec_ids(&[0xEF4018; 8]), Finding::Ok(_)));
+        assert!(check_jedec_ids(&[0xFFFFFF; 8]).is_problem());
+        assert_eq!(
+            check_jedec_ids(&[0xEF4018, 0xEF4018, 0xEF4818, 0xEF4018]),
+            Finding::Problem("JEDEC ID changed between reads: 0xEF4018 x3, 0xEF4818 x1".into())
+        );
+    }
+
+    #[test]
+    fn test_sfdp_headers_need_signature() {
+        let mut header = b"SFDP\x06\x01\x01\xFF".to_vec();
+        header.extend_from_slice(&[0; 8]);
+        assert_eq!(
+            check_sfdp_headers(&[header.clone(), header.clone()]),
+            Finding::Ok("SFDP signature (revision 1.6) identical on all 2 reads".into())
+        );
+
+        let mut flipped = header.clone();
+        flipped[1] ^= 0x10;
+        assert!(check_sfdp_headers(&[header, flipped.clone()]).is_problem());
+        assert!(check_sfdp_headers(&[flipped]).is_problem());
+        assert!(matches!(
+            check_sfdp_headers(&[vec![0xFF; 16]]),
+            Finding::Skipped(_)
+        ));
+    }
+}
@@ -1,44 +1,183 @@
 use anyhow::{Context, Result};
-use clap::{Parser, Subcommand};
-use indicatif::{ProgressBar, ProgressStyle};
-use std::path::PathBuf;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use flash_protocol::asset_pack::{self, ASSET_TABLE_ADDR};
+use flash_protocol::capabilities::Capabilities;
+use flash_protocol::geometry::{FlashGeometry, SFDP_HEADER_SIZE};
+use flash_protocol::pattern::TestPattern;
+use flash_protocol::segments::{find_overlap, Segment};
+use flash_protocol::{
+    jedec, Command, CrcMode, ImageFooter, SpiMode, FLASH_SECTOR_SIZE, IMAGE_FOOTER_SIZE,
+};
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use log::{info, warn, LevelFilter};
+use std::io::Write as _;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 use tokio::fs;
+use tokio::io::AsyncWriteExt;
 use tokio::time::timeout;
 
+mod bitcheck;
+mod blank_scan;
 mod commands;
+mod defaults;
+mod devices;
+mod digest;
+mod doctor;
+mod make_font;
+mod preserve;
+mod progress;
+mod read_resume;
+mod replay;
 mod serial;
+mod sparse;
+mod tile;
+mod tune;
+mod wear_test;
+mod write_map;
 
-use commands::FlashCommands;
-use serial::SerialConnection;
+use bitcheck::{BitReport, BITCHECK_SIZE};
+use blank_scan::BlankScan;
+use commands::{failure_summary, FlashCommands, DEFAULT_VERIFY_BLOCK_SIZE, MAX_READ_CHUNK_SIZE};
+use doctor::Finding;
+use progress::ProgressFormat;
+use read_resume::ReadProgress;
+use serial::{SerialConnection, DEFAULT_MAX_CONSECUTIVE_ERRORS};
+use sparse::SparseImage;
+use tile::Tiling;
+use tune::SpeedResult;
+use wear_test::Phase;
+use write_map::WriteMap;
 
-#[derive(Parser)]
+#[derive(Parser, Clone)]
 #[command(name = "flash-programmer")]
 #[command(about = "STM32G4 Flash Programmer Tool")]
 #[command(version = "0.1.0")]
 struct Cli {
     /// Serial port to connect to
-    #[arg(short, long, default_value = "/dev/ttyACM0")]
+    #[arg(
+        short,
+        long,
+        env = "FLASH_PROGRAMMER_PORT",
+        default_value = "/dev/ttyACM0"
+    )]
     port: String,
 
+    /// Run the command on each of these ports concurrently (comma-separated)
+    /// and report pass/fail per device
+    #[arg(long, value_delimiter = ',', value_name = "PORTS", conflicts_with_all = ["all", "trace_file"])]
+    ports: Vec<String>,
+
+    /// Run the command concurrently on every connected programmer (USB ID
+    /// c0de:cafe) and report pass/fail per device
+    #[arg(long, conflicts_with = "trace_file")]
+    all: bool,
+
     /// Baud rate (ignored for USB CDC, but kept for compatibility)
     #[arg(short, long, default_value = "115200")]
     baud: u32,
 
-    /// Connection timeout in seconds
-    #[arg(short, long, default_value = "10")]
-    timeout: u64,
+    /// Maximum wait for the port to open and the programmer to answer the
+    /// handshake (e.g. 5, 10s, 500ms; bare numbers are seconds). Kept short
+    /// so a wrong port fails fast; commands use --response-timeout
+    #[arg(
+        long,
+        env = "FLASH_PROGRAMMER_CONNECT_TIMEOUT",
+        value_parser = parse_duration,
+        default_value = "5s"
+    )]
+    connect_timeout: Duration,
+
+    /// Maximum wait for each device response (e.g. 30s, 5m for large erases).
+    /// `-t/--timeout` are accepted for older scripts
+    #[arg(
+        short = 't',
+        long,
+        alias = "timeout",
+        env = "FLASH_PROGRAMMER_RESPONSE_TIMEOUT",
+        value_parser = parse_duration,
+        default_value = "30s"
+    )]
+    response_timeout: Duration,
+
+    /// Give up once this many responses in a row time out or fail their CRC
+    /// (each failure resends the command)
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..), default_value_t = DEFAULT_MAX_CONSECUTIVE_ERRORS, value_name = "N")]
+    max_consecutive_errors: u32,
+
+    /// SPI mode to switch the programmer to before running the command (0 or 3)
+    #[arg(long, env = "FLASH_PROGRAMMER_SPI_MODE", value_parser = parse_spi_mode)]
+    spi_mode: Option<SpiMode>,
+
+    /// Block size for progressive CRC verification (hex supported; multiple of 4KB, at most 1MB).
+    /// Smaller blocks localize failures, larger blocks verify faster
+    #[arg(long, value_parser = parse_verify_block_size, default_value_t = DEFAULT_VERIFY_BLOCK_SIZE)]
+    verify_block_size: usize,
+
+    /// Bytes per read during read-back verification (hex supported).
+    /// Default: the largest read the firmware reports, or 256 if it can't
+    #[arg(long, value_parser = parse_read_chunk_size, value_name = "BYTES")]
+    read_chunk_size: Option<usize>,
+
+    /// Pause between sector erases and avoid chip erase, so boards on weak
+    /// USB power don't brown out (erases take about twice as long)
+    #[arg(long)]
+    power_safe: bool,
+
+    /// Proceed with erase/write on an unrecognized flash chip
+    #[arg(long)]
+    force: bool,
+
+    /// Abort before running the command unless the chip reports this 24-bit JEDEC ID (hex)
+    #[arg(long, value_parser = parse_jedec_id, value_name = "ID")]
+    expected_jedec: Option<u32>,
+
+    /// Only print errors and command results (no progress or status chatter)
+    #[arg(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Print debug output
+    #[arg(long)]
+    verbose: bool,
+
+    /// How to show progress: redrawn bars, a plain line every 10% for logs,
+    /// or nothing. `auto` uses bars on a terminal and plain lines otherwise
+    #[arg(long, value_enum, default_value_t = ProgressFormat::Auto, value_name = "FORMAT")]
+    progress_format: ProgressFormat,
+
+    /// Log every packet sent and response received (timestamped, hex) to this file
+    #[arg(long, value_name = "PATH")]
+    trace_file: Option<PathBuf>,
+
+    /// Negotiate 2-byte CRC-16 packet trailers instead of CRC-32 (firmware
+    /// without support keeps CRC-32)
+    #[arg(long, env = "FLASH_PROGRAMMER_CRC16")]
+    crc16: bool,
 
     #[command(subcommand)]
     command: Commands,
 }
 
-#[derive(Subcommand)]
+#[derive(Subcommand, Clone)]
 enum Commands {
     /// Get flash information
     Info,
     /// Read flash status register
     Status,
+    /// Show the programmer's runtime configuration (SPI mode/clock, blank check, payload size)
+    Config,
+    /// Show the programmer's error counters since boot (dropped packets, CRC
+    /// errors, buffer overflows, flash errors)
+    Stats {
+        /// Reset the counters to zero after showing them
+        #[arg(long)]
+        clear: bool,
+    },
+    /// Reboot the programmer into the STM32 system USB DFU bootloader to
+    /// update its firmware
+    EnterDfu,
     /// Erase flash sectors
     Erase {
         /// Start address (hex)
@@ -47,6 +186,10 @@ enum Commands {
         /// Size to erase in bytes (hex)
         #[arg(short, long, value_parser = parse_hex)]
         size: u32,
+        /// Keep this region (ADDR:SIZE, hex) intact: it is read before the erase
+        /// and programmed back after. Repeatable
+        #[arg(long, value_parser = parse_region, value_name = "ADDR:SIZE")]
+        preserve: Vec<Segment>,
     },
     /// Write file to flash
     Write {
@@ -59,12 +202,62 @@ enum Commands {
         /// Erase before writing
         #[arg(short, long)]
         erase: bool,
-        /// Verify after writing
-        #[arg(short, long)]
+        /// Verify after writing (the default; kept for existing scripts)
+        #[arg(short, long, hide = true, conflicts_with = "no_verify")]
         verify: bool,
+        /// Skip progressive CRC verification after writing
+        #[arg(long)]
+        no_verify: bool,
         /// Use basic write command instead of stream write
         #[arg(short, long)]
         basic: bool,
+        /// Re-erase and rewrite blocks that fail verification up to N times
+        #[arg(long, default_value_t = 0, conflicts_with = "no_verify")]
+        retries: u32,
+        /// When to verify: after the whole write, or each sector right after
+        /// writing it (stops at the first bad sector)
+        #[arg(
+            long,
+            value_enum,
+            default_value = "final",
+            conflicts_with = "no_verify"
+        )]
+        verify_mode: VerifyMode,
+        /// Skip this many bytes at the start of the file (hex)
+        #[arg(long, value_parser = parse_hex, default_value = "0")]
+        skip: u32,
+        /// Write only this many bytes of the file, after --skip (hex; default: the rest)
+        #[arg(long, value_parser = parse_hex)]
+        count: Option<u32>,
+        /// Write the data this many times back to back
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..), value_name = "COUNT", conflicts_with_all = ["repeat_to", "skip_if_current"])]
+        repeat: Option<u32>,
+        /// Write the data repeatedly up to this address (hex, exclusive),
+        /// cutting the last copy short if it doesn't fit
+        #[arg(long, value_parser = parse_hex, value_name = "END", conflicts_with = "skip_if_current")]
+        repeat_to: Option<u32>,
+        /// Write a footer (magic, length, CRC32) after the data so the
+        /// target can check its image at boot
+        #[arg(long, conflicts_with_all = ["repeat", "repeat_to"])]
+        append_footer: bool,
+        /// Record the written segment (address, length, CRC32) in this JSON file
+        #[arg(long, value_name = "PATH")]
+        map_file: Option<PathBuf>,
+        /// Keep this region (ADDR:SIZE, hex) intact when --erase clears its
+        /// sector; it must not overlap the written data. Repeatable
+        #[arg(long, value_parser = parse_region, value_name = "ADDR:SIZE")]
+        preserve: Vec<Segment>,
+        /// What --erase does with bytes that share a sector with the data
+        #[arg(long, value_enum, default_value = "sectors", requires = "erase")]
+        erase_mode: EraseMode,
+        /// Compare per-sector CRCs with the device first and skip the erase
+        /// and write if they all match
+        #[arg(long)]
+        skip_if_current: bool,
+        /// Input layout; a sparse image (from `read --format sparse`) is
+        /// written at its own addresses and --erase clears its whole range
+        #[arg(long, value_enum, default_value = "raw")]
+        format: ImageFormat,
     },
     /// Read flash to file
     Read {
@@ -74,9 +267,28 @@ enum Commands {
         /// Start address (hex)
         #[arg(short, long, value_parser = parse_hex, default_value = "0")]
         address: u32,
-        /// Size to read in bytes (hex)
-        #[arg(short, long, value_parser = parse_hex)]
-        size: u32,
+        /// Size to read in bytes (hex); with --until-blank, the most to read
+        /// (default: to the end of flash)
+        #[arg(short, long, value_parser = parse_hex, required_unless_present = "until_blank")]
+        size: Option<u32>,
+        /// Append to the output file, resuming from the offset recorded in <file>.offset
+        #[arg(long)]
+        append: bool,
+        /// Stop after this many consecutive all-0xFF 4KB sectors and save only
+        /// the data before them
+        #[arg(
+            long,
+            value_name = "SECTORS",
+            num_args = 0..=1,
+            default_missing_value = "4",
+            value_parser = clap::value_parser!(u32).range(1..),
+            conflicts_with = "append"
+        )]
+        until_blank: Option<u32>,
+        /// Output layout; sparse leaves out the blank (all 0xFF) 4KB sectors
+        /// so dumps of a mostly erased chip stay small and diff cleanly
+        #[arg(long, value_enum, default_value = "raw")]
+        format: ImageFormat,
     },
     /// Verify file against flash
     Verify {
@@ -87,6 +299,187 @@ enum Commands {
         #[arg(short, long, value_parser = parse_hex, default_value = "0")]
         address: u32,
     },
+    /// Write a generated RGB565 test pattern to flash
+    Pattern {
+        /// Pattern to generate
+        #[arg(short, long, value_enum, default_value = "checkerboard")]
+        pattern: PatternKind,
+        /// Start address (hex)
+        #[arg(short, long, value_parser = parse_hex, default_value = "0")]
+        address: u32,
+        /// Frame width in pixels
+        #[arg(long, default_value = "320")]
+        width: u16,
+        /// Frame height in pixels
+        #[arg(long, default_value = "172")]
+        height: u16,
+        /// Checkerboard square size in pixels
+        #[arg(long, default_value = "20")]
+        square: u16,
+        /// Foreground/solid color (RGB565, hex)
+        #[arg(long, value_parser = parse_rgb565, default_value = "0xFFFF")]
+        color: u16,
+        /// Checkerboard background color (RGB565, hex)
+        #[arg(long, value_parser = parse_rgb565, default_value = "0x0000")]
+        background: u16,
+        /// Erase before writing
+        #[arg(short, long)]
+        erase: bool,
+    },
+    /// Program every asset in an asset pack and write the asset table
+    Assets {
+        /// Asset pack file
+        pack: PathBuf,
+        /// Erase each asset region and the asset table sector before writing
+        #[arg(short, long)]
+        erase: bool,
+        /// Verify each asset after writing
+        #[arg(short, long)]
+        verify: bool,
+        /// Record every written asset and the asset table in this JSON file
+        #[arg(long, value_name = "PATH")]
+        map_file: Option<PathBuf>,
+    },
+    /// Read back a flash region and check its SHA256 against a digest file
+    /// (e.g. from `sha256sum`), without the original image
+    VerifyHash {
+        /// File with the expected SHA256 digest
+        hashfile: PathBuf,
+        /// Start address (hex)
+        #[arg(short, long, value_parser = parse_hex, default_value = "0")]
+        address: u32,
+        /// Size of the hashed image in bytes (hex)
+        #[arg(short, long, value_parser = parse_hex)]
+        size: u32,
+    },
+    /// Read back every segment recorded in a --map-file and check its CRC32
+    VerifyMap {
+        /// Map file written by `write --map-file` or `assets --map-file`
+        map: PathBuf,
+    },
+    /// Check SPI data lines by writing and reading back walking-ones/zeros
+    /// (erases the 4KB sector at the address)
+    Bitcheck {
+        /// Sector-aligned scratch address (hex)
+        #[arg(short, long, value_parser = parse_hex)]
+        address: u32,
+    },
+    /// Check the SPI link to the flash chip by reading values that never
+    /// change (JEDEC ID, SFDP header) repeatedly; read-only
+    Doctor {
+        /// Times to read each value
+        #[arg(short, long, value_parser = clap::value_parser!(u32).range(1..), default_value_t = doctor::DEFAULT_READS)]
+        reads: u32,
+    },
+    /// Erase, program and verify a scratch sector repeatedly to find a worn
+    /// sector (every cycle uses up part of the sector's endurance)
+    WearTest {
+        /// Sector-aligned scratch address (hex)
+        #[arg(short, long, value_parser = parse_hex)]
+        address: u32,
+        /// Program/erase cycles to run
+        #[arg(short, long, value_parser = clap::value_parser!(u32).range(1..), default_value_t = 100)]
+        cycles: u32,
+        /// Confirm that the scratch sector may be worn out
+        #[arg(long = "i-know-this-wears-flash")]
+        wear_confirmed: bool,
+    },
+    /// Find the fastest SPI clock that still writes and reads back cleanly
+    /// (erases the scratch region at the address)
+    Tune {
+        /// Sector-aligned scratch address (hex)
+        #[arg(short, long, value_parser = parse_hex)]
+        address: u32,
+        /// Bytes written and read back at each speed (hex; whole sectors)
+        #[arg(short, long, value_parser = parse_tune_size, default_value = "0x10000")]
+        size: u32,
+        /// SPI clocks to try in MHz; tried slowest first
+        #[arg(long, value_delimiter = ',', default_value = tune::DEFAULT_SPEEDS_MHZ)]
+        speeds: Vec<u32>,
+    },
+    /// Rasterize a BDF or TrueType/OpenType font into the firmware's font
+    /// layout, saving it and/or programming it
+    MakeFont {
+        /// BDF, TTF or OTF font file
+        font: PathBuf,
+        /// Pixel size to rasterize TrueType/OpenType fonts at (BDF fonts keep their own)
+        #[arg(long, default_value_t = 16.0)]
+        size: f32,
+        /// Characters to include besides printable ASCII
+        #[arg(long, value_name = "TEXT")]
+        chars: Option<String>,
+        /// Also include every character in this UTF-8 text file
+        #[arg(long, value_name = "PATH")]
+        chars_file: Option<PathBuf>,
+        /// Keep the TrueType/OpenType kerning between the included characters
+        #[arg(long)]
+        kerning: bool,
+        /// Save the font image to this file
+        #[arg(short, long, required_unless_present = "flash")]
+        output: Option<PathBuf>,
+        /// Program the font into flash at --address
+        #[arg(long)]
+        flash: bool,
+        /// Font base address (hex); the display example reads its 12px font at
+        /// 0x20000 and its 16px font at 0x120000
+        #[arg(short, long, value_parser = parse_hex, default_value = "0x20000")]
+        address: u32,
+        /// Erase the font's sectors before programming
+        #[arg(short, long, requires = "flash")]
+        erase: bool,
+    },
+    /// Re-send the packets recorded with --trace-file, reporting responses
+    /// that differ from the recording
+    Replay {
+        /// Trace file to replay
+        trace: PathBuf,
+        /// Send each packet as soon as the previous one is answered instead of
+        /// keeping the recorded gaps
+        #[arg(long)]
+        no_delay: bool,
+    },
+    /// Print a shell completion script (e.g. `completions zsh > _flash-programmer-tool`)
+    #[command(hide = true)]
+    Completions {
+        /// Shell to generate completions for
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+}
+
+/// Handling of partially written sectors with `write --erase`
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum EraseMode {
+    /// Erase whole sectors, losing their bytes outside the data (warns)
+    Sectors,
+    /// Read those bytes first and program them back after the write
+    Preserve,
+}
+
+/// File layout for `read` and `write`
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ImageFormat {
+    /// Every byte of the range, in address order
+    Raw,
+    /// Only the 4KB sectors holding data, each with its address
+    #[value(alias = "raw-with-gaps")]
+    Sparse,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum VerifyMode {
+    /// Write everything, then verify it block by block (fastest when writes succeed)
+    Final,
+    /// Verify each sector as soon as it is written, failing fast on a bad one
+    Interleaved,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum PatternKind {
+    Checkerboard,
+    ColorBars,
+    Gradient,
+    Solid,
 }
 
 fn parse_hex(s: &str) -> Result<u32, std::num::ParseIntError> {
@@ -97,215 +490,2126 @@ fn parse_hex(s: &str) -> Result<u32, std::num::ParseIntError> {
     }
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let cli = Cli::parse();
+/// Progress bar template for byte-oriented transfers
+const TRANSFER_TEMPLATE: &str =
+    "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})";
 
-    println!("STM32G4 Flash Programmer Tool v0.1.0");
-    println!("Connecting to {}...", cli.port);
+/// Progress bar template for `wear-test`, counted in program/erase cycles
+const CYCLE_TEMPLATE: &str =
+    "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} cycles ({eta}) {msg}";
 
-    // Connect to device
-    let mut connection = timeout(
-        Duration::from_secs(cli.timeout),
-        SerialConnection::new(&cli.port, cli.baud),
-    )
-    .await
-    .context("Connection timeout")?
-    .context("Failed to connect to device")?;
+/// Progress bar template for erases, counted in sectors
+const ERASE_TEMPLATE: &str =
+    "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} sectors ({eta}) {msg}";
 
-    println!("Connected successfully!");
+/// Set up leveled logging for status output; `RUST_LOG` still overrides
+fn init_logging(cli: &Cli) {
+    let level = if cli.quiet {
+        LevelFilter::Error
+    } else if cli.verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    };
 
-    // Create flash commands handler
-    let mut flash_commands = FlashCommands::new(&mut connection);
+    env_logger::Builder::new()
+        .filter_level(level)
+        .parse_default_env()
+        .format(|buf, record| match devices::current_port() {
+            Some(port) => writeln!(buf, "[{}] {}", port, record.args()),
+            None => writeln!(buf, "{}", record.args()),
+        })
+        .init();
+}
 
-    // Execute command
-    match cli.command {
-        Commands::Info => {
-            println!("Getting flash information...");
-            let info = flash_commands.get_info().await?;
-            println!("Flash Information:");
-            println!("  JEDEC ID: 0x{:06X}", info.jedec_id);
-            println!(
-                "  Total Size: {} MB ({} bytes)",
-                info.total_size / (1024 * 1024),
-                info.total_size
-            );
-            println!("  Page Size: {} bytes", info.page_size);
-            println!(
-                "  Sector Size: {} KB ({} bytes)",
-                info.sector_size / 1024,
-                info.sector_size
-            );
+/// Progress bar redraws per second
+///
+/// Transfers update the position after every chunk, thousands of times a
+/// second on a fast link; redrawing is capped so the terminal doesn't compete
+/// with the transfer for CPU.
+const PROGRESS_REFRESH_HZ: u8 = 15;
+
+/// Create a progress bar drawn in `progress_format` (resolved, never `Auto`)
+///
+/// In multi-device runs bars join the shared display and plain lines start
+/// with the port.
+fn new_progress_bar(len: u64, template: &str, progress_format: ProgressFormat) -> ProgressBar {
+    match progress_format {
+        ProgressFormat::None => return ProgressBar::hidden(),
+        ProgressFormat::Plain => {
+            return progress::plain_progress_bar(len, devices::current_port(), PROGRESS_REFRESH_HZ)
         }
+        ProgressFormat::Auto | ProgressFormat::Bar => {}
+    }
+    if let Ok((port, progress)) =
+        devices::DEVICE.try_with(|device| (device.port.clone(), device.progress.clone()))
+    {
+        let pb = progress.add(ProgressBar::new(len));
+        let template = format!("{{prefix:.bold}} {}", template);
+        pb.set_style(ProgressStyle::default_bar().template(&template).unwrap());
+        pb.set_prefix(port);
+        return pb;
+    }
+    let pb = ProgressBar::with_draw_target(
+        Some(len),
+        ProgressDrawTarget::stderr_with_hz(PROGRESS_REFRESH_HZ),
+    );
+    pb.set_style(ProgressStyle::default_bar().template(template).unwrap());
+    pb
+}
 
-        Commands::Status => {
-            println!("Reading flash status register...");
-            let status = flash_commands.read_status().await?;
+/// Read into `file` in segments, recording progress so an interrupted dump can resume
+async fn read_appending(
+    flash_commands: &mut FlashCommands<'_>,
+    file: &std::path::Path,
+    address: u32,
+    size: u32,
+    progress_format: ProgressFormat,
+) -> Result<()> {
+    /// Bytes read between sidecar updates
+    const SEGMENT_SIZE: u32 = 64 * 1024;
 
-            println!("Flash Status Register: 0x{:02X}", status);
-            println!(
-                "  Write In Progress (WIP): {}",
-                if status & 0x01 != 0 { "Yes" } else { "No" }
-            );
-            println!(
-                "  Write Enable Latch (WEL): {}",
-                if status & 0x02 != 0 { "Yes" } else { "No" }
-            );
-            println!(
-                "  Block Protect Bits (BP0-BP2): 0x{:01X}",
-                (status >> 2) & 0x07
-            );
-            println!(
-                "  Top/Bottom Protect (TB): {}",
-                if status & 0x20 != 0 { "Top" } else { "Bottom" }
-            );
-            println!(
-                "  Sector Protect (SEC): {}",
-                if status & 0x40 != 0 { "Yes" } else { "No" }
-            );
-            println!(
-                "  Status Register Protect (SRP0): {}",
-                if status & 0x80 != 0 { "Yes" } else { "No" }
-            );
-        }
+    let mut progress = ReadProgress::resume(file, address, size).await?;
+    if progress.offset > 0 {
+        info!(
+            "Resuming read at offset {} of {} bytes (0x{:08X})",
+            progress.offset,
+            size,
+            address + progress.offset
+        );
+    } else {
+        info!("Reading {} bytes from flash at 0x{:08X}...", size, address);
+    }
 
-        Commands::Erase { address, size } => {
-            println!(
-                "Erasing flash at 0x{:08X}, size: {} bytes...",
-                address, size
-            );
+    let mut output = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(file)
+        .await
+        .with_context(|| format!("Failed to open file: {:?}", file))?;
 
-            let pb = ProgressBar::new(1);
-            pb.set_style(
-                ProgressStyle::default_bar()
-                    .template("{spinner:.green} [{elapsed_precise}] {msg}")
-                    .unwrap(),
-            );
-            pb.set_message("Erasing...");
+    let pb = new_progress_bar(size as u64, TRANSFER_TEMPLATE, progress_format);
+    pb.set_position(progress.offset as u64);
 
-            flash_commands.erase(address, size).await?;
+    while progress.offset < size {
+        let segment = SEGMENT_SIZE.min(size - progress.offset);
+        // Buffer one segment so the file never holds a partial one the sidecar doesn't cover
+        let data = flash_commands
+            .read_with_progress(address + progress.offset, segment, &pb)
+            .await?;
 
-            pb.finish_with_message("Erase completed!");
-            println!("Flash erased successfully!");
+        output
+            .write_all(&data)
+            .await
+            .with_context(|| format!("Failed to write file: {:?}", file))?;
+        output.flush().await?;
+
+        progress.offset += segment;
+        progress.save(file).await?;
+    }
+
+    pb.finish_with_message("Read completed!");
+    ReadProgress::remove(file).await?;
+    info!("File saved successfully!");
+    Ok(())
+}
+
+/// Read up to `size` bytes, stopping at the first run of `threshold` blank
+/// sectors, and save only the used portion
+async fn read_until_blank(
+    flash_commands: &mut FlashCommands<'_>,
+    file: &std::path::Path,
+    address: u32,
+    size: u32,
+    threshold: u32,
+    progress_format: ProgressFormat,
+) -> Result<()> {
+    /// Bytes requested per read
+    const SEGMENT_SIZE: u32 = 64 * 1024;
+
+    info!(
+        "Reading up to {} bytes from flash at 0x{:08X} until {} blank sectors...",
+        size, address, threshold
+    );
+
+    let output = fs::File::create(file)
+        .await
+        .with_context(|| format!("Failed to create file: {:?}", file))?;
+    let mut output = tokio::io::BufWriter::new(output);
+
+    let pb = new_progress_bar(size as u64, TRANSFER_TEMPLATE, progress_format);
+    let mut scan = BlankScan::new(threshold);
+    let mut offset = 0;
+    'read: while offset < size {
+        let segment = SEGMENT_SIZE.min(size - offset);
+        let data = flash_commands
+            .read_with_progress(address + offset, segment, &pb)
+            .await?;
+        offset += segment;
+        output
+            .write_all(&data)
+            .await
+            .with_context(|| format!("Failed to write file: {:?}", file))?;
+
+        for sector in data.chunks(FLASH_SECTOR_SIZE) {
+            if scan.push_sector(sector) {
+                break 'read;
+            }
         }
+    }
+    output
+        .flush()
+        .await
+        .with_context(|| format!("Failed to write file: {:?}", file))?;
+    // Drop the blank tail, including blank sectors read before the run was long enough
+    output
+        .get_ref()
+        .set_len(scan.used_size())
+        .await
+        .with_context(|| format!("Failed to truncate file: {:?}", file))?;
 
-        Commands::Write {
-            file,
-            address,
-            erase,
-            verify,
-            basic,
-        } => {
-            println!("Reading file: {:?}", file);
-            let data = fs::read(&file)
-                .await
-                .with_context(|| format!("Failed to read file: {:?}", file))?;
+    pb.finish_with_message("Read completed!");
+    println!(
+        "Used size: {} bytes (0x{:X}), ends at 0x{:08X}",
+        scan.used_size(),
+        scan.used_size(),
+        address as u64 + scan.used_size()
+    );
+    info!("File saved successfully!");
+    Ok(())
+}
 
-            println!("File size: {} bytes", data.len());
+/// Read into a sparse image at `file`, leaving out blank sectors; with
+/// `until_blank`, stop like `read_until_blank` and end the range there
+async fn read_sparse(
+    flash_commands: &mut FlashCommands<'_>,
+    file: &std::path::Path,
+    address: u32,
+    size: u32,
+    until_blank: Option<u32>,
+    progress_format: ProgressFormat,
+) -> Result<()> {
+    /// Bytes requested per read
+    const SEGMENT_SIZE: u32 = 64 * 1024;
 
-            if erase {
-                println!(
-                    "Erasing flash at 0x{:08X}, size: {} bytes...",
-                    address,
-                    data.len()
-                );
-                flash_commands.erase(address, data.len() as u32).await?;
-                println!("Erase completed!");
+    info!(
+        "Reading {} bytes from flash at 0x{:08X} into a sparse image...",
+        size, address
+    );
+    let pb = new_progress_bar(size as u64, TRANSFER_TEMPLATE, progress_format);
+    let mut image = SparseImage::new(address);
+    let mut scan = until_blank.map(BlankScan::new);
+    let mut offset = 0;
+    'read: while offset < size {
+        let segment = SEGMENT_SIZE.min(size - offset);
+        let data = flash_commands
+            .read_with_progress(address + offset, segment, &pb)
+            .await?;
+        for (index, sector) in data.chunks(FLASH_SECTOR_SIZE).enumerate() {
+            image.push(
+                address + offset + (index * FLASH_SECTOR_SIZE) as u32,
+                sector,
+            );
+            if scan.as_mut().is_some_and(|scan| scan.push_sector(sector)) {
+                break 'read;
             }
+        }
+        offset += segment;
+    }
+    if let Some(scan) = scan {
+        image.range.length = scan.used_size() as u32;
+    }
 
-            println!("Writing to flash at 0x{:08X}...", address);
-            let pb = ProgressBar::new(data.len() as u64);
-            pb.set_style(ProgressStyle::default_bar()
-                .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})")
-                .unwrap());
+    fs::write(file, image.to_bytes())
+        .await
+        .with_context(|| format!("Failed to write file: {:?}", file))?;
+    pb.finish_with_message("Read completed!");
+    println!(
+        "Saved {} regions ({} bytes of data) covering {}",
+        image.regions.len(),
+        image.data_len(),
+        image.range
+    );
+    info!("File saved successfully!");
+    Ok(())
+}
 
-            if verify {
-                // Write first
-                if basic {
-                    flash_commands.write(address, &data).await?;
-                    pb.set_position(data.len() as u64);
-                } else {
-                    flash_commands
-                        .write_with_progress(address, &data, &pb)
-                        .await?;
-                }
-                pb.finish_with_message("Write completed!");
+/// Erase, write and read back `size` bytes at `address` at the current SPI
+/// clock; any failure (including a transfer error) fails the speed
+async fn tune_speed(
+    flash_commands: &mut FlashCommands<'_>,
+    address: u32,
+    size: u32,
+    hz: u32,
+    seed: u32,
+) -> SpeedResult {
+    let data = tune::pattern(size as usize, seed);
+    let progress = ProgressBar::hidden();
+    let mut result = SpeedResult {
+        hz,
+        write_rate: None,
+        read_rate: None,
+        passed: false,
+    };
+
+    if let Err(e) = flash_commands.erase(address, size).await {
+        warn!("Erase failed at {} Hz: {:#}", hz, e);
+        return result;
+    }
+    let start = std::time::Instant::now();
+    if let Err(e) = flash_commands
+        .stream_write_with_progress(address, &data, &progress)
+        .await
+    {
+        warn!("Write failed at {} Hz: {:#}", hz, e);
+        return result;
+    }
+    result.write_rate = Some(tune::rate(data.len(), start.elapsed()));
+
+    let start = std::time::Instant::now();
+    match flash_commands
+        .read_with_progress(address, size, &progress)
+        .await
+    {
+        Ok(actual) => {
+            result.read_rate = Some(tune::rate(actual.len(), start.elapsed()));
+            result.passed = actual == data;
+        }
+        Err(e) => warn!("Read failed at {} Hz: {:#}", hz, e),
+    }
+    result
+}
+
+/// Write `data` at `address` (stream or basic packets) and, unless `verify`
+/// is `None`, verify it with progressive CRC, rewriting failed blocks up to
+/// `retries` times
+async fn write_data(
+    flash_commands: &mut FlashCommands<'_>,
+    address: u32,
+    data: &[u8],
+    basic: bool,
+    verify: Option<VerifyMode>,
+    retries: u32,
+    progress_format: ProgressFormat,
+) -> Result<()> {
+    info!("Writing to flash at 0x{:08X}...", address);
+    let pb = new_progress_bar(data.len() as u64, TRANSFER_TEMPLATE, progress_format);
 
-                // Then verify using progressive CRC (fast and reliable verification)
-                println!("Verifying written data using progressive CRC32...");
+    match verify {
+        Some(VerifyMode::Final) => {
+            // Write first
+            if basic {
+                flash_commands.write(address, data).await?;
+                pb.set_position(data.len() as u64);
+            } else {
                 flash_commands
-                    .verify_with_progressive_crc(address, &data, &pb)
+                    .write_with_progress(address, data, &pb)
                     .await?;
-                pb.finish_with_message("Write and verification completed!");
-                println!("✅ Data written and verified successfully!");
+            }
+            pb.finish_with_message("Write completed!");
+
+            // Then verify using progressive CRC (fast and reliable verification),
+            // rewriting any failed blocks if retries were requested
+            info!("Verifying written data using progressive CRC32...");
+            flash_commands
+                .verify_and_repair(address, data, retries, &pb)
+                .await?;
+            pb.finish_with_message("Write and verification completed!");
+            info!("✅ Data written and verified successfully!");
+        }
+        Some(VerifyMode::Interleaved) => {
+            info!("Verifying each sector with CRC32 as it is written...");
+            flash_commands
+                .write_interleaved_with_progress(address, data, basic, retries, &pb)
+                .await?;
+            pb.finish_with_message("Write and verification completed!");
+            info!("✅ Data written and verified successfully!");
+        }
+        None => {
+            if basic {
+                // Use basic write command
+                info!("Using basic write command...");
+                flash_commands.write(address, data).await?;
+                pb.set_position(data.len() as u64);
+                pb.finish_with_message("Basic write completed!");
+                info!("✅ Data written successfully using basic write command!");
             } else {
-                if basic {
-                    // Use basic write command
-                    println!("Using basic write command...");
-                    flash_commands.write(address, &data).await?;
-                    pb.set_position(data.len() as u64);
-                    pb.finish_with_message("Basic write completed!");
-                    println!("✅ Data written successfully using basic write command!");
-                } else {
-                    // Use high-speed write only
-                    flash_commands
-                        .write_with_progress(address, &data, &pb)
-                        .await?;
-                    pb.finish_with_message("Write completed!");
-                    println!("✅ Data written successfully!");
-                }
-                println!("⚠️  Warning: Data was not verified. Use --verify flag to ensure data integrity.");
+                // Use high-speed write only
+                flash_commands
+                    .write_with_progress(address, data, &pb)
+                    .await?;
+                pb.finish_with_message("Write completed!");
+                info!("✅ Data written successfully!");
             }
+            warn!("⚠️  Warning: Data was not verified (--no-verify). Run `verify` to check it.");
         }
+    }
+    Ok(())
+}
 
-        Commands::Read {
-            file,
-            address,
-            size,
-        } => {
-            println!("Reading {} bytes from flash at 0x{:08X}...", size, address);
-
-            let pb = ProgressBar::new(size as u64);
-            pb.set_style(ProgressStyle::default_bar()
-                .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})")
-                .unwrap());
+/// Write `tiling` at `address` a chunk at a time, so the region is never held
+/// in memory, then verify it the way `write_data` would
+///
+/// With [`VerifyMode::Final`] the chunks are generated again for
+/// verification once everything is written.
+async fn write_tiled(
+    flash_commands: &mut FlashCommands<'_>,
+    address: u32,
+    tiling: &Tiling<'_>,
+    basic: bool,
+    verify: Option<VerifyMode>,
+    retries: u32,
+    progress_format: ProgressFormat,
+) -> Result<()> {
+    let (copies, partial) = tiling.copies();
+    info!(
+        "Writing {} copies{} to flash at 0x{:08X}-0x{:08X}...",
+        copies,
+        if partial > 0 {
+            format!(" and {} bytes of one more", partial)
+        } else {
+            String::new()
+        },
+        address,
+        address as u64 + tiling.len() as u64 - 1
+    );
+    let pb = new_progress_bar(tiling.len() as u64, TRANSFER_TEMPLATE, progress_format);
+    // Chunks track their own progress; the bar shows the whole region
+    let chunk_pb = ProgressBar::hidden();
 
-            let data = flash_commands
-                .read_with_progress(address, size, &pb)
+    // Chunks split at sector boundaries, so a retry never erases another chunk's data
+    for (offset, chunk) in tiling.chunks_at(address) {
+        let chunk_address = address + offset as u32;
+        if verify == Some(VerifyMode::Interleaved) {
+            flash_commands
+                .write_interleaved_with_progress(chunk_address, &chunk, basic, retries, &chunk_pb)
+                .await?;
+        } else if basic {
+            flash_commands.write(chunk_address, &chunk).await?;
+        } else {
+            flash_commands
+                .write_with_progress(chunk_address, &chunk, &chunk_pb)
                 .await?;
+        }
+        pb.set_position((offset + chunk.len()) as u64);
+    }
+    pb.finish_with_message("Write completed!");
 
-            pb.finish_with_message("Read completed!");
+    match verify {
+        Some(VerifyMode::Final) => {
+            info!("Verifying written data using progressive CRC32...");
+            pb.reset();
+            for (offset, chunk) in tiling.chunks_at(address) {
+                flash_commands
+                    .verify_and_repair(address + offset as u32, &chunk, retries, &chunk_pb)
+                    .await?;
+                pb.set_position((offset + chunk.len()) as u64);
+            }
+            pb.finish_with_message("Write and verification completed!");
+            info!("✅ Data written and verified successfully!");
+        }
+        Some(VerifyMode::Interleaved) => info!("✅ Data written and verified successfully!"),
+        None => {
+            info!("✅ Data written successfully!");
+            warn!("⚠️  Warning: Data was not verified (--no-verify). Run `verify` to check it.");
+        }
+    }
+    Ok(())
+}
 
-            println!("Writing to file: {:?}", file);
-            fs::write(&file, &data)
-                .await
-                .with_context(|| format!("Failed to write file: {:?}", file))?;
+/// Rasterize `font` for `make-font` and save the image to `output`, if given
+async fn build_font(
+    font: &Path,
+    size: f32,
+    chars: Option<&str>,
+    chars_file: Option<&Path>,
+    kerning: bool,
+    output: Option<&Path>,
+) -> Result<Vec<u8>> {
+    info!("Rasterizing font: {:?}", font);
+    let data = fs::read(font)
+        .await
+        .with_context(|| format!("Failed to read file: {:?}", font))?;
 
-            println!("File saved successfully!");
-        }
+    let mut wanted: Vec<char> = make_font::default_chars().collect();
+    wanted.extend(chars.unwrap_or_default().chars());
+    if let Some(path) = chars_file {
+        let text = fs::read_to_string(path)
+            .await
+            .with_context(|| format!("Failed to read file: {:?}", path))?;
+        wanted.extend(text.chars().filter(|c| !c.is_control()));
+    }
 
-        Commands::Verify { file, address } => {
-            println!("Reading file: {:?}", file);
-            let data = fs::read(&file)
-                .await
-                .with_context(|| format!("Failed to read file: {:?}", file))?;
+    let image = make_font::make_font(&data, size, wanted, kerning)?;
+    if !image.missing.is_empty() {
+        let shown: String = image.missing.iter().take(32).collect();
+        warn!(
+            "Font has no glyph for {} requested characters, skipped: {}{}",
+            image.missing.len(),
+            shown,
+            if image.missing.len() > 32 { "..." } else { "" }
+        );
+    }
+    println!(
+        "Font image: {} glyphs, {} kerning pairs, {} bytes",
+        image.glyph_count,
+        image.kern_pairs,
+        image.data.len()
+    );
 
-            println!("Verifying {} bytes at 0x{:08X}...", data.len(), address);
+    if let Some(output) = output {
+        fs::write(output, &image.data)
+            .await
+            .with_context(|| format!("Failed to write file: {:?}", output))?;
+        println!("Saved font image to {:?}", output);
+    }
+    Ok(image.data)
+}
 
-            let pb = ProgressBar::new(data.len() as u64);
-            pb.set_style(ProgressStyle::default_bar()
-                .template("{spinner:.green} [{elapsed_precise}] [{bar:40.yellow/blue}] {bytes}/{total_bytes} ({eta})")
-                .unwrap());
+/// Whether flash already holds `data` at `address`, by per-sector CRC
+/// (false if the firmware can't compute them)
+async fn device_matches(
+    flash_commands: &mut FlashCommands<'_>,
+    address: u32,
+    data: &[u8],
+    progress_format: ProgressFormat,
+) -> Result<bool> {
+    info!("Comparing sector CRCs with the device...");
+    let pb = new_progress_bar(data.len() as u64, TRANSFER_TEMPLATE, progress_format);
+    let matches = flash_commands.already_matches(address, data, &pb).await?;
+    pb.finish_and_clear();
+    match matches {
+        Some(true) => Ok(true),
+        Some(false) => {
+            info!("Device contents differ, writing");
+            Ok(false)
+        }
+        None => {
+            info!("Firmware can't checksum sectors, writing the whole image");
+            Ok(false)
+        }
+    }
+}
 
-            flash_commands
-                .verify_with_progressive_crc(address, &data, &pb)
-                .await?;
+/// Read the preserved regions an erase of `erased` would destroy
+async fn save_preserved(
+    flash_commands: &mut FlashCommands<'_>,
+    preserve: &[Segment],
+    erased: Segment,
+) -> Result<Vec<(Segment, Vec<u8>)>> {
+    let mut saved = Vec::new();
+    for region in preserve::regions_to_save(preserve, erased) {
+        info!("Saving preserved region {}...", region);
+        let data = flash_commands
+            .read(region.address, region.length)
+            .await
+            .with_context(|| format!("Failed to read preserved region {}", region))?;
+        saved.push((region, data));
+    }
+    Ok(saved)
+}
 
-            pb.finish_with_message("Verification completed!");
-            println!("Verification successful!");
+/// Program saved regions back and check they read back unchanged
+async fn restore_preserved(
+    flash_commands: &mut FlashCommands<'_>,
+    saved: &[(Segment, Vec<u8>)],
+) -> Result<()> {
+    for (region, data) in saved {
+        info!("Restoring preserved region {}...", region);
+        flash_commands
+            .write(region.address, data)
+            .await
+            .with_context(|| format!("Failed to restore preserved region {}", region))?;
+        let actual = flash_commands.read(region.address, region.length).await?;
+        if actual != *data {
+            anyhow::bail!(
+                "Preserved region {} did not read back correctly after restoring",
+                region
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Print what the firmware reported through Capabilities (`info`)
+fn print_capabilities(capabilities: &Capabilities) {
+    let commands: Vec<String> = (0..32u8)
+        .filter_map(|byte| Command::try_from(byte).ok())
+        .filter(|&command| capabilities.supports(command))
+        .map(|command| format!("{:?}", command))
+        .collect();
+    println!("Firmware Capabilities:");
+    println!("  Commands: {}", commands.join(", "));
+    println!("  Max Payload: {} bytes", capabilities.max_payload_size);
+    println!("  Max Read: {} bytes", capabilities.max_read_size);
+    println!(
+        "  Packet CRC: {:?} ({})",
+        capabilities.crc_mode,
+        if capabilities.hardware_crc {
+            "hardware"
+        } else {
+            "software"
         }
+    );
+    println!(
+        "  Chip Erase: {}",
+        if capabilities.chip_erase { "yes" } else { "no" }
+    );
+}
+
+/// Check the connected chip before touching its contents
+///
+/// An unrecognized JEDEC ID always produces a warning; commands that modify
+/// flash are refused unless `force` is set. Modifying commands also warn when
+/// the firmware reports active write protection.
+async fn check_chip(
+    flash_commands: &mut FlashCommands<'_>,
+    modifies_flash: bool,
+    force: bool,
+) -> Result<()> {
+    let info = flash_commands.get_info().await?;
+    if modifies_flash && info.write_protected {
+        warn!("⚠️  Write protection appears active - check WP# pin; writes and erases will fail");
+    }
+    if jedec::is_recognized(info.jedec_id) {
+        return Ok(());
     }
 
-    println!("Operation completed successfully!");
+    warn!(
+        "⚠️  Unrecognized flash chip: JEDEC ID 0x{:06X} (manufacturer: {}, reported size: {} bytes)",
+        info.jedec_id,
+        jedec::manufacturer_name(info.jedec_id).unwrap_or("unknown"),
+        info.total_size
+    );
+    if modifies_flash && !force {
+        anyhow::bail!(
+            "Refusing to modify unrecognized flash chip 0x{:06X}; pass --force to proceed at your own risk",
+            info.jedec_id
+        );
+    }
+    if modifies_flash {
+        warn!("⚠️  --force given, proceeding anyway");
+    }
     Ok(())
 }
+
+/// Fail unless the connected chip is the one the user expects (`--expected-jedec`)
+fn check_expected_jedec(jedec_id: u32, expected: u32) -> Result<()> {
+    if jedec_id == expected {
+        return Ok(());
+    }
+    let name = |id| jedec::manufacturer_name(id).unwrap_or("unknown manufacturer");
+    anyhow::bail!(
+        "Wrong flash chip: JEDEC ID 0x{:06X} ({}), expected 0x{:06X} ({}); check the board variant",
+        jedec_id,
+        name(jedec_id),
+        expected,
+        name(expected)
+    )
+}
+
+fn parse_jedec_id(s: &str) -> Result<u32, String> {
+    let id = parse_hex(s).map_err(|e| e.to_string())?;
+    if id > 0xFF_FFFF {
+        return Err(format!("JEDEC ID 0x{:X} is wider than 24 bits", id));
+    }
+    Ok(id)
+}
+
+/// Byte range of a `file_len`-byte input selected by `--skip`/`--count`
+fn file_slice(file_len: usize, skip: u32, count: Option<u32>) -> Result<Range<usize>> {
+    let start = skip as usize;
+    if start > file_len {
+        anyhow::bail!(
+            "--skip 0x{:X} is past the end of the {} byte file",
+            skip,
+            file_len
+        );
+    }
+    let end = match count {
+        Some(count) => start
+            .checked_add(count as usize)
+            .filter(|&end| end <= file_len)
+            .with_context(|| {
+                format!(
+                    "--skip 0x{:X} --count 0x{:X} runs past the end of the {} byte file",
+                    skip, count, file_len
+                )
+            })?,
+        None => file_len,
+    };
+    Ok(start..end)
+}
+
+/// Fail unless `length` bytes at `address` fit on the chip
+///
+/// Nothing downstream checks the range and the chip only decodes 24 address
+/// bits, so an oversized image would wrap around to the start of the flash.
+fn check_fits(geometry: &FlashGeometry, address: u32, length: u64) -> Result<()> {
+    let capacity = geometry.total_size as u64;
+    let end = address as u64 + length;
+    if end > capacity {
+        let capacity = if capacity.is_multiple_of(1024 * 1024) {
+            format!("{}MB", capacity / (1024 * 1024))
+        } else {
+            format!("{}KB", capacity / 1024)
+        };
+        anyhow::bail!(
+            "image of {} bytes at 0x{:08X} exceeds {} flash by {} bytes",
+            length,
+            address,
+            capacity,
+            end - geometry.total_size as u64
+        );
+    }
+    Ok(())
+}
+
+/// Extend `data` (to be written at `address`) with its [`ImageFooter`],
+/// padding with 0xFF up to the footer's alignment
+fn append_image_footer(address: u32, data: &mut Vec<u8>) -> Result<ImageFooter> {
+    let length = u32::try_from(data.len())
+        .map_err(|_| anyhow::anyhow!("Image too large for a footer: {} bytes", data.len()))?;
+    let footer = ImageFooter::for_image(data);
+    let footer_address = ImageFooter::address(address, length)
+        .map_err(|e| anyhow::anyhow!("{} ({} bytes at 0x{:08X})", e, data.len(), address))?;
+    data.resize((footer_address - address) as usize, 0xFF);
+    data.extend_from_slice(&footer.to_bytes());
+    Ok(footer)
+}
+
+/// Parse a duration such as `30s`, `2m` or `500ms`; a bare number means seconds
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    if let Ok(secs) = s.parse::<u64>() {
+        return Ok(Duration::from_secs(secs));
+    }
+    humantime::parse_duration(s).map_err(|e| format!("Invalid duration '{}': {}", s, e))
+}
+
+fn parse_spi_mode(s: &str) -> Result<SpiMode, String> {
+    let mode: u8 = s.parse().map_err(|_| format!("Invalid SPI mode: {}", s))?;
+    SpiMode::try_from(mode).map_err(|e| e.to_string())
+}
+
+/// Largest progressive CRC block; the firmware must checksum a whole block
+/// within one response timeout
+const MAX_VERIFY_BLOCK_SIZE: usize = 1024 * 1024;
+
+fn parse_verify_block_size(s: &str) -> Result<usize, String> {
+    let size = parse_hex(s).map_err(|e| e.to_string())? as usize;
+    if size == 0 || size > MAX_VERIFY_BLOCK_SIZE || size & (FLASH_SECTOR_SIZE - 1) != 0 {
+        return Err(format!(
+            "Verify block size must be a non-zero multiple of {} bytes up to {} bytes, got {}",
+            FLASH_SECTOR_SIZE, MAX_VERIFY_BLOCK_SIZE, size
+        ));
+    }
+    Ok(size)
+}
+
+fn parse_read_chunk_size(s: &str) -> Result<usize, String> {
+    let size = parse_hex(s).map_err(|e| e.to_string())? as usize;
+    if size == 0 || size > MAX_READ_CHUNK_SIZE {
+        return Err(format!(
+            "Read chunk size must be between 1 and {} bytes, got {}",
+            MAX_READ_CHUNK_SIZE, size
+        ));
+    }
+    Ok(size)
+}
+
+/// Parse an `ADDR:SIZE` region (both hex or decimal)
+fn parse_region(s: &str) -> Result<Segment, String> {
+    let (address, size) = s
+        .split_once(':')
+        .ok_or_else(|| format!("Expected ADDR:SIZE, got '{}'", s))?;
+    let address = parse_hex(address).map_err(|e| format!("Bad address '{}': {}", address, e))?;
+    let size = parse_hex(size).map_err(|e| format!("Bad size '{}': {}", size, e))?;
+    if size == 0 || address.checked_add(size).is_none() {
+        return Err(format!("Region {} is empty or wraps past 4GB", s));
+    }
+    Ok(Segment::new(address, size))
+}
+
+fn parse_tune_size(s: &str) -> Result<u32, String> {
+    let size = parse_hex(s).map_err(|e| e.to_string())?;
+    if size == 0 || size as usize & (FLASH_SECTOR_SIZE - 1) != 0 {
+        return Err(format!(
+            "Tune size must be a non-zero multiple of {} bytes, got {}",
+            FLASH_SECTOR_SIZE, size
+        ));
+    }
+    Ok(size)
+}
+
+fn parse_rgb565(s: &str) -> Result<u16, String> {
+    let value = parse_hex(s).map_err(|e| e.to_string())?;
+    u16::try_from(value)
+        .map_err(|_| format!("RGB565 color must be at most 0xFFFF, got 0x{:X}", value))
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    // Config file values fill in what flags and env vars leave out
+    let config = defaults::load(&defaults::search_paths())?;
+    let command = defaults::apply(Cli::command(), &config)?;
+    let matches = defaults::get_matches_from(&command, std::env::args_os(), &config)
+        .unwrap_or_else(|e| e.exit());
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    if let Commands::Completions { shell } = cli.command {
+        // Register under the installed binary name, not the clap display name
+        let mut command = Cli::command();
+        clap_complete::generate(
+            shell,
+            &mut command,
+            env!("CARGO_BIN_NAME"),
+            &mut std::io::stdout(),
+        );
+        return Ok(());
+    }
+    init_logging(&cli);
+
+    info!("STM32G4 Flash Programmer Tool v0.1.0");
+
+    // make-font only needs the device to program the image it builds
+    let font_image = match &cli.command {
+        Commands::MakeFont {
+            font,
+            size,
+            chars,
+            chars_file,
+            kerning,
+            output,
+            flash,
+            ..
+        } => {
+            let image = build_font(
+                font,
+                *size,
+                chars.as_deref(),
+                chars_file.as_deref(),
+                *kerning,
+                output.as_deref(),
+            )
+            .await?;
+            if !flash {
+                return Ok(());
+            }
+            Some(image)
+        }
+        _ => None,
+    };
+
+    let ports = if cli.all {
+        let ports = devices::find_programmers()?;
+        if ports.is_empty() {
+            anyhow::bail!(
+                "No programmers found (USB ID {:04x}:{:04x})",
+                devices::PROGRAMMER_VID,
+                devices::PROGRAMMER_PID
+            );
+        }
+        ports
+    } else {
+        cli.ports.clone()
+    };
+    if ports.is_empty() {
+        let port = cli.port.clone();
+        return run_device(cli, port, font_image).await;
+    }
+    run_on_devices(cli, ports, font_image).await
+}
+
+/// Run the command on every port concurrently, each with its own connection,
+/// and print a pass/fail summary; one device failing doesn't stop the others
+async fn run_on_devices(cli: Cli, ports: Vec<String>, font_image: Option<Vec<u8>>) -> Result<()> {
+    if matches!(cli.command, Commands::Read { .. }) {
+        anyhow::bail!(
+            "read saves to one file; run it once per device instead of with --ports/--all"
+        );
+    }
+    info!("Running on {} devices: {}", ports.len(), ports.join(", "));
+
+    let progress = MultiProgress::new();
+    let tasks: Vec<_> = ports
+        .iter()
+        .map(|port| {
+            let device = devices::DeviceContext {
+                port: port.clone(),
+                progress: progress.clone(),
+            };
+            tokio::spawn(devices::DEVICE.scope(
+                device,
+                run_device(cli.clone(), port.clone(), font_image.clone()),
+            ))
+        })
+        .collect();
+
+    let mut results = Vec::new();
+    for (port, task) in ports.into_iter().zip(tasks) {
+        let result = match task.await {
+            Ok(result) => result,
+            Err(e) => Err(anyhow::anyhow!("Device task failed: {}", e)),
+        };
+        results.push((port, result));
+    }
+
+    let (lines, failed) = devices::summarize(&results);
+    println!("Summary:");
+    for line in lines {
+        println!("{}", line);
+    }
+    if failed > 0 {
+        anyhow::bail!("{} of {} devices failed", failed, results.len());
+    }
+    println!("All {} devices passed", results.len());
+    Ok(())
+}
+
+/// Connect to the programmer on `port` and run the command
+async fn run_device(cli: Cli, port: String, font_image: Option<Vec<u8>>) -> Result<()> {
+    let progress_format = cli.progress_format.resolve(cli.quiet);
+    info!("Connecting to {}...", port);
+
+    // Connect to device
+    let mut connection = timeout(
+        cli.connect_timeout,
+        SerialConnection::new(
+            &port,
+            cli.baud,
+            cli.trace_file.as_deref(),
+            if cli.crc16 {
+                CrcMode::Crc16
+            } else {
+                CrcMode::Crc32
+            },
+        ),
+    )
+    .await
+    .map_err(|_| {
+        anyhow::anyhow!(
+            "No programmer answered on {} within {:?} (--connect-timeout); check the port",
+            port,
+            cli.connect_timeout
+        )
+    })?
+    .context("Failed to connect to device")?;
+    connection.set_response_timeout(cli.response_timeout);
+    connection.set_max_consecutive_errors(cli.max_consecutive_errors);
+
+    info!("Connected successfully!");
+
+    // Create flash commands handler
+    let mut flash_commands = FlashCommands::new(&mut connection);
+    flash_commands.set_verify_block_size(cli.verify_block_size);
+    flash_commands.set_power_safe(cli.power_safe);
+    if let Some(size) = cli.read_chunk_size {
+        flash_commands.set_read_chunk_size(size);
+    }
+
+    if let Some(mode) = cli.spi_mode {
+        info!("Switching SPI to mode {}...", mode as u8);
+        flash_commands.set_spi_mode(mode).await?;
+    }
+
+    // A replay must send exactly the recorded packets
+    if !matches!(cli.command, Commands::Replay { .. }) {
+        flash_commands.detect_capabilities().await?;
+    }
+
+    if let Some(expected) = cli.expected_jedec {
+        let info = flash_commands.get_info().await?;
+        check_expected_jedec(info.jedec_id, expected)?;
+        info!("JEDEC ID 0x{:06X} matches --expected-jedec", info.jedec_id);
+    }
+
+    let modifies_flash = matches!(
+        cli.command,
+        Commands::Erase { .. }
+            | Commands::Write { .. }
+            | Commands::Pattern { .. }
+            | Commands::Assets { .. }
+            | Commands::Bitcheck { .. }
+            | Commands::WearTest { .. }
+            | Commands::Tune { .. }
+            | Commands::MakeFont { .. }
+    );
+    // A replay must send exactly the recorded packets, so no Info check first
+    if !matches!(
+        cli.command,
+        Commands::Info
+            | Commands::Status
+            | Commands::Config
+            | Commands::Stats { .. }
+            | Commands::EnterDfu
+            | Commands::Doctor { .. }
+            | Commands::Replay { .. }
+    ) {
+        check_chip(&mut flash_commands, modifies_flash, cli.force).await?;
+    }
+
+    // Execute command
+    match cli.command {
+        Commands::Info => {
+            info!("Getting flash information...");
+            let info = flash_commands.get_info().await?;
+            println!("Flash Information:");
+            println!("  JEDEC ID: 0x{:06X}", info.jedec_id);
+            println!(
+                "  Manufacturer: {}",
+                jedec::manufacturer_name(info.jedec_id).unwrap_or("Unknown")
+            );
+            println!(
+                "  Total Size: {} MB ({} bytes)",
+                info.total_size / (1024 * 1024),
+                info.total_size
+            );
+            println!("  Page Size: {} bytes", info.page_size);
+            println!(
+                "  Sector Size: {} KB ({} bytes)",
+                info.sector_size / 1024,
+                info.sector_size
+            );
+            if info.write_protected {
+                println!("  Write Protection: active - check WP# pin");
+            } else {
+                println!("  Write Protection: not detected");
+            }
+            match flash_commands.capabilities() {
+                Some(capabilities) => print_capabilities(&capabilities),
+                None => println!("Firmware Capabilities: not reported (older firmware)"),
+            }
+        }
+
+        Commands::Status => {
+            info!("Reading flash status register...");
+            let status = flash_commands.read_status().await?;
+
+            println!("Flash Status Register: 0x{:02X}", status);
+            println!(
+                "  Write In Progress (WIP): {}",
+                if status & 0x01 != 0 { "Yes" } else { "No" }
+            );
+            println!(
+                "  Write Enable Latch (WEL): {}",
+                if status & 0x02 != 0 { "Yes" } else { "No" }
+            );
+            println!(
+                "  Block Protect Bits (BP0-BP2): 0x{:01X}",
+                (status >> 2) & 0x07
+            );
+            println!(
+                "  Top/Bottom Protect (TB): {}",
+                if status & 0x20 != 0 { "Top" } else { "Bottom" }
+            );
+            println!(
+                "  Sector Protect (SEC): {}",
+                if status & 0x40 != 0 { "Yes" } else { "No" }
+            );
+            println!(
+                "  Status Register Protect (SRP0): {}",
+                if status & 0x80 != 0 { "Yes" } else { "No" }
+            );
+        }
+
+        Commands::Erase {
+            address,
+            size,
+            preserve,
+        } => {
+            if let Some((a, b)) = find_overlap(&preserve) {
+                anyhow::bail!("Preserved regions {} and {} overlap", a, b);
+            }
+            let geometry = flash_commands.geometry();
+            let erased = geometry.erase_span(address, size);
+            let saved = save_preserved(&mut flash_commands, &preserve, erased).await?;
+
+            info!(
+                "Erasing flash at 0x{:08X}, size: {} bytes...",
+                address, size
+            );
+
+            if flash_commands.can_chip_erase(address, size) {
+                info!("Using a single chip erase; progress shows once it finishes");
+            }
+            let sectors = erased.length / geometry.sector_size();
+            let pb = new_progress_bar(sectors as u64, ERASE_TEMPLATE, progress_format);
+
+            let result = flash_commands.erase_with_progress(address, size, &pb).await;
+            restore_preserved(&mut flash_commands, &saved).await?;
+            result?;
+
+            pb.finish_with_message("Erase completed!");
+            info!("Flash erased successfully!");
+        }
+
+        Commands::Write {
+            file,
+            format: ImageFormat::Sparse,
+            address,
+            erase,
+            no_verify,
+            basic,
+            retries,
+            verify_mode,
+            skip,
+            count,
+            repeat,
+            repeat_to,
+            append_footer,
+            map_file,
+            preserve,
+            skip_if_current,
+            ..
+        } => {
+            let reshapes = address != 0
+                || skip != 0
+                || count.is_some()
+                || repeat.is_some()
+                || repeat_to.is_some()
+                || append_footer
+                || skip_if_current
+                || !preserve.is_empty();
+            if reshapes {
+                anyhow::bail!(
+                    "A sparse image is written as recorded; --address, --skip, --count, --repeat, --repeat-to, --append-footer, --skip-if-current and --preserve don't apply"
+                );
+            }
+
+            info!("Reading sparse image: {:?}", file);
+            let bytes = fs::read(&file)
+                .await
+                .with_context(|| format!("Failed to read file: {:?}", file))?;
+            let image = SparseImage::from_bytes(&bytes)
+                .with_context(|| format!("Invalid sparse image: {:?}", file))?;
+            info!(
+                "Image covers {} with {} regions ({} bytes of data)",
+                image.range,
+                image.regions.len(),
+                image.data_len()
+            );
+            let geometry = flash_commands.geometry();
+            check_fits(&geometry, image.range.address, image.range.length as u64)?;
+
+            if erase {
+                // The gaps must read back erased, as they were when dumped
+                let erased = geometry.erase_span(image.range.address, image.range.length);
+                if erased != image.range {
+                    warn!(
+                        "Erasing whole sectors also clears {} outside the image range {}",
+                        erased, image.range
+                    );
+                }
+                info!(
+                    "Erasing flash at 0x{:08X}, size: {} bytes...",
+                    erased.address, erased.length
+                );
+                flash_commands.erase(erased.address, erased.length).await?;
+                info!("Erase completed!");
+            }
+            let verify = (!no_verify).then_some(verify_mode);
+            for region in &image.regions {
+                write_data(
+                    &mut flash_commands,
+                    region.address,
+                    &region.data,
+                    basic,
+                    verify,
+                    retries,
+                    progress_format,
+                )
+                .await?;
+            }
+
+            if let Some(map_file) = map_file {
+                let name = file.file_name().unwrap_or(file.as_os_str());
+                let mut map = WriteMap::default();
+                for region in &image.regions {
+                    map.add(name.to_string_lossy(), region.address, &region.data);
+                }
+                map.save(&map_file).await?;
+                info!("Write map saved to {:?}", map_file);
+            }
+        }
+
+        Commands::Write {
+            file,
+            format: ImageFormat::Raw,
+            address,
+            erase,
+            verify: _,
+            no_verify,
+            basic,
+            retries,
+            verify_mode,
+            skip,
+            count,
+            repeat,
+            repeat_to,
+            append_footer,
+            map_file,
+            preserve,
+            erase_mode,
+            skip_if_current,
+        } => {
+            info!("Reading file: {:?}", file);
+            let mut data = fs::read(&file)
+                .await
+                .with_context(|| format!("Failed to read file: {:?}", file))?;
+
+            info!("File size: {} bytes", data.len());
+
+            let range = file_slice(data.len(), skip, count)?;
+            if range.len() != data.len() {
+                info!(
+                    "Writing file bytes 0x{:X}..0x{:X} ({} bytes)",
+                    range.start,
+                    range.end,
+                    range.len()
+                );
+                data.truncate(range.end);
+                data.drain(..range.start);
+            }
+            if append_footer {
+                let footer = append_image_footer(address, &mut data)?;
+                info!(
+                    "Appending footer at 0x{:08X} (length {} bytes, CRC32 0x{:08X})",
+                    address + data.len() as u32 - IMAGE_FOOTER_SIZE as u32,
+                    footer.length,
+                    footer.crc32
+                );
+            }
+
+            let tiling = match (repeat, repeat_to) {
+                (Some(count), _) => Some(Tiling::repeat(&data, count)?),
+                (None, Some(end)) => Some(Tiling::repeat_to(&data, address, end)?),
+                (None, None) => None,
+            };
+            let length = tiling.as_ref().map_or(data.len(), Tiling::len);
+            check_fits(&flash_commands.geometry(), address, length as u64)?;
+            let length = length as u32;
+
+            let up_to_date = length > 0
+                && skip_if_current
+                && device_matches(&mut flash_commands, address, &data, progress_format).await?;
+            if length == 0 {
+                println!("Nothing to write, {:?} selects no data", file);
+            } else if up_to_date {
+                println!("Device already up to date, skipping");
+            } else {
+                let written = Segment::new(address, length);
+                preserve::check_preserved(&preserve, written)?;
+                let geometry = flash_commands.geometry();
+                let erased = geometry.erase_span(address, length);
+                let neighbors = preserve::neighbors(written, &geometry);
+                let saved = if !erase {
+                    Vec::new()
+                } else if erase_mode == EraseMode::Preserve {
+                    // Covers any --preserve region too, since those can't overlap the data
+                    save_preserved(&mut flash_commands, &neighbors, erased).await?
+                } else {
+                    for region in &neighbors {
+                        let kept = preserve
+                            .iter()
+                            .any(|p| find_overlap(&[*p, *region]).is_some());
+                        if !kept {
+                            warn!(
+                                "Erasing whole sectors also clears {} outside the data; use --erase-mode preserve to keep it",
+                                region
+                            );
+                        }
+                    }
+                    save_preserved(&mut flash_commands, &preserve, erased).await?
+                };
+
+                let result = async {
+                    if erase {
+                        info!(
+                            "Erasing flash at 0x{:08X}, size: {} bytes...",
+                            erased.address, erased.length
+                        );
+                        flash_commands.erase(erased.address, erased.length).await?;
+                        info!("Erase completed!");
+                    }
+                    let verify = (!no_verify).then_some(verify_mode);
+                    match &tiling {
+                        Some(tiling) => {
+                            write_tiled(
+                                &mut flash_commands,
+                                address,
+                                tiling,
+                                basic,
+                                verify,
+                                retries,
+                                progress_format,
+                            )
+                            .await
+                        }
+                        None => {
+                            write_data(
+                                &mut flash_commands,
+                                address,
+                                &data,
+                                basic,
+                                verify,
+                                retries,
+                                progress_format,
+                            )
+                            .await
+                        }
+                    }
+                }
+                .await;
+                // Put preserved data back even if the write failed
+                restore_preserved(&mut flash_commands, &saved).await?;
+                result?;
+            }
+
+            if let Some(map_file) = map_file {
+                let name = file.file_name().unwrap_or(file.as_os_str());
+                let mut map = WriteMap::default();
+                match &tiling {
+                    Some(tiling) => {
+                        map.add_checksum(name.to_string_lossy(), address, length, tiling.crc32())
+                    }
+                    None => map.add(name.to_string_lossy(), address, &data),
+                }
+                map.save(&map_file).await?;
+                info!("Write map saved to {:?}", map_file);
+            }
+        }
+
+        Commands::Read {
+            file,
+            address,
+            size,
+            append,
+            until_blank,
+            format,
+        } => {
+            let total_size = flash_commands.geometry().total_size;
+            let size = size.unwrap_or(total_size.saturating_sub(address));
+            if format == ImageFormat::Sparse {
+                if append {
+                    anyhow::bail!("--append resumes raw dumps only; drop it for --format sparse");
+                }
+                read_sparse(
+                    &mut flash_commands,
+                    &file,
+                    address,
+                    size,
+                    until_blank,
+                    progress_format,
+                )
+                .await?;
+            } else if let Some(threshold) = until_blank {
+                read_until_blank(
+                    &mut flash_commands,
+                    &file,
+                    address,
+                    size,
+                    threshold,
+                    progress_format,
+                )
+                .await?;
+            } else if append {
+                read_appending(&mut flash_commands, &file, address, size, progress_format).await?;
+            } else {
+                info!("Reading {} bytes from flash at 0x{:08X}...", size, address);
+
+                let pb = new_progress_bar(size as u64, TRANSFER_TEMPLATE, progress_format);
+
+                // Stream chunks straight to the file so large dumps don't sit in RAM
+                let output = fs::File::create(&file)
+                    .await
+                    .with_context(|| format!("Failed to create file: {:?}", file))?;
+                let mut output = tokio::io::BufWriter::new(output);
+                flash_commands
+                    .read_to_writer(address, size, &mut output, &pb)
+                    .await?;
+                output
+                    .flush()
+                    .await
+                    .with_context(|| format!("Failed to write file: {:?}", file))?;
+
+                pb.finish_with_message("Read completed!");
+                info!("File saved successfully!");
+            }
+        }
+
+        Commands::Verify { file, address } => {
+            info!("Reading file: {:?}", file);
+            let data = fs::read(&file)
+                .await
+                .with_context(|| format!("Failed to read file: {:?}", file))?;
+
+            info!("Verifying {} bytes at 0x{:08X}...", data.len(), address);
+
+            let pb = new_progress_bar(
+                data.len() as u64,
+                "{spinner:.green} [{elapsed_precise}] [{bar:40.yellow/blue}] {bytes}/{total_bytes} ({eta})",
+                progress_format,
+            );
+
+            // Check every block so the report covers all bad regions, not just the first
+            let failures = flash_commands
+                .verify_with_progressive_crc_report(address, &data, &pb)
+                .await?;
+            if !failures.is_empty() {
+                pb.abandon_with_message("Verification failed!");
+                return Err(anyhow::anyhow!(
+                    "❌ {} of {} blocks failed CRC verification: {}",
+                    failures.len(),
+                    data.len().div_ceil(flash_commands.verify_block_size()),
+                    failure_summary(&failures)
+                ));
+            }
+
+            pb.finish_with_message("Verification completed!");
+            info!("Verification successful!");
+        }
+
+        Commands::Pattern {
+            pattern,
+            address,
+            width,
+            height,
+            square,
+            color,
+            background,
+            erase,
+        } => {
+            let pattern = match pattern {
+                PatternKind::Checkerboard => TestPattern::Checkerboard {
+                    square,
+                    fg: color,
+                    bg: background,
+                },
+                PatternKind::ColorBars => TestPattern::ColorBars,
+                PatternKind::Gradient => TestPattern::Gradient,
+                PatternKind::Solid => TestPattern::Solid(color),
+            };
+
+            let mut data = vec![0u8; TestPattern::frame_size(width, height)];
+            pattern.fill_bytes(width, height, 0, &mut data);
+            info!(
+                "Generated {:?} pattern: {}x{} RGB565, {} bytes",
+                pattern,
+                width,
+                height,
+                data.len()
+            );
+
+            if erase {
+                info!(
+                    "Erasing flash at 0x{:08X}, size: {} bytes...",
+                    address,
+                    data.len()
+                );
+                flash_commands.erase(address, data.len() as u32).await?;
+                info!("Erase completed!");
+            }
+
+            info!("Writing pattern to flash at 0x{:08X}...", address);
+            let pb = new_progress_bar(data.len() as u64, TRANSFER_TEMPLATE, progress_format);
+
+            flash_commands
+                .write_with_progress(address, &data, &pb)
+                .await?;
+            pb.finish_with_message("Pattern written!");
+            info!("✅ Test pattern written successfully!");
+        }
+
+        Commands::Assets {
+            pack,
+            erase,
+            verify,
+            map_file,
+        } => {
+            info!("Reading asset pack: {:?}", pack);
+            let pack = fs::read(&pack)
+                .await
+                .with_context(|| format!("Failed to read file: {:?}", pack))?;
+            let entries = asset_pack::parse_pack(&pack)
+                .map_err(|e| anyhow::anyhow!("Invalid asset pack: {}", e))?;
+
+            let total: u64 = entries.iter().map(|e| e.length as u64).sum();
+            info!("Asset pack: {} assets, {} bytes", entries.len(), total);
+
+            if erase {
+                for entry in &entries {
+                    info!(
+                        "Erasing flash at 0x{:08X}, size: {} bytes...",
+                        entry.address, entry.length
+                    );
+                    flash_commands.erase(entry.address, entry.length).await?;
+                }
+                flash_commands
+                    .erase(ASSET_TABLE_ADDR, FLASH_SECTOR_SIZE as u32)
+                    .await?;
+                info!("Erase completed!");
+            }
+
+            // One bar per asset: the write and verify helpers report positions
+            // relative to the start of the data they were given
+            for entry in &entries {
+                info!(
+                    "Writing {:?} asset ({} bytes) at 0x{:08X}...",
+                    entry.asset_type, entry.length, entry.address
+                );
+                let pb = new_progress_bar(entry.length as u64, TRANSFER_TEMPLATE, progress_format);
+                flash_commands
+                    .write_with_progress(entry.address, entry.data(&pack), &pb)
+                    .await?;
+                pb.finish_with_message("Asset written!");
+            }
+
+            if verify {
+                for entry in &entries {
+                    info!(
+                        "Verifying {:?} asset at 0x{:08X}...",
+                        entry.asset_type, entry.address
+                    );
+                    let pb =
+                        new_progress_bar(entry.length as u64, TRANSFER_TEMPLATE, progress_format);
+                    flash_commands
+                        .verify_with_progressive_crc(entry.address, entry.data(&pack), &pb)
+                        .await
+                        .with_context(|| {
+                            format!(
+                                "Verification failed for {:?} asset at 0x{:08X}",
+                                entry.asset_type, entry.address
+                            )
+                        })?;
+                    pb.finish_with_message("Asset verified!");
+                }
+            }
+
+            let table: Vec<_> = entries.iter().map(|e| e.table_entry(&pack)).collect();
+            let encoded_table = asset_pack::encode_table(&table);
+            info!("Writing asset table at 0x{:08X}...", ASSET_TABLE_ADDR);
+            flash_commands
+                .write(ASSET_TABLE_ADDR, &encoded_table)
+                .await?;
+            info!("✅ {} assets programmed successfully!", entries.len());
+
+            if let Some(map_file) = map_file {
+                let mut map = WriteMap::default();
+                for entry in &entries {
+                    map.add(
+                        format!("{:?}", entry.asset_type),
+                        entry.address,
+                        entry.data(&pack),
+                    );
+                }
+                map.add("asset table", ASSET_TABLE_ADDR, &encoded_table);
+                map.save(&map_file).await?;
+                info!("Write map saved to {:?}", map_file);
+            }
+        }
+
+        Commands::VerifyHash {
+            hashfile,
+            address,
+            size,
+        } => {
+            let text = fs::read_to_string(&hashfile)
+                .await
+                .with_context(|| format!("Failed to read hash file: {:?}", hashfile))?;
+            let expected = digest::parse_sha256_file(&text)
+                .with_context(|| format!("Bad hash file: {:?}", hashfile))?;
+            check_fits(&flash_commands.geometry(), address, size as u64)?;
+
+            info!(
+                "Verifying {} bytes at 0x{:08X} against SHA256 {}...",
+                size,
+                address,
+                hex::encode(expected)
+            );
+            let pb = new_progress_bar(size as u64, TRANSFER_TEMPLATE, progress_format);
+            if let Err(e) = flash_commands
+                .verify_against_hash(address, size, &expected, &pb)
+                .await
+            {
+                pb.abandon_with_message("Verification failed!");
+                return Err(e);
+            }
+            pb.finish_with_message("Verification completed!");
+            info!("Verification successful!");
+        }
+
+        Commands::VerifyMap { map } => {
+            let map = WriteMap::load(&map).await?;
+            let total: u64 = map.segments.iter().map(|s| s.length as u64).sum();
+            info!(
+                "Verifying {} segments ({} bytes)...",
+                map.segments.len(),
+                total
+            );
+
+            let pb = new_progress_bar(total, TRANSFER_TEMPLATE, progress_format);
+            let mut failed = 0;
+            for segment in &map.segments {
+                let data = flash_commands
+                    .read_with_progress(segment.address, segment.length, &pb)
+                    .await
+                    .with_context(|| format!("Failed to read segment {:?}", segment.name))?;
+
+                let crc32 = flash_protocol::crc32::Crc32::checksum(&data);
+                if crc32 == segment.crc32 {
+                    pb.println(format!(
+                        "✅ {} at 0x{:08X} ({} bytes)",
+                        segment.name, segment.address, segment.length
+                    ));
+                } else {
+                    failed += 1;
+                    pb.println(format!(
+                        "❌ {} at 0x{:08X} ({} bytes): CRC32 0x{:08X}, expected 0x{:08X}",
+                        segment.name, segment.address, segment.length, crc32, segment.crc32
+                    ));
+                }
+            }
+            pb.finish_and_clear();
+
+            if failed > 0 {
+                anyhow::bail!(
+                    "{} of {} segments do not match the map",
+                    failed,
+                    map.segments.len()
+                );
+            }
+            info!("✅ All {} segments match the map", map.segments.len());
+        }
+
+        Commands::Bitcheck { address } => {
+            let sector_size = flash_commands.geometry().sector_size();
+            if !address.is_multiple_of(sector_size) {
+                anyhow::bail!(
+                    "Bitcheck address 0x{:08X} must be aligned to a {}-byte sector",
+                    address,
+                    sector_size
+                );
+            }
+
+            info!(
+                "Checking data lines with the scratch sector at 0x{:08X} (its {} bytes will be lost)...",
+                address, sector_size
+            );
+            let pattern = bitcheck::pattern();
+            flash_commands.erase(address, BITCHECK_SIZE as u32).await?;
+            flash_commands.write(address, &pattern).await?;
+            let actual = flash_commands.read(address, BITCHECK_SIZE as u32).await?;
+
+            let report = BitReport::analyze(&pattern, &actual);
+            if report.is_ok() {
+                println!("All 8 data bits toggle correctly");
+            } else {
+                for bit in report.failing_bits() {
+                    println!(
+                        "  D{}: read 0 where 1 was written in {} bytes, read 1 where 0 was written in {} bytes",
+                        bit, report.stuck_low[bit], report.stuck_high[bit]
+                    );
+                }
+                anyhow::bail!(
+                    "Data bits {:?} failed to toggle - check the MOSI/MISO wiring",
+                    report.failing_bits()
+                );
+            }
+        }
+        Commands::Doctor { reads } => {
+            info!(
+                "Reading the JEDEC ID and SFDP header {} times each...",
+                reads
+            );
+            let mut ids = Vec::with_capacity(reads as usize);
+            for _ in 0..reads {
+                ids.push(flash_commands.get_info().await?.jedec_id);
+            }
+            let mut headers = Vec::with_capacity(reads as usize);
+            for _ in 0..reads {
+                match flash_commands.read_sfdp(0, SFDP_HEADER_SIZE as u32).await? {
+                    Some(header) => headers.push(header),
+                    None => break,
+                }
+            }
+
+            let findings = [
+                doctor::check_jedec_ids(&ids),
+                if headers.is_empty() {
+                    Finding::Skipped("SFDP not checked (firmware has no ReadSfdp)".into())
+                } else {
+                    doctor::check_sfdp_headers(&headers)
+                },
+            ];
+            println!("SPI link check:");
+            for finding in &findings {
+                println!("{}", finding);
+            }
+            if findings.iter().any(Finding::is_problem) {
+                anyhow::bail!(
+                    "SPI link looks unreliable - check the wiring (short leads, shared ground) or lower the SPI clock (see `tune`)"
+                );
+            }
+            println!("SPI link looks healthy");
+        }
+        Commands::WearTest {
+            address,
+            cycles,
+            wear_confirmed,
+        } => {
+            if !wear_confirmed {
+                anyhow::bail!(
+                    "wear-test wears out the sector at 0x{:08X}; pass --i-know-this-wears-flash to run it",
+                    address
+                );
+            }
+            let sector_size = flash_commands.geometry().sector_size();
+            if !address.is_multiple_of(sector_size) {
+                anyhow::bail!(
+                    "Wear test address 0x{:08X} must be aligned to a {}-byte sector",
+                    address,
+                    sector_size
+                );
+            }
+            check_fits(&flash_commands.geometry(), address, sector_size as u64)?;
+
+            info!(
+                "Cycling the scratch sector at 0x{:08X} {} times (its {} bytes will be lost)...",
+                address, cycles, sector_size
+            );
+            let programmed = vec![Phase::Program.expected(); sector_size as usize];
+            let pb = new_progress_bar(cycles as u64, CYCLE_TEMPLATE, progress_format);
+            for cycle in 1..=cycles {
+                flash_commands
+                    .erase(address, sector_size)
+                    .await
+                    .with_context(|| format!("Erase failed in cycle {}", cycle))?;
+                let mut mismatch = wear_test::check(
+                    Phase::Erase,
+                    &flash_commands.read(address, sector_size).await?,
+                );
+                if mismatch.is_none() {
+                    flash_commands
+                        .write(address, &programmed)
+                        .await
+                        .with_context(|| format!("Program failed in cycle {}", cycle))?;
+                    mismatch = wear_test::check(
+                        Phase::Program,
+                        &flash_commands.read(address, sector_size).await?,
+                    );
+                }
+                if let Some(mismatch) = mismatch {
+                    pb.abandon();
+                    anyhow::bail!(
+                        "Cycle {} failed after {} good cycles: {}",
+                        cycle,
+                        cycle - 1,
+                        mismatch
+                    );
+                }
+                pb.inc(1);
+            }
+            pb.finish_with_message("Wear test completed!");
+            println!(
+                "Sector 0x{:08X} passed all {} program/erase cycles",
+                address, cycles
+            );
+        }
+        Commands::Tune {
+            address,
+            size,
+            mut speeds,
+        } => {
+            let sector_size = flash_commands.geometry().sector_size();
+            if !address.is_multiple_of(sector_size) {
+                anyhow::bail!(
+                    "Tune address 0x{:08X} must be aligned to a {}-byte sector",
+                    address,
+                    sector_size
+                );
+            }
+            speeds.sort_unstable();
+            speeds.dedup();
+            speeds.retain(|&mhz| mhz != 0);
+
+            let original_hz = flash_commands.get_config().await?.spi_frequency_hz;
+            info!(
+                "Sweeping SPI clocks with the scratch region 0x{:08X}-0x{:08X} (its contents will be lost)...",
+                address,
+                address + size - 1
+            );
+
+            println!("  SPI clock    Write KB/s   Read KB/s  Result");
+            let mut results = Vec::new();
+            for (seed, &mhz) in speeds.iter().enumerate() {
+                let hz = flash_commands.set_spi_frequency(mhz * 1_000_000).await?;
+                let result = tune_speed(&mut flash_commands, address, size, hz, seed as u32).await;
+                println!("{}", tune::format_row(&result));
+                let passed = result.passed;
+                results.push(result);
+                if !passed {
+                    break;
+                }
+            }
+
+            // Leave the programmer at the clock it started with
+            if original_hz != 0 {
+                flash_commands.set_spi_frequency(original_hz).await?;
+            }
+
+            match tune::fastest_passing(&results) {
+                Some(best) => println!(
+                    "Fastest reliable SPI clock: {:.2} MHz",
+                    best.hz as f64 / 1_000_000.0
+                ),
+                None => anyhow::bail!("No SPI clock passed - check the wiring"),
+            }
+        }
+        Commands::Config => {
+            let config = flash_commands.get_config().await?;
+            println!("Device Configuration:");
+            println!("  SPI Mode: {}", config.spi_mode as u8);
+            match config.spi_frequency_hz {
+                0 => println!("  SPI Clock: unknown"),
+                hz => println!("  SPI Clock: {} MHz ({} Hz)", hz as f64 / 1_000_000.0, hz),
+            }
+            println!("  Blank Check: {:?}", config.blank_check);
+            println!("  Max Payload: {} bytes", config.max_payload_size);
+        }
+        Commands::Stats { clear } => {
+            let counters = flash_commands.error_counters(clear).await?;
+            println!("Error Counters (since boot or last clear):");
+            println!("  Dropped packets: {}", counters.dropped_packets);
+            println!("  CRC errors: {}", counters.crc_errors);
+            println!("  Buffer overflows: {}", counters.buffer_overflows);
+            println!("  Flash errors: {}", counters.flash_errors);
+            if clear {
+                println!("Counters cleared");
+            }
+        }
+        Commands::EnterDfu => {
+            flash_commands.enter_bootloader().await?;
+            println!("Programmer is rebooting into the STM32 DFU bootloader (USB 0483:df11).");
+            println!("Flash new firmware with:");
+            println!("  dfu-util -a 0 -s 0x08000000:leave -D firmware.bin");
+        }
+        Commands::MakeFont { address, erase, .. } => {
+            let image = font_image.expect("font image is built before connecting");
+            if erase {
+                info!(
+                    "Erasing flash at 0x{:08X}, size: {} bytes...",
+                    address,
+                    image.len()
+                );
+                flash_commands.erase(address, image.len() as u32).await?;
+                info!("Erase completed!");
+            }
+            write_data(
+                &mut flash_commands,
+                address,
+                &image,
+                false,
+                Some(VerifyMode::Final),
+                0,
+                progress_format,
+            )
+            .await?;
+            println!("Font programmed at 0x{:08X}", address);
+        }
+
+        Commands::Completions { .. } => unreachable!("completions are printed before connecting"),
+
+        Commands::Replay { trace, no_delay } => {
+            let text = fs::read_to_string(&trace)
+                .await
+                .with_context(|| format!("Failed to read trace file: {}", trace.display()))?;
+            let steps = replay::parse_trace(&text)?;
+            info!(
+                "Replaying {} packets from {}...",
+                steps.len(),
+                trace.display()
+            );
+
+            let mut differences = 0;
+            let mut last_sent = tokio::time::Instant::now();
+            for (index, step) in steps.iter().enumerate() {
+                if !no_delay {
+                    tokio::time::sleep_until(last_sent + step.delay).await;
+                }
+                last_sent = tokio::time::Instant::now();
+
+                let packet = &step.packet;
+                let statuses = flash_commands
+                    .replay_packet(packet, step.recorded.len())
+                    .await
+                    .with_context(|| {
+                        format!(
+                            "Packet {} ({:?} at 0x{:08X})",
+                            index + 1,
+                            packet.command,
+                            packet.address
+                        )
+                    })?;
+                if statuses != step.recorded {
+                    differences += 1;
+                    println!(
+                        "Packet {} ({:?} at 0x{:08X}): recorded {:?}, got {:?}",
+                        index + 1,
+                        packet.command,
+                        packet.address,
+                        step.recorded,
+                        statuses
+                    );
+                }
+            }
+
+            println!(
+                "Replayed {} packets; {} answered differently than recorded",
+                steps.len(),
+                differences
+            );
+        }
+    }
+
+    info!("Operation completed successfully!");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration_accepts_units_and_bare_seconds() {
+        assert_eq!(parse_duration("10"), Ok(Duration::from_secs(10)));
+        assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("2m"), Ok(Duration::from_secs(120)));
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert!(parse_duration("soon").is_err());
+    }
+
+    #[test]
+    fn test_expected_jedec() {
+        assert!(check_expected_jedec(0xEF4018, 0xEF4018).is_ok());
+        let message = check_expected_jedec(0xC84017, 0xEF4018)
+            .unwrap_err()
+            .to_string();
+        assert!(message.contains("0xC84017 (GigaDevice)"), "{}", message);
+        assert!(
+            message.contains("expected 0xEF4018 (Winbond)"),
+            "{}",
+            message
+        );
+
+        assert_eq!(parse_jedec_id("0xEF4018"), Ok(0xEF4018));
+        assert!(parse_jedec_id("0x1EF4018").is_err());
+    }
+
+    #[test]
+    fn test_file_slice_bounds() {
+        assert_eq!(file_slice(0x3000, 0, None).unwrap(), 0..0x3000);
+        assert_eq!(file_slice(0x3000, 0x1000, None).unwrap(), 0x1000..0x3000);
+        assert_eq!(
+            file_slice(0x3000, 0x1000, Some(0x800)).unwrap(),
+            0x1000..0x1800
+        );
+        assert_eq!(file_slice(0x3000, 0, Some(0x3000)).unwrap(), 0..0x3000);
+        // Empty selections are allowed; the write then does nothing
+        assert_eq!(file_slice(0, 0, None).unwrap(), 0..0);
+        assert_eq!(file_slice(0x3000, 0x3000, None).unwrap(), 0x3000..0x3000);
+        assert_eq!(file_slice(0x3000, 0x1000, Some(0)).unwrap(), 0x1000..0x1000);
+
+        assert!(file_slice(0x3000, 0x3001, None).is_err());
+        assert!(file_slice(0x3000, 0x2000, Some(0x1001)).is_err());
+    }
+
+    #[test]
+    fn test_check_fits_reports_overflow() {
+        let geometry = FlashGeometry::W25Q128;
+        assert!(check_fits(&geometry, 0, 16 * 1024 * 1024).is_ok());
+        assert!(check_fits(&geometry, 0xFF_F000, 0x1000).is_ok());
+        let error = check_fits(&geometry, 0xFF_F000, 0x1800).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "image of 6144 bytes at 0x00FFF000 exceeds 16MB flash by 2048 bytes"
+        );
+    }
+
+    #[test]
+    fn test_append_image_footer_aligns_after_data() {
+        let mut data = vec![1, 2, 3, 4, 5];
+        let footer = append_image_footer(0x1002, &mut data).unwrap();
+        assert_eq!(footer.length, 5);
+        // Data ends at 0x1007, so one pad byte puts the footer at 0x1008
+        assert_eq!(data.len(), 6 + IMAGE_FOOTER_SIZE);
+        assert_eq!(data[5], 0xFF);
+        let decoded = ImageFooter::from_bytes(&data[6..]).unwrap();
+        assert!(decoded.matches(&[1, 2, 3, 4, 5]));
+
+        // A footer that would run past the end of the address space is refused
+        let mut data = vec![0; 16];
+        assert!(append_image_footer(0xFFFF_FFF0, &mut data).is_err());
+        assert_eq!(data.len(), 16);
+    }
+
+    #[test]
+    fn test_timeout_alias_sets_response_timeout() {
+        let timeouts = |args: &[&str]| {
+            let cli = Cli::try_parse_from(
+                ["flash-programmer-tool"]
+                    .iter()
+                    .chain(args)
+                    .chain(&["info"]),
+            )
+            .unwrap();
+            (cli.connect_timeout, cli.response_timeout)
+        };
+        assert_eq!(
+            timeouts(&[]),
+            (Duration::from_secs(5), Duration::from_secs(30))
+        );
+        assert_eq!(
+            timeouts(&["--connect-timeout", "500ms"]),
+            (Duration::from_millis(500), Duration::from_secs(30))
+        );
+        // Older scripts lengthen long erases and reads with -t/--timeout
+        assert_eq!(
+            timeouts(&["--timeout", "2m"]),
+            (Duration::from_secs(5), Duration::from_secs(120))
+        );
+        assert_eq!(
+            timeouts(&["-t", "1m"]),
+            (Duration::from_secs(5), Duration::from_secs(60))
+        );
+    }
+
+    #[test]
+    fn test_config_file_values_are_overridden_by_flags() {
+        let config = vec![
+            ("port".to_string(), vec!["/dev/ttyACM1".to_string()]),
+            ("connect_timeout".to_string(), vec!["10s".to_string()]),
+            ("crc16".to_string(), vec!["true".to_string()]),
+        ];
+        let parse = |args: &[&str]| {
+            let command = defaults::apply(Cli::command(), &config).unwrap();
+            let matches = defaults::get_matches_from(
+                &command,
+                ["flash-programmer-tool"].iter().chain(args),
+                &config,
+            )
+            .unwrap();
+            Cli::from_arg_matches(&matches).unwrap()
+        };
+
+        let cli = parse(&["info"]);
+        assert_eq!(cli.port, "/dev/ttyACM1");
+        assert_eq!(cli.connect_timeout, Duration::from_secs(10));
+        assert!(cli.crc16);
+        assert_eq!(cli.response_timeout, Duration::from_secs(30));
+
+        let cli = parse(&["--port", "/dev/ttyUSB0", "--connect-timeout", "1", "info"]);
+        assert_eq!(cli.port, "/dev/ttyUSB0");
+        assert_eq!(cli.connect_timeout, Duration::from_secs(1));
+
+        let cli = parse(&["--no-crc16", "info"]);
+        assert!(!cli.crc16);
+
+        let unknown = vec![("speed".to_string(), vec!["1".to_string()])];
+        assert!(defaults::apply(Cli::command(), &unknown).is_err());
+    }
+
+    #[test]
+    fn test_config_file_flags_yield_to_conflicting_flags() {
+        let parse = |config: &[(&str, &str)], args: &[&str]| {
+            let config: Vec<_> = config
+                .iter()
+                .map(|(id, value)| (id.to_string(), vec![value.to_string()]))
+                .collect();
+            let command = defaults::apply(Cli::command(), &config).unwrap();
+            defaults::get_matches_from(
+                &command,
+                ["flash-programmer-tool"].iter().chain(args),
+                &config,
+            )
+            .map(|matches| Cli::from_arg_matches(&matches).unwrap())
+        };
+
+        let cli = parse(&[("quiet", "true")], &["--verbose", "info"]).unwrap();
+        assert!(cli.verbose);
+        assert!(!cli.quiet);
+
+        let cli = parse(&[("quiet", "true")], &["info"]).unwrap();
+        assert!(cli.quiet);
+
+        let cli = parse(&[("all", "true")], &["--ports", "a,b", "info"]).unwrap();
+        assert!(!cli.all);
+        assert_eq!(cli.ports, ["a", "b"]);
+
+        // Conflicts within the file itself are still errors
+        assert!(parse(&[("quiet", "true"), ("verbose", "true")], &["info"]).is_err());
+    }
+
+    #[test]
+    fn test_write_verifies_unless_no_verify() {
+        let write = |args: &[&str]| -> Result<bool, clap::Error> {
+            let cli = Cli::try_parse_from(
+                ["flash-programmer-tool", "write", "-f", "x.bin"]
+                    .iter()
+                    .chain(args),
+            )?;
+            match cli.command {
+                Commands::Write { no_verify, .. } => Ok(no_verify),
+                _ => unreachable!(),
+            }
+        };
+
+        assert!(!write(&[]).unwrap());
+        assert!(!write(&["--verify"]).unwrap());
+        assert!(write(&["--no-verify"]).unwrap());
+        assert!(write(&["--no-verify", "--retries", "2"]).is_err());
+        assert!(write(&["--no-verify", "--verify-mode", "interleaved"]).is_err());
+    }
+
+    #[test]
+    fn test_read_until_blank_args() {
+        let read = |args: &[&str]| -> Result<(Option<u32>, Option<u32>), clap::Error> {
+            let cli = Cli::try_parse_from(
+                ["flash-programmer-tool", "read", "-f", "dump.bin"]
+                    .iter()
+                    .chain(args),
+            )?;
+            match cli.command {
+                Commands::Read {
+                    size, until_blank, ..
+                } => Ok((size, until_blank)),
+                _ => unreachable!(),
+            }
+        };
+
+        assert_eq!(read(&["--until-blank"]).unwrap(), (None, Some(4)));
+        assert_eq!(
+            read(&["--until-blank", "16", "-s", "0x100000"]).unwrap(),
+            (Some(0x100000), Some(16))
+        );
+        assert!(read(&[]).is_err());
+        assert!(read(&["--until-blank", "0"]).is_err());
+        assert!(read(&["--until-blank", "--append", "-s", "0x1000"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_verify_block_size_requires_whole_sectors() {
+        assert_eq!(parse_verify_block_size("0x1000"), Ok(4096));
+        assert_eq!(parse_verify_block_size("65536"), Ok(65536));
+        assert!(parse_verify_block_size("0").is_err());
+        assert!(parse_verify_block_size("0x1800").is_err());
+        assert!(parse_verify_block_size("0x200000").is_err());
+    }
+
+    #[test]
+    fn test_parse_read_chunk_size_bounds() {
+        assert_eq!(parse_read_chunk_size("0x400"), Ok(1024));
+        assert_eq!(parse_read_chunk_size("65536"), Ok(MAX_READ_CHUNK_SIZE));
+        assert!(parse_read_chunk_size("0").is_err());
+        assert!(parse_read_chunk_size("0x10001").is_err());
+    }
+}
@@ -0,0 +1,407 @@
+//! Rasterize a BDF or TrueType/OpenType font into the firmware's flash font
+//! layout (`make-font`)
+//!
+//! Every glyph is drawn into a cell as tall as the font's ascent plus
+//! descent, with the baseline at the same row. The firmware aligns glyphs by
+//! their bottom edge, so equal-height cells keep descenders below the line.
+
+use anyhow::{anyhow, bail, Context, Result};
+use flash_protocol::font::{self, Glyph, KernPair, FONT_REGION_SIZE};
+use flash_protocol::glyph::BitmapFormat;
+use fontdue::{Font, FontSettings};
+
+/// Coverage at or above which an anti-aliased pixel is set
+const COVERAGE_THRESHOLD: u8 = 128;
+
+/// Characters every font gets: printable ASCII
+pub fn default_chars() -> impl Iterator<Item = char> {
+    ' '..='~'
+}
+
+/// Vertical metrics shared by every glyph of a font
+#[derive(Debug, Clone, Copy)]
+struct Cell {
+    /// Rows above the baseline
+    ascent: i32,
+    height: u8,
+}
+
+impl Cell {
+    fn new(ascent: i32, descent: i32) -> Result<Self> {
+        let height = u8::try_from(ascent + descent)
+            .ok()
+            .filter(|&h| h > 0)
+            .ok_or_else(|| anyhow!("Font line height {} is outside 1..=255", ascent + descent))?;
+        Ok(Self { ascent, height })
+    }
+
+    /// Draw a `glyph_width` x `glyph_height` box whose bottom-left pixel sits
+    /// `left` pixels right of the cell's edge and `bottom` pixels above the
+    /// baseline into a `width`-wide cell bitmap
+    fn draw(
+        &self,
+        unicode: u32,
+        width: i32,
+        (left, bottom): (i32, i32),
+        (glyph_width, glyph_height): (usize, usize),
+        pixel: impl Fn(usize, usize) -> bool,
+    ) -> Result<Glyph> {
+        let width = u8::try_from(width.max(0))
+            .map_err(|_| anyhow!("Glyph U+{:04X} is wider than 255 pixels", unicode))?;
+        let format = BitmapFormat::default();
+        let mut bitmap = vec![0u8; format.bitmap_size(width, self.height)];
+        let bytes_per_row = (width as usize).div_ceil(8);
+
+        let top = self.ascent - bottom - glyph_height as i32;
+        for row in 0..glyph_height {
+            for col in 0..glyph_width {
+                let x = left + col as i32;
+                let y = top + row as i32;
+                // Ink outside the cell (overhangs, very tall accents) is dropped
+                if x < 0 || y < 0 || x >= width as i32 || y >= self.height as i32 {
+                    continue;
+                }
+                if pixel(col, row) {
+                    bitmap[y as usize * bytes_per_row + x as usize / 8] |= 0x80 >> (x % 8);
+                }
+            }
+        }
+
+        Ok(Glyph {
+            unicode,
+            width,
+            height: self.height,
+            bitmap,
+        })
+    }
+}
+
+/// A built font image
+pub struct FontImage {
+    pub data: Vec<u8>,
+    pub glyph_count: u32,
+    pub kern_pairs: usize,
+    /// Requested characters the source font has no glyph for
+    pub missing: Vec<char>,
+}
+
+/// Rasterize the requested characters and build a checked font image
+///
+/// Characters the font doesn't have are skipped and reported in `missing`.
+/// With `kerning`, the TrueType/OpenType kerning between the included
+/// characters is kept as a kerning table.
+pub fn make_font(
+    data: &[u8],
+    size: f32,
+    chars: impl IntoIterator<Item = char>,
+    kerning: bool,
+) -> Result<FontImage> {
+    let mut chars: Vec<char> = chars.into_iter().collect();
+    chars.sort_unstable();
+    chars.dedup();
+
+    let (mut glyphs, mut kern_pairs, missing) = if data.starts_with(b"STARTFONT") {
+        if kerning {
+            bail!("BDF fonts carry no kerning data; drop --kerning");
+        }
+        let text = std::str::from_utf8(data).context("BDF font is not valid UTF-8")?;
+        let (glyphs, missing) = rasterize_bdf(text, &chars)?;
+        (glyphs, Vec::new(), missing)
+    } else {
+        rasterize_outline(data, size, &chars, kerning)?
+    };
+
+    let image = font::encode_font(&mut glyphs, &mut kern_pairs, BitmapFormat::default())
+        .map_err(|e| anyhow!("Failed to build font: {}", e))?;
+    let glyph_count =
+        font::check_font(&image).map_err(|e| anyhow!("Built font is invalid: {}", e))?;
+    if image.len() > FONT_REGION_SIZE as usize {
+        bail!(
+            "Font is {} bytes, more than the {}-byte font region; use fewer characters or a smaller size",
+            image.len(),
+            FONT_REGION_SIZE
+        );
+    }
+    Ok(FontImage {
+        data: image,
+        glyph_count,
+        kern_pairs: kern_pairs.len(),
+        missing,
+    })
+}
+
+/// Rasterize a TrueType/OpenType font at `size` pixels, with the non-zero
+/// kerning between its glyphs if `kerning`
+fn rasterize_outline(
+    data: &[u8],
+    size: f32,
+    chars: &[char],
+    kerning: bool,
+) -> Result<(Vec<Glyph>, Vec<KernPair>, Vec<char>)> {
+    let font = Font::from_bytes(
+        data,
+        FontSettings {
+            scale: size,
+            ..FontSettings::default()
+        },
+    )
+    .map_err(|e| anyhow!("Failed to parse font: {}", e))?;
+    let line = font
+        .horizontal_line_metrics(size)
+        .ok_or_else(|| anyhow!("Font has no horizontal line metrics"))?;
+    let cell = Cell::new(line.ascent.ceil() as i32, (-line.descent).ceil() as i32)?;
+
+    let mut glyphs = Vec::new();
+    let mut missing = Vec::new();
+    for &ch in chars {
+        if !font.has_glyph(ch) {
+            missing.push(ch);
+            continue;
+        }
+        let (metrics, coverage) = font.rasterize(ch, size);
+        let width = (metrics.advance_width.round() as i32).max(metrics.xmin + metrics.width as i32);
+        glyphs.push(cell.draw(
+            ch as u32,
+            width,
+            (metrics.xmin, metrics.ymin),
+            (metrics.width, metrics.height),
+            |col, row| coverage[row * metrics.width + col] >= COVERAGE_THRESHOLD,
+        )?);
+    }
+
+    let mut kern_pairs = Vec::new();
+    if kerning {
+        let present: Vec<char> = chars
+            .iter()
+            .copied()
+            .filter(|&ch| font.has_glyph(ch))
+            .collect();
+        for &left in &present {
+            for &right in &present {
+                let Some(adjust) = font.horizontal_kern(left, right, size) else {
+                    continue;
+                };
+                let adjust = adjust.round().clamp(i8::MIN as f32, i8::MAX as f32) as i8;
+                if adjust != 0 {
+                    kern_pairs.push(KernPair {
+                        left: left as u32,
+                        right: right as u32,
+                        adjust,
+                    });
+                }
+            }
+        }
+    }
+    Ok((glyphs, kern_pairs, missing))
+}
+
+/// Convert the requested characters of a BDF bitmap font
+fn rasterize_bdf(text: &str, chars: &[char]) -> Result<(Vec<Glyph>, Vec<char>)> {
+    let mut ascent = None;
+    let mut descent = None;
+    let mut bounding_box = None;
+    let mut found = Vec::new();
+
+    let mut lines = text.lines().enumerate();
+    while let Some((index, line)) = lines.next() {
+        let mut fields = line.split_whitespace();
+        match fields.next() {
+            Some("FONT_ASCENT") => ascent = Some(bdf_numbers(index, fields)?[0]),
+            Some("FONT_DESCENT") => descent = Some(bdf_numbers(index, fields)?[0]),
+            Some("FONTBOUNDINGBOX") => bounding_box = Some(bdf_numbers(index, fields)?),
+            Some("STARTCHAR") => {
+                let glyph = BdfGlyph::parse(&mut lines)?;
+                if let Some(ch) = char::from_u32(glyph.encoding as u32) {
+                    if chars.binary_search(&ch).is_ok() {
+                        found.push((ch, glyph));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Without FONT_ASCENT/FONT_DESCENT properties the font bounding box gives them
+    let (ascent, descent) = match (ascent, descent, bounding_box) {
+        (Some(ascent), Some(descent), _) => (ascent, descent),
+        (_, _, Some(bbx)) if bbx.len() == 4 => (bbx[1] + bbx[3], -bbx[3]),
+        _ => bail!("BDF font has neither FONT_ASCENT/FONT_DESCENT nor FONTBOUNDINGBOX"),
+    };
+    let cell = Cell::new(ascent, descent)?;
+
+    let mut glyphs = Vec::new();
+    for (ch, glyph) in &found {
+        let bytes_per_row = glyph.width.div_ceil(8);
+        glyphs.push(cell.draw(
+            *ch as u32,
+            glyph.advance.max(glyph.x_offset + glyph.width as i32),
+            (glyph.x_offset, glyph.y_offset),
+            (glyph.width, glyph.rows.len() / bytes_per_row.max(1)),
+            |col, row| glyph.rows[row * bytes_per_row + col / 8] & (0x80 >> (col % 8)) != 0,
+        )?);
+    }
+
+    let missing = chars
+        .iter()
+        .copied()
+        .filter(|ch| !found.iter().any(|(found, _)| found == ch))
+        .collect();
+    Ok((glyphs, missing))
+}
+
+/// One STARTCHAR..ENDCHAR block
+struct BdfGlyph {
+    encoding: i32,
+    advance: i32,
+    width: usize,
+    x_offset: i32,
+    y_offset: i32,
+    /// Bitmap rows, MSB first, each padded to whole bytes
+    rows: Vec<u8>,
+}
+
+impl BdfGlyph {
+    fn parse<'a>(lines: &mut impl Iterator<Item = (usize, &'a str)>) -> Result<Self> {
+        let mut glyph = BdfGlyph {
+            encoding: -1,
+            advance: 0,
+            width: 0,
+            x_offset: 0,
+            y_offset: 0,
+            rows: Vec::new(),
+        };
+        let mut height = 0;
+        let mut in_bitmap = false;
+
+        for (index, line) in lines {
+            let mut fields = line.split_whitespace();
+            let keyword = fields.next();
+            if keyword == Some("ENDCHAR") {
+                let bytes_per_row = glyph.width.div_ceil(8);
+                if glyph.rows.len() != bytes_per_row * height {
+                    bail!(
+                        "BDF line {}: glyph {} has {} bitmap bytes, expected {}",
+                        index + 1,
+                        glyph.encoding,
+                        glyph.rows.len(),
+                        bytes_per_row * height
+                    );
+                }
+                return Ok(glyph);
+            }
+            if in_bitmap {
+                let row = hex::decode(line.trim())
+                    .with_context(|| format!("BDF line {}: invalid bitmap row", index + 1))?;
+                // Rows carry at least enough bytes for the box width
+                let bytes_per_row = glyph.width.div_ceil(8);
+                if row.len() < bytes_per_row {
+                    bail!("BDF line {}: bitmap row too short", index + 1);
+                }
+                glyph.rows.extend_from_slice(&row[..bytes_per_row]);
+                continue;
+            }
+            match keyword {
+                Some("ENCODING") => glyph.encoding = bdf_numbers(index, fields)?[0],
+                Some("DWIDTH") => glyph.advance = bdf_numbers(index, fields)?[0],
+                Some("BBX") => {
+                    let bbx = bdf_numbers(index, fields)?;
+                    if bbx.len() != 4 || bbx[0] < 0 || bbx[1] < 0 {
+                        bail!("BDF line {}: BBX needs width height xoff yoff", index + 1);
+                    }
+                    glyph.width = bbx[0] as usize;
+                    height = bbx[1] as usize;
+                    glyph.x_offset = bbx[2];
+                    glyph.y_offset = bbx[3];
+                }
+                Some("BITMAP") => in_bitmap = true,
+                _ => {}
+            }
+        }
+        bail!("BDF font ends inside glyph {}", glyph.encoding)
+    }
+}
+
+/// The integer fields of a BDF line (at least one)
+fn bdf_numbers<'a>(index: usize, fields: impl Iterator<Item = &'a str>) -> Result<Vec<i32>> {
+    let numbers = fields
+        .map(str::parse)
+        .collect::<Result<Vec<i32>, _>>()
+        .with_context(|| format!("BDF line {}: expected numbers", index + 1))?;
+    if numbers.is_empty() {
+        bail!("BDF line {}: missing value", index + 1);
+    }
+    Ok(numbers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 4px wide, ascent 4 / descent 1; 'g' descends one row below the baseline
+    const BDF: &str = "STARTFONT 2.1
+FONT test
+SIZE 5 75 75
+FONTBOUNDINGBOX 4 5 0 -1
+STARTPROPERTIES 2
+FONT_ASCENT 4
+FONT_DESCENT 1
+ENDPROPERTIES
+CHARS 2
+STARTCHAR g
+ENCODING 103
+SWIDTH 500 0
+DWIDTH 4 0
+BBX 3 4 0 -1
+BITMAP
+E0
+A0
+E0
+20
+ENDCHAR
+STARTCHAR A
+ENCODING 65
+SWIDTH 500 0
+DWIDTH 4 0
+BBX 3 3 0 0
+BITMAP
+40
+E0
+A0
+ENDCHAR
+ENDFONT
+";
+
+    #[test]
+    fn test_bdf_glyphs_share_a_baseline() {
+        let image = make_font(BDF.as_bytes(), 16.0, "Agz".chars(), false).unwrap();
+        assert_eq!(image.missing, ['z']);
+        assert_eq!(image.glyph_count, 2);
+        assert!(make_font(BDF.as_bytes(), 16.0, "A".chars(), true).is_err());
+
+        let (glyphs, _) = rasterize_bdf(BDF, &['A', 'g']).unwrap();
+        let a = glyphs.iter().find(|g| g.unicode == 'A' as u32).unwrap();
+        assert_eq!((a.width, a.height), (4, 5));
+        // One blank row above, the 3 rows of 'A', then the descender row
+        assert_eq!(a.bitmap, [0x00, 0x40, 0xE0, 0xA0, 0x00]);
+
+        let g = glyphs.iter().find(|g| g.unicode == 'g' as u32).unwrap();
+        assert_eq!(g.bitmap, [0x00, 0xE0, 0xA0, 0xE0, 0x20]);
+    }
+
+    #[test]
+    fn test_cell_drops_ink_outside_it() {
+        let cell = Cell::new(2, 1).unwrap();
+        // 3x4 solid box starting one pixel left of the cell, bottom on the descender row
+        let glyph = cell.draw(0x41, 2, (-1, -1), (3, 4), |_, _| true).unwrap();
+        assert_eq!((glyph.width, glyph.height), (2, 3));
+        assert_eq!(glyph.bitmap, [0xC0, 0xC0, 0xC0]);
+
+        assert!(Cell::new(0, 0).is_err());
+    }
+
+    #[test]
+    fn test_truncated_bdf_is_rejected() {
+        let truncated = &BDF[..BDF.find("ENDCHAR").unwrap()];
+        assert!(make_font(truncated.as_bytes(), 16.0, default_chars(), false).is_err());
+    }
+}
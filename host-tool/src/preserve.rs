@@ -0,0 +1,109 @@
+//! Regions kept intact across an erase (`--preserve`)
+//!
+//! Erases work on whole sectors (4KB, or 64KB on some chips), so a region
+//! sharing a sector with the erased range is lost even if it lies outside
+//! it. Such regions are read before the erase and programmed back
+//! afterwards.
+
+use anyhow::{bail, Result};
+use flash_protocol::geometry::FlashGeometry;
+use flash_protocol::segments::{find_overlap, Segment};
+
+/// Parts of the erased sectors outside `written`: the data an erase of
+/// `written` destroys without the write putting anything back
+pub fn neighbors(written: Segment, geometry: &FlashGeometry) -> Vec<Segment> {
+    let erased = geometry.erase_span(written.address, written.length);
+    let mut regions = Vec::new();
+    if written.address > erased.address {
+        regions.push(Segment::new(
+            erased.address,
+            written.address - erased.address,
+        ));
+    }
+    if erased.end() > written.end() {
+        regions.push(Segment::new(
+            written.end() as u32,
+            (erased.end() - written.end()) as u32,
+        ));
+    }
+    regions
+}
+
+/// Preserved regions the erase would destroy and that must be saved
+pub fn regions_to_save(preserve: &[Segment], erased: Segment) -> Vec<Segment> {
+    preserve
+        .iter()
+        .copied()
+        .filter(|region| find_overlap(&[*region, erased]).is_some())
+        .collect()
+}
+
+/// Refuse preserved regions that overlap the data being written (or each
+/// other), since restoring them would overwrite the new data
+pub fn check_preserved(preserve: &[Segment], written: Segment) -> Result<()> {
+    if let Some((a, b)) = find_overlap(preserve) {
+        bail!("Preserved regions {} and {} overlap", a, b);
+    }
+    if let Some(region) = preserve
+        .iter()
+        .find(|region| find_overlap(&[**region, written]).is_some())
+    {
+        bail!(
+            "Preserved region {} overlaps the data being written ({})",
+            region,
+            written
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_regions_sharing_an_erased_sector_are_saved() {
+        // Writing 0x100 bytes at 0x1010 erases the whole 0x1000 sector
+        let erased = FlashGeometry::W25Q128.erase_span(0x1010, 0x100);
+        assert_eq!(erased, Segment::new(0x1000, 0x1000));
+
+        let preserve = [
+            Segment::new(0x1F00, 0x100),
+            Segment::new(0x2000, 0x100),
+            Segment::new(0x0000, 0x1000),
+        ];
+        assert_eq!(
+            regions_to_save(&preserve, erased),
+            vec![Segment::new(0x1F00, 0x100)]
+        );
+    }
+
+    #[test]
+    fn test_neighbors_of_partial_sectors() {
+        let geometry = FlashGeometry::W25Q128;
+        assert!(neighbors(Segment::new(0x1000, 0x2000), &geometry).is_empty());
+        assert_eq!(
+            neighbors(Segment::new(0x1010, 0x1000), &geometry),
+            vec![Segment::new(0x1000, 0x10), Segment::new(0x2010, 0xFF0)]
+        );
+
+        // A chip that only erases 64KB loses far more around the same write
+        let geometry = FlashGeometry::from_jedec_id(0x202017);
+        assert_eq!(
+            neighbors(Segment::new(0x1010, 0x1000), &geometry),
+            vec![Segment::new(0x0, 0x1010), Segment::new(0x2010, 0xDFF0)]
+        );
+    }
+
+    #[test]
+    fn test_check_preserved_rejects_overlap() {
+        let written = Segment::new(0x0000, 0x8000);
+        assert!(check_preserved(&[Segment::new(0x8000, 0x1000)], written).is_ok());
+
+        let err = check_preserved(&[Segment::new(0x7FF0, 0x20)], written).unwrap_err();
+        assert!(err.to_string().contains("0x00007FF0-0x0000800F"), "{}", err);
+
+        let twice = [Segment::new(0x9000, 0x100), Segment::new(0x9080, 0x100)];
+        assert!(check_preserved(&twice, written).is_err());
+    }
+}
@@ -0,0 +1,163 @@
+//! Progress output for terminals and logs (`--progress-format`)
+//!
+//! Progress is always tracked with an indicatif [`ProgressBar`]; the format
+//! only picks where it is drawn. `plain` draws through [`PlainLines`], which
+//! prints a line each time the bar passes another [`PLAIN_STEP_PERCENT`]
+//! instead of redrawing in place, so CI logs and pipes get no carriage
+//! returns.
+
+use clap::ValueEnum;
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressState, ProgressStyle, TermLike};
+use std::io::{IsTerminal, Write as _};
+use std::sync::Mutex;
+
+/// Percentage between two lines of `plain` progress
+pub const PLAIN_STEP_PERCENT: u32 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ProgressFormat {
+    /// `bar` on a terminal, `plain` when stdout or stderr is redirected
+    Auto,
+    /// Redrawn progress bars
+    Bar,
+    /// A line every 10%
+    Plain,
+    /// No progress output
+    None,
+}
+
+impl ProgressFormat {
+    /// The format to draw with; `--quiet` hides progress whatever was asked
+    pub fn resolve(self, quiet: bool) -> Self {
+        match self {
+            _ if quiet => Self::None,
+            Self::Auto if std::io::stdout().is_terminal() && std::io::stderr().is_terminal() => {
+                Self::Bar
+            }
+            Self::Auto => Self::Plain,
+            format => format,
+        }
+    }
+}
+
+/// A bar of `len` printing plain lines to stderr, each starting with `prefix`
+pub fn plain_progress_bar(len: u64, prefix: Option<String>, refresh_hz: u8) -> ProgressBar {
+    let pb = ProgressBar::with_draw_target(
+        Some(len),
+        ProgressDrawTarget::term_like_with_hz(Box::new(PlainLines::default()), refresh_hz),
+    );
+    let style = ProgressStyle::default_bar()
+        .template("{prefix}{step} {msg}")
+        .unwrap()
+        .with_key(
+            "step",
+            |state: &ProgressState, w: &mut dyn std::fmt::Write| {
+                let _ = write!(w, "{}%", plain_step(state.fraction()));
+            },
+        );
+    pb.set_style(style);
+    if let Some(prefix) = prefix {
+        pb.set_prefix(format!("[{}] ", prefix));
+    }
+    pb
+}
+
+/// `fraction` rounded down to a multiple of [`PLAIN_STEP_PERCENT`]
+fn plain_step(fraction: f32) -> u32 {
+    let percent = (fraction.clamp(0.0, 1.0) * 100.0) as u32;
+    percent - percent % PLAIN_STEP_PERCENT
+}
+
+/// Draw target that prints each distinct rendered line once
+///
+/// Redraws of an unchanged line (the bar only moved within a step) and the
+/// cursor movement indicatif uses to redraw in place are dropped.
+#[derive(Debug, Default)]
+struct PlainLines {
+    lines: Mutex<PlainState>,
+}
+
+#[derive(Debug, Default)]
+struct PlainState {
+    current: String,
+    last: String,
+}
+
+impl PlainLines {
+    /// The line to print for what was drawn since the last flush, if it is new
+    fn take_line(&self) -> Option<String> {
+        let mut state = self.lines.lock().unwrap();
+        let line = std::mem::take(&mut state.current).trim().to_string();
+        if line.is_empty() || line == state.last {
+            return None;
+        }
+        state.last = line.clone();
+        Some(line)
+    }
+}
+
+impl TermLike for PlainLines {
+    fn width(&self) -> u16 {
+        // Wider than any line drawn, so indicatif never wraps one
+        256
+    }
+
+    fn move_cursor_up(&self, _n: usize) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn move_cursor_down(&self, _n: usize) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn move_cursor_right(&self, _n: usize) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn move_cursor_left(&self, _n: usize) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn write_line(&self, s: &str) -> std::io::Result<()> {
+        self.write_str(s)
+    }
+
+    fn write_str(&self, s: &str) -> std::io::Result<()> {
+        self.lines.lock().unwrap().current.push_str(s);
+        Ok(())
+    }
+
+    fn clear_line(&self) -> std::io::Result<()> {
+        self.lines.lock().unwrap().current.clear();
+        Ok(())
+    }
+
+    fn flush(&self) -> std::io::Result<()> {
+        match self.take_line() {
+            Some(line) => writeln!(std::io::stderr(), "{}", line),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_lines_print_each_step_once() {
+        assert_eq!(plain_step(0.0), 0);
+        assert_eq!(plain_step(0.199), 10);
+        assert_eq!(plain_step(1.0), 100);
+
+        let lines = PlainLines::default();
+        lines.write_str("\r10% ").unwrap();
+        assert_eq!(lines.take_line().as_deref(), Some("10%"));
+        lines.clear_line().unwrap();
+        lines.write_str("\r10% ").unwrap();
+        assert_eq!(lines.take_line(), None);
+        lines.write_str("100% Read completed!").unwrap();
+        assert_eq!(lines.take_line().as_deref(), Some("100% Read completed!"));
+        assert_eq!(lines.take_line(), None);
+    }
+}
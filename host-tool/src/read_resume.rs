@@ -0,0 +1,172 @@
+//! Resumable read state for `read --append`
+//!
+//! Progress is tracked in a small sidecar file next to the output
+//! (`<file>.offset`) so an interrupted dump can continue where it stopped.
+
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+
+/// Persisted progress of a partial read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadProgress {
+    /// Flash start address of the whole read
+    pub address: u32,
+    /// Total number of bytes to read
+    pub size: u32,
+    /// Bytes already written to the output file
+    pub offset: u32,
+}
+
+impl ReadProgress {
+    /// Sidecar path for an output file
+    pub fn sidecar_path(file: &Path) -> PathBuf {
+        let mut name = file.as_os_str().to_owned();
+        name.push(".offset");
+        PathBuf::from(name)
+    }
+
+    /// Load progress from the sidecar, or `None` if there is none
+    pub async fn load(file: &Path) -> Result<Option<Self>> {
+        let path = Self::sidecar_path(file);
+        match tokio::fs::read_to_string(&path).await {
+            Ok(text) => Self::parse(&text)
+                .map(Some)
+                .with_context(|| format!("Invalid read sidecar: {:?}", path)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Failed to read sidecar: {:?}", path)),
+        }
+    }
+
+    /// Write progress to the sidecar
+    pub async fn save(&self, file: &Path) -> Result<()> {
+        let path = Self::sidecar_path(file);
+        tokio::fs::write(&path, self.to_string())
+            .await
+            .with_context(|| format!("Failed to write sidecar: {:?}", path))
+    }
+
+    /// Delete the sidecar once the read has completed
+    pub async fn remove(file: &Path) -> Result<()> {
+        let path = Self::sidecar_path(file);
+        match tokio::fs::remove_file(&path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("Failed to remove sidecar: {:?}", path))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Work out where to continue reading `address`/`size` into `file`
+    ///
+    /// Fails if the sidecar describes a different read or the file length
+    /// does not match the recorded offset.
+    pub async fn resume(file: &Path, address: u32, size: u32) -> Result<Self> {
+        let existing_len = match tokio::fs::metadata(file).await {
+            Ok(metadata) => Some(metadata.len()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e).with_context(|| format!("Failed to stat {:?}", file)),
+        };
+
+        let progress = match (Self::load(file).await?, existing_len) {
+            (None, None) | (None, Some(0)) => Self {
+                address,
+                size,
+                offset: 0,
+            },
+            (None, Some(len)) => bail!(
+                "{:?} already has {} bytes but no {:?}; remove it or drop --append",
+                file,
+                len,
+                Self::sidecar_path(file)
+            ),
+            (Some(progress), len) => {
+                if progress.address != address || progress.size != size {
+                    bail!(
+                        "Sidecar is for a read of {} bytes at 0x{:08X}, not {} bytes at 0x{:08X}",
+                        progress.size,
+                        progress.address,
+                        size,
+                        address
+                    );
+                }
+                let len = len.unwrap_or(0);
+                if len != progress.offset as u64 {
+                    bail!(
+                        "{:?} is {} bytes but the sidecar expects {}; refusing to append",
+                        file,
+                        len,
+                        progress.offset
+                    );
+                }
+                progress
+            }
+        };
+
+        Ok(progress)
+    }
+
+    fn parse(text: &str) -> Result<Self> {
+        let mut address = None;
+        let mut size = None;
+        let mut offset = None;
+
+        for line in text.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let value = value.trim();
+            let parsed = match value.strip_prefix("0x") {
+                Some(hex) => u32::from_str_radix(hex, 16),
+                None => value.parse(),
+            }
+            .with_context(|| format!("Invalid value for {}: {}", key, value))?;
+
+            match key.trim() {
+                "address" => address = Some(parsed),
+                "size" => size = Some(parsed),
+                "offset" => offset = Some(parsed),
+                _ => {}
+            }
+        }
+
+        match (address, size, offset) {
+            (Some(address), Some(size), Some(offset)) if offset <= size => Ok(Self {
+                address,
+                size,
+                offset,
+            }),
+            _ => bail!("Missing or inconsistent address/size/offset"),
+        }
+    }
+}
+
+impl std::fmt::Display for ReadProgress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "address=0x{:08X}", self.address)?;
+        writeln!(f, "size={}", self.size)?;
+        writeln!(f, "offset={}", self.offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sidecar_round_trip() {
+        let progress = ReadProgress {
+            address: 0x100000,
+            size: 16 * 1024 * 1024,
+            offset: 65536,
+        };
+        assert_eq!(
+            ReadProgress::parse(&progress.to_string()).unwrap(),
+            progress
+        );
+    }
+
+    #[test]
+    fn test_sidecar_rejects_offset_past_size() {
+        assert!(ReadProgress::parse("address=0x0\nsize=10\noffset=11\n").is_err());
+    }
+}
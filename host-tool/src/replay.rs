@@ -0,0 +1,123 @@
+//! Re-sending a `--trace-file` capture for `replay`
+//!
+//! Only `TX` lines are sent; the `RX` lines that follow each one are kept as
+//! the statuses the device answered with at capture time, so the replay can
+//! point out where the device now behaves differently.
+
+use anyhow::{anyhow, bail, Context, Result};
+use flash_protocol::{CrcMode, Packet, Response, Status};
+use std::time::{Duration, SystemTime};
+
+/// One recorded packet and what the device answered
+#[derive(Debug, Clone)]
+pub struct ReplayStep {
+    /// Time since the previous packet was sent (zero for the first)
+    pub delay: Duration,
+    pub packet: Packet,
+    /// Statuses of the responses recorded after this packet (several for streamed reads)
+    pub recorded: Vec<Status>,
+}
+
+/// Parse a trace written by `--trace-file`
+pub fn parse_trace(text: &str) -> Result<Vec<ReplayStep>> {
+    let mut steps: Vec<ReplayStep> = Vec::new();
+    let mut last_sent: Option<SystemTime> = None;
+
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let mut fields = line.split_whitespace();
+        let (Some(timestamp), Some(direction), Some(bytes)) =
+            (fields.next(), fields.next(), line.rsplit(' ').next())
+        else {
+            bail!(
+                "Trace line {}: expected timestamp, direction and bytes",
+                line_number
+            );
+        };
+        let time = humantime::parse_rfc3339(timestamp)
+            .with_context(|| format!("Trace line {}: bad timestamp", line_number))?;
+        let bytes = hex::decode(bytes)
+            .with_context(|| format!("Trace line {}: bad hex data", line_number))?;
+
+        match direction {
+            "TX" => {
+                // Traces recorded with --crc16 have 2-byte trailers (except Info)
+                let packet = Packet::from_bytes(&bytes)
+                    .or_else(|e| Packet::from_bytes_with(&bytes, CrcMode::Crc16).map_err(|_| e))
+                    .map_err(|e| anyhow!("Trace line {}: {}", line_number, e))?;
+                let delay = last_sent
+                    .and_then(|previous| time.duration_since(previous).ok())
+                    .unwrap_or_default();
+                last_sent = Some(time);
+                steps.push(ReplayStep {
+                    delay,
+                    packet,
+                    recorded: Vec::new(),
+                });
+            }
+            "RX" => {
+                let response = Response::from_bytes(&bytes)
+                    .or_else(|e| Response::from_bytes_with(&bytes, CrcMode::Crc16).map_err(|_| e))
+                    .map_err(|e| anyhow!("Trace line {}: {}", line_number, e))?;
+                let step = steps.last_mut().ok_or_else(|| {
+                    anyhow!("Trace line {}: response before any packet", line_number)
+                })?;
+                step.recorded.push(response.status);
+            }
+            other => bail!("Trace line {}: unknown direction '{}'", line_number, other),
+        }
+    }
+
+    Ok(steps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flash_protocol::Command;
+
+    fn line(time: &str, direction: &str, bytes: &[u8]) -> String {
+        format!("{} {} Info len=0 {}\n", time, direction, hex::encode(bytes))
+    }
+
+    #[test]
+    fn test_parse_trace_keeps_timing_and_responses() {
+        let info = Packet::new(Command::Info, 0, Vec::new());
+        let read = Packet::new(Command::ReadStream, 0x1000, 8u32.to_le_bytes().to_vec());
+        let ok = Response::new(Status::Success, vec![1, 2, 3, 4]);
+
+        let mut text = line("2026-01-01T00:00:00.000000Z", "TX", &info.to_bytes());
+        text += &line("2026-01-01T00:00:00.010000Z", "RX", &ok.to_bytes());
+        text += "\n";
+        text += &line("2026-01-01T00:00:00.250000Z", "TX", &read.to_bytes());
+        text += &line("2026-01-01T00:00:00.260000Z", "RX", &ok.to_bytes());
+        text += &line("2026-01-01T00:00:00.270000Z", "RX", &ok.to_bytes());
+
+        let steps = parse_trace(&text).unwrap();
+        assert_eq!(steps.len(), 2);
+        assert_eq!(steps[0].delay, Duration::ZERO);
+        assert_eq!(steps[0].packet.to_bytes(), info.to_bytes());
+        assert_eq!(steps[0].recorded, vec![Status::Success]);
+        assert_eq!(steps[1].delay, Duration::from_millis(250));
+        assert_eq!(steps[1].packet.address, 0x1000);
+        assert_eq!(steps[1].recorded.len(), 2);
+    }
+
+    #[test]
+    fn test_parse_trace_reports_line_numbers() {
+        let ok = Response::new(Status::Success, Vec::new());
+        let text = line("2026-01-01T00:00:00Z", "RX", &ok.to_bytes());
+        let err = parse_trace(&text).unwrap_err().to_string();
+        assert!(err.contains("line 1"), "{}", err);
+
+        let err = parse_trace("2026-01-01T00:00:00Z TX Info zz\n")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("bad hex"), "{}", err);
+    }
+}
@@ -1,24 +1,165 @@
 use anyhow::{Context, Result};
 use flash_protocol::*;
-use std::time::Duration;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use log::{debug, warn};
+use std::fs::File;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::time::timeout;
 use tokio_serial::SerialStream;
 
-pub struct SerialConnection {
-    port: SerialStream,
+/// Default maximum wait for a single response
+const DEFAULT_RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long to wait for the device to answer the connection handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long to wait for the reply to a command older firmware may not know
+const PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Default number of link failures in a row before a command gives up
+pub const DEFAULT_MAX_CONSECUTIVE_ERRORS: u32 = 5;
+
+/// Connection to the programmer over `P`, the USB serial port outside tests
+pub struct SerialConnection<P = SerialStream> {
+    port: P,
+    response_timeout: Duration,
+    /// Received bytes not yet consumed by a response (streamed reads send
+    /// several responses back-to-back)
+    rx_buffer: Vec<u8>,
+    /// Log of every packet sent and response received (`--trace-file`)
+    trace: Option<File>,
+    /// Sequence number for the next request; replies echo it
+    next_sequence: u16,
+    /// Trailer checksum negotiated during the handshake
+    crc_mode: CrcMode,
+    /// Trailer of the replies to the last request sent
+    reply_crc_mode: CrcMode,
+    /// Link failures since the last good reply (`--max-consecutive-errors`)
+    link_errors: ConsecutiveErrors,
 }
 
 impl SerialConnection {
-    pub async fn new(port_name: &str, baud_rate: u32) -> Result<Self> {
+    /// Open `port_name` and handshake with the programmer
+    ///
+    /// If `trace_path` is given the file is created (truncating any previous
+    /// trace) before the handshake, so the handshake exchange is logged too.
+    /// The handshake asks for `crc_mode` trailers; firmware that doesn't
+    /// support them keeps CRC-32.
+    pub async fn new(
+        port_name: &str,
+        baud_rate: u32,
+        trace_path: Option<&Path>,
+        crc_mode: CrcMode,
+    ) -> Result<Self> {
+        let trace = trace_path
+            .map(|path| {
+                File::create(path)
+                    .with_context(|| format!("Failed to create trace file: {}", path.display()))
+            })
+            .transpose()?;
+
         let port = SerialStream::open(&tokio_serial::new(port_name, baud_rate))
             .with_context(|| format!("Failed to open serial port: {}", port_name))?;
 
-        Ok(Self { port })
+        let mut connection = Self::with_port(port, trace);
+        connection.handshake(crc_mode).await.with_context(|| {
+            format!(
+                "Port {} opened but device did not respond as a flash programmer",
+                port_name
+            )
+        })?;
+
+        Ok(connection)
+    }
+}
+
+impl<P: AsyncRead + AsyncWrite + Unpin> SerialConnection<P> {
+    /// Wrap an open port, before any handshake
+    pub(crate) fn with_port(port: P, trace: Option<File>) -> Self {
+        Self {
+            port,
+            response_timeout: DEFAULT_RESPONSE_TIMEOUT,
+            rx_buffer: Vec::new(),
+            trace,
+            // The handshake goes out as sequence 0
+            next_sequence: 1,
+            crc_mode: CrcMode::Crc32,
+            reply_crc_mode: CrcMode::Crc32,
+            link_errors: ConsecutiveErrors::new(DEFAULT_MAX_CONSECUTIVE_ERRORS),
+        }
     }
 
+    /// Send an Info command requesting `crc_mode` and wait for a
+    /// well-formed response
+    ///
+    /// Any reply with the response magic and a valid CRC counts, whatever its
+    /// status, so a device with a missing flash chip still connects. Bytes
+    /// before the magic are skipped rather than treated as a failure.
+    async fn handshake(&mut self, crc_mode: CrcMode) -> Result<()> {
+        self.send_packet(&Packet::new(Command::Info, 0, vec![crc_mode as u8]))
+            .await?;
+
+        let mut buffer = Vec::new();
+        let mut temp_buf = [0u8; 256];
+        let deadline = tokio::time::Instant::now() + HANDSHAKE_TIMEOUT;
+
+        loop {
+            let n = tokio::time::timeout_at(deadline, self.port.read(&mut temp_buf))
+                .await
+                .map_err(|_| anyhow::anyhow!("No valid response within {:?}", HANDSHAKE_TIMEOUT))?
+                .context("Serial read error during handshake")?;
+            buffer.extend_from_slice(&temp_buf[..n]);
+
+            if let Some(response) = find_response(&buffer) {
+                self.trace_response(&response)?;
+                self.crc_mode = granted_crc_mode(&response);
+                if self.crc_mode != crc_mode {
+                    warn!(
+                        "Firmware doesn't support {:?} trailers, using {:?}",
+                        crc_mode, self.crc_mode
+                    );
+                }
+                return Ok(());
+            }
+            if buffer.len() > 4096 {
+                return Err(anyhow::anyhow!(
+                    "Received {} bytes without a valid response",
+                    buffer.len()
+                ));
+            }
+        }
+    }
+
+    /// Trailer checksum in use for everything but Info packets
+    pub fn crc_mode(&self) -> CrcMode {
+        self.crc_mode
+    }
+
+    /// How long each response is waited for
+    pub fn response_timeout(&self) -> Duration {
+        self.response_timeout
+    }
+
+    /// Set how long to wait for each response before giving up
+    pub fn set_response_timeout(&mut self, response_timeout: Duration) {
+        self.response_timeout = response_timeout;
+    }
+
+    /// Set how many link failures in a row a command tolerates
+    pub fn set_max_consecutive_errors(&mut self, limit: u32) {
+        self.link_errors = ConsecutiveErrors::new(limit);
+    }
+
+    /// Send `packet` as-is; its `crc` must match the negotiated mode
+    ///
+    /// No zero-length packet is needed when the packet fills whole 64-byte
+    /// USB packets: the firmware frames packets by their length field, not by
+    /// USB transfer boundaries, and the OS CDC driver owns the bulk transfer
+    /// (an empty write to the port sends nothing).
     pub async fn send_packet(&mut self, packet: &Packet) -> Result<()> {
-        let data = packet.to_bytes();
+        self.reply_crc_mode = self.crc_mode.for_command(packet.command);
+        let data = packet.to_bytes_with(self.reply_crc_mode);
 
         // Send packet
         self.port
@@ -26,66 +167,575 @@ impl SerialConnection {
             .await
             .context("Failed to write packet to serial port")?;
 
+        if let Some(trace) = &mut self.trace {
+            write_trace_line(
+                trace,
+                &format_packet_trace(SystemTime::now(), packet, &data),
+            )?;
+        }
+
         Ok(())
     }
 
+    fn trace_response(&mut self, response: &Response) -> Result<()> {
+        if let Some(trace) = &mut self.trace {
+            let bytes = response.to_bytes_with(self.reply_crc_mode);
+            write_trace_line(
+                trace,
+                &format_response_trace(SystemTime::now(), response, &bytes),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Read the next response, using its header's length field to know when
+    /// it is complete
+    ///
+    /// A header with the wrong magic or an implausible length fails at once
+    /// instead of waiting for bytes that will never come, and a response cut
+    /// short by the timeout reports how much of it arrived.
     pub async fn receive_response(&mut self) -> Result<Response> {
-        let mut buffer = Vec::new();
         let mut temp_buf = [0u8; 1024];
 
-        // Read response with timeout
         loop {
-            match timeout(Duration::from_secs(30), self.port.read(&mut temp_buf)).await {
-                Ok(Ok(n)) if n > 0 => {
-                    buffer.extend_from_slice(&temp_buf[..n]);
-
-                    // Try to parse response
-                    if let Ok(response) = Response::from_bytes(&buffer) {
-                        return Ok(response);
-                    }
+            // A previous read may already have delivered the next response
+            match response_size(&self.rx_buffer, self.reply_crc_mode) {
+                Err(e) => {
+                    self.rx_buffer.clear();
+                    return Err(e);
+                }
+                Ok(Some(size)) if self.rx_buffer.len() >= size => {
+                    let result =
+                        Response::from_bytes_with(&self.rx_buffer[..size], self.reply_crc_mode);
+                    self.rx_buffer.drain(..size);
+                    let response =
+                        result.map_err(|e| anyhow::anyhow!("Malformed response: {}", e))?;
+                    self.trace_response(&response)?;
+                    return Ok(response);
+                }
+                Ok(_) => {}
+            }
 
-                    // If buffer gets too large, something is wrong
-                    if buffer.len() > 65536 {
-                        return Err(anyhow::anyhow!("Response buffer overflow"));
-                    }
+            match timeout(self.response_timeout, self.port.read(&mut temp_buf)).await {
+                Ok(Ok(n)) if n > 0 => {
+                    self.rx_buffer.extend_from_slice(&temp_buf[..n]);
                 }
                 Ok(Ok(_)) => {
                     // No data received, continue
                     continue;
                 }
                 Ok(Err(e)) => {
-                    return Err(anyhow::anyhow!("Serial read error: {}", e));
+                    return Err(e).context("Serial read error");
                 }
                 Err(_) => {
-                    return Err(anyhow::anyhow!("Response timeout"));
+                    let error = incomplete_response_error(&self.rx_buffer, self.reply_crc_mode);
+                    self.rx_buffer.clear();
+                    return Err(error);
                 }
             }
         }
     }
 
+    /// Number `packet` with the next sequence number and send it, returning
+    /// the sequence its replies will echo
+    ///
+    /// Any sequence number the caller set is replaced, so every request on
+    /// the connection is distinct.
+    pub async fn send_request(&mut self, mut packet: Packet) -> Result<u16> {
+        let sequence = self.next_sequence;
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+        packet.sequence = sequence;
+        packet.crc = packet.calculate_crc_with(self.crc_mode.for_command(packet.command));
+        self.send_packet(&packet).await?;
+        Ok(sequence)
+    }
+
+    /// Receive the next reply to request `sequence`
+    ///
+    /// Replies to earlier requests (one that timed out, or the unread acks of
+    /// a stream write) are discarded rather than taken as this one's answer.
+    pub async fn receive_reply(&mut self, sequence: u16) -> Result<Response> {
+        loop {
+            let response = self.receive_response().await?;
+            if response.sequence == sequence {
+                return Ok(response);
+            }
+            // Expected after stream writes, whose acks are never read
+            debug!(
+                "Discarding stale {:?} response for request {} (waiting for {})",
+                response.status, response.sequence, sequence
+            );
+        }
+    }
+
     pub async fn send_packet_no_ack(&mut self, packet: Packet) -> Result<()> {
         // Send packet without waiting for ACK (for batch operations)
-        self.send_packet(&packet).await
+        self.send_request(packet).await?;
+        Ok(())
+    }
+
+    /// Send `packet` once and wait briefly for its reply, `None` if none came
+    ///
+    /// Firmware drops packets whose command byte it doesn't know, so this
+    /// tries a newer command without sitting out the full response timeout
+    /// and the resends of [`send_command`](Self::send_command).
+    pub async fn probe(&mut self, packet: Packet) -> Result<Option<Response>> {
+        let command = packet.command;
+        let sequence = self.send_request(packet).await?;
+        let response_timeout = std::mem::replace(&mut self.response_timeout, PROBE_TIMEOUT);
+        let result = self.receive_reply(sequence).await;
+        self.response_timeout = response_timeout;
+        match result {
+            Ok(response) => Ok(Some(response)),
+            Err(e) if e.is::<std::io::Error>() => Err(e),
+            Err(e) => {
+                debug!("No reply to {:?}: {:#}", command, e);
+                Ok(None)
+            }
+        }
     }
 
+    /// Send `packet` and wait for its reply, resending it after link failures
+    ///
+    /// A reply that times out, arrives garbled or reports a packet CRC error
+    /// is a link failure: the request is resent until a reply gets through or
+    /// the `--max-consecutive-errors` limit says the link is broken. Errors
+    /// from the port itself are returned at once.
+    ///
+    /// A CRC error means the device dropped the packet, so any command is
+    /// resent. Without a reply the device may already have run it, and
+    /// commands that change flash are not repeated (see [`safe_to_repeat`]):
+    /// a repeated write would fail the blank check, and a repeated chip
+    /// erase takes minutes.
     pub async fn send_command(&mut self, packet: Packet) -> Result<Response> {
-        // Send packet
-        self.send_packet(&packet).await?;
+        loop {
+            let sequence = self.send_request(packet.clone()).await?;
+            let failure = match self.receive_reply(sequence).await {
+                Ok(response) if response.status != Status::CrcError => {
+                    self.link_errors.reset();
+                    return check_status(response);
+                }
+                Ok(_) => anyhow::anyhow!("Device reported a packet CRC error"),
+                Err(e) if e.is::<std::io::Error>() => return Err(e),
+                Err(e) if !safe_to_repeat(packet.command) => {
+                    return Err(e.context(format!(
+                        "No reply to {:?}; not resending it since the device may have run it",
+                        packet.command
+                    )));
+                }
+                Err(e) => e,
+            };
+            if self.link_errors.record() {
+                return Err(failure.context(format!(
+                    "Link appears broken — {} consecutive failures",
+                    self.link_errors.count()
+                )));
+            }
+            warn!(
+                "{:#}; resending {:?} ({} consecutive failures)",
+                failure,
+                packet.command,
+                self.link_errors.count()
+            );
+        }
+    }
+}
+
+/// Whether running `command` twice has the same effect as running it once
+fn safe_to_repeat(command: Command) -> bool {
+    !matches!(
+        command,
+        Command::Write | Command::StreamWrite | Command::Erase
+    )
+}
+
+/// Link failures in a row, against the limit at which the link counts as broken
+#[derive(Debug)]
+struct ConsecutiveErrors {
+    count: u32,
+    limit: u32,
+}
+
+impl ConsecutiveErrors {
+    fn new(limit: u32) -> Self {
+        Self {
+            count: 0,
+            limit: limit.max(1),
+        }
+    }
+
+    fn count(&self) -> u32 {
+        self.count
+    }
 
-        // Receive response
-        let response = self.receive_response().await?;
-
-        // Check response status
-        match response.status {
-            Status::Success => Ok(response),
-            Status::InvalidCommand => Err(anyhow::anyhow!("Invalid command")),
-            Status::InvalidAddress => Err(anyhow::anyhow!("Invalid address or size")),
-            Status::FlashError => Err(anyhow::anyhow!("Flash operation failed")),
-            Status::CrcError => Err(anyhow::anyhow!("CRC error")),
-            Status::BufferOverflow => Err(anyhow::anyhow!("Buffer overflow")),
-            Status::Timeout => Err(anyhow::anyhow!("Operation timeout")),
-            Status::VerificationFailed => Err(anyhow::anyhow!("Data verification failed")),
-            Status::Unknown => Err(anyhow::anyhow!("Unknown error")),
+    /// A reply got through
+    fn reset(&mut self) {
+        self.count = 0;
+    }
+
+    /// Count a failure; true once the limit is reached
+    fn record(&mut self) -> bool {
+        self.count += 1;
+        self.count >= self.limit
+    }
+}
+
+/// Magic, status, sequence and length: enough to know the full response size
+const RESPONSE_HEADER_SIZE: usize = 2 + 1 + 2 + 4;
+
+/// Largest response data length accepted; the firmware never sends more than
+/// a payload plus a small tag, so anything bigger is a corrupt header
+const MAX_RESPONSE_LENGTH: usize = 64 * 1024;
+
+/// Total size of the response at the start of `buffer` with a `crc_mode`
+/// trailer, or `None` until its header has arrived
+fn response_size(buffer: &[u8], crc_mode: CrcMode) -> Result<Option<usize>> {
+    if buffer.len() < 2 {
+        return Ok(None);
+    }
+    let magic = u16::from_le_bytes([buffer[0], buffer[1]]);
+    if magic != RESPONSE_MAGIC {
+        anyhow::bail!(
+            "Bad response magic 0x{:04X} (expected 0x{:04X})",
+            magic,
+            RESPONSE_MAGIC
+        );
+    }
+    if buffer.len() < RESPONSE_HEADER_SIZE {
+        return Ok(None);
+    }
+    let length = u32::from_le_bytes([buffer[5], buffer[6], buffer[7], buffer[8]]) as usize;
+    if length > MAX_RESPONSE_LENGTH {
+        anyhow::bail!(
+            "Response length {} exceeds the {} byte limit (corrupt header?)",
+            length,
+            MAX_RESPONSE_LENGTH
+        );
+    }
+    // RESPONSE_OVERHEAD counts a CRC-32 trailer
+    Ok(Some(
+        RESPONSE_OVERHEAD - 4 + crc_mode.trailer_size() + length,
+    ))
+}
+
+/// Timeout error describing how much of a response arrived
+fn incomplete_response_error(buffer: &[u8], crc_mode: CrcMode) -> anyhow::Error {
+    if buffer.is_empty() {
+        return anyhow::anyhow!("Response timeout (no data received)");
+    }
+    match response_size(buffer, crc_mode) {
+        Ok(Some(size)) => anyhow::anyhow!(
+            "Response timeout: truncated response, expected {} bytes, got {}",
+            size,
+            buffer.len()
+        ),
+        Ok(None) => anyhow::anyhow!(
+            "Response timeout: truncated response header, expected {} bytes, got {}",
+            RESPONSE_HEADER_SIZE,
+            buffer.len()
+        ),
+        Err(e) => e,
+    }
+}
+
+/// Trailer mode the device reported in its handshake Info response
+///
+/// Older firmware ignores the request and never sets the flag.
+fn granted_crc_mode(response: &Response) -> CrcMode {
+    match response.data.get(16..20) {
+        Some(flags) if response.status == Status::Success => {
+            let flags = u32::from_le_bytes([flags[0], flags[1], flags[2], flags[3]]);
+            if flags & INFO_FLAG_CRC16 != 0 {
+                CrcMode::Crc16
+            } else {
+                CrcMode::Crc32
+            }
         }
+        _ => CrcMode::Crc32,
+    }
+}
+
+/// Turn a non-success response status into an error
+pub fn check_status(response: Response) -> Result<Response> {
+    match response.status {
+        Status::Success => Ok(response),
+        Status::InvalidCommand => Err(anyhow::anyhow!("Invalid command")),
+        Status::InvalidAddress => Err(anyhow::anyhow!("Invalid address or size")),
+        // Several causes share this status; newer firmware says which
+        Status::FlashError => match response.error_detail() {
+            Some(detail) => Err(anyhow::anyhow!(
+                "Flash operation failed: {}",
+                detail.description()
+            )),
+            None => Err(anyhow::anyhow!("Flash operation failed")),
+        },
+        Status::CrcError => Err(anyhow::anyhow!("CRC error")),
+        Status::BufferOverflow => Err(anyhow::anyhow!("Buffer overflow")),
+        Status::Timeout => Err(anyhow::anyhow!("Operation timeout")),
+        Status::VerificationFailed => Err(anyhow::anyhow!("Data verification failed")),
+        Status::NotErased => Err(anyhow::anyhow!(
+            "Target region not erased (erase before writing)"
+        )),
+        Status::WriteProtected => Err(anyhow::anyhow!(
+            "Write protection appears active - check WP# pin"
+        )),
+        Status::Aborted => Err(anyhow::anyhow!("Operation aborted")),
+        Status::Unknown => Err(anyhow::anyhow!("Unknown error")),
+    }
+}
+
+/// Trace lines go straight to the file (unbuffered) so a hang or crash
+/// still leaves the last exchange on disk
+fn write_trace_line(trace: &mut File, line: &str) -> Result<()> {
+    // Not imported at module level: SerialStream implements both write traits
+    use std::io::Write;
+    writeln!(trace, "{}", line).context("Failed to write trace file")
+}
+
+/// One `--trace-file` line for a sent packet: header fields, then the raw bytes
+fn format_packet_trace(time: SystemTime, packet: &Packet, bytes: &[u8]) -> String {
+    format!(
+        "{} TX {:?} addr=0x{:08X} len={} seq={} crc=0x{:08X} {}",
+        humantime::format_rfc3339_micros(time),
+        packet.command,
+        packet.address,
+        packet.length,
+        packet.sequence,
+        packet.crc,
+        hex::encode(bytes)
+    )
+}
+
+/// One `--trace-file` line for a received response and its raw bytes
+fn format_response_trace(time: SystemTime, response: &Response, bytes: &[u8]) -> String {
+    format!(
+        "{} RX {:?} len={} seq={} crc=0x{:08X} {}",
+        humantime::format_rfc3339_micros(time),
+        response.status,
+        response.length,
+        response.sequence,
+        response.crc,
+        hex::encode(bytes)
+    )
+}
+
+/// Find the first valid response in `buffer`, skipping leading noise
+fn find_response(buffer: &[u8]) -> Option<Response> {
+    let magic = RESPONSE_MAGIC.to_le_bytes();
+    buffer
+        .windows(2)
+        .enumerate()
+        .filter(|(_, window)| *window == magic)
+        .find_map(|(i, _)| Response::from_bytes(&buffer[i..]).ok())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use flash_protocol::framing::try_parse_packet;
+    use tokio::io::DuplexStream;
+
+    /// Answer each packet with the next status in `script` (`None` leaves it
+    /// unanswered, as do packets past the end), returning every packet
+    /// received once the host hangs up
+    async fn scripted_device(port: DuplexStream, script: Vec<Option<Status>>) -> Vec<Packet> {
+        let mut script = script.into_iter();
+        answering_device(port, move |_| script.next().flatten()).await
+    }
+
+    /// Answer each packet with the status `answer` picks for it (`None`
+    /// leaves it unanswered), returning every packet received once the host
+    /// hangs up
+    pub(crate) async fn answering_device(
+        mut port: DuplexStream,
+        mut answer: impl FnMut(&Packet) -> Option<Status>,
+    ) -> Vec<Packet> {
+        let mut buffer = Vec::new();
+        let mut packets = Vec::new();
+        let mut temp = [0u8; 1024];
+        loop {
+            while let Some(packet) = try_parse_packet(&mut buffer) {
+                if let Some(status) = answer(&packet) {
+                    let response = Response::new_with_sequence(status, Vec::new(), packet.sequence);
+                    port.write_all(&response.to_bytes()).await.unwrap();
+                }
+                packets.push(packet);
+            }
+            match port.read(&mut temp).await.unwrap() {
+                0 => return packets,
+                n => buffer.extend_from_slice(&temp[..n]),
+            }
+        }
+    }
+
+    /// Send `packet` with `send_command` to a device following `script`
+    async fn exchange(
+        packet: Packet,
+        script: Vec<Option<Status>>,
+    ) -> (Result<Response>, Vec<Packet>) {
+        let (host, device) = tokio::io::duplex(4096);
+        let device = tokio::spawn(scripted_device(device, script));
+        let mut connection = SerialConnection::with_port(host, None);
+        connection.set_response_timeout(Duration::from_millis(50));
+        let result = connection.send_command(packet).await;
+        drop(connection);
+        (result, device.await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_send_command_resends_after_crc_error() {
+        let write = Packet::new(Command::Write, 0x1000, vec![0x5A; 16]);
+        let (result, packets) =
+            exchange(write, vec![Some(Status::CrcError), Some(Status::Success)]).await;
+        assert_eq!(result.unwrap().status, Status::Success);
+        assert_eq!(packets.len(), 2);
+        assert_ne!(packets[0].sequence, packets[1].sequence);
+        assert!(packets.iter().all(|p| p.command == Command::Write));
+
+        // Until the limit says the link is broken
+        let status = Packet::new(Command::Status, 0, Vec::new());
+        let script = vec![Some(Status::CrcError); DEFAULT_MAX_CONSECUTIVE_ERRORS as usize + 1];
+        let (result, packets) = exchange(status, script).await;
+        assert!(result.is_err());
+        assert_eq!(packets.len(), DEFAULT_MAX_CONSECUTIVE_ERRORS as usize);
+    }
+
+    #[tokio::test]
+    async fn test_only_repeatable_commands_resent_after_timeout() {
+        let mut read = Packet::new(Command::Read, 0, Vec::new());
+        read.length = 16;
+        let (result, packets) = exchange(read, vec![None, Some(Status::Success)]).await;
+        assert_eq!(result.unwrap().status, Status::Success);
+        assert_eq!(packets.len(), 2);
+
+        // A resent write would hit the blank check (NotErased), a resent chip
+        // erase would take minutes each time
+        let write = Packet::new(Command::Write, 0, vec![0x00; 16]);
+        let chip_erase = Packet::new(
+            Command::Erase,
+            0,
+            flash_protocol::erase_progress::request_with_flags(
+                16 * 1024 * 1024,
+                flash_protocol::erase_progress::FLAG_CHIP_ERASE,
+            ),
+        );
+        for packet in [write, chip_erase] {
+            let (result, packets) = exchange(packet, vec![None, Some(Status::NotErased)]).await;
+            let message = format!("{:#}", result.unwrap_err());
+            assert!(message.contains("not resending"), "{}", message);
+            assert_eq!(packets.len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_consecutive_errors_reset_on_success() {
+        let mut errors = ConsecutiveErrors::new(3);
+        assert!(!errors.record());
+        assert!(!errors.record());
+        errors.reset();
+        assert!(!errors.record());
+        assert!(!errors.record());
+        assert!(errors.record());
+        assert_eq!(errors.count(), 3);
+
+        // A limit of zero still sends each command once
+        assert!(ConsecutiveErrors::new(0).record());
+    }
+
+    #[test]
+    fn test_find_response_skips_noise() {
+        let response = Response::new(Status::Success, vec![1, 2, 3]);
+        let mut buffer = b"$GPGGA,noise".to_vec();
+        buffer.extend_from_slice(&response.to_bytes());
+
+        let found = find_response(&buffer).unwrap();
+        assert_eq!(found.data, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_find_response_rejects_bad_crc() {
+        let mut bytes = Response::new(Status::Success, vec![1, 2, 3]).to_bytes();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xFF;
+
+        assert!(find_response(&bytes).is_none());
+    }
+
+    #[test]
+    fn test_response_size_from_header() {
+        let bytes = Response::new(Status::Success, vec![0; 10]).to_bytes();
+        let crc32 = CrcMode::Crc32;
+        assert_eq!(response_size(&bytes[..1], crc32).unwrap(), None);
+        assert_eq!(response_size(&bytes[..8], crc32).unwrap(), None);
+        assert_eq!(
+            response_size(&bytes[..9], crc32).unwrap(),
+            Some(bytes.len())
+        );
+        assert_eq!(
+            response_size(&bytes[..9], CrcMode::Crc16).unwrap(),
+            Some(bytes.len() - 2)
+        );
+        assert!(response_size(b"\x00\x00", crc32).is_err());
+
+        let mut huge = bytes.clone();
+        huge[5..9].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(response_size(&huge, crc32).is_err());
+    }
+
+    #[test]
+    fn test_incomplete_response_error_reports_sizes() {
+        let bytes = Response::new(Status::Success, vec![0; 10]).to_bytes();
+        let message = incomplete_response_error(&bytes[..15], CrcMode::Crc32).to_string();
+        assert!(message.contains("expected 23 bytes, got 15"), "{}", message);
+
+        let message = incomplete_response_error(&bytes[..3], CrcMode::Crc32).to_string();
+        assert!(message.contains("expected 9 bytes, got 3"), "{}", message);
+        assert!(incomplete_response_error(&[], CrcMode::Crc32)
+            .to_string()
+            .contains("no data"));
+    }
+
+    #[test]
+    fn test_granted_crc_mode_from_info_flags() {
+        let info = |flags: u32| {
+            let mut data = vec![0; 16];
+            data.extend_from_slice(&flags.to_le_bytes());
+            Response::new(Status::Success, data)
+        };
+        assert_eq!(granted_crc_mode(&info(INFO_FLAG_CRC16)), CrcMode::Crc16);
+        assert_eq!(
+            granted_crc_mode(&info(INFO_FLAG_WRITE_PROTECTED)),
+            CrcMode::Crc32
+        );
+        // Old firmware: no flags word at all
+        let short = Response::new(Status::Success, vec![0; 16]);
+        assert_eq!(granted_crc_mode(&short), CrcMode::Crc32);
+    }
+
+    #[test]
+    fn test_trace_lines() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_micros(1_500_000);
+        let packet = Packet::new(Command::Write, 0x1000, vec![0xAA, 0x55]);
+        let line = format_packet_trace(time, &packet, &packet.to_bytes());
+        assert!(line.starts_with(
+            "1970-01-01T00:00:01.500000Z TX Write addr=0x00001000 len=2 seq=0 crc=0x"
+        ));
+        assert!(line.ends_with(&hex::encode(packet.to_bytes())));
+
+        let response = Response::new(Status::NotErased, Vec::new());
+        let line = format_response_trace(time, &response, &response.to_bytes());
+        assert!(line.contains(" RX NotErased len=0 seq=0 "));
+        assert!(line.ends_with(&hex::encode(response.to_bytes())));
+    }
+
+    #[test]
+    fn test_check_status_reports_error_detail() {
+        let response = Response::new(Status::FlashError, vec![ErrorDetail::Bus as u8]);
+        let err = check_status(response).unwrap_err().to_string();
+        assert_eq!(err, "Flash operation failed: SPI transfer failed");
+
+        let response = Response::new(Status::FlashError, Vec::new());
+        let err = check_status(response).unwrap_err().to_string();
+        assert_eq!(err, "Flash operation failed");
     }
 }
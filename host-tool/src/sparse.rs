@@ -0,0 +1,199 @@
+//! Sparse flash images (`read --format sparse`, `write --format sparse`)
+//!
+//! A dump of a mostly erased chip is mostly 0xFF. A sparse image keeps only
+//! the 4KB sectors that hold data, each with its address, so two dumps diff
+//! without the gaps and a dump can be written back without a full-size file.
+//! The layout is little-endian:
+//!
+//! | Offset | Size | Field |
+//! |--------|------|-------|
+//! | 0 | 4 | Magic `FPSI` |
+//! | 4 | 4 | Version ([`SPARSE_VERSION`]) |
+//! | 8 | 4 | Start address of the dumped range |
+//! | 12 | 4 | Length of the dumped range |
+//!
+//! followed, up to the end of the file, by one record per region in address
+//! order: address (4 bytes), length (4 bytes) and the data. Bytes of the range
+//! outside every region are 0xFF.
+
+use anyhow::{bail, Context, Result};
+use flash_protocol::segments::Segment;
+
+pub const SPARSE_MAGIC: [u8; 4] = *b"FPSI";
+
+/// Layout version this tool writes
+pub const SPARSE_VERSION: u32 = 1;
+
+/// Bytes before the first record
+pub const SPARSE_HEADER_SIZE: usize = 16;
+
+/// Bytes before each region's data
+const RECORD_HEADER_SIZE: usize = 8;
+
+/// Data at one address; consecutive non-blank sectors share a region
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+impl Region {
+    pub fn segment(&self) -> Segment {
+        Segment::new(self.address, self.data.len() as u32)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SparseImage {
+    /// Dumped range; everything in it outside `regions` is erased
+    pub range: Segment,
+    pub regions: Vec<Region>,
+}
+
+impl SparseImage {
+    /// An image of the range starting at `address`, with nothing pushed yet
+    pub fn new(address: u32) -> Self {
+        Self {
+            range: Segment::new(address, 0),
+            regions: Vec::new(),
+        }
+    }
+
+    /// Add `data` read at `address`, directly after the previous push,
+    /// unless every byte is 0xFF
+    pub fn push(&mut self, address: u32, data: &[u8]) {
+        self.range.length = address + data.len() as u32 - self.range.address;
+        if data.iter().all(|&b| b == 0xFF) {
+            return;
+        }
+        match self.regions.last_mut() {
+            Some(region) if region.segment().end() == address as u64 => {
+                region.data.extend_from_slice(data)
+            }
+            _ => self.regions.push(Region {
+                address,
+                data: data.to_vec(),
+            }),
+        }
+    }
+
+    /// Bytes of data held (without the erased gaps)
+    pub fn data_len(&self) -> usize {
+        self.regions.iter().map(|region| region.data.len()).sum()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(
+            SPARSE_HEADER_SIZE + self.regions.len() * RECORD_HEADER_SIZE + self.data_len(),
+        );
+        bytes.extend_from_slice(&SPARSE_MAGIC);
+        for field in [SPARSE_VERSION, self.range.address, self.range.length] {
+            bytes.extend_from_slice(&field.to_le_bytes());
+        }
+        for region in &self.regions {
+            bytes.extend_from_slice(&region.address.to_le_bytes());
+            bytes.extend_from_slice(&(region.data.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&region.data);
+        }
+        bytes
+    }
+
+    /// Decode an image, checking that its regions are in order and inside the range
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let word = |offset: usize| -> Result<u32> {
+            let field = bytes
+                .get(offset..offset + 4)
+                .context("Sparse image is truncated")?;
+            Ok(u32::from_le_bytes([field[0], field[1], field[2], field[3]]))
+        };
+        if bytes.get(..4) != Some(&SPARSE_MAGIC[..]) {
+            bail!("Not a sparse image (no FPSI header)");
+        }
+        let version = word(4)?;
+        if version != SPARSE_VERSION {
+            bail!("Unsupported sparse image version {}", version);
+        }
+        let range = Segment::new(word(8)?, word(12)?);
+        if range.end() > u32::MAX as u64 + 1 {
+            bail!(
+                "Sparse image range {} is beyond a 32-bit address space",
+                range
+            );
+        }
+
+        let mut regions: Vec<Region> = Vec::new();
+        let mut offset = SPARSE_HEADER_SIZE;
+        while offset < bytes.len() {
+            let address = word(offset)?;
+            let length = word(offset + 4)? as usize;
+            let start = offset + RECORD_HEADER_SIZE;
+            let data = bytes
+                .get(start..start + length)
+                .context("Sparse image is truncated")?;
+            let region = Region {
+                address,
+                data: data.to_vec(),
+            };
+            let segment = region.segment();
+            let overlaps_previous = regions
+                .last()
+                .is_some_and(|previous| previous.segment().end() > address as u64);
+            if overlaps_previous || address < range.address || segment.end() > range.end() {
+                bail!(
+                    "Sparse image region {} is out of order or outside {}",
+                    segment,
+                    range
+                );
+            }
+            regions.push(region);
+            offset = start + length;
+        }
+        Ok(Self { range, regions })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sparse_image_drops_blank_sectors() {
+        let mut image = SparseImage::new(0x1000);
+        image.push(0x1000, &[1; 4]);
+        image.push(0x1004, &[2; 4]);
+        image.push(0x1008, &[0xFF; 4]);
+        image.push(0x100C, &[3; 4]);
+        image.push(0x1010, &[0xFF; 4]);
+
+        assert_eq!(image.range, Segment::new(0x1000, 0x14));
+        assert_eq!(
+            image.regions,
+            vec![
+                Region {
+                    address: 0x1000,
+                    data: [[1; 4], [2; 4]].concat(),
+                },
+                Region {
+                    address: 0x100C,
+                    data: vec![3; 4],
+                },
+            ]
+        );
+        assert_eq!(image.data_len(), 12);
+
+        let bytes = image.to_bytes();
+        assert_eq!(&bytes[..4], b"FPSI");
+        assert_eq!(
+            bytes.len(),
+            SPARSE_HEADER_SIZE + 2 * RECORD_HEADER_SIZE + 12
+        );
+        assert_eq!(SparseImage::from_bytes(&bytes).unwrap(), image);
+
+        assert!(SparseImage::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(SparseImage::from_bytes(&[0xFF; 32]).is_err());
+        // A region past the end of the range
+        let mut bytes = bytes;
+        bytes[12] = 0x0F;
+        assert!(SparseImage::from_bytes(&bytes).is_err());
+    }
+}
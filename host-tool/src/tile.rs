@@ -0,0 +1,171 @@
+//! Writing a small image repeatedly across a region (`write --repeat`,
+//! `write --repeat-to`)
+//!
+//! The region is generated a chunk at a time from the tile, so filling the
+//! whole flash never holds more than one chunk in memory.
+
+use anyhow::{bail, Result};
+use flash_protocol::crc32::Crc32;
+
+/// Bytes of the tiled region generated (and written) at a time
+pub const TILE_CHUNK_SIZE: usize = 64 * 1024;
+
+/// `tile` repeated over `length` bytes, the last copy cut short if needed
+pub struct Tiling<'a> {
+    tile: &'a [u8],
+    length: usize,
+}
+
+impl<'a> Tiling<'a> {
+    /// `count` whole copies of `tile`
+    pub fn repeat(tile: &'a [u8], count: u32) -> Result<Self> {
+        Self::new(tile, tile.len() * count as usize)
+    }
+
+    /// Copies of `tile` from `address` up to (not including) `end`
+    pub fn repeat_to(tile: &'a [u8], address: u32, end: u32) -> Result<Self> {
+        if end <= address {
+            bail!(
+                "--repeat-to 0x{:08X} must be after the start address 0x{:08X}",
+                end,
+                address
+            );
+        }
+        Self::new(tile, (end - address) as usize)
+    }
+
+    fn new(tile: &'a [u8], length: usize) -> Result<Self> {
+        if tile.is_empty() {
+            bail!("Nothing to repeat: the data to write is empty");
+        }
+        if length == 0 {
+            bail!("--repeat needs at least one copy");
+        }
+        if u32::try_from(length).is_err() {
+            bail!(
+                "Repeated region of {} bytes exceeds the address space",
+                length
+            );
+        }
+        Ok(Self { tile, length })
+    }
+
+    /// Total bytes in the region
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    /// Whole copies of the tile, and the bytes of the partial copy after them
+    pub fn copies(&self) -> (usize, usize) {
+        (self.length / self.tile.len(), self.length % self.tile.len())
+    }
+
+    /// The region as `(offset, bytes)` chunks of [`TILE_CHUNK_SIZE`]
+    pub fn chunks(&self) -> impl Iterator<Item = (usize, Vec<u8>)> + '_ {
+        self.chunks_at(0)
+    }
+
+    /// The region written at `address` as `(offset, bytes)` chunks of at
+    /// most [`TILE_CHUNK_SIZE`], split at multiples of it in flash
+    ///
+    /// Flash sectors (4KB or 64KB) then never straddle two chunks, so
+    /// erasing a sector to repair one chunk can't clear data of another.
+    pub fn chunks_at(&self, address: u32) -> impl Iterator<Item = (usize, Vec<u8>)> + '_ {
+        let first = TILE_CHUNK_SIZE - address as usize % TILE_CHUNK_SIZE;
+        let mut offset = 0;
+        std::iter::from_fn(move || {
+            if offset >= self.length {
+                return None;
+            }
+            let limit = if offset == 0 { first } else { TILE_CHUNK_SIZE };
+            let end = (offset + limit).min(self.length);
+            let bytes = (offset..end)
+                .map(|i| self.tile[i % self.tile.len()])
+                .collect();
+            let chunk = (offset, bytes);
+            offset = end;
+            Some(chunk)
+        })
+    }
+
+    /// CRC32 of the whole region (for `--map-file`)
+    pub fn crc32(&self) -> u32 {
+        let mut crc = Crc32::new();
+        for (_, chunk) in self.chunks() {
+            crc.update(&chunk);
+        }
+        crc.value()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partial_final_copy() {
+        let tile = [1, 2, 3];
+        let tiling = Tiling::repeat_to(&tile, 0x1000, 0x1008).unwrap();
+        assert_eq!(tiling.copies(), (2, 2));
+
+        let bytes: Vec<u8> = tiling.chunks().flat_map(|(_, chunk)| chunk).collect();
+        assert_eq!(bytes, vec![1, 2, 3, 1, 2, 3, 1, 2]);
+        assert_eq!(tiling.crc32(), Crc32::checksum(&bytes));
+
+        assert!(Tiling::repeat_to(&tile, 0x1000, 0x1000).is_err());
+        assert!(Tiling::repeat(&[], 4).is_err());
+    }
+
+    #[test]
+    fn test_chunks_continue_the_pattern() {
+        // 5-byte tile, so chunk boundaries fall mid-copy
+        let tile = [0, 1, 2, 3, 4];
+        let tiling = Tiling::repeat(&tile, 30_000).unwrap();
+        assert_eq!(tiling.len(), 150_000);
+
+        let chunks: Vec<(usize, Vec<u8>)> = tiling.chunks().collect();
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[1].0, TILE_CHUNK_SIZE);
+        assert_eq!(chunks[1].1[0], (TILE_CHUNK_SIZE % 5) as u8);
+        assert_eq!(chunks[2].1.len(), 150_000 - 2 * TILE_CHUNK_SIZE);
+        assert_eq!(*chunks[2].1.last().unwrap(), 4);
+    }
+
+    #[test]
+    fn test_unaligned_chunks_keep_sectors_whole() {
+        use flash_protocol::geometry::{EraseUnit, FlashGeometry};
+
+        // --repeat at an address that isn't sector-aligned
+        let address = 0x1800;
+        let tile = [0x11, 0x22, 0x33];
+        let tiling = Tiling::repeat(&tile, 70_000).unwrap();
+        let chunks: Vec<(usize, Vec<u8>)> = tiling.chunks_at(address).collect();
+        assert_eq!(chunks[0].1.len(), TILE_CHUNK_SIZE - 0x1800);
+        assert_eq!(chunks[1].0 as u32 + address, 0x10000);
+        let bytes: Vec<u8> = chunks.iter().flat_map(|(_, chunk)| chunk.clone()).collect();
+        assert_eq!(bytes.len(), tiling.len());
+        assert_eq!(Crc32::checksum(&bytes), tiling.crc32());
+
+        // The sector erased to repair a chunk's first or last byte holds no
+        // data of any other chunk
+        let mut block_geometry = FlashGeometry::W25Q128;
+        block_geometry.erase_unit = EraseUnit::Block;
+        for geometry in [FlashGeometry::W25Q128, block_geometry] {
+            for (offset, chunk) in &chunks {
+                let start = address + *offset as u32;
+                let end = start + chunk.len() as u32;
+                for byte in [start, end - 1] {
+                    let erased = geometry.erase_span(byte, 1);
+                    for (other, other_chunk) in &chunks {
+                        if other == offset {
+                            continue;
+                        }
+                        let other_start = address + *other as u32;
+                        let other_end = other_start + other_chunk.len() as u32;
+                        assert!(erased.end() <= other_start as u64 || erased.address >= other_end);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,95 @@
+//! SPI clock sweep for `tune`
+//!
+//! Each speed writes a pseudo-random pattern to a scratch region, reads it
+//! back and compares. The pattern is seeded per speed, so data left over from
+//! a slower pass can't make a faster one look clean. The sweep stops at the
+//! first speed that fails; the fastest one before it is the recommendation.
+
+use std::time::Duration;
+
+/// Default clocks to try, in MHz
+pub const DEFAULT_SPEEDS_MHZ: &str = "1,4,8,16,20,30";
+
+/// Outcome of one speed
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpeedResult {
+    /// Clock the firmware reported using (it may cap the request)
+    pub hz: u32,
+    /// Throughput in bytes per second, `None` if that phase failed
+    pub write_rate: Option<f64>,
+    pub read_rate: Option<f64>,
+    pub passed: bool,
+}
+
+/// Bytes per second for `bytes` transferred in `elapsed`
+pub fn rate(bytes: usize, elapsed: Duration) -> f64 {
+    bytes as f64 / elapsed.as_secs_f64().max(f64::EPSILON)
+}
+
+/// Pseudo-random test data (xorshift32), never all 0xFF
+pub fn pattern(size: usize, seed: u32) -> Vec<u8> {
+    let mut state = seed.wrapping_mul(0x9E37_79B9) | 1;
+    (0..size)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+/// Fastest passing speed of a sweep run slowest first
+pub fn fastest_passing(results: &[SpeedResult]) -> Option<&SpeedResult> {
+    results.iter().take_while(|r| r.passed).last()
+}
+
+/// One row of the results table
+pub fn format_row(result: &SpeedResult) -> String {
+    let kb = |rate: Option<f64>| match rate {
+        Some(rate) => format!("{:.1}", rate / 1024.0),
+        None => "-".to_string(),
+    };
+    format!(
+        "{:>9.2} MHz  {:>10}  {:>10}  {}",
+        result.hz as f64 / 1_000_000.0,
+        kb(result.write_rate),
+        kb(result.read_rate),
+        if result.passed { "PASS" } else { "FAIL" }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(mhz: u32, passed: bool) -> SpeedResult {
+        SpeedResult {
+            hz: mhz * 1_000_000,
+            write_rate: Some(2048.0),
+            read_rate: None,
+            passed,
+        }
+    }
+
+    #[test]
+    fn test_fastest_passing_stops_at_first_failure() {
+        let results = [result(1, true), result(8, true), result(16, false)];
+        assert_eq!(fastest_passing(&results).unwrap().hz, 8_000_000);
+        assert!(fastest_passing(&[result(1, false)]).is_none());
+
+        assert_eq!(
+            format_row(&results[2]),
+            "    16.00 MHz         2.0           -  FAIL"
+        );
+    }
+
+    #[test]
+    fn test_pattern_depends_on_seed() {
+        let a = pattern(4096, 1);
+        assert_eq!(a.len(), 4096);
+        assert_ne!(a, pattern(4096, 2));
+        assert!(a.iter().any(|&b| b != 0xFF));
+        assert_eq!(a, pattern(4096, 1));
+    }
+}
@@ -0,0 +1,103 @@
+//! Program/erase cycling of a scratch sector for `wear-test`
+//!
+//! Each cycle erases the sector and checks it reads back all 0xFF, then
+//! programs every byte to 0x00 and checks that, so every cell goes through
+//! one full program/erase cycle. An already worn sector shows up as bytes
+//! that stop erasing or programming. NOR flash is typically rated for 100k
+//! cycles per sector, so a run is a check for damage, not a way to find the
+//! limit of a healthy sector, and it uses up part of that sector's life.
+
+use std::fmt;
+
+/// Half of a cycle, with the value every byte must read back afterwards
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Erase,
+    Program,
+}
+
+impl Phase {
+    pub const fn expected(self) -> u8 {
+        match self {
+            Self::Erase => 0xFF,
+            Self::Program => 0x00,
+        }
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Erase => write!(f, "erase"),
+            Self::Program => write!(f, "program"),
+        }
+    }
+}
+
+/// Bytes that didn't reach the value of a [`Phase`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mismatch {
+    pub phase: Phase,
+    /// Offset of the first bad byte in the sector
+    pub offset: usize,
+    pub actual: u8,
+    pub bad_bytes: usize,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} bytes wrong after {}, first at offset 0x{:X} (read 0x{:02X}, expected 0x{:02X})",
+            self.bad_bytes,
+            self.phase,
+            self.offset,
+            self.actual,
+            self.phase.expected()
+        )
+    }
+}
+
+/// Compare `data` read back after `phase` with the value it should hold
+pub fn check(phase: Phase, data: &[u8]) -> Option<Mismatch> {
+    let expected = phase.expected();
+    let offset = data.iter().position(|&byte| byte != expected)?;
+    Some(Mismatch {
+        phase,
+        offset,
+        actual: data[offset],
+        bad_bytes: data[offset..]
+            .iter()
+            .filter(|&&byte| byte != expected)
+            .count(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_reports_first_bad_byte() {
+        assert_eq!(check(Phase::Erase, &[0xFF; 16]), None);
+        assert_eq!(check(Phase::Program, &[0x00; 16]), None);
+
+        let mut data = [0xFF; 16];
+        data[5] = 0xEF;
+        data[9] = 0x00;
+        let mismatch = check(Phase::Erase, &data).unwrap();
+        assert_eq!(
+            mismatch,
+            Mismatch {
+                phase: Phase::Erase,
+                offset: 5,
+                actual: 0xEF,
+                bad_bytes: 2,
+            }
+        );
+        assert_eq!(
+            mismatch.to_string(),
+            "2 bytes wrong after erase, first at offset 0x5 (read 0xEF, expected 0xFF)"
+        );
+    }
+}
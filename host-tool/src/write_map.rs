@@ -0,0 +1,85 @@
+//! Map files recording what was written where (`--map-file`, `verify-map`)
+//!
+//! A map lists every segment a command programmed with its address, length
+//! and CRC32, so the flash can be checked later without the original source
+//! files:
+//!
+//! ```json
+//! {
+//!   "segments": [
+//!     { "name": "firmware.bin", "address": 0, "length": 65536, "crc32": 3735928559 }
+//!   ]
+//! }
+//! ```
+
+use anyhow::{Context, Result};
+use flash_protocol::crc32::Crc32;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// One programmed region
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MapSegment {
+    /// Where the data came from (file name, asset type, ...)
+    pub name: String,
+    pub address: u32,
+    pub length: u32,
+    pub crc32: u32,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WriteMap {
+    pub segments: Vec<MapSegment>,
+}
+
+impl WriteMap {
+    /// Record `data` as programmed at `address`
+    pub fn add(&mut self, name: impl Into<String>, address: u32, data: &[u8]) {
+        self.add_checksum(name, address, data.len() as u32, Crc32::checksum(data));
+    }
+
+    /// Record `length` bytes with CRC32 `crc32` as programmed at `address`,
+    /// for data never held in memory at once
+    pub fn add_checksum(&mut self, name: impl Into<String>, address: u32, length: u32, crc32: u32) {
+        self.segments.push(MapSegment {
+            name: name.into(),
+            address,
+            length,
+            crc32,
+        });
+    }
+
+    pub async fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        tokio::fs::write(path, json + "\n")
+            .await
+            .with_context(|| format!("Failed to write map file: {:?}", path))
+    }
+
+    pub async fn load(path: &Path) -> Result<Self> {
+        let json = tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("Failed to read map file: {:?}", path))?;
+        serde_json::from_str(&json).with_context(|| format!("Invalid map file: {:?}", path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_map_json_round_trip() {
+        let mut map = WriteMap::default();
+        map.add("boot.bin", 0x0, b"123456789");
+        map.add("Font", 0x20000, &[0xFF; 16]);
+
+        // Standard CRC-32 check value
+        assert_eq!(map.segments[0].crc32, 0xCBF4_3926);
+        assert_eq!(map.segments[1].length, 16);
+
+        let json = serde_json::to_string(&map).unwrap();
+        assert!(json.contains(r#""name":"boot.bin","address":0,"length":9"#));
+        assert_eq!(serde_json::from_str::<WriteMap>(&json).unwrap(), map);
+    }
+}
@@ -0,0 +1,278 @@
+//! Asset pack format for provisioning display resources in one step
+//!
+//! A pack bundles the assets a display firmware needs (boot screen, fonts,
+//! ...) together with the flash address each one belongs at:
+//!
+//! ```text
+//! header: magic "FPAK" | version u16 | entry count u16
+//! entry:  type u8 | reserved [u8; 3] | address u32 | offset u32 | length u32
+//! data:   asset blobs, located by each entry's offset from the start of the pack
+//! ```
+//!
+//! After the assets are programmed, the host writes an asset table at
+//! [`ASSET_TABLE_ADDR`] so firmware can locate and check them:
+//!
+//! ```text
+//! header: magic "FATB" | version u16 | entry count u16
+//! entry:  type u8 | reserved [u8; 3] | address u32 | length u32 | crc32 u32
+//! ```
+//!
+//! All integers are little-endian.
+
+use super::Vec;
+use crate::crc32::Crc32;
+use crate::segments::{find_overlap, Segment};
+use crate::{FLASH_SECTOR_SIZE, FLASH_TOTAL_SIZE};
+
+pub const PACK_MAGIC: [u8; 4] = *b"FPAK";
+pub const TABLE_MAGIC: [u8; 4] = *b"FATB";
+pub const FORMAT_VERSION: u16 = 1;
+
+/// Flash address of the asset table: the last sector before the font region
+/// in the display example's layout
+pub const ASSET_TABLE_ADDR: u32 = 0x0001_F000;
+
+const HEADER_SIZE: usize = 8;
+const ENTRY_SIZE: usize = 16;
+
+/// Kind of asset an entry holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum AssetType {
+    BootScreen = 0x01,
+    Font = 0x02,
+    UiGraphics = 0x03,
+    Data = 0x04,
+}
+
+impl TryFrom<u8> for AssetType {
+    type Error = &'static str;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x01 => Ok(AssetType::BootScreen),
+            0x02 => Ok(AssetType::Font),
+            0x03 => Ok(AssetType::UiGraphics),
+            0x04 => Ok(AssetType::Data),
+            _ => Err("Invalid asset type"),
+        }
+    }
+}
+
+/// One asset inside a pack
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PackEntry {
+    pub asset_type: AssetType,
+    /// Flash address the asset is programmed at
+    pub address: u32,
+    /// Offset of the asset data from the start of the pack
+    pub offset: u32,
+    pub length: u32,
+}
+
+impl PackEntry {
+    /// The asset's bytes within `pack` (which must have been validated by `parse_pack`)
+    pub fn data<'a>(&self, pack: &'a [u8]) -> &'a [u8] {
+        &pack[self.offset as usize..(self.offset + self.length) as usize]
+    }
+
+    /// Asset table entry describing this asset once programmed
+    pub fn table_entry(&self, pack: &[u8]) -> TableEntry {
+        TableEntry {
+            asset_type: self.asset_type,
+            address: self.address,
+            length: self.length,
+            crc32: Crc32::checksum(self.data(pack)),
+        }
+    }
+}
+
+/// One asset as recorded in the on-flash asset table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TableEntry {
+    pub asset_type: AssetType,
+    pub address: u32,
+    pub length: u32,
+    pub crc32: u32,
+}
+
+/// Validate a pack and return its entries
+///
+/// Rejects packs whose data lies outside the pack, whose assets overlap each
+/// other or the asset table sector, or that don't fit in flash.
+pub fn parse_pack(pack: &[u8]) -> Result<Vec<PackEntry>, &'static str> {
+    let count = parse_header(pack, PACK_MAGIC)?;
+    if pack.len() < HEADER_SIZE + count * ENTRY_SIZE {
+        return Err("Asset pack truncated");
+    }
+
+    let mut entries: Vec<PackEntry> = Vec::with_capacity(count);
+    for raw in pack[HEADER_SIZE..HEADER_SIZE + count * ENTRY_SIZE].chunks_exact(ENTRY_SIZE) {
+        let entry = PackEntry {
+            asset_type: AssetType::try_from(raw[0])?,
+            address: read_u32(&raw[4..]),
+            offset: read_u32(&raw[8..]),
+            length: read_u32(&raw[12..]),
+        };
+
+        let data_end = entry.offset.checked_add(entry.length);
+        if !matches!(data_end, Some(end) if end as usize <= pack.len()) {
+            return Err("Asset data outside pack");
+        }
+        let flash_end = entry.address.checked_add(entry.length);
+        if !matches!(flash_end, Some(end) if end as usize <= FLASH_TOTAL_SIZE) {
+            return Err("Asset does not fit in flash");
+        }
+        if overlaps(
+            entry.address,
+            entry.length,
+            ASSET_TABLE_ADDR,
+            FLASH_SECTOR_SIZE as u32,
+        ) {
+            return Err("Asset overlaps the asset table");
+        }
+        entries.push(entry);
+    }
+
+    let segments: Vec<Segment> = entries
+        .iter()
+        .map(|entry| Segment::new(entry.address, entry.length))
+        .collect();
+    if find_overlap(&segments).is_some() {
+        return Err("Assets overlap in flash");
+    }
+    Ok(entries)
+}
+
+/// Build a pack from (type, flash address, data) triples
+pub fn build_pack(assets: &[(AssetType, u32, &[u8])]) -> Vec<u8> {
+    let mut pack = header(PACK_MAGIC, assets.len());
+    let mut offset = (HEADER_SIZE + assets.len() * ENTRY_SIZE) as u32;
+    for (asset_type, address, data) in assets {
+        push_entry(
+            &mut pack,
+            *asset_type,
+            [*address, offset, data.len() as u32],
+        );
+        offset += data.len() as u32;
+    }
+    for (_, _, data) in assets {
+        pack.extend_from_slice(data);
+    }
+    pack
+}
+
+/// Serialize an asset table for writing at [`ASSET_TABLE_ADDR`]
+pub fn encode_table(entries: &[TableEntry]) -> Vec<u8> {
+    let mut table = header(TABLE_MAGIC, entries.len());
+    for entry in entries {
+        push_entry(
+            &mut table,
+            entry.asset_type,
+            [entry.address, entry.length, entry.crc32],
+        );
+    }
+    table
+}
+
+/// Parse an asset table read back from flash
+pub fn parse_table(bytes: &[u8]) -> Result<Vec<TableEntry>, &'static str> {
+    let count = parse_header(bytes, TABLE_MAGIC)?;
+    if bytes.len() < HEADER_SIZE + count * ENTRY_SIZE {
+        return Err("Asset table truncated");
+    }
+
+    bytes[HEADER_SIZE..HEADER_SIZE + count * ENTRY_SIZE]
+        .chunks_exact(ENTRY_SIZE)
+        .map(|raw| {
+            Ok(TableEntry {
+                asset_type: AssetType::try_from(raw[0])?,
+                address: read_u32(&raw[4..]),
+                length: read_u32(&raw[8..]),
+                crc32: read_u32(&raw[12..]),
+            })
+        })
+        .collect()
+}
+
+/// Check magic and version, returning the entry count
+fn parse_header(bytes: &[u8], magic: [u8; 4]) -> Result<usize, &'static str> {
+    if bytes.len() < HEADER_SIZE || bytes[..4] != magic {
+        return Err("Invalid asset magic");
+    }
+    if u16::from_le_bytes([bytes[4], bytes[5]]) != FORMAT_VERSION {
+        return Err("Unsupported asset format version");
+    }
+    Ok(u16::from_le_bytes([bytes[6], bytes[7]]) as usize)
+}
+
+fn header(magic: [u8; 4], count: usize) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(HEADER_SIZE + count * ENTRY_SIZE);
+    bytes.extend_from_slice(&magic);
+    bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&(count as u16).to_le_bytes());
+    bytes
+}
+
+fn push_entry(bytes: &mut Vec<u8>, asset_type: AssetType, fields: [u32; 3]) {
+    bytes.extend_from_slice(&[asset_type as u8, 0, 0, 0]);
+    for field in fields {
+        bytes.extend_from_slice(&field.to_le_bytes());
+    }
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn overlaps(a_start: u32, a_len: u32, b_start: u32, b_len: u32) -> bool {
+    a_len != 0 && b_len != 0 && a_start < b_start + b_len && b_start < a_start + a_len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pack_round_trip() {
+        let boot = [0x11u8; 10];
+        let font = [0x22u8; 6];
+        let pack = build_pack(&[
+            (AssetType::BootScreen, 0, &boot),
+            (AssetType::Font, 0x20000, &font),
+        ]);
+
+        let entries = parse_pack(&pack).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].asset_type, AssetType::Font);
+        assert_eq!(entries[1].address, 0x20000);
+        assert_eq!(entries[0].data(&pack), &boot);
+        assert_eq!(entries[1].data(&pack), &font);
+
+        let table: Vec<TableEntry> = entries.iter().map(|e| e.table_entry(&pack)).collect();
+        assert_eq!(parse_table(&encode_table(&table)).unwrap(), table);
+        assert_eq!(table[0].crc32, Crc32::checksum(&boot));
+    }
+
+    #[test]
+    fn test_rejects_bad_packs() {
+        let data = [0u8; 8];
+        let overlapping = build_pack(&[
+            (AssetType::BootScreen, 0, &data),
+            (AssetType::Font, 4, &data),
+        ]);
+        assert_eq!(parse_pack(&overlapping), Err("Assets overlap in flash"));
+
+        let on_table = build_pack(&[(AssetType::Data, ASSET_TABLE_ADDR, &data)]);
+        assert_eq!(parse_pack(&on_table), Err("Asset overlaps the asset table"));
+
+        let mut truncated = build_pack(&[(AssetType::Font, 0x20000, &data)]);
+        truncated.pop();
+        assert_eq!(parse_pack(&truncated), Err("Asset data outside pack"));
+
+        assert_eq!(
+            parse_pack(b"JUNK\x01\x00\x00\x00"),
+            Err("Invalid asset magic")
+        );
+    }
+}
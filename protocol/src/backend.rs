@@ -0,0 +1,134 @@
+//! Flash backend abstraction
+//!
+//! The protocol handler only talks to flash through [`FlashBackend`], so the
+//! same command dispatch works with any SPI NOR driver (or an in-memory mock
+//! on the host).
+
+use super::Vec;
+use crate::error_counters::ErrorCounters;
+use crate::{ErrorDetail, SpiMode};
+
+/// Errors reported by a flash backend
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BackendError {
+    /// Flash chip not detected or driver not set up
+    NotInitialized,
+    /// Bus transfer failed
+    Bus,
+    /// Operation did not complete in time
+    Timeout,
+    /// Address or length outside the device
+    InvalidAddress,
+    /// Write did not take effect
+    WriteFailed,
+    /// Write enable latch won't set (WP# held low or chip protected)
+    WriteProtected,
+    /// Operation not supported by this backend
+    Unsupported,
+    /// Cancelled by an `Abort` command before it finished
+    Aborted,
+}
+
+impl From<BackendError> for ErrorDetail {
+    fn from(error: BackendError) -> Self {
+        match error {
+            BackendError::NotInitialized => ErrorDetail::NotInitialized,
+            BackendError::Bus => ErrorDetail::Bus,
+            BackendError::Timeout => ErrorDetail::Timeout,
+            BackendError::InvalidAddress => ErrorDetail::InvalidAddress,
+            BackendError::WriteFailed => ErrorDetail::WriteFailed,
+            BackendError::WriteProtected => ErrorDetail::WriteProtected,
+            BackendError::Unsupported => ErrorDetail::Unsupported,
+            BackendError::Aborted => ErrorDetail::Aborted,
+        }
+    }
+}
+
+/// Async SPI NOR flash operations required by the protocol handler
+///
+/// Erase operations take the address of any byte inside the sector/block
+/// being erased. `read` may return fewer bytes than requested if the
+/// backend limits single transfers; callers chunk accordingly.
+#[allow(async_fn_in_trait)]
+pub trait FlashBackend {
+    /// Read up to `length` bytes starting at `address`
+    async fn read(&mut self, address: u32, length: u32) -> Result<Vec<u8>, BackendError>;
+
+    /// Program `data` starting at `address` (target must be erased)
+    async fn write(&mut self, address: u32, data: &[u8]) -> Result<(), BackendError>;
+
+    /// Erase the 4KB sector containing `address` (chips whose
+    /// [`FlashGeometry`](crate::geometry::FlashGeometry) has 64KB sectors
+    /// never get this call)
+    async fn erase_sector(&mut self, address: u32) -> Result<(), BackendError>;
+
+    /// Erase the 64KB block containing `address`
+    async fn erase_block(&mut self, address: u32) -> Result<(), BackendError>;
+
+    /// Erase the whole chip
+    async fn chip_erase(&mut self) -> Result<(), BackendError>;
+
+    /// Read the 24-bit JEDEC ID (manufacturer, memory type, capacity)
+    async fn jedec_id(&mut self) -> Result<u32, BackendError>;
+
+    /// Read up to `length` bytes of the chip's SFDP tables at `address`
+    /// (command 0x5A), used to work out its geometry
+    async fn read_sfdp(&mut self, address: u32, length: u32) -> Result<Vec<u8>, BackendError> {
+        let _ = (address, length);
+        Err(BackendError::Unsupported)
+    }
+
+    /// Read status register 1
+    async fn status(&mut self) -> Result<u8, BackendError>;
+
+    /// Issue a write enable and report whether the write enable latch set
+    ///
+    /// Used at init and in Info to catch hardware write protection before a
+    /// write fails. Implementations should leave the latch cleared afterwards.
+    async fn write_enable_check(&mut self) -> Result<bool, BackendError> {
+        Err(BackendError::Unsupported)
+    }
+
+    /// Reconfigure the bus clock polarity/phase
+    async fn set_spi_mode(&mut self, mode: SpiMode) -> Result<(), BackendError> {
+        let _ = mode;
+        Err(BackendError::Unsupported)
+    }
+
+    /// Bus clock polarity/phase currently in use (backends that implement
+    /// `set_spi_mode` should track and report it)
+    fn spi_mode(&self) -> SpiMode {
+        SpiMode::Mode0
+    }
+
+    /// SPI clock in Hz, or 0 if the backend doesn't know it
+    fn spi_frequency_hz(&self) -> u32 {
+        0
+    }
+
+    /// Run the bus at no more than `hz`, returning the clock now in use
+    /// (backends clamp to what the chip and wiring support)
+    async fn set_spi_frequency(&mut self, hz: u32) -> Result<u32, BackendError> {
+        let _ = hz;
+        Err(BackendError::Unsupported)
+    }
+
+    /// Wait `ms` milliseconds with the chip idle, to pace a power-safe erase
+    /// (see [`erase_progress`](crate::erase_progress)); backends without a
+    /// timer return at once
+    async fn pause(&mut self, ms: u32) {
+        let _ = ms;
+    }
+
+    /// Error counters kept by the firmware since boot or the last
+    /// [`clear_error_counters`](Self::clear_error_counters)
+    async fn error_counters(&mut self) -> Result<ErrorCounters, BackendError> {
+        Err(BackendError::Unsupported)
+    }
+
+    /// Reset every error counter to zero
+    async fn clear_error_counters(&mut self) -> Result<(), BackendError> {
+        Err(BackendError::Unsupported)
+    }
+}
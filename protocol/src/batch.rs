@@ -0,0 +1,56 @@
+//! Batch checksums for stream writes (`Command::BatchChecksum`)
+//!
+//! `StreamWrite` packets are never acknowledged individually. After each
+//! batch the host sends a `BatchChecksum` packet whose address is the start
+//! of the batch and whose data is:
+//!
+//! ```text
+//! crc32 u32 | length u32
+//! ```
+//!
+//! (little-endian). The device reads the range back from flash and answers
+//! `Success` if the CRC matches, or `VerificationFailed` with the CRC it
+//! computed (u32) as data.
+
+use super::Vec;
+use crate::crc32::Crc32;
+
+/// Encoded payload size
+pub const BATCH_CHECKSUM_SIZE: usize = 8;
+
+/// Largest range a single batch checksum may cover
+pub const MAX_BATCH_LENGTH: u32 = 64 * 1024;
+
+/// Expected CRC of a batch of programmed bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchChecksum {
+    pub crc32: u32,
+    pub length: u32,
+}
+
+impl BatchChecksum {
+    /// Checksum of the bytes a batch programs
+    pub fn for_data(data: &[u8]) -> Self {
+        Self {
+            crc32: Crc32::checksum(data),
+            length: data.len() as u32,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(BATCH_CHECKSUM_SIZE);
+        data.extend_from_slice(&self.crc32.to_le_bytes());
+        data.extend_from_slice(&self.length.to_le_bytes());
+        data
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, &'static str> {
+        if data.len() < BATCH_CHECKSUM_SIZE {
+            return Err("Batch checksum payload too short");
+        }
+        Ok(Self {
+            crc32: u32::from_le_bytes([data[0], data[1], data[2], data[3]]),
+            length: u32::from_le_bytes([data[4], data[5], data[6], data[7]]),
+        })
+    }
+}
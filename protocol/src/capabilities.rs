@@ -0,0 +1,197 @@
+//! Self-description reported by `Command::Capabilities`
+//!
+//! One response tells the host everything it tunes itself with: which
+//! commands the firmware really implements, its transfer limits and the
+//! attached chip's geometry. The response data is a little-endian layout:
+//!
+//! | Offset | Size | Field |
+//! |--------|------|-------|
+//! | 0 | 1 | Layout version ([`CAPABILITIES_VERSION`]) |
+//! | 1 | 1 | Packet trailer in effect ([`CrcMode`]) |
+//! | 2 | 2 | Flags ([`FLAG_HARDWARE_CRC`], [`FLAG_CHIP_ERASE`], [`FLAG_POWER_SAFE_ERASE`]) |
+//! | 4 | 4 | Supported commands: bit n is set if command byte n is implemented |
+//! | 8 | 4 | Largest payload accepted per packet |
+//! | 12 | 4 | Largest length of a single Read |
+//! | 16 | 4 | Flash capacity in bytes (0 if no chip was detected) |
+//! | 20 | 4 | Page size in bytes (0 if no chip was detected) |
+//! | 24 | 4 | Smallest erase in bytes (0 if no chip was detected) |
+//!
+//! Existing fields never move. New ones are appended and bump the version,
+//! so a reader decodes the fields of the version it knows and ignores the
+//! rest. Firmware that predates the command drops the packet unanswered.
+
+use super::Vec;
+use crate::geometry::{EraseUnit, FlashGeometry};
+use crate::{Command, CrcMode};
+
+/// Layout version this crate writes
+pub const CAPABILITIES_VERSION: u8 = 1;
+
+/// Bytes in a version 1 [`Capabilities`] response
+pub const CAPABILITIES_SIZE: usize = 28;
+
+/// Flag set when the firmware computes CRCs with a hardware peripheral
+pub const FLAG_HARDWARE_CRC: u16 = 1 << 0;
+
+/// Flag set when an Erase can clear the whole chip with one chip-erase
+/// command (see [`erase_progress::FLAG_CHIP_ERASE`](crate::erase_progress::FLAG_CHIP_ERASE))
+pub const FLAG_CHIP_ERASE: u16 = 1 << 1;
+
+/// Flag set when an Erase can pause between sectors (see
+/// [`erase_progress::FLAG_POWER_SAFE`](crate::erase_progress::FLAG_POWER_SAFE))
+pub const FLAG_POWER_SAFE_ERASE: u16 = 1 << 2;
+
+/// Bit for `command` in [`Capabilities::commands`]
+pub const fn command_bit(command: Command) -> u32 {
+    1 << (command as u8)
+}
+
+/// What the firmware and the attached chip can do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Capabilities {
+    /// Implemented commands, one [`command_bit`] each
+    pub commands: u32,
+    /// Trailer checksum negotiated by the last Info
+    pub crc_mode: CrcMode,
+    pub hardware_crc: bool,
+    pub chip_erase: bool,
+    pub power_safe_erase: bool,
+    pub max_payload_size: u32,
+    pub max_read_size: u32,
+    /// `None` if the firmware found no chip
+    pub geometry: Option<FlashGeometry>,
+}
+
+impl Capabilities {
+    /// Whether the firmware implements `command`
+    pub fn supports(&self, command: Command) -> bool {
+        self.commands & command_bit(command) != 0
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut flags = 0u16;
+        if self.hardware_crc {
+            flags |= FLAG_HARDWARE_CRC;
+        }
+        if self.chip_erase {
+            flags |= FLAG_CHIP_ERASE;
+        }
+        if self.power_safe_erase {
+            flags |= FLAG_POWER_SAFE_ERASE;
+        }
+        let (total_size, page_size, sector_size) = match self.geometry {
+            Some(geometry) => (
+                geometry.total_size,
+                geometry.page_size,
+                geometry.sector_size(),
+            ),
+            None => (0, 0, 0),
+        };
+
+        let mut data = Vec::with_capacity(CAPABILITIES_SIZE);
+        data.push(CAPABILITIES_VERSION);
+        data.push(self.crc_mode as u8);
+        data.extend_from_slice(&flags.to_le_bytes());
+        for field in [
+            self.commands,
+            self.max_payload_size,
+            self.max_read_size,
+            total_size,
+            page_size,
+            sector_size,
+        ] {
+            data.extend_from_slice(&field.to_le_bytes());
+        }
+        data
+    }
+
+    /// Decode a Capabilities response, ignoring fields added after this version
+    pub fn from_bytes(data: &[u8]) -> Result<Self, &'static str> {
+        if data.first() == Some(&0) {
+            return Err("Invalid capabilities version");
+        }
+        if data.len() < CAPABILITIES_SIZE {
+            return Err("Capabilities response too short");
+        }
+        let word = |offset: usize| {
+            u32::from_le_bytes([
+                data[offset],
+                data[offset + 1],
+                data[offset + 2],
+                data[offset + 3],
+            ])
+        };
+        let flags = u16::from_le_bytes([data[2], data[3]]);
+
+        let (total_size, page_size) = (word(16), word(20));
+        let geometry = EraseUnit::from_size(word(24))
+            .filter(|_| total_size != 0 && page_size != 0)
+            .map(|erase_unit| FlashGeometry {
+                total_size,
+                page_size,
+                erase_unit,
+            });
+
+        Ok(Self {
+            commands: word(4),
+            crc_mode: CrcMode::try_from(data[1])?,
+            hardware_crc: flags & FLAG_HARDWARE_CRC != 0,
+            chip_erase: flags & FLAG_CHIP_ERASE != 0,
+            power_safe_erase: flags & FLAG_POWER_SAFE_ERASE != 0,
+            max_payload_size: word(8),
+            max_read_size: word(12),
+            geometry,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capabilities_round_trip_tolerates_extra_fields() {
+        let capabilities = Capabilities {
+            commands: command_bit(Command::Info) | command_bit(Command::ReadStream),
+            crc_mode: CrcMode::Crc16,
+            hardware_crc: true,
+            chip_erase: false,
+            power_safe_erase: false,
+            max_payload_size: 1024,
+            max_read_size: 1024,
+            geometry: Some(FlashGeometry::W25Q128),
+        };
+        let mut data = capabilities.to_bytes();
+        assert_eq!(data.len(), CAPABILITIES_SIZE);
+        assert_eq!(data[0], CAPABILITIES_VERSION);
+        assert_eq!(Capabilities::from_bytes(&data), Ok(capabilities));
+        assert!(capabilities.supports(Command::ReadStream));
+        assert!(!capabilities.supports(Command::VerifyCRC));
+
+        // A later version with an appended field
+        data[0] = CAPABILITIES_VERSION + 1;
+        data.extend_from_slice(&[0xAA; 4]);
+        assert_eq!(Capabilities::from_bytes(&data), Ok(capabilities));
+        assert!(Capabilities::from_bytes(&data[..CAPABILITIES_SIZE - 1]).is_err());
+        data[0] = 0;
+        assert!(Capabilities::from_bytes(&data).is_err());
+    }
+
+    #[test]
+    fn test_missing_chip_has_no_geometry() {
+        let capabilities = Capabilities {
+            commands: command_bit(Command::Info),
+            crc_mode: CrcMode::Crc32,
+            hardware_crc: false,
+            chip_erase: true,
+            power_safe_erase: true,
+            max_payload_size: 1024,
+            max_read_size: 1024,
+            geometry: None,
+        };
+        let data = capabilities.to_bytes();
+        assert_eq!(&data[16..], &[0; 12]);
+        assert_eq!(Capabilities::from_bytes(&data), Ok(capabilities));
+    }
+}
@@ -0,0 +1,78 @@
+//! Runtime configuration reported by `Command::GetConfig`
+//!
+//! The response data is a fixed little-endian layout:
+//!
+//! | Offset | Size | Field |
+//! |--------|------|-------|
+//! | 0 | 1 | SPI mode (0 or 3) |
+//! | 1 | 1 | Blank check (0 off, 1 warn, 2 reject) |
+//! | 2 | 2 | Reserved (0) |
+//! | 4 | 4 | SPI clock in Hz (0 if the backend doesn't know) |
+//! | 8 | 4 | Largest payload accepted per packet |
+//!
+//! New settings are appended, so readers must accept longer responses.
+
+use super::Vec;
+use crate::handler::BlankCheck;
+use crate::SpiMode;
+
+/// Bytes in an encoded [`RuntimeConfig`]
+pub const CONFIG_SIZE: usize = 12;
+
+/// Current values of every runtime-configurable setting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RuntimeConfig {
+    pub spi_mode: SpiMode,
+    pub blank_check: BlankCheck,
+    /// SPI clock in Hz, 0 if unknown
+    pub spi_frequency_hz: u32,
+    pub max_payload_size: u32,
+}
+
+impl RuntimeConfig {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(CONFIG_SIZE);
+        data.push(self.spi_mode as u8);
+        data.push(self.blank_check as u8);
+        data.extend_from_slice(&[0, 0]);
+        data.extend_from_slice(&self.spi_frequency_hz.to_le_bytes());
+        data.extend_from_slice(&self.max_payload_size.to_le_bytes());
+        data
+    }
+
+    /// Decode a GetConfig response, ignoring fields added after this version
+    pub fn from_bytes(data: &[u8]) -> Result<Self, &'static str> {
+        if data.len() < CONFIG_SIZE {
+            return Err("Config response too short");
+        }
+        Ok(Self {
+            spi_mode: SpiMode::try_from(data[0])?,
+            blank_check: BlankCheck::try_from(data[1])?,
+            spi_frequency_hz: u32::from_le_bytes([data[4], data[5], data[6], data[7]]),
+            max_payload_size: u32::from_le_bytes([data[8], data[9], data[10], data[11]]),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_round_trip_tolerates_extra_fields() {
+        let config = RuntimeConfig {
+            spi_mode: SpiMode::Mode3,
+            blank_check: BlankCheck::Reject,
+            spi_frequency_hz: 20_000_000,
+            max_payload_size: 1024,
+        };
+        let mut data = config.to_bytes();
+        assert_eq!(data.len(), CONFIG_SIZE);
+        assert_eq!(RuntimeConfig::from_bytes(&data), Ok(config));
+
+        data.extend_from_slice(&[0xAA; 4]);
+        assert_eq!(RuntimeConfig::from_bytes(&data), Ok(config));
+        assert!(RuntimeConfig::from_bytes(&data[..CONFIG_SIZE - 1]).is_err());
+    }
+}
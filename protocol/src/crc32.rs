@@ -0,0 +1,87 @@
+//! Streaming CRC-32 (ISO-HDLC, same parameters as packet CRCs)
+//!
+//! Mirrors the reset/feed/read model of the STM32 CRC peripheral: state
+//! accumulates across `update` calls until `reset`, so independent
+//! computations must start from a reset engine. Works without `std`, which
+//! lets firmware checksum flash contents in chunks. Built on [`CRC32`], the
+//! engine behind packet and response CRCs, so there is one implementation.
+
+use crate::CRC32;
+use crc::Digest;
+
+/// Incremental CRC-32 calculator
+#[derive(Clone)]
+pub struct Crc32 {
+    digest: Digest<'static, u32>,
+}
+
+impl Crc32 {
+    pub const fn new() -> Self {
+        Self {
+            digest: CRC32.digest(),
+        }
+    }
+
+    /// Discard accumulated state; required before each independent computation
+    pub fn reset(&mut self) {
+        self.digest = CRC32.digest();
+    }
+
+    /// Fold `data` into the running CRC
+    pub fn update(&mut self, data: &[u8]) {
+        self.digest.update(data);
+    }
+
+    /// CRC of everything fed since the last reset
+    pub fn value(&self) -> u32 {
+        self.digest.clone().finalize()
+    }
+
+    /// One-shot CRC of `data`
+    pub fn checksum(data: &[u8]) -> u32 {
+        CRC32.checksum(data)
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_value() {
+        assert_eq!(Crc32::checksum(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_reset_between_computations() {
+        let mut crc = Crc32::new();
+        crc.update(b"flash");
+        let first = crc.value();
+
+        // Without a reset the previous data is folded into the next result
+        crc.update(b"flash");
+        assert_ne!(crc.value(), first);
+
+        crc.reset();
+        crc.update(b"flash");
+        assert_eq!(crc.value(), first);
+    }
+
+    #[test]
+    fn test_chunked_matches_one_shot() {
+        use crate::Vec;
+
+        let data: Vec<u8> = (0..=255).collect();
+        let mut crc = Crc32::new();
+        for chunk in data.chunks(7) {
+            crc.update(chunk);
+        }
+        assert_eq!(crc.value(), Crc32::checksum(&data));
+    }
+}
@@ -0,0 +1,119 @@
+//! Progress reports for long erases
+//!
+//! An Erase packet's data is the size (u32 LE), optionally followed by a
+//! flags byte. With [`FLAG_REPORT_PROGRESS`] set the firmware answers with
+//! one response per erased sector instead of a single one at the end; each
+//! successful response's data is the sectors done and the sector total (both
+//! u32 LE), and the one with `done == total` is the last. A non-success
+//! response ends the erase early. Older firmware ignores the flags byte and
+//! sends its usual single response with no data.
+//!
+//! With [`FLAG_CHIP_ERASE`] set the packet must cover the whole chip, which
+//! is cleared with one chip-erase command and answered once, without
+//! progress. Only send it to firmware whose
+//! [`Capabilities`](crate::capabilities::Capabilities) has `chip_erase`;
+//! older firmware would erase sector by sector.
+//!
+//! With [`FLAG_POWER_SAFE`] set the firmware rests [`POWER_SAFE_PAUSE_MS`]
+//! between sector erases, lowering the average current a long erase draws so
+//! bus-powered boards on weak USB ports don't brown out. The erase takes about
+//! twice as long. It doesn't apply to a chip erase, which the chip runs as
+//! one operation. Only firmware whose Capabilities has `power_safe_erase`
+//! paces; older firmware ignores the bit.
+
+use super::Vec;
+
+/// Flags byte bit asking for a response per erased sector
+pub const FLAG_REPORT_PROGRESS: u8 = 1 << 0;
+
+/// Flags byte bit asking for a single chip erase
+pub const FLAG_CHIP_ERASE: u8 = 1 << 1;
+
+/// Flags byte bit asking for a pause between sector erases
+pub const FLAG_POWER_SAFE: u8 = 1 << 2;
+
+/// Rest between two sector erases of a power-safe erase, about as long as a
+/// typical 4KB sector erase
+pub const POWER_SAFE_PAUSE_MS: u32 = 50;
+
+/// Data of an Erase packet for `size` bytes
+pub fn request(size: u32, report_progress: bool) -> Vec<u8> {
+    let flags = if report_progress {
+        FLAG_REPORT_PROGRESS
+    } else {
+        0
+    };
+    request_with_flags(size, flags)
+}
+
+/// Data of an Erase packet for `size` bytes with a flags byte (omitted if 0)
+pub fn request_with_flags(size: u32, flags: u8) -> Vec<u8> {
+    let mut data = size.to_le_bytes().to_vec();
+    if flags != 0 {
+        data.push(flags);
+    }
+    data
+}
+
+/// Data of an Erase packet clearing the whole `total_size`-byte chip
+pub fn chip_erase_request(total_size: u32) -> Vec<u8> {
+    let mut data = total_size.to_le_bytes().to_vec();
+    data.push(FLAG_CHIP_ERASE);
+    data
+}
+
+/// Whether an Erase packet's data asks for progress reports
+pub fn wants_progress(data: &[u8]) -> bool {
+    data.get(4)
+        .is_some_and(|flags| flags & FLAG_REPORT_PROGRESS != 0)
+}
+
+/// Whether an Erase packet's data asks for a chip erase
+pub fn wants_chip_erase(data: &[u8]) -> bool {
+    data.get(4)
+        .is_some_and(|flags| flags & FLAG_CHIP_ERASE != 0)
+}
+
+/// Whether an Erase packet's data asks for pauses between sector erases
+pub fn wants_power_safe(data: &[u8]) -> bool {
+    data.get(4)
+        .is_some_and(|flags| flags & FLAG_POWER_SAFE != 0)
+}
+
+/// Data of one progress response
+pub fn encode(done: u32, total: u32) -> Vec<u8> {
+    let mut data = Vec::with_capacity(8);
+    data.extend_from_slice(&done.to_le_bytes());
+    data.extend_from_slice(&total.to_le_bytes());
+    data
+}
+
+/// (done, total) from a progress response, or `None` for the single empty
+/// response of firmware that doesn't report progress
+pub fn decode(data: &[u8]) -> Option<(u32, u32)> {
+    let done = u32::from_le_bytes(data.get(0..4)?.try_into().ok()?);
+    let total = u32::from_le_bytes(data.get(4..8)?.try_into().ok()?);
+    Some((done, total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_and_progress_round_trip() {
+        assert!(!wants_progress(&request(0x1000, false)));
+        assert!(wants_progress(&request(0x1000, true)));
+        assert_eq!(&request(0x1000, true)[..4], &0x1000u32.to_le_bytes());
+        assert!(!wants_chip_erase(&request(0x1000, true)));
+        assert!(wants_chip_erase(&chip_erase_request(0x100_0000)));
+        assert!(!wants_progress(&chip_erase_request(0x100_0000)));
+        let paced = request_with_flags(0x1000, FLAG_REPORT_PROGRESS | FLAG_POWER_SAFE);
+        assert!(wants_progress(&paced) && wants_power_safe(&paced));
+        assert!(!wants_power_safe(&request(0x1000, true)));
+        assert_eq!(request_with_flags(0x1000, 0), request(0x1000, false));
+
+        assert_eq!(decode(&encode(3, 256)), Some((3, 256)));
+        assert_eq!(decode(&[]), None);
+    }
+}
@@ -0,0 +1,136 @@
+//! Error counters reported by `Command::GetErrorCounters`
+//!
+//! The firmware counts transport and flash errors since boot (or since the
+//! last `Command::ClearErrorCounters`), so intermittent trouble such as a few
+//! packets dropped during a long stream write becomes visible. The response
+//! data is a fixed little-endian layout:
+//!
+//! | Offset | Size | Field |
+//! |--------|------|-------|
+//! | 0 | 4 | Packets dropped by the parser (unknown command, bad length) |
+//! | 4 | 4 | Packets whose CRC trailer didn't match |
+//! | 8 | 4 | Receive buffer overflows (stalled bytes dropped) |
+//! | 12 | 4 | Flash operations that failed |
+//!
+//! New counters are appended, so readers must accept longer responses.
+
+use super::Vec;
+use crate::{ErrorDetail, Response};
+
+/// Bytes in an encoded [`ErrorCounters`]
+pub const ERROR_COUNTERS_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ErrorCounters {
+    pub dropped_packets: u32,
+    pub crc_errors: u32,
+    pub buffer_overflows: u32,
+    pub flash_errors: u32,
+}
+
+impl ErrorCounters {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(ERROR_COUNTERS_SIZE);
+        for counter in [
+            self.dropped_packets,
+            self.crc_errors,
+            self.buffer_overflows,
+            self.flash_errors,
+        ] {
+            data.extend_from_slice(&counter.to_le_bytes());
+        }
+        data
+    }
+
+    /// Decode a GetErrorCounters response, ignoring counters added after
+    /// this version
+    pub fn from_bytes(data: &[u8]) -> Result<Self, &'static str> {
+        if data.len() < ERROR_COUNTERS_SIZE {
+            return Err("Error counters response too short");
+        }
+        let word = |offset: usize| {
+            u32::from_le_bytes([
+                data[offset],
+                data[offset + 1],
+                data[offset + 2],
+                data[offset + 3],
+            ])
+        };
+        Ok(Self {
+            dropped_packets: word(0),
+            crc_errors: word(4),
+            buffer_overflows: word(8),
+            flash_errors: word(12),
+        })
+    }
+
+    /// Sum of every counter
+    pub fn total(&self) -> u64 {
+        [
+            self.dropped_packets,
+            self.crc_errors,
+            self.buffer_overflows,
+            self.flash_errors,
+        ]
+        .iter()
+        .map(|&counter| counter as u64)
+        .sum()
+    }
+}
+
+/// Whether `response` reports a failed flash operation
+///
+/// Those carry the backend's [`ErrorDetail`]; an unsupported command, an
+/// address outside the chip or an abort is not a flash fault.
+pub fn is_flash_error(response: &Response) -> bool {
+    !matches!(
+        response.error_detail(),
+        None | Some(ErrorDetail::Unsupported | ErrorDetail::InvalidAddress | ErrorDetail::Aborted)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::BackendError;
+    use crate::Status;
+
+    #[test]
+    fn test_error_counters_round_trip_tolerates_extra_fields() {
+        let counters = ErrorCounters {
+            dropped_packets: 3,
+            crc_errors: 1,
+            buffer_overflows: 0,
+            flash_errors: 70_000,
+        };
+        let mut data = counters.to_bytes();
+        assert_eq!(data.len(), ERROR_COUNTERS_SIZE);
+        assert_eq!(ErrorCounters::from_bytes(&data), Ok(counters));
+        assert_eq!(counters.total(), 70_004);
+
+        data.extend_from_slice(&[0xAA; 4]);
+        assert_eq!(ErrorCounters::from_bytes(&data), Ok(counters));
+        assert!(ErrorCounters::from_bytes(&data[..ERROR_COUNTERS_SIZE - 1]).is_err());
+    }
+
+    #[test]
+    fn test_only_backend_faults_are_flash_errors() {
+        let with_detail = |error: BackendError| {
+            Response::new(
+                Status::FlashError,
+                [ErrorDetail::from(error) as u8].to_vec(),
+            )
+        };
+        assert!(is_flash_error(&with_detail(BackendError::Bus)));
+        assert!(is_flash_error(&with_detail(BackendError::WriteProtected)));
+        assert!(!is_flash_error(&with_detail(BackendError::Unsupported)));
+        assert!(!is_flash_error(&with_detail(BackendError::InvalidAddress)));
+        assert!(!is_flash_error(&with_detail(BackendError::Aborted)));
+        assert!(!is_flash_error(&Response::new(Status::Success, Vec::new())));
+        assert!(!is_flash_error(&Response::new(
+            Status::InvalidAddress,
+            Vec::new()
+        )));
+    }
+}
@@ -0,0 +1,40 @@
+//! Logging shim: forwards to defmt when the `defmt` feature is enabled and
+//! compiles to nothing otherwise (arguments are still evaluated by reference
+//! so host builds don't warn about unused variables).
+#![allow(unused_macros)]
+
+macro_rules! debug {
+    ($s:literal $(, $x:expr)* $(,)?) => {{
+        #[cfg(feature = "defmt")]
+        ::defmt::debug!($s $(, $x)*);
+        #[cfg(not(feature = "defmt"))]
+        let _ = ($(&$x),*);
+    }};
+}
+
+macro_rules! info {
+    ($s:literal $(, $x:expr)* $(,)?) => {{
+        #[cfg(feature = "defmt")]
+        ::defmt::info!($s $(, $x)*);
+        #[cfg(not(feature = "defmt"))]
+        let _ = ($(&$x),*);
+    }};
+}
+
+macro_rules! warn {
+    ($s:literal $(, $x:expr)* $(,)?) => {{
+        #[cfg(feature = "defmt")]
+        ::defmt::warn!($s $(, $x)*);
+        #[cfg(not(feature = "defmt"))]
+        let _ = ($(&$x),*);
+    }};
+}
+
+macro_rules! error {
+    ($s:literal $(, $x:expr)* $(,)?) => {{
+        #[cfg(feature = "defmt")]
+        ::defmt::error!($s $(, $x)*);
+        #[cfg(not(feature = "defmt"))]
+        let _ = ($(&$x),*);
+    }};
+}
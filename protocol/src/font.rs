@@ -0,0 +1,329 @@
+//! Flash font layout read by the display firmware's text renderers
+//!
+//! ```text
+//! header:  char count (bits 0-23) | flags (bits 24-31), u32
+//! entry:   unicode u32 | width u8 | height u8 | bitmap offset u32
+//! kerning: pair count u32, then pairs of left u32 | right u32 | adjust i8
+//!          (only with FONT_FLAG_KERNING)
+//! data:    glyph bitmaps, located by each entry's offset from the start of the font
+//! ```
+//!
+//! Entries are sorted by code point and kerning pairs by (left, right), with
+//! no duplicates, because the firmware finds both by binary search. A pair's
+//! adjustment is added to the advance between the two characters. All
+//! integers are little-endian.
+
+use super::Vec;
+use crate::glyph::{self, BitmapFormat, FONT_FLAG_KERNING};
+
+pub const FONT_HEADER_SIZE: usize = 4;
+/// Size of one character-info entry
+pub const CHAR_INFO_SIZE: usize = 10;
+/// Size of one kerning pair
+pub const KERN_PAIR_SIZE: usize = 9;
+
+/// Flash space reserved for each font in the display example's layout
+pub const FONT_REGION_SIZE: u32 = 0x0010_0000;
+
+/// Entries firmware reads at startup to spot-check the table's sort order
+pub const TABLE_SAMPLES: u32 = 8;
+
+/// One rasterized character
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Glyph {
+    pub unicode: u32,
+    pub width: u8,
+    pub height: u8,
+    /// Pixels in the font's [`BitmapFormat`]
+    pub bitmap: Vec<u8>,
+}
+
+/// Spacing adjustment between two adjacent characters, in pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KernPair {
+    pub left: u32,
+    pub right: u32,
+    pub adjust: i8,
+}
+
+impl KernPair {
+    pub fn to_bytes(self) -> [u8; KERN_PAIR_SIZE] {
+        let mut bytes = [0; KERN_PAIR_SIZE];
+        bytes[..4].copy_from_slice(&self.left.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.right.to_le_bytes());
+        bytes[8] = self.adjust as u8;
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, &'static str> {
+        if bytes.len() < KERN_PAIR_SIZE {
+            return Err("Kerning pair too short");
+        }
+        Ok(Self {
+            left: u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            right: u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+            adjust: bytes[8] as i8,
+        })
+    }
+
+    /// Sort key of the kerning table
+    pub fn key(&self) -> (u32, u32) {
+        (self.left, self.right)
+    }
+}
+
+/// Whether a font header word announces a kerning table
+pub fn has_kerning(header_word: u32) -> bool {
+    (header_word >> 24) as u8 & FONT_FLAG_KERNING != 0
+}
+
+/// Offset of the kerning table (its pair count) in a font of `char_count` glyphs
+pub fn kerning_table_offset(char_count: u32) -> usize {
+    FONT_HEADER_SIZE + char_count as usize * CHAR_INFO_SIZE
+}
+
+/// Build a font image from glyphs and kerning pairs in any order
+///
+/// Glyphs are sorted by code point and pairs by (left, right); duplicates and
+/// bitmaps whose size doesn't match their dimensions are rejected. Without
+/// pairs no kerning table is written.
+pub fn encode_font(
+    glyphs: &mut [Glyph],
+    kerning: &mut [KernPair],
+    format: BitmapFormat,
+) -> Result<Vec<u8>, &'static str> {
+    if glyphs.is_empty() {
+        return Err("Font has no glyphs");
+    }
+    if glyphs.len() > glyph::FONT_HEADER_COUNT_MASK as usize {
+        return Err("Too many glyphs for the font header");
+    }
+    glyphs.sort_by_key(|g| g.unicode);
+    if glyphs
+        .windows(2)
+        .any(|pair| pair[0].unicode == pair[1].unicode)
+    {
+        return Err("Duplicate code point in font");
+    }
+    kerning.sort_by_key(KernPair::key);
+    if kerning
+        .windows(2)
+        .any(|pair| pair[0].key() == pair[1].key())
+    {
+        return Err("Duplicate kerning pair in font");
+    }
+
+    let mut flags = format.header_flags();
+    let mut table_end = kerning_table_offset(glyphs.len() as u32);
+    if !kerning.is_empty() {
+        flags |= FONT_FLAG_KERNING;
+        table_end += 4 + kerning.len() * KERN_PAIR_SIZE;
+    }
+    let header = glyphs.len() as u32 | (flags as u32) << 24;
+    let bitmap_total: usize = glyphs.iter().map(|g| g.bitmap.len()).sum();
+    if table_end + bitmap_total > u32::MAX as usize {
+        return Err("Font too large");
+    }
+
+    let mut out = Vec::with_capacity(table_end + bitmap_total);
+    out.extend_from_slice(&header.to_le_bytes());
+    let mut offset = table_end as u32;
+    for g in glyphs.iter() {
+        if g.bitmap.len() != format.bitmap_size(g.width, g.height) {
+            return Err("Glyph bitmap size doesn't match its dimensions");
+        }
+        out.extend_from_slice(&g.unicode.to_le_bytes());
+        out.push(g.width);
+        out.push(g.height);
+        out.extend_from_slice(&offset.to_le_bytes());
+        offset += g.bitmap.len() as u32;
+    }
+    if !kerning.is_empty() {
+        out.extend_from_slice(&(kerning.len() as u32).to_le_bytes());
+        for pair in kerning.iter() {
+            out.extend_from_slice(&pair.to_bytes());
+        }
+    }
+    for g in glyphs.iter() {
+        out.extend_from_slice(&g.bitmap);
+    }
+    Ok(out)
+}
+
+/// Check a font image the way the firmware will use it: header, strictly
+/// ascending code points and kerning pairs, and in-bounds bitmaps. Returns
+/// the glyph count
+pub fn check_font(data: &[u8]) -> Result<u32, &'static str> {
+    let header = data
+        .get(..FONT_HEADER_SIZE)
+        .ok_or("Font too short for header")?;
+    let word = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+    let (count, format) = glyph::parse_font_header(word)?;
+    let mut table_end = kerning_table_offset(count);
+    let table = data
+        .get(FONT_HEADER_SIZE..table_end)
+        .ok_or("Font too short for its character table")?;
+
+    if has_kerning(word) {
+        let pair_count = data
+            .get(table_end..table_end + 4)
+            .ok_or("Font too short for its kerning table")?;
+        let pair_count =
+            u32::from_le_bytes([pair_count[0], pair_count[1], pair_count[2], pair_count[3]]);
+        let pairs_start = table_end + 4;
+        table_end = pairs_start + pair_count as usize * KERN_PAIR_SIZE;
+        let pairs = data
+            .get(pairs_start..table_end)
+            .ok_or("Font too short for its kerning table")?;
+
+        let mut previous = None;
+        for bytes in pairs.chunks_exact(KERN_PAIR_SIZE) {
+            let key = KernPair::from_bytes(bytes)?.key();
+            if previous.is_some_and(|p| p >= key) {
+                return Err("Kerning table is not sorted by character pair");
+            }
+            previous = Some(key);
+        }
+    }
+
+    let mut previous = None;
+    for entry in table.chunks_exact(CHAR_INFO_SIZE) {
+        let unicode = u32::from_le_bytes([entry[0], entry[1], entry[2], entry[3]]);
+        if previous.is_some_and(|p| p >= unicode) {
+            return Err("Character table is not sorted by code point");
+        }
+        previous = Some(unicode);
+
+        let offset = u32::from_le_bytes([entry[6], entry[7], entry[8], entry[9]]) as usize;
+        let size = format.bitmap_size(entry[4], entry[5]);
+        if offset < table_end || offset + size > data.len() {
+            return Err("Glyph bitmap outside the font");
+        }
+    }
+    Ok(count)
+}
+
+/// Indices of up to [`TABLE_SAMPLES`] entries spread evenly over a table of
+/// `count` entries, ascending and always including the first and last
+pub fn table_sample_indices(count: u32) -> impl Iterator<Item = u32> {
+    let samples = TABLE_SAMPLES.min(count);
+    (0..samples).map(move |i| {
+        if i + 1 == samples {
+            count - 1
+        } else {
+            (i as u64 * (count - 1) as u64 / (samples - 1) as u64) as u32
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glyph(unicode: u32) -> Glyph {
+        // 9x2: two bytes per row
+        Glyph {
+            unicode,
+            width: 9,
+            height: 2,
+            bitmap: vec![unicode as u8, 0x80, 0x00, 0x80],
+        }
+    }
+
+    #[test]
+    fn test_encode_sorts_and_locates_bitmaps() {
+        let mut glyphs = [glyph(0x4E2D), glyph(0x41), glyph(0x20)];
+        let font = encode_font(&mut glyphs, &mut [], BitmapFormat::default()).unwrap();
+
+        assert_eq!(check_font(&font), Ok(3));
+        assert_eq!(&font[..4], &3u32.to_le_bytes());
+        let table_end = FONT_HEADER_SIZE + 3 * CHAR_INFO_SIZE;
+        assert_eq!(font.len(), table_end + 3 * 4);
+
+        // Second entry is 'A', whose bitmap follows the space's
+        let entry = &font[FONT_HEADER_SIZE + CHAR_INFO_SIZE..][..CHAR_INFO_SIZE];
+        assert_eq!(&entry[..4], &0x41u32.to_le_bytes());
+        assert_eq!(&entry[4..6], &[9, 2]);
+        let offset = u32::from_le_bytes([entry[6], entry[7], entry[8], entry[9]]) as usize;
+        assert_eq!(offset, table_end + 4);
+        assert_eq!(font[offset], 0x41);
+    }
+
+    #[test]
+    fn test_encode_rejects_bad_glyphs() {
+        let format = BitmapFormat::default();
+        assert!(encode_font(&mut [], &mut [], format).is_err());
+        assert!(encode_font(&mut [glyph(0x41), glyph(0x41)], &mut [], format).is_err());
+
+        let mut short = glyph(0x41);
+        short.bitmap.pop();
+        assert!(encode_font(&mut [short], &mut [], format).is_err());
+    }
+
+    #[test]
+    fn test_kerning_table_sits_between_entries_and_bitmaps() {
+        let pair = |left, right, adjust| KernPair {
+            left,
+            right,
+            adjust,
+        };
+        let mut kerning = [pair(0x56, 0x41, -2), pair(0x41, 0x56, -1)];
+        let font = encode_font(
+            &mut [glyph(0x41), glyph(0x56)],
+            &mut kerning,
+            BitmapFormat::default(),
+        )
+        .unwrap();
+        assert_eq!(check_font(&font), Ok(2));
+        assert_eq!(font[3], FONT_FLAG_KERNING);
+
+        let table = kerning_table_offset(2);
+        assert_eq!(&font[table..table + 4], &2u32.to_le_bytes());
+        let first = KernPair::from_bytes(&font[table + 4..]).unwrap();
+        assert_eq!(first, pair(0x41, 0x56, -1));
+
+        // The first bitmap starts after the pairs
+        let offset = u32::from_le_bytes([font[10], font[11], font[12], font[13]]) as usize;
+        assert_eq!(offset, table + 4 + 2 * KERN_PAIR_SIZE);
+
+        let mut duplicate = [pair(0x41, 0x56, -1), pair(0x41, 0x56, 1)];
+        assert!(encode_font(&mut [glyph(0x41)], &mut duplicate, BitmapFormat::default()).is_err());
+
+        let mut unsorted = font.clone();
+        unsorted[table + 4..table + 4 + KERN_PAIR_SIZE]
+            .copy_from_slice(&pair(0x57, 0x41, -1).to_bytes());
+        assert!(check_font(&unsorted).is_err());
+    }
+
+    #[test]
+    fn test_table_samples_span_the_table() {
+        let samples: Vec<u32> = table_sample_indices(1000).collect();
+        assert_eq!(samples.len(), TABLE_SAMPLES as usize);
+        assert_eq!((samples[0], samples[7]), (0, 999));
+        assert!(samples.windows(2).all(|pair| pair[0] < pair[1]));
+
+        assert_eq!(table_sample_indices(3).collect::<Vec<_>>(), [0, 1, 2]);
+        assert_eq!(table_sample_indices(1).collect::<Vec<_>>(), [0]);
+        assert_eq!(table_sample_indices(0).count(), 0);
+    }
+
+    #[test]
+    fn test_check_rejects_unsorted_table() {
+        let mut font = encode_font(
+            &mut [glyph(0x41), glyph(0x42)],
+            &mut [],
+            BitmapFormat::default(),
+        )
+        .unwrap();
+        // Swap the code points of the two entries
+        font[FONT_HEADER_SIZE] = 0x42;
+        font[FONT_HEADER_SIZE + CHAR_INFO_SIZE] = 0x41;
+        assert_eq!(
+            check_font(&font),
+            Err("Character table is not sorted by code point")
+        );
+
+        font.truncate(FONT_HEADER_SIZE + CHAR_INFO_SIZE);
+        assert!(check_font(&font).is_err());
+    }
+}
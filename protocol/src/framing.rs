@@ -0,0 +1,315 @@
+//! Reassembly of command packets from a byte stream
+//!
+//! Used by the firmware to pull packets out of the USB CDC stream. Headers
+//! are validated before any payload is buffered so a corrupted or malicious
+//! length field can neither request a huge allocation nor wedge the stream.
+
+use super::Vec;
+use crate::{read_trailer, Command, CrcMode, Packet, MAX_PAYLOAD_SIZE, PACKET_MAGIC};
+
+/// Header size: magic(2) + command(1) + length(4) + address(4) + sequence(2)
+pub const HEADER_SIZE: usize = 13;
+
+/// Trailing CRC size in the default CRC-32 mode
+pub const CRC_SIZE: usize = 4;
+
+/// Largest packet on the wire: header, biggest payload and CRC-32 trailer
+pub const MAX_PACKET_SIZE: usize = HEADER_SIZE + MAX_PAYLOAD_SIZE + CRC_SIZE;
+
+/// Bytes kept when no magic number is found (partial magic may follow)
+const MAX_UNSYNCED_BYTES: usize = 1024;
+
+/// Try to extract one complete packet from the front of `buffer`
+///
+/// Consumed bytes (including garbage before the magic number and rejected
+/// headers) are drained from the buffer; returns `None` if more data is
+/// needed.
+pub fn try_parse_packet(buffer: &mut Vec<u8>) -> Option<Packet> {
+    try_parse_packet_with(buffer, CrcMode::Crc32)
+}
+
+/// [`try_parse_packet`] for a connection that negotiated `crc_mode`
+///
+/// Info packets always have a CRC-32 trailer (see [`CrcMode`]).
+pub fn try_parse_packet_with(buffer: &mut Vec<u8>, crc_mode: CrcMode) -> Option<Packet> {
+    try_parse_packet_counting(buffer, crc_mode, &mut 0)
+}
+
+/// [`try_parse_packet_with`] that adds the number of headers it rejected
+/// (unknown command, length out of range) to `rejected`
+pub fn try_parse_packet_counting(
+    buffer: &mut Vec<u8>,
+    crc_mode: CrcMode,
+    rejected: &mut u32,
+) -> Option<Packet> {
+    let magic_bytes = PACKET_MAGIC.to_le_bytes();
+
+    loop {
+        // Drop anything before the next magic number
+        match buffer.windows(2).position(|w| w == magic_bytes) {
+            Some(0) => {}
+            Some(pos) => {
+                debug!("Parse: Removed {} bytes before magic number", pos);
+                buffer.drain(..pos);
+            }
+            None => {
+                debug!("Parse: No magic number found in {} bytes", buffer.len());
+                if buffer.len() > MAX_UNSYNCED_BYTES {
+                    let excess = buffer.len() - MAX_UNSYNCED_BYTES;
+                    buffer.drain(..excess);
+                }
+                return None;
+            }
+        }
+
+        if buffer.len() < HEADER_SIZE {
+            debug!("Parse: Not enough data for header");
+            return None;
+        }
+
+        let command_byte = buffer[2];
+        let length = u32::from_le_bytes([buffer[3], buffer[4], buffer[5], buffer[6]]);
+        let address = u32::from_le_bytes([buffer[7], buffer[8], buffer[9], buffer[10]]);
+        let sequence = u16::from_le_bytes([buffer[11], buffer[12]]);
+
+        let command = match Command::try_from(command_byte) {
+            Ok(command) => command,
+            Err(_) => {
+                warn!("Parse: Unknown command: 0x{:02x}", command_byte);
+                *rejected += 1;
+                // Resync on the next magic number
+                buffer.drain(..2);
+                continue;
+            }
+        };
+
+        // Read and ReadSfdp carry the requested size in `length` and no
+        // payload; every other command carries `length` bytes of data. Read
+        // sizes are checked by the handler, which knows the chip's capacity
+        let data_length = match command {
+            Command::Read | Command::ReadStream => 0,
+            _ if length as usize > MAX_PAYLOAD_SIZE => {
+                warn!("Parse: Payload of {} bytes too large, rejecting", length);
+                *rejected += 1;
+                buffer.drain(..2);
+                continue;
+            }
+            Command::ReadSfdp => 0,
+            _ => length as usize,
+        };
+
+        let crc_size = crc_mode.for_command(command).trailer_size();
+        let total_size = HEADER_SIZE + data_length + crc_size;
+        if buffer.len() < total_size {
+            debug!(
+                "Parse: Incomplete packet: have {} bytes, need {}",
+                buffer.len(),
+                total_size
+            );
+            return None;
+        }
+
+        let data = buffer[HEADER_SIZE..HEADER_SIZE + data_length].to_vec();
+        let crc_start = HEADER_SIZE + data_length;
+        let crc = read_trailer(&buffer[crc_start..total_size]);
+
+        // The trailer is checked by `ProtocolHandler::handle_packet`, which
+        // answers a mismatch with `CrcError` and the packet's sequence number
+
+        buffer.drain(..total_size);
+        debug!(
+            "Parse: Packet cmd 0x{:02x} addr 0x{:08x} len {}",
+            command_byte, address, length
+        );
+
+        return Some(Packet {
+            magic: PACKET_MAGIC,
+            command,
+            length,
+            address,
+            sequence,
+            data,
+            crc,
+        });
+    }
+}
+
+/// Make room for `incoming` more bytes in a reassembly buffer capped at
+/// `max_size` (at least [`MAX_PACKET_SIZE`] plus `incoming`), returning the
+/// number of bytes dropped
+///
+/// Instead of clearing the buffer, the stalled data at its front is dropped
+/// and the tail is kept from the earliest magic number that could start a
+/// packet still in flight, i.e. one within the last [`MAX_PACKET_SIZE`]
+/// bytes. The earliest rather than the latest is kept because a later magic
+/// may just be payload bytes of that packet; if it is not a real packet,
+/// parsing resyncs past it as usual.
+pub fn compact(buffer: &mut Vec<u8>, incoming: usize, max_size: usize) -> usize {
+    if buffer.len() + incoming <= max_size {
+        return 0;
+    }
+    let magic_bytes = PACKET_MAGIC.to_le_bytes();
+    // Skip the front, which is what stalled
+    let window = buffer.len().saturating_sub(MAX_PACKET_SIZE).max(1);
+    let keep_from = match buffer
+        .get(window..)
+        .and_then(|tail| tail.windows(2).position(|w| w == magic_bytes))
+    {
+        Some(pos) => window + pos,
+        // Keep a trailing first magic byte, whose partner may be incoming
+        None if buffer.last() == Some(&magic_bytes[0]) => buffer.len() - 1,
+        None => buffer.len(),
+    };
+    buffer.drain(..keep_from);
+    keep_from
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(command: Command, length: u32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&PACKET_MAGIC.to_le_bytes());
+        bytes.push(command as u8);
+        bytes.extend_from_slice(&length.to_le_bytes());
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        bytes.extend_from_slice(&0u16.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn test_parses_packet_after_noise() {
+        let packet = Packet::new(Command::Write, 0x1000, vec![1, 2, 3]);
+        let mut buffer = vec![0x00, 0x11];
+        buffer.extend_from_slice(&packet.to_bytes());
+
+        let parsed = try_parse_packet(&mut buffer).unwrap();
+        assert_eq!(parsed.address, 0x1000);
+        assert_eq!(parsed.data, vec![1, 2, 3]);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_huge_length_is_rejected_without_wedging() {
+        for command in [Command::Write, Command::ReadSfdp] {
+            let mut buffer = header(command, 0xFFFF_FFFF);
+            buffer.extend_from_slice(&[0; 4]);
+            let valid = Packet::new(Command::Info, 0, Vec::new());
+            buffer.extend_from_slice(&valid.to_bytes());
+
+            // The bogus header is skipped and the following packet still parses
+            let parsed = try_parse_packet(&mut buffer).unwrap();
+            assert_eq!(parsed.command, Command::Info);
+            assert!(buffer.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_rejected_headers_are_counted() {
+        let mut buffer = header(Command::Write, 0xFFFF_FFFF);
+        buffer[2] = 0x7F;
+        buffer.extend_from_slice(&header(Command::ReadSfdp, 0xFFFF_FFFF));
+        let valid = Packet::new(Command::Info, 0, Vec::new());
+        buffer.extend_from_slice(&valid.to_bytes());
+
+        let mut rejected = 0;
+        let parsed = try_parse_packet_counting(&mut buffer, CrcMode::Crc32, &mut rejected);
+        assert_eq!(parsed.unwrap().command, Command::Info);
+        assert_eq!(rejected, 2);
+    }
+
+    #[test]
+    fn test_compact_keeps_packet_straddling_overflow() {
+        const MAX: usize = 4096;
+        let valid = Packet::new(Command::Write, 0x1000, vec![0xAB; 100]).to_bytes();
+        let (head, tail) = valid.split_at(40);
+
+        // Stalled data ahead of a packet whose end is still to arrive
+        let mut buffer = header(Command::Write, 1024);
+        buffer.resize(MAX - head.len(), 0x00);
+        buffer.extend_from_slice(head);
+        assert_eq!(compact(&mut buffer, 64, MAX), MAX - head.len());
+        assert_eq!(buffer, head);
+
+        buffer.extend_from_slice(tail);
+        let parsed = try_parse_packet(&mut buffer).unwrap();
+        assert_eq!(parsed.data, vec![0xAB; 100]);
+
+        // Nothing to keep but half a magic number
+        let mut buffer = vec![0x00; MAX - 1];
+        buffer.push(PACKET_MAGIC.to_le_bytes()[0]);
+        compact(&mut buffer, 64, MAX);
+        assert_eq!(buffer, vec![PACKET_MAGIC.to_le_bytes()[0]]);
+
+        // Room left: untouched
+        let mut buffer = vec![0x00; 100];
+        assert_eq!(compact(&mut buffer, 64, MAX), 0);
+        assert_eq!(buffer.len(), 100);
+    }
+
+    #[test]
+    fn test_read_packet_has_no_payload() {
+        let mut packet = Packet::new(Command::Read, 0x2000, Vec::new());
+        packet.length = 256;
+        packet.crc = packet.calculate_crc();
+        let mut buffer = packet.to_bytes();
+
+        let parsed = try_parse_packet(&mut buffer).unwrap();
+        assert_eq!(parsed.length, 256);
+        assert!(parsed.data.is_empty());
+    }
+
+    #[test]
+    fn test_read_larger_than_default_flash_parses() {
+        // 32MB chips: the handler, not the framing, bounds the read
+        for command in [Command::Read, Command::ReadStream] {
+            let mut packet = Packet::new(command, 0, Vec::new());
+            packet.length = 32 * 1024 * 1024;
+            packet.crc = packet.calculate_crc();
+            let mut buffer = packet.to_bytes();
+
+            let parsed = try_parse_packet(&mut buffer).unwrap();
+            assert_eq!(parsed.command, command);
+            assert_eq!(parsed.length, 32 * 1024 * 1024);
+            assert!(buffer.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_packet_filling_whole_usb_packets_needs_no_terminator() {
+        // 17 bytes of header and CRC-32 trailer + 47 data bytes = one full
+        // 64-byte USB packet; the firmware reads the stream chunk by chunk and
+        // must finish on the length field alone, without a zero-length packet
+        for data_len in [47, 47 + 64] {
+            let packet = Packet::new(Command::Write, 0x1000, vec![0x5A; data_len]);
+            let bytes = packet.to_bytes();
+            assert_eq!(bytes.len() % 64, 0);
+
+            let mut buffer = Vec::new();
+            let chunks: Vec<_> = bytes.chunks(64).collect();
+            for (i, chunk) in chunks.iter().enumerate() {
+                buffer.extend_from_slice(chunk);
+                let parsed = try_parse_packet(&mut buffer);
+                assert_eq!(parsed.is_some(), i + 1 == chunks.len());
+            }
+            assert!(buffer.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_crc16_trailer_except_info() {
+        let mut write = Packet::new(Command::Write, 0x1000, vec![1, 2, 3]);
+        write.crc = write.calculate_crc_with(CrcMode::Crc16);
+        let info = Packet::new(Command::Info, 0, vec![CrcMode::Crc16 as u8]);
+        let mut buffer = write.to_bytes_with(CrcMode::Crc16);
+        buffer.extend_from_slice(&info.to_bytes());
+
+        let parsed = try_parse_packet_with(&mut buffer, CrcMode::Crc16).unwrap();
+        assert!(parsed.verify_crc_with(CrcMode::Crc16));
+        let parsed = try_parse_packet_with(&mut buffer, CrcMode::Crc16).unwrap();
+        assert_eq!(parsed.command, Command::Info);
+        assert!(parsed.verify_crc());
+        assert!(buffer.is_empty());
+    }
+}
@@ -0,0 +1,225 @@
+//! Flash chip geometry: capacity, page size and smallest erase
+//!
+//! The W25Q128 erases 4KB sectors, but some parts (Micron M25P, several
+//! Spansion S25FL models) can only erase 64KB blocks. The geometry comes
+//! from the chip's SFDP Basic Flash Parameter Table (BFPT) when it has one,
+//! else from its JEDEC ID. Every erase path rounds through
+//! [`FlashGeometry::erase_span`]: the handler's Erase command, and the host's
+//! `--preserve` and verification retry logic.
+
+use crate::segments::Segment;
+use crate::{jedec, FLASH_BLOCK_SIZE, FLASH_PAGE_SIZE, FLASH_SECTOR_SIZE, FLASH_TOTAL_SIZE};
+
+/// "SFDP" as read from address 0 of the SFDP space (little-endian)
+pub const SFDP_SIGNATURE: u32 = 0x5044_4653;
+
+/// SFDP header plus the first parameter header, which locates the BFPT
+pub const SFDP_HEADER_SIZE: usize = 16;
+
+/// Opcodes the backend's `erase_sector` / `erase_block` send
+const SECTOR_ERASE_OPCODE: u8 = 0x20;
+const BLOCK_ERASE_OPCODE: u8 = 0xD8;
+
+/// The smallest erase a chip supports, as a backend operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum EraseUnit {
+    /// 4KB sector erase (`FlashBackend::erase_sector`)
+    Sector,
+    /// 64KB block erase (`FlashBackend::erase_block`)
+    Block,
+}
+
+impl EraseUnit {
+    pub const fn size(self) -> u32 {
+        match self {
+            EraseUnit::Sector => FLASH_SECTOR_SIZE as u32,
+            EraseUnit::Block => FLASH_BLOCK_SIZE as u32,
+        }
+    }
+
+    /// The unit erasing `size` bytes, if the backend has one
+    pub fn from_size(size: u32) -> Option<Self> {
+        [EraseUnit::Sector, EraseUnit::Block]
+            .into_iter()
+            .find(|unit| unit.size() == size)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FlashGeometry {
+    pub total_size: u32,
+    pub page_size: u32,
+    pub erase_unit: EraseUnit,
+}
+
+impl FlashGeometry {
+    /// Winbond W25Q128: 16MB, 256B pages, 4KB sectors
+    pub const W25Q128: Self = Self {
+        total_size: FLASH_TOTAL_SIZE as u32,
+        page_size: FLASH_PAGE_SIZE as u32,
+        erase_unit: EraseUnit::Sector,
+    };
+
+    /// Geometry implied by a JEDEC ID, for chips without SFDP
+    ///
+    /// Unknown capacities fall back to the W25Q128's 16MB, and only the
+    /// families known to lack a 4KB erase get 64KB sectors.
+    pub fn from_jedec_id(jedec_id: u32) -> Self {
+        Self {
+            total_size: jedec::capacity_bytes(jedec_id).unwrap_or(FLASH_TOTAL_SIZE as u32),
+            page_size: FLASH_PAGE_SIZE as u32,
+            erase_unit: if jedec::lacks_sector_erase(jedec_id) {
+                EraseUnit::Block
+            } else {
+                EraseUnit::Sector
+            },
+        }
+    }
+
+    /// Geometry described by a Basic Flash Parameter Table
+    ///
+    /// `None` if the table is too short, or the chip has neither a 4KB erase
+    /// with opcode 0x20 nor a 64KB erase with opcode 0xD8.
+    pub fn from_bfpt(bfpt: &[u8]) -> Option<Self> {
+        let dword = |index: usize| {
+            let bytes = bfpt.get(index * 4..index * 4 + 4)?;
+            Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        };
+
+        // DWORD 2: density in bits, either N-1 or (bit 31 set) 2^N
+        let density = dword(1)?;
+        let bits = if density & 0x8000_0000 == 0 {
+            density as u64 + 1
+        } else {
+            1u64.checked_shl(density & 0x7FFF_FFFF)?
+        };
+        let total_size = u32::try_from(bits / 8).ok()?;
+
+        // DWORDs 8 and 9: up to four erase types, each a size exponent and an opcode
+        let erase_types = [dword(7)?.to_le_bytes(), dword(8)?.to_le_bytes()];
+        let supports = |unit: EraseUnit, opcode: u8| {
+            erase_types.iter().flat_map(|d| d.chunks(2)).any(|t| {
+                t[0] != 0 && 1u32.checked_shl(t[0] as u32) == Some(unit.size()) && t[1] == opcode
+            })
+        };
+        let erase_unit = if supports(EraseUnit::Sector, SECTOR_ERASE_OPCODE) {
+            EraseUnit::Sector
+        } else if supports(EraseUnit::Block, BLOCK_ERASE_OPCODE) {
+            EraseUnit::Block
+        } else {
+            return None;
+        };
+
+        Some(Self {
+            total_size,
+            page_size: FLASH_PAGE_SIZE as u32,
+            erase_unit,
+        })
+    }
+
+    /// Smallest erasable unit in bytes
+    pub const fn sector_size(&self) -> u32 {
+        self.erase_unit.size()
+    }
+
+    /// The whole sectors an erase of `size` bytes at `address` clears
+    pub fn erase_span(&self, address: u32, size: u32) -> Segment {
+        if size == 0 {
+            return Segment::new(address, 0);
+        }
+        let sector = self.sector_size() as u64;
+        let start = address as u64 / sector * sector;
+        let end = (address as u64 + size as u64).div_ceil(sector) * sector;
+        Segment::new(start as u32, (end - start) as u32)
+    }
+}
+
+impl Default for FlashGeometry {
+    fn default() -> Self {
+        Self::W25Q128
+    }
+}
+
+/// Address and length in bytes of the BFPT, from the first
+/// [`SFDP_HEADER_SIZE`] bytes of SFDP space
+pub fn bfpt_location(header: &[u8]) -> Option<(u32, u32)> {
+    let header = header.get(..SFDP_HEADER_SIZE)?;
+    let signature = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+    // The first parameter header always describes the BFPT (ID 0xFF00)
+    if signature != SFDP_SIGNATURE || header[8] != 0x00 || header[15] != 0xFF {
+        return None;
+    }
+    let length = header[11] as u32 * 4;
+    let address = u32::from_le_bytes([header[12], header[13], header[14], 0]);
+    Some((address, length))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// SFDP header and a JESD216 (nine DWORD) BFPT at 0x30 for a chip of
+    /// `bits` density with the given (size exponent, opcode) erase types
+    fn sfdp(bits: u32, erase_types: [(u8, u8); 4]) -> ([u8; 16], [u8; 36]) {
+        let mut header = [0u8; 16];
+        header[..4].copy_from_slice(&SFDP_SIGNATURE.to_le_bytes());
+        header[5] = 1;
+        header[7] = 0xFF;
+        header[10] = 1;
+        header[11] = 9;
+        header[12] = 0x30;
+        header[15] = 0xFF;
+
+        let mut bfpt = [0xFFu8; 36];
+        bfpt[4..8].copy_from_slice(&(bits - 1).to_le_bytes());
+        for (i, (size, opcode)) in erase_types.iter().enumerate() {
+            bfpt[28 + i * 2] = *size;
+            bfpt[29 + i * 2] = *opcode;
+        }
+        (header, bfpt)
+    }
+
+    #[test]
+    fn test_bfpt_with_4kb_erase() {
+        let (header, bfpt) = sfdp(128 << 20, [(12, 0x20), (15, 0x52), (16, 0xD8), (0, 0)]);
+        assert_eq!(bfpt_location(&header), Some((0x30, 36)));
+        assert_eq!(
+            FlashGeometry::from_bfpt(&bfpt),
+            Some(FlashGeometry::W25Q128)
+        );
+    }
+
+    #[test]
+    fn test_64kb_sector_only_chip() {
+        let (_, bfpt) = sfdp(64 << 20, [(16, 0xD8), (0, 0), (0, 0), (0, 0)]);
+        let geometry = FlashGeometry::from_bfpt(&bfpt).unwrap();
+        assert_eq!(geometry.total_size, 8 * 1024 * 1024);
+        assert_eq!(geometry.erase_unit, EraseUnit::Block);
+        assert_eq!(geometry.sector_size(), 0x10000);
+
+        // A small erase still clears the whole 64KB around it
+        assert_eq!(geometry.erase_span(0x1000, 1), Segment::new(0, 0x10000));
+        assert_eq!(geometry.erase_span(0xFFFF, 2), Segment::new(0, 0x20000));
+        assert_eq!(geometry.erase_span(0x30000, 0), Segment::new(0x30000, 0));
+
+        // Same geometry from the JEDEC ID of a chip without SFDP (M25P64)
+        assert_eq!(FlashGeometry::from_jedec_id(0x202017), geometry);
+    }
+
+    #[test]
+    fn test_unusable_sfdp_falls_back() {
+        // No erase type the backend can issue (a 256KB-only erase)
+        let (mut header, bfpt) = sfdp(128 << 20, [(18, 0xD8), (0, 0), (0, 0), (0, 0)]);
+        assert_eq!(FlashGeometry::from_bfpt(&bfpt), None);
+        assert_eq!(FlashGeometry::from_bfpt(&bfpt[..20]), None);
+
+        header[0] = 0xFF;
+        assert_eq!(bfpt_location(&header), None);
+        assert_eq!(
+            FlashGeometry::from_jedec_id(0xEF4018).erase_span(0x1010, 0x100),
+            Segment::new(0x1000, 0x1000)
+        );
+    }
+}
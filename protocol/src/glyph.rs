@@ -0,0 +1,221 @@
+//! Bitmap glyph rasterization shared by the display firmware's text renderers
+//!
+//! Glyphs are stored 1 bit per pixel. The web font tool writes rows, MSB
+//! first, each row padded to a whole byte; other generators (font8x8, many
+//! LCD tools) use LSB first or column-major bytes instead. A font declares
+//! its layout once, in the top byte of its header word, and every glyph is
+//! drawn through the same [`BitmapFormat`].
+
+/// Which pixel the most significant bit of a bitmap byte holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BitOrder {
+    /// Bit 7 is the first pixel of the byte
+    #[default]
+    MsbFirst,
+    /// Bit 0 is the first pixel of the byte
+    LsbFirst,
+}
+
+/// Whether bitmap bytes run along rows or down columns
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ByteOrder {
+    /// Each byte holds 8 horizontal pixels; rows are padded to whole bytes
+    #[default]
+    RowMajor,
+    /// Each byte holds 8 vertical pixels; columns are padded to whole bytes
+    ColumnMajor,
+}
+
+/// Bit and byte layout of a font's glyph bitmaps
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BitmapFormat {
+    pub bit_order: BitOrder,
+    pub byte_order: ByteOrder,
+}
+
+/// Header flag: bitmap bytes are LSB first
+pub const FORMAT_FLAG_LSB_FIRST: u8 = 1 << 0;
+/// Header flag: bitmap bytes are column-major
+pub const FORMAT_FLAG_COLUMN_MAJOR: u8 = 1 << 1;
+/// Header flag: a kerning table follows the character table (see
+/// [`crate::font`]); not part of the bitmap format
+pub const FONT_FLAG_KERNING: u8 = 1 << 2;
+
+/// Bits of the font header word holding the character count; the top byte
+/// holds the format flags (zero for fonts from the web tool)
+pub const FONT_HEADER_COUNT_MASK: u32 = 0x00FF_FFFF;
+
+impl BitmapFormat {
+    /// Decode the format flags from the top byte of a font header word
+    pub fn from_header_flags(flags: u8) -> Result<Self, &'static str> {
+        if flags & !(FORMAT_FLAG_LSB_FIRST | FORMAT_FLAG_COLUMN_MAJOR | FONT_FLAG_KERNING) != 0 {
+            return Err("Unknown font bitmap format flags");
+        }
+        Ok(Self {
+            bit_order: if flags & FORMAT_FLAG_LSB_FIRST != 0 {
+                BitOrder::LsbFirst
+            } else {
+                BitOrder::MsbFirst
+            },
+            byte_order: if flags & FORMAT_FLAG_COLUMN_MAJOR != 0 {
+                ByteOrder::ColumnMajor
+            } else {
+                ByteOrder::RowMajor
+            },
+        })
+    }
+
+    /// The header flag byte describing this format
+    pub fn header_flags(self) -> u8 {
+        let mut flags = 0;
+        if self.bit_order == BitOrder::LsbFirst {
+            flags |= FORMAT_FLAG_LSB_FIRST;
+        }
+        if self.byte_order == ByteOrder::ColumnMajor {
+            flags |= FORMAT_FLAG_COLUMN_MAJOR;
+        }
+        flags
+    }
+
+    /// Bytes needed for a `width` x `height` glyph
+    pub fn bitmap_size(self, width: u8, height: u8) -> usize {
+        match self.byte_order {
+            ByteOrder::RowMajor => (width as usize).div_ceil(8) * height as usize,
+            ByteOrder::ColumnMajor => (height as usize).div_ceil(8) * width as usize,
+        }
+    }
+
+    /// Whether the pixel at (`col`, `row`) is set; out-of-range bytes read as unset
+    pub fn pixel(self, bitmap: &[u8], width: u8, height: u8, col: usize, row: usize) -> bool {
+        let (byte_index, offset) = match self.byte_order {
+            ByteOrder::RowMajor => (row * (width as usize).div_ceil(8) + col / 8, col % 8),
+            ByteOrder::ColumnMajor => (col * (height as usize).div_ceil(8) + row / 8, row % 8),
+        };
+        let bit_index = match self.bit_order {
+            BitOrder::MsbFirst => 7 - offset,
+            BitOrder::LsbFirst => offset,
+        };
+        bitmap
+            .get(byte_index)
+            .is_some_and(|byte| (byte >> bit_index) & 1 != 0)
+    }
+}
+
+/// Split a font header word into its character count and bitmap format
+pub fn parse_font_header(word: u32) -> Result<(u32, BitmapFormat), &'static str> {
+    let format = BitmapFormat::from_header_flags((word >> 24) as u8)?;
+    Ok((word & FONT_HEADER_COUNT_MASK, format))
+}
+
+/// Screen positions of the set pixels of a glyph drawn at (`x`, `y`)
+///
+/// Pixels that would land outside a `screen_width` x `screen_height` panel,
+/// including at negative coordinates, are skipped so callers never address
+/// memory past the display's window.
+#[allow(clippy::too_many_arguments)]
+pub fn lit_pixels(
+    bitmap: &[u8],
+    format: BitmapFormat,
+    width: u8,
+    height: u8,
+    x: i32,
+    y: i32,
+    screen_width: u16,
+    screen_height: u16,
+) -> impl Iterator<Item = (u16, u16)> + '_ {
+    (0..height as i32).flat_map(move |row| {
+        (0..width as i32).filter_map(move |col| {
+            if !format.pixel(bitmap, width, height, col as usize, row as usize) {
+                return None;
+            }
+
+            let pixel_x = x + col;
+            let pixel_y = y + row;
+            if pixel_x < 0
+                || pixel_y < 0
+                || pixel_x >= screen_width as i32
+                || pixel_y >= screen_height as i32
+            {
+                return None;
+            }
+            Some((pixel_x as u16, pixel_y as u16))
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Vec;
+
+    // 10x2 glyph: every pixel set
+    const SOLID: [u8; 4] = [0xFF, 0xC0, 0xFF, 0xC0];
+
+    const MSB_ROWS: BitmapFormat = BitmapFormat {
+        bit_order: BitOrder::MsbFirst,
+        byte_order: ByteOrder::RowMajor,
+    };
+
+    #[test]
+    fn test_lit_pixels_follow_msb_first_rows() {
+        // 3x2: row 0 = X.X, row 1 = .X.
+        let pixels: Vec<_> = lit_pixels(&[0xA0, 0x40], MSB_ROWS, 3, 2, 5, 7, 320, 172).collect();
+        assert_eq!(pixels, [(5, 7), (7, 7), (6, 8)]);
+        assert_eq!(
+            lit_pixels(&SOLID, MSB_ROWS, 10, 2, 0, 0, 320, 172).count(),
+            20
+        );
+    }
+
+    #[test]
+    fn test_partly_off_screen_glyph_is_clipped() {
+        let pixels: Vec<_> = lit_pixels(&SOLID, MSB_ROWS, 10, 2, 315, 171, 320, 172).collect();
+        assert_eq!(pixels.len(), 5);
+        assert!(pixels.iter().all(|&(x, y)| x < 320 && y < 172));
+
+        let left: Vec<_> = lit_pixels(&SOLID, MSB_ROWS, 10, 2, -8, -1, 320, 172).collect();
+        assert_eq!(left, [(0, 0), (1, 0)]);
+    }
+
+    #[test]
+    fn test_every_format_decodes_the_same_glyph() {
+        // 9x2 glyph: row 0 = X.......X, row 1 = .X.......
+        let expected = [(0, 0), (8, 0), (1, 1)];
+        let cases: [(u8, &[u8]); 4] = [
+            (0, &[0x80, 0x80, 0x40, 0x00]),
+            (FORMAT_FLAG_LSB_FIRST, &[0x01, 0x01, 0x02, 0x00]),
+            (
+                FORMAT_FLAG_COLUMN_MAJOR,
+                &[0x80, 0x40, 0, 0, 0, 0, 0, 0, 0x80],
+            ),
+            (
+                FORMAT_FLAG_LSB_FIRST | FORMAT_FLAG_COLUMN_MAJOR,
+                &[0x01, 0x02, 0, 0, 0, 0, 0, 0, 0x01],
+            ),
+        ];
+
+        for (flags, bitmap) in cases {
+            let format = BitmapFormat::from_header_flags(flags).unwrap();
+            assert_eq!(format.header_flags(), flags);
+            assert_eq!(format.bitmap_size(9, 2), bitmap.len());
+            let pixels: Vec<_> = lit_pixels(bitmap, format, 9, 2, 0, 0, 320, 172).collect();
+            assert_eq!(pixels, expected, "flags {:#04x}", flags);
+        }
+    }
+
+    #[test]
+    fn test_font_header_splits_count_and_format() {
+        assert_eq!(
+            parse_font_header(95).unwrap(),
+            (95, BitmapFormat::default())
+        );
+
+        let (count, format) = parse_font_header(0x0300_1000).unwrap();
+        assert_eq!(count, 0x1000);
+        assert_eq!(format.bit_order, BitOrder::LsbFirst);
+        assert_eq!(format.byte_order, ByteOrder::ColumnMajor);
+
+        // Erased flash is not a font
+        assert!(parse_font_header(u32::MAX).is_err());
+    }
+}
@@ -0,0 +1,1615 @@
+//! Command dispatch shared by every firmware backend
+//!
+//! [`ProtocolHandler`] turns a parsed [`Packet`] into a [`Response`] using
+//! only the [`FlashBackend`] trait, so it is independent of both the flash
+//! driver and the transport.
+
+use super::Vec;
+use crate::backend::{BackendError, FlashBackend};
+use crate::batch::{BatchChecksum, MAX_BATCH_LENGTH};
+use crate::capabilities::{self, Capabilities};
+use crate::config::RuntimeConfig;
+use crate::crc32::Crc32;
+use crate::geometry::{self, EraseUnit, FlashGeometry};
+use crate::segments::Segment;
+use crate::{erase_progress, jedec, read_stream};
+use crate::{
+    Command, CrcMode, ErrorDetail, Packet, Response, SpiMode, Status, INFO_FLAG_CRC16,
+    INFO_FLAG_WRITE_PROTECTED, MAX_PAYLOAD_SIZE,
+};
+
+/// Destination for responses produced by [`ProtocolHandler::handle_packet`]
+#[allow(async_fn_in_trait)]
+pub trait ResponseSink {
+    type Error;
+
+    /// Transmit one complete response, framed with a `crc_mode` trailer
+    /// (its `crc` already holds that kind of checksum)
+    async fn send(&mut self, response: &Response, crc_mode: CrcMode) -> Result<(), Self::Error>;
+}
+
+/// Forwards responses to the real sink, noting what a command sent for its
+/// `RESULT` log line
+struct Outcome<'s, S> {
+    sink: &'s mut S,
+    /// Status of the last response; `None` if nothing was sent
+    status: Option<Status>,
+    /// Payload bytes over every response
+    bytes: usize,
+}
+
+impl<S: ResponseSink> ResponseSink for Outcome<'_, S> {
+    type Error = S::Error;
+
+    async fn send(&mut self, response: &Response, crc_mode: CrcMode) -> Result<(), S::Error> {
+        self.status = Some(response.status);
+        self.bytes += response.data.len();
+        self.sink.send(response, crc_mode).await
+    }
+}
+
+/// Pre-program check for cells that are not blank
+///
+/// NOR flash can only clear bits when programming; writing over data that
+/// would need a 0 -> 1 transition silently produces `old & new`. The check
+/// reads the target range back before each write to catch missing erases.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum BlankCheck {
+    /// No pre-program read (fastest)
+    Off = 0,
+    /// Log the first offending address and program anyway
+    Warn = 1,
+    /// Refuse the write with `Status::NotErased`
+    Reject = 2,
+}
+
+impl TryFrom<u8> for BlankCheck {
+    type Error = &'static str;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(BlankCheck::Off),
+            1 => Ok(BlankCheck::Warn),
+            2 => Ok(BlankCheck::Reject),
+            _ => Err("Invalid blank check mode"),
+        }
+    }
+}
+
+/// Commands reported as supported by `Command::Capabilities`
+///
+/// The batch ACK commands do nothing, so they are left out.
+const SUPPORTED_COMMANDS: [Command; 19] = [
+    Command::Info,
+    Command::Erase,
+    Command::Write,
+    Command::Read,
+    Command::Verify,
+    Command::VerifyCRC,
+    Command::StreamWrite,
+    Command::Status,
+    Command::SetSpiMode,
+    Command::ReadStream,
+    Command::BatchChecksum,
+    Command::SetSpiFrequency,
+    Command::Abort,
+    Command::Capabilities,
+    Command::ReadSfdp,
+    Command::GetErrorCounters,
+    Command::ClearErrorCounters,
+    Command::GetConfig,
+    Command::EnterBootloader,
+];
+
+/// Protocol command dispatcher over a flash backend
+pub struct ProtocolHandler<B: FlashBackend> {
+    backend: B,
+    blank_check: BlankCheck,
+    /// Reported by Capabilities; the transport owns the CRC peripheral
+    hardware_crc: bool,
+    /// Trailer checksum negotiated by the last Info packet
+    crc_mode: CrcMode,
+    /// Chip geometry, detected by the first Info or Erase and redetected by
+    /// every Info
+    geometry: Option<FlashGeometry>,
+}
+
+impl<B: FlashBackend> ProtocolHandler<B> {
+    pub fn new(backend: B) -> Self {
+        Self {
+            backend,
+            blank_check: BlankCheck::Off,
+            hardware_crc: false,
+            crc_mode: CrcMode::Crc32,
+            geometry: None,
+        }
+    }
+
+    /// Trailer checksum for packets after the last Info exchange; the
+    /// transport must parse with it (see [`CrcMode`])
+    pub fn crc_mode(&self) -> CrcMode {
+        self.crc_mode
+    }
+
+    /// Select how writes over non-erased cells are handled
+    pub fn set_blank_check(&mut self, blank_check: BlankCheck) {
+        self.blank_check = blank_check;
+    }
+
+    /// Report whether packet CRCs are computed by a hardware peripheral
+    pub fn set_hardware_crc(&mut self, hardware_crc: bool) {
+        self.hardware_crc = hardware_crc;
+    }
+
+    /// Access the underlying backend
+    pub fn backend(&mut self) -> &mut B {
+        &mut self.backend
+    }
+
+    /// Consume the handler and return the backend
+    pub fn into_backend(self) -> B {
+        self.backend
+    }
+
+    /// Execute a command and send every response it produces to `sink`
+    ///
+    /// Most commands produce exactly one response; `ReadStream` produces one
+    /// per chunk, and an Erase asking for progress one per sector.
+    ///
+    /// A packet whose trailer doesn't match its contents under the negotiated
+    /// [`CrcMode`] isn't executed: it is answered with `CrcError` so the host
+    /// resends it.
+    ///
+    /// Each command ends with one info-level `RESULT` log line with fixed
+    /// `key=value` fields for probe-side scripts: the command, its address and
+    /// length field, the status of its last response and the payload bytes
+    /// sent back. Building with `DEFMT_LOG=warn` compiles it out.
+    pub async fn handle_packet<S: ResponseSink>(
+        &mut self,
+        packet: &Packet,
+        sink: &mut S,
+    ) -> Result<(), S::Error> {
+        let mut outcome = Outcome {
+            sink,
+            status: None,
+            bytes: 0,
+        };
+        let sent = self.dispatch(packet, &mut outcome).await;
+        info!(
+            "RESULT cmd={} addr=0x{:08X} len={} status={} bytes={}",
+            packet.command, packet.address, packet.length, outcome.status, outcome.bytes
+        );
+        sent
+    }
+
+    async fn dispatch<S: ResponseSink>(
+        &mut self,
+        packet: &Packet,
+        sink: &mut S,
+    ) -> Result<(), S::Error> {
+        let crc_mode = self.crc_mode.for_command(packet.command);
+        if !packet.verify_crc_with(crc_mode) {
+            warn!(
+                "Rejecting {} packet (seq {}) with a bad CRC",
+                packet.command, packet.sequence
+            );
+            let response =
+                Response::new_with_sequence(Status::CrcError, Vec::new(), packet.sequence);
+            return sink.send(&frame(response, crc_mode), crc_mode).await;
+        }
+        if packet.command == Command::Erase
+            && erase_progress::wants_progress(&packet.data)
+            && !erase_progress::wants_chip_erase(&packet.data)
+        {
+            info!("Protocol: Processing Erase command with progress reports");
+            return self.erase_with_progress(packet, sink).await;
+        }
+        if packet.command != Command::ReadStream {
+            let response = self.process_packet(packet).await;
+            return sink.send(&response, crc_mode).await;
+        }
+
+        info!("Protocol: Processing ReadStream command");
+        let sequence = packet.sequence;
+        if packet.length == 0 || packet.length > read_stream::MAX_STREAM_LENGTH {
+            let response =
+                Response::new_with_sequence(Status::InvalidAddress, Vec::new(), sequence);
+            return sink.send(&frame(response, crc_mode), crc_mode).await;
+        }
+
+        let total = read_stream::chunk_count(packet.length);
+        for index in 0..total {
+            let offset = index as u32 * read_stream::CHUNK_SIZE as u32;
+            let length = (packet.length - offset).min(read_stream::CHUNK_SIZE as u32);
+            let address = packet.address.wrapping_add(offset);
+
+            match self.read_exact(address, length).await {
+                Ok(payload) => {
+                    let data = read_stream::encode_chunk(index, total, &payload);
+                    let response = Response::new_with_sequence(Status::Success, data, sequence);
+                    sink.send(&frame(response, crc_mode), crc_mode).await?;
+                }
+                Err(e) => {
+                    error!("Stream read error at 0x{:08X}: {:?}", address, e);
+                    // A non-success response terminates the stream
+                    let response = error_response(e).with_sequence(sequence);
+                    return sink.send(&frame(response, crc_mode), crc_mode).await;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Execute a single command packet and return the response to send back
+    ///
+    /// The response echoes the packet's sequence number and carries a
+    /// checksum for the negotiated [`CrcMode`]. `ReadStream` needs several
+    /// responses and is only served by [`handle_packet`](Self::handle_packet).
+    pub async fn process_packet(&mut self, packet: &Packet) -> Response {
+        let crc_mode = self.crc_mode.for_command(packet.command);
+        let response = self.execute(packet).await.with_sequence(packet.sequence);
+        frame(response, crc_mode)
+    }
+
+    async fn execute(&mut self, packet: &Packet) -> Response {
+        match packet.command {
+            Command::Info => {
+                info!("Protocol: Processing Info command");
+                // The optional first byte requests the trailer for later packets
+                let requested = match packet.data.first() {
+                    None => CrcMode::Crc32,
+                    Some(&mode) => CrcMode::try_from(mode).unwrap_or_else(|_| {
+                        warn!("Unsupported CRC mode {}, using CRC-32", mode);
+                        CrcMode::Crc32
+                    }),
+                };
+                match self.backend.jedec_id().await {
+                    Ok(jedec_id) => {
+                        let geometry = self.detect_geometry(jedec_id).await;
+                        self.geometry = Some(geometry);
+                        let mut data = Vec::new();
+                        data.extend_from_slice(&jedec_id.to_le_bytes());
+                        data.extend_from_slice(&geometry.total_size.to_le_bytes());
+                        data.extend_from_slice(&geometry.page_size.to_le_bytes());
+                        data.extend_from_slice(&geometry.sector_size().to_le_bytes());
+                        let mut flags = 0;
+                        if let Ok(false) = self.backend.write_enable_check().await {
+                            warn!("Write protection appears active - check WP# pin");
+                            flags |= INFO_FLAG_WRITE_PROTECTED;
+                        }
+                        self.crc_mode = requested;
+                        if requested == CrcMode::Crc16 {
+                            flags |= INFO_FLAG_CRC16;
+                        }
+                        data.extend_from_slice(&flags.to_le_bytes());
+                        Response::new(Status::Success, data)
+                    }
+                    Err(e) => {
+                        error!("Flash info error: {:?}", e);
+                        // No flags to report a switch with, so stay on the default
+                        self.crc_mode = CrcMode::Crc32;
+                        error_response(e)
+                    }
+                }
+            }
+            Command::Read => {
+                info!("Protocol: Processing Read command");
+                if packet.length as usize > MAX_PAYLOAD_SIZE {
+                    error!("Read of {} bytes too large, use ReadStream", packet.length);
+                    return Response::new(Status::InvalidAddress, Vec::new());
+                }
+                if packet.length == 0 {
+                    // Nothing to read, so the address isn't checked either
+                    return Response::new(Status::Success, Vec::new());
+                }
+                match self.read_exact(packet.address, packet.length).await {
+                    Ok(data) => Response::new(Status::Success, data),
+                    Err(e) => {
+                        error!("Flash read error: {:?}", e);
+                        error_response(e)
+                    }
+                }
+            }
+            Command::Write | Command::StreamWrite => {
+                info!("Protocol: Processing Write command");
+                if packet.data.is_empty() {
+                    // Nothing to program: succeed without touching the chip
+                    return Response::new(Status::Success, Vec::new());
+                }
+                if let Some(response) = self.check_blank(packet.address, &packet.data).await {
+                    return response;
+                }
+                match self.backend.write(packet.address, &packet.data).await {
+                    Ok(()) => {
+                        debug!(
+                            "Wrote {} bytes at 0x{:08X}",
+                            packet.data.len(),
+                            packet.address
+                        );
+                        Response::new(Status::Success, Vec::new())
+                    }
+                    Err(e) => {
+                        error!("Flash write error at 0x{:08X}: {:?}", packet.address, e);
+                        error_response(e)
+                    }
+                }
+            }
+            Command::Erase => {
+                info!("Protocol: Processing Erase command");
+                self.handle_erase(packet).await
+            }
+            Command::Verify => {
+                info!("Protocol: Processing Verify command");
+                self.handle_verify(packet).await
+            }
+            Command::VerifyCRC => {
+                info!("Protocol: Processing VerifyCRC command");
+                self.handle_verify_crc(packet).await
+            }
+            Command::BatchChecksum => {
+                info!("Protocol: Processing BatchChecksum command");
+                self.handle_batch_checksum(packet).await
+            }
+            Command::Status => {
+                info!("Protocol: Processing Status command");
+                match self.backend.status().await {
+                    Ok(status) => {
+                        info!("Flash status register: 0x{:02X}", status);
+                        Response::new(Status::Success, [status].to_vec())
+                    }
+                    Err(e) => {
+                        error!("Flash status read error: {:?}", e);
+                        error_response(e)
+                    }
+                }
+            }
+            Command::SetSpiMode => {
+                info!("Protocol: Processing SetSpiMode command");
+                let mode = match packet.data.first().map(|&m| SpiMode::try_from(m)) {
+                    Some(Ok(mode)) => mode,
+                    _ => {
+                        error!("Rejecting SPI mode {:?}", packet.data.first());
+                        return Response::new(Status::InvalidCommand, Vec::new());
+                    }
+                };
+                match self.backend.set_spi_mode(mode).await {
+                    Ok(()) => Response::new(Status::Success, Vec::new()),
+                    Err(e) => {
+                        error!("SPI mode change error: {:?}", e);
+                        error_response(e)
+                    }
+                }
+            }
+            Command::SetSpiFrequency => {
+                info!("Protocol: Processing SetSpiFrequency command");
+                let hz = match packet.data.get(0..4) {
+                    Some(bytes) => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+                    None => 0,
+                };
+                if hz == 0 {
+                    error!("Rejecting SPI frequency {}", hz);
+                    return Response::new(Status::InvalidCommand, Vec::new());
+                }
+                match self.backend.set_spi_frequency(hz).await {
+                    Ok(actual) => Response::new(Status::Success, actual.to_le_bytes().to_vec()),
+                    Err(e) => {
+                        error!("SPI frequency change error: {:?}", e);
+                        error_response(e)
+                    }
+                }
+            }
+            Command::Abort => {
+                info!("Protocol: Processing Abort command");
+                // The transport raises the cancel flag when the packet
+                // arrives; by now the interrupted command has already failed
+                Response::new(Status::Success, Vec::new())
+            }
+            Command::Capabilities => {
+                info!("Protocol: Processing Capabilities command");
+                let geometry = match self.geometry().await {
+                    Ok(geometry) => Some(geometry),
+                    Err(e) => {
+                        warn!("No flash geometry to report: {:?}", e);
+                        None
+                    }
+                };
+                let capabilities = Capabilities {
+                    commands: SUPPORTED_COMMANDS.iter().fold(0, |bits, &command| {
+                        bits | capabilities::command_bit(command)
+                    }),
+                    crc_mode: self.crc_mode,
+                    hardware_crc: self.hardware_crc,
+                    chip_erase: true,
+                    power_safe_erase: true,
+                    max_payload_size: MAX_PAYLOAD_SIZE as u32,
+                    max_read_size: MAX_PAYLOAD_SIZE as u32,
+                    geometry,
+                };
+                Response::new(Status::Success, capabilities.to_bytes())
+            }
+            Command::ReadSfdp => {
+                info!("Protocol: Processing ReadSfdp command");
+                if packet.length as usize > MAX_PAYLOAD_SIZE {
+                    error!("SFDP read of {} bytes too large", packet.length);
+                    return Response::new(Status::InvalidAddress, Vec::new());
+                }
+                match self.backend.read_sfdp(packet.address, packet.length).await {
+                    Ok(data) => Response::new(Status::Success, data),
+                    Err(e) => {
+                        warn!("SFDP read error: {:?}", e);
+                        error_response(e)
+                    }
+                }
+            }
+            Command::GetErrorCounters => {
+                info!("Protocol: Processing GetErrorCounters command");
+                match self.backend.error_counters().await {
+                    Ok(counters) => Response::new(Status::Success, counters.to_bytes()),
+                    Err(e) => error_response(e),
+                }
+            }
+            Command::ClearErrorCounters => {
+                info!("Protocol: Processing ClearErrorCounters command");
+                match self.backend.clear_error_counters().await {
+                    Ok(()) => Response::new(Status::Success, Vec::new()),
+                    Err(e) => error_response(e),
+                }
+            }
+            Command::GetConfig => {
+                info!("Protocol: Processing GetConfig command");
+                let config = RuntimeConfig {
+                    spi_mode: self.backend.spi_mode(),
+                    blank_check: self.blank_check,
+                    spi_frequency_hz: self.backend.spi_frequency_hz(),
+                    max_payload_size: MAX_PAYLOAD_SIZE as u32,
+                };
+                Response::new(Status::Success, config.to_bytes())
+            }
+            Command::EnterBootloader => {
+                info!("Protocol: Processing EnterBootloader command");
+                // The transport reboots after sending this, as only it knows
+                // when the response has left
+                Response::new(Status::Success, Vec::new())
+            }
+            Command::ReadStream => Response::new(Status::InvalidCommand, Vec::new()),
+            Command::BatchWrite | Command::BatchAck => {
+                info!("Protocol: Processing batch command");
+                // These commands are not implemented yet, but don't error
+                Response::new(Status::Success, Vec::new())
+            }
+        }
+    }
+
+    /// Read exactly `length` bytes, issuing several backend reads if it returns short
+    async fn read_exact(&mut self, address: u32, length: u32) -> Result<Vec<u8>, BackendError> {
+        let mut data = self.backend.read(address, length).await?;
+        while (data.len() as u32) < length {
+            let offset = data.len() as u32;
+            let more = self.backend.read(address + offset, length - offset).await?;
+            if more.is_empty() {
+                return Err(BackendError::Bus);
+            }
+            data.extend_from_slice(&more);
+        }
+        data.truncate(length as usize);
+        Ok(data)
+    }
+
+    /// Run the configured blank check; returns a response if the write must not proceed
+    async fn check_blank(&mut self, address: u32, data: &[u8]) -> Option<Response> {
+        if self.blank_check == BlankCheck::Off {
+            return None;
+        }
+
+        let conflict = match self.find_program_conflict(address, data).await {
+            Ok(conflict) => conflict,
+            Err(e) => {
+                error!("Blank check read error at 0x{:08X}: {:?}", address, e);
+                return Some(error_response(e));
+            }
+        };
+
+        let conflict_address = conflict?;
+        warn!(
+            "Programming non-erased cells at 0x{:08X} (write at 0x{:08X}, {} bytes)",
+            conflict_address,
+            address,
+            data.len()
+        );
+
+        match self.blank_check {
+            BlankCheck::Reject => Some(Response::new(Status::NotErased, Vec::new())),
+            _ => None,
+        }
+    }
+
+    /// First address where programming `data` would need to set a bit
+    async fn find_program_conflict(
+        &mut self,
+        address: u32,
+        data: &[u8],
+    ) -> Result<Option<u32>, BackendError> {
+        let mut offset = 0;
+        while offset < data.len() {
+            let chunk_address = address + offset as u32;
+            let current = self
+                .backend
+                .read(chunk_address, (data.len() - offset) as u32)
+                .await?;
+            if current.is_empty() {
+                return Err(BackendError::Bus);
+            }
+
+            // A bit must be set wherever new data has a 1 the cell no longer has
+            if let Some(i) = current
+                .iter()
+                .zip(&data[offset..])
+                .position(|(old, new)| new & !old != 0)
+            {
+                return Ok(Some(chunk_address + i as u32));
+            }
+            offset += current.len();
+        }
+        Ok(None)
+    }
+
+    /// Compare the CRC of a stream-written range against the host's
+    async fn handle_batch_checksum(&mut self, packet: &Packet) -> Response {
+        let expected = match BatchChecksum::from_bytes(&packet.data) {
+            Ok(expected) => expected,
+            Err(e) => {
+                error!("Bad batch checksum payload: {}", e);
+                return Response::new(Status::InvalidCommand, Vec::new());
+            }
+        };
+        if expected.length == 0 || expected.length > MAX_BATCH_LENGTH {
+            error!("Batch checksum length {} out of range", expected.length);
+            return Response::new(Status::InvalidAddress, Vec::new());
+        }
+
+        self.check_range_crc(packet.address, expected).await
+    }
+
+    /// Check a `crc32 u32 | length u32` payload (the [`BatchChecksum`]
+    /// layout) against flash; unlike BatchChecksum the block may be any size
+    async fn handle_verify_crc(&mut self, packet: &Packet) -> Response {
+        let expected = match BatchChecksum::from_bytes(&packet.data) {
+            Ok(expected) => expected,
+            Err(e) => {
+                error!("Bad VerifyCRC payload: {}", e);
+                return Response::new(Status::InvalidCommand, Vec::new());
+            }
+        };
+        if packet.address.checked_add(expected.length).is_none() {
+            error!(
+                "VerifyCRC range overflows: 0x{:08X} + {}",
+                packet.address, expected.length
+            );
+            return Response::new(Status::InvalidAddress, Vec::new());
+        }
+        self.check_range_crc(packet.address, expected).await
+    }
+
+    /// Read `expected.length` bytes at `address` back and compare their CRC,
+    /// answering `VerificationFailed` with the computed CRC on a mismatch
+    async fn check_range_crc(&mut self, address: u32, expected: BatchChecksum) -> Response {
+        let mut crc = Crc32::new();
+        let mut offset = 0;
+        while offset < expected.length {
+            let length = (expected.length - offset).min(MAX_PAYLOAD_SIZE as u32);
+            match self.read_exact(address.wrapping_add(offset), length).await {
+                Ok(data) => crc.update(&data),
+                Err(e) => {
+                    error!("Checksum read error: {:?}", e);
+                    return error_response(e);
+                }
+            }
+            offset += length;
+        }
+
+        if crc.value() == expected.crc32 {
+            Response::new(Status::Success, Vec::new())
+        } else {
+            warn!(
+                "Range at 0x{:08X} ({} bytes): CRC 0x{:08X}, expected 0x{:08X}",
+                address,
+                expected.length,
+                crc.value(),
+                expected.crc32
+            );
+            Response::new(
+                Status::VerificationFailed,
+                crc.value().to_le_bytes().to_vec(),
+            )
+        }
+    }
+
+    /// Compare the packet's data with what flash holds at its address
+    async fn handle_verify(&mut self, packet: &Packet) -> Response {
+        match self
+            .read_exact(packet.address, packet.data.len() as u32)
+            .await
+        {
+            Ok(data) if data == packet.data => Response::new(Status::Success, Vec::new()),
+            Ok(_) => {
+                warn!(
+                    "Verify at 0x{:08X} ({} bytes): flash differs",
+                    packet.address,
+                    packet.data.len()
+                );
+                Response::new(Status::VerificationFailed, Vec::new())
+            }
+            Err(e) => {
+                error!("Verify read error: {:?}", e);
+                error_response(e)
+            }
+        }
+    }
+
+    /// Geometry of the attached chip, detecting it if no Info has yet
+    async fn geometry(&mut self) -> Result<FlashGeometry, BackendError> {
+        if let Some(geometry) = self.geometry {
+            return Ok(geometry);
+        }
+        let jedec_id = self.backend.jedec_id().await?;
+        let geometry = self.detect_geometry(jedec_id).await;
+        self.geometry = Some(geometry);
+        Ok(geometry)
+    }
+
+    /// Geometry from the chip's SFDP tables, else from its JEDEC ID
+    async fn detect_geometry(&mut self, jedec_id: u32) -> FlashGeometry {
+        if let Some(geometry) = self
+            .read_bfpt()
+            .await
+            .and_then(|bfpt| FlashGeometry::from_bfpt(&bfpt))
+        {
+            return geometry;
+        }
+        if jedec::capacity_bytes(jedec_id).is_none() {
+            warn!("Unknown capacity in JEDEC ID 0x{:06X}", jedec_id);
+        }
+        FlashGeometry::from_jedec_id(jedec_id)
+    }
+
+    /// The SFDP Basic Flash Parameter Table, if the chip and backend have one
+    async fn read_bfpt(&mut self) -> Option<Vec<u8>> {
+        let header = self
+            .backend
+            .read_sfdp(0, geometry::SFDP_HEADER_SIZE as u32)
+            .await
+            .ok()?;
+        let (address, length) = geometry::bfpt_location(&header)?;
+        self.backend.read_sfdp(address, length).await.ok()
+    }
+
+    /// Erase every sector overlapping `[address, address + size)`
+    async fn handle_erase(&mut self, packet: &Packet) -> Response {
+        let (geometry, span) = match self.erase_plan(packet).await {
+            Ok(plan) => plan,
+            Err(response) => return response,
+        };
+
+        if erase_progress::wants_chip_erase(&packet.data) {
+            if span != Segment::new(0, geometry.total_size) {
+                error!("Chip erase must cover the whole chip");
+                return Response::new(Status::InvalidAddress, Vec::new());
+            }
+            info!("Erasing the whole chip");
+            return match self.backend.chip_erase().await {
+                Ok(()) => Response::new(Status::Success, Vec::new()),
+                Err(e) => {
+                    error!("Chip erase error: {:?}", e);
+                    error_response(e)
+                }
+            };
+        }
+
+        let sector_size = geometry.sector_size();
+        let power_safe = erase_progress::wants_power_safe(&packet.data);
+        for sector in 0..span.length / sector_size {
+            let sector_address = span.address + sector * sector_size;
+            if power_safe && sector > 0 {
+                self.backend
+                    .pause(erase_progress::POWER_SAFE_PAUSE_MS)
+                    .await;
+            }
+            if let Err(response) = self.erase_one(geometry.erase_unit, sector_address).await {
+                return response;
+            }
+        }
+
+        Response::new(Status::Success, Vec::new())
+    }
+
+    /// Erase like [`handle_erase`](Self::handle_erase), sending a progress
+    /// response after each sector (see [`erase_progress`])
+    async fn erase_with_progress<S: ResponseSink>(
+        &mut self,
+        packet: &Packet,
+        sink: &mut S,
+    ) -> Result<(), S::Error> {
+        let crc_mode = self.crc_mode.for_command(packet.command);
+        let sequence = packet.sequence;
+        let (geometry, span) = match self.erase_plan(packet).await {
+            Ok(plan) => plan,
+            Err(response) => {
+                let response = response.with_sequence(sequence);
+                return sink.send(&frame(response, crc_mode), crc_mode).await;
+            }
+        };
+
+        let sector_size = geometry.sector_size();
+        let total = span.length / sector_size;
+        if total == 0 {
+            let response = Response::new_with_sequence(
+                Status::Success,
+                erase_progress::encode(0, 0),
+                sequence,
+            );
+            return sink.send(&frame(response, crc_mode), crc_mode).await;
+        }
+        let power_safe = erase_progress::wants_power_safe(&packet.data);
+        for sector in 0..total {
+            let sector_address = span.address + sector * sector_size;
+            if power_safe && sector > 0 {
+                self.backend
+                    .pause(erase_progress::POWER_SAFE_PAUSE_MS)
+                    .await;
+            }
+            let response = match self.erase_one(geometry.erase_unit, sector_address).await {
+                Ok(()) => Response::new(Status::Success, erase_progress::encode(sector + 1, total)),
+                Err(response) => response,
+            };
+            let failed = response.status != Status::Success;
+            let response = response.with_sequence(sequence);
+            sink.send(&frame(response, crc_mode), crc_mode).await?;
+            // A non-success response ends the erase
+            if failed {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Geometry and sector-aligned span an Erase packet covers, or the
+    /// response refusing it
+    async fn erase_plan(&mut self, packet: &Packet) -> Result<(FlashGeometry, Segment), Response> {
+        // Size is carried in the first 4 data bytes (little-endian)
+        if packet.data.len() < 4 {
+            error!("Erase command missing size data");
+            return Err(Response::new(Status::InvalidAddress, Vec::new()));
+        }
+        let size = u32::from_le_bytes([
+            packet.data[0],
+            packet.data[1],
+            packet.data[2],
+            packet.data[3],
+        ]);
+
+        if packet.address.checked_add(size).is_none() {
+            error!("Erase range overflows: 0x{:08X} + {}", packet.address, size);
+            return Err(Response::new(Status::InvalidAddress, Vec::new()));
+        }
+
+        let geometry = self.geometry().await.map_err(|e| {
+            error!("Flash geometry error: {:?}", e);
+            error_response(e)
+        })?;
+        let span = geometry.erase_span(packet.address, size);
+
+        info!(
+            "Erasing {} sectors of {} bytes (0x{:08X} to 0x{:08X})",
+            span.length / geometry.sector_size(),
+            geometry.sector_size(),
+            span.address,
+            span.end()
+        );
+        Ok((geometry, span))
+    }
+
+    /// Erase the sector at `address` with the chip's smallest erase
+    async fn erase_one(&mut self, unit: EraseUnit, address: u32) -> Result<(), Response> {
+        let result = match unit {
+            EraseUnit::Sector => self.backend.erase_sector(address).await,
+            EraseUnit::Block => self.backend.erase_block(address).await,
+        };
+        if let Err(e) = result {
+            error!("Flash erase error at 0x{:08X}: {:?}", address, e);
+            return Err(error_response(e));
+        }
+        debug!("Erased sector at 0x{:08X}", address);
+        Ok(())
+    }
+}
+
+/// Give `response` the checksum its `crc_mode` trailer needs (constructors
+/// compute CRC-32, so only CRC-16 needs redoing)
+fn frame(response: Response, crc_mode: CrcMode) -> Response {
+    match crc_mode {
+        CrcMode::Crc32 => response,
+        CrcMode::Crc16 => response.with_crc_mode(crc_mode),
+    }
+}
+
+/// Map a backend failure onto a protocol status, with its [`ErrorDetail`]
+/// as the data
+fn error_response(error: BackendError) -> Response {
+    let status = match error {
+        BackendError::InvalidAddress => Status::InvalidAddress,
+        BackendError::Timeout => Status::Timeout,
+        BackendError::Unsupported => Status::InvalidCommand,
+        BackendError::WriteProtected => Status::WriteProtected,
+        BackendError::Aborted => Status::Aborted,
+        _ => Status::FlashError,
+    };
+    Response::new(status, [ErrorDetail::from(error) as u8].to_vec())
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::memory_backend::MemoryBackend;
+    use core::future::Future;
+    use core::task::{Context, Poll, Waker};
+
+    /// Drive a future to completion; the memory backend never pends
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = core::pin::pin!(future);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    fn handler() -> ProtocolHandler<MemoryBackend> {
+        ProtocolHandler::new(MemoryBackend::with_size(64 * 1024))
+    }
+
+    fn send(handler: &mut ProtocolHandler<MemoryBackend>, packet: Packet) -> Response {
+        block_on(handler.process_packet(&packet))
+    }
+
+    fn read_packet(address: u32, length: u32) -> Packet {
+        let mut packet = Packet::new(Command::Read, address, Vec::new());
+        packet.length = length;
+        packet.crc = packet.calculate_crc();
+        packet
+    }
+
+    fn erase_packet(address: u32, size: u32) -> Packet {
+        Packet::new(Command::Erase, address, size.to_le_bytes().to_vec())
+    }
+
+    #[test]
+    fn test_info_reports_jedec_and_capacity() {
+        let mut handler = handler();
+        let response = send(&mut handler, Packet::new(Command::Info, 0, Vec::new()));
+
+        assert_eq!(response.status, Status::Success);
+        assert_eq!(&response.data[0..4], &0xEF4018u32.to_le_bytes());
+        assert_eq!(&response.data[4..8], &(16 * 1024 * 1024u32).to_le_bytes());
+    }
+
+    #[test]
+    fn test_info_derives_capacity_from_jedec() {
+        let mut handler =
+            ProtocolHandler::new(MemoryBackend::with_size(1024).with_jedec_id(0xEF4017));
+        let response = send(&mut handler, Packet::new(Command::Info, 0, Vec::new()));
+
+        assert_eq!(&response.data[4..8], &(8 * 1024 * 1024u32).to_le_bytes());
+    }
+
+    #[test]
+    fn test_64kb_sector_chip_erases_whole_blocks() {
+        use crate::geometry::EraseUnit;
+
+        // Geometry from SFDP, or from the JEDEC ID of a chip without it (M25P64)
+        let backends = [
+            MemoryBackend::with_size(0x30000)
+                .with_erase_unit(EraseUnit::Block)
+                .with_sfdp(),
+            MemoryBackend::with_size(0x30000)
+                .with_erase_unit(EraseUnit::Block)
+                .with_jedec_id(0x202017),
+        ];
+        for backend in backends {
+            let mut handler = ProtocolHandler::new(backend);
+            let write = Packet::new(Command::Write, 0xFFFE, vec![0; 4]);
+            send(&mut handler, write);
+
+            // Erases before any Info detect the geometry themselves
+            let response = send(&mut handler, erase_packet(0x1000, 1));
+            assert_eq!(response.status, Status::Success);
+            let response = send(&mut handler, read_packet(0xFFFE, 4));
+            assert_eq!(response.data, vec![0xFF, 0xFF, 0x00, 0x00]);
+
+            let info = send(&mut handler, Packet::new(Command::Info, 0, Vec::new()));
+            assert_eq!(&info.data[12..16], &0x10000u32.to_le_bytes());
+        }
+
+        // SFDP overrides the JEDEC capacity
+        let mut handler = ProtocolHandler::new(MemoryBackend::with_size(0x30000).with_sfdp());
+        let info = send(&mut handler, Packet::new(Command::Info, 0, Vec::new()));
+        assert_eq!(&info.data[4..8], &0x30000u32.to_le_bytes());
+        assert_eq!(&info.data[12..16], &0x1000u32.to_le_bytes());
+    }
+
+    #[test]
+    fn test_write_protection_is_reported() {
+        let mut protected =
+            ProtocolHandler::new(MemoryBackend::with_size(8192).with_write_protected());
+
+        let info = send(&mut protected, Packet::new(Command::Info, 0, Vec::new()));
+        assert_eq!(&info.data[16..20], &INFO_FLAG_WRITE_PROTECTED.to_le_bytes());
+
+        let write = send(&mut protected, Packet::new(Command::Write, 0, vec![0x00]));
+        assert_eq!(write.status, Status::WriteProtected);
+        assert_eq!(write.error_detail(), Some(ErrorDetail::WriteProtected));
+
+        let info = send(&mut handler(), Packet::new(Command::Info, 0, Vec::new()));
+        assert_eq!(&info.data[16..20], &0u32.to_le_bytes());
+    }
+
+    #[test]
+    fn test_corrupted_packet_is_rejected_unexecuted() {
+        let mut handler = handler();
+        let mut packet = Packet::new_with_sequence(Command::Write, 0x100, vec![0x00; 4], 7);
+        // A bit flipped in transit, after the trailer was computed
+        packet.address ^= 0x1000;
+        let mut sink = VecSink(Vec::new());
+        block_on(handler.handle_packet(&packet, &mut sink)).unwrap();
+
+        assert_eq!(sink.0.len(), 1);
+        assert_eq!(sink.0[0].status, Status::CrcError);
+        assert_eq!(sink.0[0].sequence, 7);
+        assert!(sink.0[0].verify_crc());
+        assert_eq!(handler.backend().data()[0x1100..0x1104], [0xFF; 4]);
+    }
+
+    #[test]
+    fn test_zero_length_operations_are_no_ops() {
+        // Write protection makes any flash access that does happen fail
+        let mut protected =
+            ProtocolHandler::new(MemoryBackend::with_size(8192).with_write_protected());
+
+        let write = send(&mut protected, Packet::new(Command::Write, 0, Vec::new()));
+        assert_eq!(write.status, Status::Success);
+        let write = send(
+            &mut protected,
+            Packet::new(Command::StreamWrite, 0, Vec::new()),
+        );
+        assert_eq!(write.status, Status::Success);
+
+        let erase = send(&mut protected, erase_packet(0x1000, 0));
+        assert_eq!(erase.status, Status::Success);
+        let mut sink = VecSink(Vec::new());
+        let packet = Packet::new(Command::Erase, 0x1000, erase_progress::request(0, true));
+        block_on(protected.handle_packet(&packet, &mut sink)).unwrap();
+        assert_eq!(sink.0.len(), 1);
+        assert_eq!(sink.0[0].status, Status::Success);
+        assert_eq!(erase_progress::decode(&sink.0[0].data), Some((0, 0)));
+
+        // Even past the end of the chip, there is nothing to read
+        for address in [0, 0x1000_0000] {
+            let read = send(&mut protected, read_packet(address, 0));
+            assert_eq!(read.status, Status::Success);
+            assert!(read.data.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_response_echoes_sequence() {
+        let mut handler = handler();
+        let packet = Packet::new_with_sequence(Command::Status, 0, Vec::new(), 0xBEEF);
+        let response = send(&mut handler, packet);
+        assert_eq!(response.sequence, 0xBEEF);
+        assert!(response.verify_crc());
+
+        let packet = Packet::new_with_sequence(Command::Erase, 0, Vec::new(), 42);
+        let response = send(&mut handler, packet);
+        assert_eq!(response.status, Status::InvalidAddress);
+        assert_eq!(response.sequence, 42);
+    }
+
+    #[test]
+    fn test_info_negotiates_crc16() {
+        let mut handler = handler();
+        let info = send(
+            &mut handler,
+            Packet::new(Command::Info, 0, vec![CrcMode::Crc16 as u8]),
+        );
+        assert_eq!(&info.data[16..20], &INFO_FLAG_CRC16.to_le_bytes());
+        assert!(info.verify_crc());
+        assert_eq!(handler.crc_mode(), CrcMode::Crc16);
+
+        let status = send(&mut handler, Packet::new(Command::Status, 0, Vec::new()));
+        assert!(status.verify_crc_with(CrcMode::Crc16));
+
+        // An Info without a request goes back to CRC-32
+        send(&mut handler, Packet::new(Command::Info, 0, Vec::new()));
+        assert_eq!(handler.crc_mode(), CrcMode::Crc32);
+    }
+
+    #[test]
+    fn test_crc16_trailer_is_checked() {
+        let mut handler = handler();
+        send(
+            &mut handler,
+            Packet::new(Command::Info, 0, vec![CrcMode::Crc16 as u8]),
+        );
+
+        let mut write = Packet::new_with_sequence(Command::Write, 0x200, vec![0x00; 4], 3);
+        write.crc = write.calculate_crc_with(CrcMode::Crc16);
+        let mut corrupted = write.clone();
+        corrupted.data[1] = 0x01;
+        // A CRC-32 trailer cut to 16 bits doesn't pass either
+        let mut crc32 = write.clone();
+        crc32.crc = write.calculate_crc() & 0xFFFF;
+
+        for packet in [corrupted, crc32] {
+            let mut sink = VecSink(Vec::new());
+            block_on(handler.handle_packet(&packet, &mut sink)).unwrap();
+            assert_eq!(sink.0[0].status, Status::CrcError);
+            assert_eq!(sink.0[0].sequence, 3);
+            assert!(sink.0[0].verify_crc_with(CrcMode::Crc16));
+        }
+        assert_eq!(handler.backend().data()[0x200..0x204], [0xFF; 4]);
+
+        let mut sink = VecSink(Vec::new());
+        block_on(handler.handle_packet(&write, &mut sink)).unwrap();
+        assert_eq!(sink.0[0].status, Status::Success);
+        assert_eq!(handler.backend().data()[0x200..0x204], [0x00; 4]);
+    }
+
+    #[test]
+    fn test_capabilities_describe_handler() {
+        use crate::capabilities::Capabilities;
+
+        let mut handler = handler();
+        handler.set_hardware_crc(true);
+        send(
+            &mut handler,
+            Packet::new(Command::Info, 0, vec![CrcMode::Crc16 as u8]),
+        );
+        let response = send(
+            &mut handler,
+            Packet::new(Command::Capabilities, 0, Vec::new()),
+        );
+        assert_eq!(response.status, Status::Success);
+
+        let capabilities = Capabilities::from_bytes(&response.data).unwrap();
+        assert!(capabilities.supports(Command::ReadStream));
+        assert!(capabilities.supports(Command::Capabilities));
+        assert!(capabilities.supports(Command::VerifyCRC));
+        assert_eq!(capabilities.crc_mode, CrcMode::Crc16);
+        assert!(capabilities.hardware_crc);
+        assert!(capabilities.chip_erase);
+        assert_eq!(capabilities.max_read_size, MAX_PAYLOAD_SIZE as u32);
+        assert_eq!(capabilities.geometry, Some(FlashGeometry::W25Q128));
+    }
+
+    #[test]
+    fn test_read_sfdp_returns_signature() {
+        let mut packet = Packet::new(Command::ReadSfdp, 0, Vec::new());
+        packet.length = geometry::SFDP_HEADER_SIZE as u32;
+        packet.crc = packet.calculate_crc();
+
+        let mut with_sfdp = ProtocolHandler::new(MemoryBackend::new().with_sfdp());
+        let response = send(&mut with_sfdp, packet.clone());
+        assert_eq!(response.status, Status::Success);
+        assert_eq!(response.data.len(), geometry::SFDP_HEADER_SIZE);
+        assert_eq!(&response.data[..4], b"SFDP");
+
+        // A chip without SFDP answers with the reason instead
+        let response = send(&mut handler(), packet);
+        assert_eq!(response.status, Status::InvalidCommand);
+        assert_eq!(response.error_detail(), Some(ErrorDetail::Unsupported));
+    }
+
+    #[test]
+    fn test_read_accepts_advertised_max_read_size() {
+        use crate::capabilities::Capabilities;
+
+        let mut handler = handler();
+        let data: Vec<u8> = (0..MAX_PAYLOAD_SIZE * 2).map(|i| i as u8).collect();
+        for (i, chunk) in data.chunks(MAX_PAYLOAD_SIZE).enumerate() {
+            let address = (i * MAX_PAYLOAD_SIZE) as u32;
+            send(
+                &mut handler,
+                Packet::new(Command::Write, address, chunk.to_vec()),
+            );
+        }
+
+        let response = send(
+            &mut handler,
+            Packet::new(Command::Capabilities, 0, Vec::new()),
+        );
+        let max_read_size = Capabilities::from_bytes(&response.data)
+            .unwrap()
+            .max_read_size;
+
+        // Reads at the limit return everything in one response, from any offset
+        let response = send(&mut handler, read_packet(0x10, max_read_size));
+        assert_eq!(response.status, Status::Success);
+        assert_eq!(response.data, &data[0x10..0x10 + max_read_size as usize]);
+        let response = send(&mut handler, read_packet(0, max_read_size + 1));
+        assert_eq!(response.status, Status::InvalidAddress);
+    }
+
+    #[test]
+    fn test_chip_erase_requires_whole_chip() {
+        let mut handler = ProtocolHandler::new(MemoryBackend::with_size(0x30000).with_sfdp());
+        send(&mut handler, Packet::new(Command::Write, 0x2FFFF, vec![0]));
+
+        let partial = Packet::new(
+            Command::Erase,
+            0,
+            erase_progress::chip_erase_request(0x20000),
+        );
+        assert_eq!(send(&mut handler, partial).status, Status::InvalidAddress);
+
+        let whole = Packet::new(
+            Command::Erase,
+            0,
+            erase_progress::chip_erase_request(0x30000),
+        );
+        assert_eq!(send(&mut handler, whole).status, Status::Success);
+        assert_eq!(send(&mut handler, read_packet(0x2FFFF, 1)).data, vec![0xFF]);
+    }
+
+    #[test]
+    fn test_get_config_reflects_runtime_changes() {
+        let mut handler = handler();
+        handler.set_blank_check(BlankCheck::Warn);
+        send(&mut handler, Packet::new(Command::SetSpiMode, 0, vec![3]));
+
+        let response = send(&mut handler, Packet::new(Command::GetConfig, 0, Vec::new()));
+        assert_eq!(response.status, Status::Success);
+        let config = RuntimeConfig::from_bytes(&response.data).unwrap();
+        assert_eq!(config.spi_mode, SpiMode::Mode3);
+        assert_eq!(config.blank_check, BlankCheck::Warn);
+        assert_eq!(config.spi_frequency_hz, 0);
+        assert_eq!(config.max_payload_size, MAX_PAYLOAD_SIZE as u32);
+    }
+
+    #[test]
+    fn test_error_counters_read_and_clear() {
+        use crate::error_counters::ErrorCounters;
+
+        let counters = ErrorCounters {
+            dropped_packets: 3,
+            crc_errors: 1,
+            buffer_overflows: 2,
+            flash_errors: 0,
+        };
+        let mut handler = handler();
+        handler.backend().set_error_counters(counters);
+
+        let get = Packet::new(Command::GetErrorCounters, 0, Vec::new());
+        let response = send(&mut handler, get.clone());
+        assert_eq!(response.status, Status::Success);
+        assert_eq!(ErrorCounters::from_bytes(&response.data), Ok(counters));
+
+        let clear = Packet::new(Command::ClearErrorCounters, 0, Vec::new());
+        assert_eq!(send(&mut handler, clear).status, Status::Success);
+        let response = send(&mut handler, get);
+        assert_eq!(
+            ErrorCounters::from_bytes(&response.data),
+            Ok(ErrorCounters::default())
+        );
+    }
+
+    #[test]
+    fn test_set_spi_frequency() {
+        let mut handler = handler();
+        let response = send(
+            &mut handler,
+            Packet::new(
+                Command::SetSpiFrequency,
+                0,
+                8_000_000u32.to_le_bytes().to_vec(),
+            ),
+        );
+        assert_eq!(response.status, Status::Success);
+        assert_eq!(response.data, 8_000_000u32.to_le_bytes());
+
+        let response = send(&mut handler, Packet::new(Command::GetConfig, 0, Vec::new()));
+        let config = RuntimeConfig::from_bytes(&response.data).unwrap();
+        assert_eq!(config.spi_frequency_hz, 8_000_000);
+
+        for data in [Vec::new(), 0u32.to_le_bytes().to_vec()] {
+            let response = send(&mut handler, Packet::new(Command::SetSpiFrequency, 0, data));
+            assert_eq!(response.status, Status::InvalidCommand);
+        }
+    }
+
+    #[test]
+    fn test_abort_is_acknowledged() {
+        let response = send(&mut handler(), Packet::new(Command::Abort, 0, Vec::new()));
+        assert_eq!(response.status, Status::Success);
+
+        let response = error_response(BackendError::Aborted);
+        let decoded = Response::from_bytes(&response.to_bytes()).unwrap();
+        assert_eq!(decoded.status, Status::Aborted);
+        assert_eq!(decoded.error_detail(), Some(ErrorDetail::Aborted));
+    }
+
+    #[test]
+    fn test_write_then_read_back() {
+        let mut handler = handler();
+        let data = vec![0x12, 0x34, 0x56, 0x78];
+
+        let response = send(
+            &mut handler,
+            Packet::new(Command::Write, 0x100, data.clone()),
+        );
+        assert_eq!(response.status, Status::Success);
+
+        let response = send(&mut handler, read_packet(0x100, 4));
+        assert_eq!(response.status, Status::Success);
+        assert_eq!(response.data, data);
+
+        let response = send(&mut handler, Packet::new(Command::Verify, 0x100, data));
+        assert_eq!(response.status, Status::Success);
+    }
+
+    #[test]
+    fn test_program_over_unerased_data_ands_bits() {
+        let mut handler = handler();
+
+        send(&mut handler, Packet::new(Command::Write, 0, vec![0xF0]));
+        send(&mut handler, Packet::new(Command::Write, 0, vec![0x3C]));
+
+        let response = send(&mut handler, read_packet(0, 1));
+        assert_eq!(response.data, vec![0x30]);
+    }
+
+    #[test]
+    fn test_erase_restores_whole_sectors() {
+        let mut handler = handler();
+        send(
+            &mut handler,
+            Packet::new(Command::Write, 0x0FFE, vec![0; 4]),
+        );
+
+        // Erasing one byte in the second sector clears that whole sector only
+        let response = send(&mut handler, erase_packet(0x1000, 1));
+        assert_eq!(response.status, Status::Success);
+
+        let response = send(&mut handler, read_packet(0x0FFE, 4));
+        assert_eq!(response.data, vec![0x00, 0x00, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn test_blank_check_reject_refuses_unerased_target() {
+        let mut handler = handler();
+        handler.set_blank_check(BlankCheck::Reject);
+        send(&mut handler, Packet::new(Command::Write, 0, vec![0xF0]));
+
+        // Clearing more bits is a valid program; setting one is not
+        let response = send(&mut handler, Packet::new(Command::Write, 0, vec![0x30]));
+        assert_eq!(response.status, Status::Success);
+        let response = send(&mut handler, Packet::new(Command::Write, 0, vec![0x31]));
+        assert_eq!(response.status, Status::NotErased);
+
+        let response = send(&mut handler, read_packet(0, 1));
+        assert_eq!(response.data, vec![0x30]);
+    }
+
+    #[test]
+    fn test_blank_check_warn_still_programs() {
+        let mut handler = handler();
+        handler.set_blank_check(BlankCheck::Warn);
+        send(&mut handler, Packet::new(Command::Write, 0, vec![0xF0]));
+
+        let response = send(&mut handler, Packet::new(Command::Write, 0, vec![0x0F]));
+        assert_eq!(response.status, Status::Success);
+
+        let response = send(&mut handler, read_packet(0, 1));
+        assert_eq!(response.data, vec![0x00]);
+    }
+
+    #[test]
+    fn test_set_spi_mode_validates_mode() {
+        let mut handler = handler();
+
+        let response = send(&mut handler, Packet::new(Command::SetSpiMode, 0, vec![3]));
+        assert_eq!(response.status, Status::Success);
+        assert_eq!(handler.backend().spi_mode(), SpiMode::Mode3);
+
+        let response = send(&mut handler, Packet::new(Command::SetSpiMode, 0, vec![1]));
+        assert_eq!(response.status, Status::InvalidCommand);
+        let response = send(
+            &mut handler,
+            Packet::new(Command::SetSpiMode, 0, Vec::new()),
+        );
+        assert_eq!(response.status, Status::InvalidCommand);
+    }
+
+    struct VecSink(Vec<Response>);
+
+    impl ResponseSink for VecSink {
+        type Error = ();
+
+        async fn send(&mut self, response: &Response, _crc_mode: CrcMode) -> Result<(), ()> {
+            self.0.push(response.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_read_stream_splits_into_tagged_chunks() {
+        let mut handler = handler();
+        let data: Vec<u8> = (0..2500u32).map(|i| i as u8).collect();
+        for (i, chunk) in data.chunks(MAX_PAYLOAD_SIZE).enumerate() {
+            let address = (i * MAX_PAYLOAD_SIZE) as u32;
+            send(
+                &mut handler,
+                Packet::new(Command::Write, address, chunk.to_vec()),
+            );
+        }
+
+        let mut packet = Packet::new_with_sequence(Command::ReadStream, 0, Vec::new(), 7);
+        packet.length = data.len() as u32;
+        packet.crc = packet.calculate_crc();
+        let mut sink = VecSink(Vec::new());
+        block_on(handler.handle_packet(&packet, &mut sink)).unwrap();
+
+        assert_eq!(sink.0.len(), 3);
+        let mut reassembled = Vec::new();
+        for (i, response) in sink.0.iter().enumerate() {
+            assert_eq!(response.status, Status::Success);
+            assert_eq!(response.sequence, 7);
+            let (index, total, payload) = read_stream::decode_chunk(&response.data).unwrap();
+            assert_eq!((index as usize, total), (i, 3));
+            reassembled.extend_from_slice(payload);
+        }
+        assert_eq!(reassembled, data);
+    }
+
+    #[test]
+    fn test_outcome_notes_last_status_and_bytes() {
+        let mut handler = handler();
+        let mut packet = read_packet(0, 4);
+        packet.command = Command::ReadStream;
+        packet.crc = packet.calculate_crc();
+        let mut sink = VecSink(Vec::new());
+        let mut outcome = Outcome {
+            sink: &mut sink,
+            status: None,
+            bytes: 0,
+        };
+        block_on(handler.dispatch(&packet, &mut outcome)).unwrap();
+        assert_eq!(outcome.status, Some(Status::Success));
+        assert_eq!(outcome.bytes, sink.0[0].data.len());
+
+        let mut outcome = Outcome {
+            sink: &mut sink,
+            status: None,
+            bytes: 0,
+        };
+        packet.length = 0;
+        packet.crc = packet.calculate_crc();
+        block_on(handler.dispatch(&packet, &mut outcome)).unwrap();
+        assert_eq!(outcome.status, Some(Status::InvalidAddress));
+        assert_eq!(outcome.bytes, 0);
+    }
+
+    #[test]
+    fn test_power_safe_erase_pauses_between_sectors() {
+        use erase_progress::{FLAG_POWER_SAFE, FLAG_REPORT_PROGRESS, POWER_SAFE_PAUSE_MS};
+
+        let mut handler = handler();
+        let data = erase_progress::request_with_flags(0x3000, FLAG_POWER_SAFE);
+        let response = send(&mut handler, Packet::new(Command::Erase, 0, data));
+        assert_eq!(response.status, Status::Success);
+        assert_eq!(handler.backend().paused_ms(), 2 * POWER_SAFE_PAUSE_MS);
+
+        let flags = FLAG_POWER_SAFE | FLAG_REPORT_PROGRESS;
+        let packet = Packet::new(
+            Command::Erase,
+            0,
+            erase_progress::request_with_flags(0x2000, flags),
+        );
+        let mut sink = VecSink(Vec::new());
+        block_on(handler.handle_packet(&packet, &mut sink)).unwrap();
+        assert_eq!(sink.0.len(), 2);
+        assert_eq!(handler.backend().paused_ms(), 3 * POWER_SAFE_PAUSE_MS);
+
+        // Plain erases never pause
+        send(
+            &mut handler,
+            Packet::new(Command::Erase, 0, erase_progress::request(0x3000, false)),
+        );
+        assert_eq!(handler.backend().paused_ms(), 3 * POWER_SAFE_PAUSE_MS);
+    }
+
+    #[test]
+    fn test_erase_reports_progress_per_sector() {
+        let mut handler = handler();
+        send(
+            &mut handler,
+            Packet::new(Command::Write, 0x2FFF, vec![0; 2]),
+        );
+
+        let packet = Packet::new_with_sequence(
+            Command::Erase,
+            0x1800,
+            erase_progress::request(0x2000, true),
+            9,
+        );
+        let mut sink = VecSink(Vec::new());
+        block_on(handler.handle_packet(&packet, &mut sink)).unwrap();
+
+        // 0x1800..0x3800 touches the sectors at 0x1000, 0x2000 and 0x3000
+        let progress: Vec<_> = sink
+            .0
+            .iter()
+            .map(|r| (r.status, r.sequence, erase_progress::decode(&r.data)))
+            .collect();
+        assert_eq!(
+            progress,
+            [
+                (Status::Success, 9, Some((1, 3))),
+                (Status::Success, 9, Some((2, 3))),
+                (Status::Success, 9, Some((3, 3))),
+            ]
+        );
+        let response = send(&mut handler, read_packet(0x2FFF, 2));
+        assert_eq!(response.data, vec![0xFF, 0xFF]);
+
+        // Without the flag the erase still answers once
+        let packet = Packet::new(Command::Erase, 0, erase_progress::request(0x2000, false));
+        let mut sink = VecSink(Vec::new());
+        block_on(handler.handle_packet(&packet, &mut sink)).unwrap();
+        assert_eq!(sink.0.len(), 1);
+        assert!(sink.0[0].data.is_empty());
+
+        // A failing sector ends the reports
+        let packet = Packet::new(
+            Command::Erase,
+            0xF000,
+            erase_progress::request(0x2000, true),
+        );
+        let mut sink = VecSink(Vec::new());
+        block_on(handler.handle_packet(&packet, &mut sink)).unwrap();
+        assert_eq!(sink.0.len(), 2);
+        assert_eq!(sink.0[1].status, Status::InvalidAddress);
+    }
+
+    #[test]
+    fn test_read_stream_error_ends_stream() {
+        let mut handler = handler();
+        let mut packet = Packet::new(Command::ReadStream, 63 * 1024, Vec::new());
+        packet.length = 4096;
+        packet.crc = packet.calculate_crc();
+        let mut sink = VecSink(Vec::new());
+        block_on(handler.handle_packet(&packet, &mut sink)).unwrap();
+
+        // One good chunk, then the out-of-range chunk terminates the stream
+        assert_eq!(sink.0.len(), 2);
+        assert_eq!(sink.0[0].status, Status::Success);
+        assert_eq!(sink.0[1].status, Status::InvalidAddress);
+    }
+
+    #[test]
+    fn test_verify_crc_checks_blocks_of_any_size() {
+        let mut handler = ProtocolHandler::new(MemoryBackend::with_size(0x20000));
+        // Longer than a batch checksum may cover
+        let block = vec![0xFF; MAX_BATCH_LENGTH as usize + 0x8000];
+        let check = Packet::new(
+            Command::VerifyCRC,
+            0x1000,
+            BatchChecksum::for_data(&block).to_bytes(),
+        );
+        assert_eq!(send(&mut handler, check.clone()).status, Status::Success);
+
+        send(
+            &mut handler,
+            Packet::new(Command::Write, 0x12345, vec![0x00]),
+        );
+        let response = send(&mut handler, check);
+        assert_eq!(response.status, Status::VerificationFailed);
+        let mut written = block.clone();
+        written[0x12345 - 0x1000] = 0x00;
+        assert_eq!(
+            response.data,
+            Crc32::checksum(&written).to_le_bytes().to_vec()
+        );
+
+        // The old payload without a length is refused rather than passed
+        let response = send(
+            &mut handler,
+            Packet::new(Command::VerifyCRC, 0, 0u32.to_le_bytes().to_vec()),
+        );
+        assert_eq!(response.status, Status::InvalidCommand);
+    }
+
+    #[test]
+    fn test_verify_compares_flash_contents() {
+        let mut handler = handler();
+        send(
+            &mut handler,
+            Packet::new(Command::Write, 0x100, vec![0x12, 0x34]),
+        );
+
+        let response = send(
+            &mut handler,
+            Packet::new(Command::Verify, 0x100, vec![0x12, 0x34]),
+        );
+        assert_eq!(response.status, Status::Success);
+        let response = send(
+            &mut handler,
+            Packet::new(Command::Verify, 0x100, vec![0x12, 0x35]),
+        );
+        assert_eq!(response.status, Status::VerificationFailed);
+    }
+
+    #[test]
+    fn test_batch_checksum_detects_missing_packet() {
+        let mut handler = handler();
+        let data: Vec<u8> = (0..3000u32).map(|i| (i * 7) as u8).collect();
+        let checksum = BatchChecksum::for_data(&data);
+        let check = Packet::new(Command::BatchChecksum, 0x1000, checksum.to_bytes());
+
+        // The middle packet of the batch "gets lost"
+        for (i, chunk) in data.chunks(MAX_PAYLOAD_SIZE).enumerate() {
+            if i != 1 {
+                let address = 0x1000 + (i * MAX_PAYLOAD_SIZE) as u32;
+                send(
+                    &mut handler,
+                    Packet::new(Command::StreamWrite, address, chunk.to_vec()),
+                );
+            }
+        }
+        let response = send(&mut handler, check.clone());
+        assert_eq!(response.status, Status::VerificationFailed);
+        assert_eq!(response.data.len(), 4);
+
+        // Resending the batch programs the erased gap
+        for (i, chunk) in data.chunks(MAX_PAYLOAD_SIZE).enumerate() {
+            let address = 0x1000 + (i * MAX_PAYLOAD_SIZE) as u32;
+            send(
+                &mut handler,
+                Packet::new(Command::StreamWrite, address, chunk.to_vec()),
+            );
+        }
+        assert_eq!(send(&mut handler, check).status, Status::Success);
+
+        let too_long = BatchChecksum {
+            crc32: 0,
+            length: MAX_BATCH_LENGTH + 1,
+        };
+        let response = send(
+            &mut handler,
+            Packet::new(Command::BatchChecksum, 0, too_long.to_bytes()),
+        );
+        assert_eq!(response.status, Status::InvalidAddress);
+    }
+
+    #[test]
+    fn test_erase_without_size_is_rejected() {
+        let mut handler = handler();
+        let response = send(&mut handler, Packet::new(Command::Erase, 0, Vec::new()));
+        assert_eq!(response.status, Status::InvalidAddress);
+    }
+
+    #[test]
+    fn test_out_of_range_access_reports_invalid_address() {
+        let mut handler = handler();
+
+        let response = send(&mut handler, read_packet(64 * 1024 - 2, 4));
+        assert_eq!(response.status, Status::InvalidAddress);
+
+        let response = send(
+            &mut handler,
+            Packet::new(Command::Write, 64 * 1024, vec![0]),
+        );
+        assert_eq!(response.status, Status::InvalidAddress);
+    }
+}
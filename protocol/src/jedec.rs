@@ -0,0 +1,97 @@
+//! JEDEC ID decoding
+//!
+//! The 24-bit JEDEC ID returned by command 0x9F is manufacturer (byte 0),
+//! memory type (byte 1) and capacity (byte 2). Capacity codes up to 0x19
+//! encode 2^n bytes; larger parts continue the sequence at 0x20.
+
+/// Manufacturer byte of the JEDEC ID
+pub fn manufacturer_id(jedec_id: u32) -> u8 {
+    (jedec_id >> 16) as u8
+}
+
+/// Capacity byte of the JEDEC ID
+pub fn capacity_code(jedec_id: u32) -> u8 {
+    jedec_id as u8
+}
+
+/// Known manufacturer name for a JEDEC ID
+pub fn manufacturer_name(jedec_id: u32) -> Option<&'static str> {
+    match manufacturer_id(jedec_id) {
+        0xEF => Some("Winbond"),
+        0xC8 => Some("GigaDevice"),
+        0xC2 => Some("Macronix"),
+        0x20 => Some("Micron/XMC"),
+        0x9D => Some("ISSI"),
+        0x01 => Some("Infineon/Spansion"),
+        0x1F => Some("Adesto"),
+        0xBF => Some("SST/Microchip"),
+        0x68 => Some("Boya"),
+        0x85 => Some("Puya"),
+        _ => None,
+    }
+}
+
+/// Device size in bytes for a JEDEC capacity code
+pub fn capacity_bytes(jedec_id: u32) -> Option<u32> {
+    Some(match capacity_code(jedec_id) {
+        0x11 => 128 * 1024,        // 1Mbit  (W25X10)
+        0x12 => 256 * 1024,        // 2Mbit  (W25X20)
+        0x13 => 512 * 1024,        // 4Mbit  (W25Q40)
+        0x14 => 1024 * 1024,       // 8Mbit  (W25Q80)
+        0x15 => 2 * 1024 * 1024,   // 16Mbit (W25Q16)
+        0x16 => 4 * 1024 * 1024,   // 32Mbit (W25Q32)
+        0x17 => 8 * 1024 * 1024,   // 64Mbit (W25Q64)
+        0x18 => 16 * 1024 * 1024,  // 128Mbit (W25Q128)
+        0x19 => 32 * 1024 * 1024,  // 256Mbit (W25Q256)
+        0x20 => 64 * 1024 * 1024,  // 512Mbit (W25Q512)
+        0x21 => 128 * 1024 * 1024, // 1Gbit  (W25Q01)
+        _ => return None,
+    })
+}
+
+/// Whether the chip only erases 64KB blocks (no 4KB sector erase)
+///
+/// Only consulted for chips without SFDP: Micron/ST M25P parts up to
+/// 64Mbit. (The M25P128's 256KB sectors are beyond the backend entirely.)
+pub fn lacks_sector_erase(jedec_id: u32) -> bool {
+    manufacturer_id(jedec_id) == 0x20
+        && (jedec_id >> 8) as u8 == 0x20
+        && capacity_code(jedec_id) <= 0x17
+}
+
+/// Whether both the manufacturer and the capacity code are known
+///
+/// All-zero or all-one IDs (no chip, floating MISO) are never recognized.
+pub fn is_recognized(jedec_id: u32) -> bool {
+    manufacturer_name(jedec_id).is_some() && capacity_bytes(jedec_id).is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decodes_winbond_parts() {
+        assert_eq!(manufacturer_name(0xEF4018), Some("Winbond"));
+        assert_eq!(capacity_bytes(0xEF4017), Some(8 * 1024 * 1024));
+        assert_eq!(capacity_bytes(0xEF4018), Some(16 * 1024 * 1024));
+        assert_eq!(capacity_bytes(0xEF4019), Some(32 * 1024 * 1024));
+    }
+
+    #[test]
+    fn test_unknown_values() {
+        assert_eq!(manufacturer_name(0x000000), None);
+        assert_eq!(capacity_bytes(0xFFFFFF), None);
+        assert!(!is_recognized(0x000000));
+        assert!(!is_recognized(0xFFFFFF));
+        assert!(is_recognized(0xEF4018));
+    }
+
+    #[test]
+    fn test_sector_erase_support() {
+        assert!(lacks_sector_erase(0x202015)); // M25P16
+        assert!(!lacks_sector_erase(0x202018)); // M25P128 (256KB sectors)
+        assert!(!lacks_sector_erase(0x20BA18)); // N25Q128 has 4KB erase
+        assert!(!lacks_sector_erase(0xEF4018));
+    }
+}
@@ -0,0 +1,784 @@
+//! Streaming baseline JPEG decoder for compressed boot screens
+//!
+//! Decodes one MCU (8x8 to 16x16 pixels) at a time straight from a byte
+//! source, so a full-screen image can be shown without holding the frame or
+//! the compressed file in RAM. The decoder state is a few KB, independent of
+//! the image size.
+//!
+//! Supported: baseline and extended Huffman sequential JPEG, 8-bit, grayscale
+//! or YCbCr with 1x1/2x1/1x2/2x2 sampling, restart intervals. Progressive and
+//! arithmetic-coded files are rejected.
+
+/// Byte source the decoder pulls compressed data from
+#[allow(async_fn_in_trait)]
+pub trait JpegSource {
+    /// Fill `buf` with the next bytes of the file, returning how many were
+    /// read; 0 means end of data
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, &'static str>;
+}
+
+impl JpegSource for &[u8] {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, &'static str> {
+        let n = buf.len().min(self.len());
+        buf[..n].copy_from_slice(&self[..n]);
+        *self = &self[n..];
+        Ok(n)
+    }
+}
+
+/// Whether `header` starts with a JPEG start-of-image marker
+pub fn is_jpeg(header: &[u8]) -> bool {
+    header.len() >= 3 && header[..3] == [0xFF, 0xD8, 0xFF]
+}
+
+/// One decoded MCU, clipped to the image
+pub struct Mcu<'a> {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+    /// Row-major RGB565 pixels, `width` per row
+    pub pixels: &'a [u16],
+}
+
+/// Zigzag index -> natural (row-major) coefficient index
+const ZIGZAG: [u8; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20,
+    13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59,
+    52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+/// Largest MCU: 2x2 luma blocks plus one block per chroma component
+const MAX_BLOCKS: usize = 6;
+const MAX_MCU_PIXELS: usize = 16 * 16;
+const INPUT_BUFFER_SIZE: usize = 128;
+
+#[derive(Clone, Copy)]
+struct HuffmanTable {
+    defined: bool,
+    values: [u8; 256],
+    max_code: [i32; 16],
+    val_ptr: [i32; 16],
+    min_code: [i32; 16],
+}
+
+impl HuffmanTable {
+    const EMPTY: Self = Self {
+        defined: false,
+        values: [0; 256],
+        max_code: [-1; 16],
+        val_ptr: [0; 16],
+        min_code: [0; 16],
+    };
+
+    /// Derive the decode tables from the per-length code counts (ITU T.81 F.2.2.3)
+    fn build(&mut self, counts: &[u8; 16]) {
+        let mut code = 0i32;
+        let mut index = 0i32;
+        for (len, &count) in counts.iter().enumerate() {
+            if count == 0 {
+                self.max_code[len] = -1;
+            } else {
+                self.val_ptr[len] = index;
+                self.min_code[len] = code;
+                code += count as i32;
+                index += count as i32;
+                self.max_code[len] = code - 1;
+            }
+            code <<= 1;
+        }
+        self.defined = true;
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Component {
+    id: u8,
+    h: u8,
+    v: u8,
+    quant_table: u8,
+    dc_table: u8,
+    ac_table: u8,
+    dc_pred: i32,
+}
+
+/// Decoder for a single baseline JPEG image
+pub struct JpegDecoder<S> {
+    source: S,
+    input: [u8; INPUT_BUFFER_SIZE],
+    input_pos: usize,
+    input_len: usize,
+
+    width: u16,
+    height: u16,
+    components: [Component; 3],
+    component_count: usize,
+    quant_tables: [[u16; 64]; 4],
+    dc_tables: [HuffmanTable; 2],
+    ac_tables: [HuffmanTable; 2],
+    restart_interval: u16,
+
+    max_h: u8,
+    max_v: u8,
+    mcus_x: u16,
+    mcus_y: u16,
+    next_mcu: u32,
+    mcus_until_restart: u16,
+
+    bit_buffer: u8,
+    bit_count: u8,
+    /// Marker found inside entropy-coded data; zeros are fed until it's consumed
+    pending_marker: Option<u8>,
+
+    samples: [[u8; 64]; MAX_BLOCKS],
+    pixels: [u16; MAX_MCU_PIXELS],
+}
+
+impl<S: JpegSource> JpegDecoder<S> {
+    /// Parse the headers up to the start of scan
+    pub async fn new(source: S) -> Result<Self, &'static str> {
+        let mut decoder = Self {
+            source,
+            input: [0; INPUT_BUFFER_SIZE],
+            input_pos: 0,
+            input_len: 0,
+            width: 0,
+            height: 0,
+            components: [Component::default(); 3],
+            component_count: 0,
+            quant_tables: [[0; 64]; 4],
+            dc_tables: [HuffmanTable::EMPTY; 2],
+            ac_tables: [HuffmanTable::EMPTY; 2],
+            restart_interval: 0,
+            max_h: 1,
+            max_v: 1,
+            mcus_x: 0,
+            mcus_y: 0,
+            next_mcu: 0,
+            mcus_until_restart: 0,
+            bit_buffer: 0,
+            bit_count: 0,
+            pending_marker: None,
+            samples: [[0; 64]; MAX_BLOCKS],
+            pixels: [0; MAX_MCU_PIXELS],
+        };
+        decoder.read_headers().await?;
+        Ok(decoder)
+    }
+
+    /// Image width in pixels
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    /// Image height in pixels
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    /// Decode the next MCU, left to right and top to bottom
+    ///
+    /// Returns `None` once the whole image has been decoded.
+    pub async fn next_mcu(&mut self) -> Result<Option<Mcu<'_>>, &'static str> {
+        let total = self.mcus_x as u32 * self.mcus_y as u32;
+        if self.next_mcu >= total {
+            return Ok(None);
+        }
+
+        if self.restart_interval != 0 {
+            if self.mcus_until_restart == 0 {
+                self.handle_restart().await?;
+                self.mcus_until_restart = self.restart_interval;
+            }
+            self.mcus_until_restart -= 1;
+        }
+
+        let mut block = 0;
+        for c in 0..self.component_count {
+            let component = self.components[c];
+            for _ in 0..component.v as usize * component.h as usize {
+                let mut coefficients = [0i32; 64];
+                self.decode_block(c, &mut coefficients).await?;
+                idct_block(&coefficients, &mut self.samples[block]);
+                block += 1;
+            }
+        }
+
+        let mcu_width = 8 * self.max_h as u16;
+        let mcu_height = 8 * self.max_v as u16;
+        let x = (self.next_mcu % self.mcus_x as u32) as u16 * mcu_width;
+        let y = (self.next_mcu / self.mcus_x as u32) as u16 * mcu_height;
+        let width = mcu_width.min(self.width - x);
+        let height = mcu_height.min(self.height - y);
+        self.convert_mcu(width, height);
+        self.next_mcu += 1;
+
+        Ok(Some(Mcu {
+            x,
+            y,
+            width,
+            height,
+            pixels: &self.pixels[..width as usize * height as usize],
+        }))
+    }
+
+    async fn read_headers(&mut self) -> Result<(), &'static str> {
+        if self.read_u8().await? != 0xFF || self.read_u8().await? != 0xD8 {
+            return Err("Not a JPEG file");
+        }
+
+        loop {
+            match self.read_marker().await? {
+                0xC0 | 0xC1 => self.read_frame_header().await?,
+                0xC2 | 0xC6 | 0xCA | 0xCE => return Err("Progressive JPEG not supported"),
+                0xC3 | 0xC5 | 0xC7 | 0xC9 | 0xCB | 0xCD | 0xCF => {
+                    return Err("Lossless or arithmetic JPEG not supported")
+                }
+                0xC4 => self.read_huffman_tables().await?,
+                0xDB => self.read_quant_tables().await?,
+                0xDD => {
+                    self.read_u16().await?;
+                    self.restart_interval = self.read_u16().await?;
+                }
+                0xDA => return self.read_scan_header().await,
+                0xD9 => return Err("No image data before end of file"),
+                _ => {
+                    let length = self.read_u16().await?;
+                    self.skip(length.saturating_sub(2) as usize).await?;
+                }
+            }
+        }
+    }
+
+    async fn read_frame_header(&mut self) -> Result<(), &'static str> {
+        self.read_u16().await?;
+        if self.read_u8().await? != 8 {
+            return Err("Only 8-bit JPEG supported");
+        }
+        self.height = self.read_u16().await?;
+        self.width = self.read_u16().await?;
+        if self.width == 0 || self.height == 0 {
+            return Err("Invalid image size");
+        }
+
+        self.component_count = self.read_u8().await? as usize;
+        if self.component_count != 1 && self.component_count != 3 {
+            return Err("Only grayscale and YCbCr JPEG supported");
+        }
+
+        for c in 0..self.component_count {
+            let id = self.read_u8().await?;
+            let sampling = self.read_u8().await?;
+            let quant_table = self.read_u8().await?;
+            let (h, v) = (sampling >> 4, sampling & 0x0F);
+            if !(1..=2).contains(&h) || !(1..=2).contains(&v) || quant_table > 3 {
+                return Err("Unsupported sampling factors");
+            }
+            self.components[c] = Component {
+                id,
+                h,
+                v,
+                quant_table,
+                ..Component::default()
+            };
+        }
+
+        if self.component_count == 1 {
+            // A single-component scan is not interleaved: one block per MCU
+            self.components[0].h = 1;
+            self.components[0].v = 1;
+        }
+        let components = &self.components[..self.component_count];
+        self.max_h = components.iter().map(|c| c.h).max().unwrap_or(1);
+        self.max_v = components.iter().map(|c| c.v).max().unwrap_or(1);
+        let blocks: usize = components.iter().map(|c| c.h as usize * c.v as usize).sum();
+        if blocks > MAX_BLOCKS {
+            return Err("Unsupported sampling factors");
+        }
+
+        let mcu_width = 8 * self.max_h as u16;
+        let mcu_height = 8 * self.max_v as u16;
+        self.mcus_x = self.width.div_ceil(mcu_width);
+        self.mcus_y = self.height.div_ceil(mcu_height);
+        Ok(())
+    }
+
+    async fn read_huffman_tables(&mut self) -> Result<(), &'static str> {
+        let mut remaining = self.read_u16().await?.saturating_sub(2) as usize;
+        while remaining > 0 {
+            let info = self.read_u8().await?;
+            let (class, id) = (info >> 4, (info & 0x0F) as usize);
+            if class > 1 || id > 1 {
+                return Err("Invalid Huffman table");
+            }
+
+            let mut counts = [0u8; 16];
+            for count in counts.iter_mut() {
+                *count = self.read_u8().await?;
+            }
+            let total: usize = counts.iter().map(|&n| n as usize).sum();
+            if total > 256 || 17 + total > remaining {
+                return Err("Invalid Huffman table");
+            }
+
+            let mut table = HuffmanTable::EMPTY;
+            for value in table.values[..total].iter_mut() {
+                *value = self.read_u8().await?;
+            }
+            table.build(&counts);
+            if class == 0 {
+                self.dc_tables[id] = table;
+            } else {
+                self.ac_tables[id] = table;
+            }
+            remaining -= 17 + total;
+        }
+        Ok(())
+    }
+
+    async fn read_quant_tables(&mut self) -> Result<(), &'static str> {
+        let mut remaining = self.read_u16().await?.saturating_sub(2) as usize;
+        while remaining > 0 {
+            let info = self.read_u8().await?;
+            let (precision, id) = (info >> 4, (info & 0x0F) as usize);
+            if precision > 1 || id > 3 {
+                return Err("Invalid quantization table");
+            }
+
+            // Kept in zigzag order, matching the coefficient stream
+            for k in 0..64 {
+                self.quant_tables[id][k] = if precision == 0 {
+                    self.read_u8().await? as u16
+                } else {
+                    self.read_u16().await?
+                };
+            }
+            let size = 1 + 64 * (precision as usize + 1);
+            remaining = remaining
+                .checked_sub(size)
+                .ok_or("Invalid quantization table")?;
+        }
+        Ok(())
+    }
+
+    async fn read_scan_header(&mut self) -> Result<(), &'static str> {
+        if self.component_count == 0 {
+            return Err("Scan before frame header");
+        }
+
+        self.read_u16().await?;
+        if self.read_u8().await? as usize != self.component_count {
+            return Err("Non-interleaved scans not supported");
+        }
+        for _ in 0..self.component_count {
+            let id = self.read_u8().await?;
+            let tables = self.read_u8().await?;
+            let component = self.components[..self.component_count]
+                .iter_mut()
+                .find(|c| c.id == id)
+                .ok_or("Scan references unknown component")?;
+            component.dc_table = tables >> 4;
+            component.ac_table = tables & 0x0F;
+            if component.dc_table > 1 || component.ac_table > 1 {
+                return Err("Invalid Huffman table");
+            }
+        }
+        // Spectral selection and approximation are fixed for sequential scans
+        self.skip(3).await?;
+        self.mcus_until_restart = self.restart_interval;
+        Ok(())
+    }
+
+    async fn decode_block(
+        &mut self,
+        c: usize,
+        coefficients: &mut [i32; 64],
+    ) -> Result<(), &'static str> {
+        let component = self.components[c];
+        let quant = self.quant_tables[component.quant_table as usize];
+        let dc_table = component.dc_table as usize;
+        let ac_table = component.ac_table as usize;
+        if !self.dc_tables[dc_table].defined || !self.ac_tables[ac_table].defined {
+            return Err("Missing Huffman table");
+        }
+
+        let size = self.decode_huffman(false, dc_table).await?;
+        let diff = self.receive_extend(size).await?;
+        let dc = component.dc_pred + diff;
+        self.components[c].dc_pred = dc;
+        coefficients[0] = dc * quant[0] as i32;
+
+        let mut k = 1;
+        while k < 64 {
+            let rs = self.decode_huffman(true, ac_table).await?;
+            let (run, size) = ((rs >> 4) as usize, rs & 0x0F);
+            if size == 0 {
+                if run == 15 {
+                    k += 16;
+                    continue;
+                }
+                break;
+            }
+            k += run;
+            if k > 63 {
+                return Err("Corrupt coefficient data");
+            }
+            let value = self.receive_extend(size).await?;
+            coefficients[ZIGZAG[k] as usize] = value * quant[k] as i32;
+            k += 1;
+        }
+        Ok(())
+    }
+
+    async fn decode_huffman(&mut self, ac: bool, id: usize) -> Result<u8, &'static str> {
+        let mut code = 0i32;
+        for len in 0..16 {
+            code = (code << 1) | self.read_bit().await? as i32;
+            let table = if ac {
+                &self.ac_tables[id]
+            } else {
+                &self.dc_tables[id]
+            };
+            if code <= table.max_code[len] {
+                let index = table.val_ptr[len] + code - table.min_code[len];
+                return Ok(table.values[index as usize]);
+            }
+        }
+        Err("Corrupt Huffman code")
+    }
+
+    /// Read `size` bits and sign-extend them (ITU T.81 F.2.2.1)
+    async fn receive_extend(&mut self, size: u8) -> Result<i32, &'static str> {
+        if size == 0 {
+            return Ok(0);
+        }
+        if size > 16 {
+            return Err("Corrupt coefficient data");
+        }
+        let mut value = 0i32;
+        for _ in 0..size {
+            value = (value << 1) | self.read_bit().await? as i32;
+        }
+        if value < 1 << (size - 1) {
+            value -= (1 << size) - 1;
+        }
+        Ok(value)
+    }
+
+    async fn read_bit(&mut self) -> Result<u8, &'static str> {
+        if self.bit_count == 0 {
+            self.bit_buffer = if self.pending_marker.is_some() {
+                0
+            } else {
+                match self.read_u8().await? {
+                    0xFF => match self.read_u8().await? {
+                        // Stuffed byte
+                        0x00 => 0xFF,
+                        marker => {
+                            self.pending_marker = Some(marker);
+                            0
+                        }
+                    },
+                    byte => byte,
+                }
+            };
+            self.bit_count = 8;
+        }
+        self.bit_count -= 1;
+        Ok((self.bit_buffer >> self.bit_count) & 1)
+    }
+
+    /// Consume the RSTn marker between restart intervals and reset predictors
+    async fn handle_restart(&mut self) -> Result<(), &'static str> {
+        self.bit_count = 0;
+        let marker = match self.pending_marker.take() {
+            Some(marker) => marker,
+            None => self.read_marker().await?,
+        };
+        if !(0xD0..=0xD7).contains(&marker) {
+            return Err("Missing restart marker");
+        }
+        for component in self.components.iter_mut() {
+            component.dc_pred = 0;
+        }
+        Ok(())
+    }
+
+    /// Convert the decoded blocks of the current MCU to RGB565
+    fn convert_mcu(&mut self, width: u16, height: u16) {
+        let mut first_block = [0usize; 3];
+        let mut block = 0;
+        for (c, first) in first_block
+            .iter_mut()
+            .enumerate()
+            .take(self.component_count)
+        {
+            *first = block;
+            block += self.components[c].h as usize * self.components[c].v as usize;
+        }
+
+        for py in 0..height as usize {
+            for px in 0..width as usize {
+                let mut values = [0i32; 3];
+                for (c, value) in values.iter_mut().enumerate().take(self.component_count) {
+                    let component = &self.components[c];
+                    // Chroma with lower sampling covers several output pixels
+                    let cx = px * component.h as usize / self.max_h as usize;
+                    let cy = py * component.v as usize / self.max_v as usize;
+                    let block = first_block[c] + (cy / 8) * component.h as usize + cx / 8;
+                    *value = self.samples[block][(cy % 8) * 8 + cx % 8] as i32;
+                }
+
+                let (r, g, b) = if self.component_count == 1 {
+                    (values[0], values[0], values[0])
+                } else {
+                    ycbcr_to_rgb(values[0], values[1], values[2])
+                };
+                self.pixels[py * width as usize + px] =
+                    ((r as u16 >> 3) << 11) | ((g as u16 >> 2) << 5) | (b as u16 >> 3);
+            }
+        }
+    }
+
+    async fn read_marker(&mut self) -> Result<u8, &'static str> {
+        // Skip anything up to the next 0xFF, then any fill bytes
+        while self.read_u8().await? != 0xFF {}
+        loop {
+            match self.read_u8().await? {
+                0xFF => continue,
+                0x00 => return Err("Expected marker"),
+                marker => return Ok(marker),
+            }
+        }
+    }
+
+    async fn read_u8(&mut self) -> Result<u8, &'static str> {
+        if self.input_pos == self.input_len {
+            self.input_len = self.source.read(&mut self.input).await?;
+            self.input_pos = 0;
+            if self.input_len == 0 {
+                return Err("Unexpected end of JPEG data");
+            }
+        }
+        let byte = self.input[self.input_pos];
+        self.input_pos += 1;
+        Ok(byte)
+    }
+
+    async fn read_u16(&mut self) -> Result<u16, &'static str> {
+        let high = self.read_u8().await?;
+        let low = self.read_u8().await?;
+        Ok(u16::from_be_bytes([high, low]))
+    }
+
+    async fn skip(&mut self, count: usize) -> Result<(), &'static str> {
+        for _ in 0..count {
+            self.read_u8().await?;
+        }
+        Ok(())
+    }
+}
+
+fn ycbcr_to_rgb(y: i32, cb: i32, cr: i32) -> (i32, i32, i32) {
+    // JFIF conversion in 16.16 fixed point
+    let (cb, cr) = (cb - 128, cr - 128);
+    let r = y + ((91881 * cr + 32768) >> 16);
+    let g = y - ((22554 * cb + 46802 * cr + 32768) >> 16);
+    let b = y + ((116130 * cb + 32768) >> 16);
+    (r.clamp(0, 255), g.clamp(0, 255), b.clamp(0, 255))
+}
+
+/// Fixed-point constant with 12 fractional bits
+const fn f2f(x: f32) -> i32 {
+    (x * 4096.0 + 0.5) as i32
+}
+
+/// One-dimensional 8-point IDCT; returns the even (x0..x3) and odd (t0..t3) parts
+fn idct_1d(s: [i32; 8]) -> ([i32; 4], [i32; 4]) {
+    let p1 = (s[2] + s[6]) * f2f(0.541_196_1);
+    let t2 = p1 + s[6] * f2f(-1.847_759_1);
+    let t3 = p1 + s[2] * f2f(0.765_366_87);
+    let t0 = (s[0] + s[4]) * 4096;
+    let t1 = (s[0] - s[4]) * 4096;
+    let even = [t0 + t3, t1 + t2, t1 - t2, t0 - t3];
+
+    let (mut t0, mut t1, mut t2, mut t3) = (s[7], s[5], s[3], s[1]);
+    let p3 = t0 + t2;
+    let p4 = t1 + t3;
+    let p1 = t0 + t3;
+    let p2 = t1 + t2;
+    let p5 = (p3 + p4) * f2f(1.175_875_6);
+    t0 *= f2f(0.298_631_34);
+    t1 *= f2f(2.053_12);
+    t2 *= f2f(3.072_711);
+    t3 *= f2f(1.501_321_1);
+    let p1 = p5 + p1 * f2f(-0.899_976_2);
+    let p2 = p5 + p2 * f2f(-2.562_915_4);
+    let p3 = p3 * f2f(-1.961_570_6);
+    let p4 = p4 * f2f(-0.390_180_64);
+    t3 += p1 + p4;
+    t2 += p2 + p3;
+    t1 += p2 + p4;
+    t0 += p1 + p3;
+    (even, [t0, t1, t2, t3])
+}
+
+/// Integer inverse DCT of dequantized coefficients (natural order) into level-shifted samples
+fn idct_block(coefficients: &[i32; 64], out: &mut [u8; 64]) {
+    let mut temp = [0i32; 64];
+
+    // Columns, keeping 2 extra bits of precision
+    for col in 0..8 {
+        let s: [i32; 8] = core::array::from_fn(|row| coefficients[row * 8 + col]);
+        if s[1..].iter().all(|&v| v == 0) {
+            for row in 0..8 {
+                temp[row * 8 + col] = s[0] * 4;
+            }
+            continue;
+        }
+        let (x, t) = idct_1d(s);
+        for i in 0..4 {
+            let x = x[i] + 512;
+            temp[i * 8 + col] = (x + t[3 - i]) >> 10;
+            temp[(7 - i) * 8 + col] = (x - t[3 - i]) >> 10;
+        }
+    }
+
+    // Rows; remove the remaining scale and shift back to 0..=255
+    for row in 0..8 {
+        let s: [i32; 8] = core::array::from_fn(|col| temp[row * 8 + col]);
+        let (x, t) = idct_1d(s);
+        for i in 0..4 {
+            let x = x[i] + 65536 + (128 << 17);
+            out[row * 8 + i] = ((x + t[3 - i]) >> 17).clamp(0, 255) as u8;
+            out[row * 8 + 7 - i] = ((x - t[3 - i]) >> 17).clamp(0, 255) as u8;
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::pattern::rgb565_components;
+    use core::future::Future;
+    use core::pin::pin;
+    use core::task::{Context, Poll, Waker};
+    use jpeg_encoder::{ColorType, Encoder, SamplingFactor};
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    /// Smooth RGB test image (JPEG handles gradients with little error)
+    fn gradient(width: u16, height: u16) -> Vec<u8> {
+        let mut rgb = Vec::new();
+        for y in 0..height as u32 {
+            for x in 0..width as u32 {
+                rgb.push((x * 255 / width as u32) as u8);
+                rgb.push((y * 255 / height as u32) as u8);
+                rgb.push(128);
+            }
+        }
+        rgb
+    }
+
+    fn encode(
+        rgb: &[u8],
+        width: u16,
+        height: u16,
+        color: ColorType,
+        configure: impl FnOnce(&mut Encoder<&mut Vec<u8>>),
+    ) -> Vec<u8> {
+        let mut jpeg = Vec::new();
+        let mut encoder = Encoder::new(&mut jpeg, 100);
+        configure(&mut encoder);
+        encoder.encode(rgb, width, height, color).unwrap();
+        jpeg
+    }
+
+    /// Decode every MCU into a full RGB565 frame, checking each pixel is written once
+    fn decode(jpeg: &[u8]) -> (u16, u16, Vec<u16>) {
+        block_on(async {
+            let mut decoder = JpegDecoder::new(jpeg).await.unwrap();
+            let (width, height) = (decoder.width(), decoder.height());
+            let mut frame = vec![None; width as usize * height as usize];
+            while let Some(mcu) = decoder.next_mcu().await.unwrap() {
+                for row in 0..mcu.height as usize {
+                    for col in 0..mcu.width as usize {
+                        let index = (mcu.y as usize + row) * width as usize + mcu.x as usize + col;
+                        assert!(frame[index].is_none());
+                        frame[index] = Some(mcu.pixels[row * mcu.width as usize + col]);
+                    }
+                }
+            }
+            (
+                width,
+                height,
+                frame.into_iter().map(Option::unwrap).collect(),
+            )
+        })
+    }
+
+    /// Largest per-channel error, in 8-bit units, between decoded RGB565 and source RGB
+    fn max_error(frame: &[u16], rgb: &[u8]) -> u8 {
+        frame
+            .iter()
+            .zip(rgb.chunks(3))
+            .map(|(&pixel, expected)| {
+                let (r, g, b) = rgb565_components(pixel);
+                let decoded = [r << 3, g << 2, b << 3];
+                (0..3)
+                    .map(|i| decoded[i].abs_diff(expected[i] & [0xF8, 0xFC, 0xF8][i]))
+                    .max()
+                    .unwrap()
+            })
+            .max()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_decodes_subsampled_color_image() {
+        // Odd size exercises clipping of the right and bottom MCUs
+        let (width, height) = (50, 30);
+        let rgb = gradient(width, height);
+        let jpeg = encode(&rgb, width, height, ColorType::Rgb, |e| {
+            e.set_sampling_factor(SamplingFactor::F_2_2)
+        });
+
+        let (w, h, frame) = decode(&jpeg);
+        assert_eq!((w, h), (width, height));
+        assert!(max_error(&frame, &rgb) <= 12);
+    }
+
+    #[test]
+    fn test_decodes_grayscale_with_restart_markers() {
+        let (width, height) = (40, 24);
+        let luma: Vec<u8> = (0..width as usize * height as usize)
+            .map(|i| (i % width as usize * 6) as u8)
+            .collect();
+        let jpeg = encode(&luma, width, height, ColorType::Luma, |e| {
+            e.set_restart_interval(2)
+        });
+
+        let (_, _, frame) = decode(&jpeg);
+        let rgb: Vec<u8> = luma.iter().flat_map(|&y| [y, y, y]).collect();
+        assert!(max_error(&frame, &rgb) <= 4);
+    }
+
+    #[test]
+    fn test_rejects_progressive_and_non_jpeg() {
+        let rgb = gradient(16, 16);
+        let jpeg = encode(&rgb, 16, 16, ColorType::Rgb, |e| e.set_progressive(true));
+        assert!(block_on(JpegDecoder::new(&jpeg[..])).is_err());
+
+        assert!(is_jpeg(&jpeg));
+        assert!(!is_jpeg(&[0x00, 0xF8, 0x00]));
+        assert!(block_on(JpegDecoder::new(&[0x00u8, 0xF8][..])).is_err());
+    }
+}
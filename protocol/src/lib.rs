@@ -12,19 +12,51 @@ extern crate alloc;
 #[cfg(not(feature = "std"))]
 use alloc::vec::Vec;
 
-// Hardware CRC-32 will be used on STM32 side
-// Software fallback for host tools
+// Unit tests also run without `std` (`cargo test --no-default-features`) to
+// cover the no_std code paths; only the test harness links std
+#[cfg(all(test, not(feature = "std")))]
+#[macro_use]
+extern crate std;
+
+#[macro_use]
+mod fmt;
+
+pub mod asset_pack;
+pub mod backend;
+pub mod batch;
+pub mod capabilities;
+pub mod config;
+pub mod crc32;
+pub mod erase_progress;
+pub mod error_counters;
+pub mod font;
+pub mod framing;
+pub mod geometry;
+pub mod glyph;
+pub mod handler;
+pub mod jedec;
+#[cfg(feature = "jpeg")]
+pub mod jpeg;
 #[cfg(feature = "std")]
+pub mod memory_backend;
+pub mod pattern;
+pub mod read_cache;
+pub mod read_stream;
+pub mod segments;
+
 use crc::{Crc, CRC_32_ISO_HDLC};
 
-#[cfg(feature = "std")]
-/// CRC-32 calculator for packet integrity (software fallback)
+/// Table-driven CRC-32 calculator for packet and response trailers, with or
+/// without `std`
 pub const CRC32: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
 
 /// Magic numbers for packet synchronization
 pub const PACKET_MAGIC: u16 = 0xABCD;
 pub const RESPONSE_MAGIC: u16 = 0xDCBA;
 
+/// Bytes in a response besides its data: magic, status, sequence, length and CRC
+pub const RESPONSE_OVERHEAD: usize = 2 + 1 + 2 + 4 + 4;
+
 /// Maximum data payload size per packet (optimized for speed and stability - 1KB packets)
 pub const MAX_PAYLOAD_SIZE: usize = 1024;
 
@@ -34,11 +66,168 @@ pub const FLASH_PAGE_SIZE: usize = 256;
 /// Flash sector size for W25Q128 (4KB)
 pub const FLASH_SECTOR_SIZE: usize = 4096;
 
+/// Flash block size for W25Q128 (64KB)
+pub const FLASH_BLOCK_SIZE: usize = 64 * 1024;
+
 /// Total flash size for W25Q128 (16MB)
 pub const FLASH_TOTAL_SIZE: usize = 16 * 1024 * 1024;
 
+/// Flag in the Info response (fifth u32) set when the firmware's write-enable
+/// check failed, i.e. the chip won't accept program/erase commands
+pub const INFO_FLAG_WRITE_PROTECTED: u32 = 1 << 0;
+
+/// Flag in the Info response set when packets after it use CRC-16 trailers
+pub const INFO_FLAG_CRC16: u32 = 1 << 1;
+
+/// Trailer checksum used by packets and responses
+///
+/// Negotiated with the Info command: Info packets and their responses always
+/// carry a CRC-32 trailer, so either side can resynchronize, and the Info
+/// packet's optional first data byte selects the mode for everything after
+/// it (no data selects CRC-32). The response reports the mode in effect with
+/// [`INFO_FLAG_CRC16`]. CRC-16 saves two bytes per packet for constrained
+/// hosts; CRC-32 is the default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum CrcMode {
+    /// CRC-32/ISO-HDLC, 4-byte trailer
+    #[default]
+    Crc32 = 0,
+    /// CRC-16/CCITT-FALSE ([`calculate_crc16`]), 2-byte trailer
+    Crc16 = 1,
+}
+
+impl CrcMode {
+    /// Bytes in the trailing checksum
+    pub fn trailer_size(self) -> usize {
+        match self {
+            CrcMode::Crc32 => 4,
+            CrcMode::Crc16 => 2,
+        }
+    }
+
+    /// Mode used to frame a packet with `command` and its responses
+    pub fn for_command(self, command: Command) -> CrcMode {
+        match command {
+            Command::Info => CrcMode::Crc32,
+            _ => self,
+        }
+    }
+}
+
+impl TryFrom<u8> for CrcMode {
+    type Error = &'static str;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(CrcMode::Crc32),
+            1 => Ok(CrcMode::Crc16),
+            _ => Err("Unsupported CRC mode"),
+        }
+    }
+}
+
+/// CRC-16/CCITT-FALSE (poly 0x1021, init 0xFFFF, not reflected) used by
+/// [`CrcMode::Crc16`] trailers
+pub fn calculate_crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFFu16;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            if crc & 0x8000 != 0 {
+                crc = (crc << 1) ^ 0x1021;
+            } else {
+                crc <<= 1;
+            }
+        }
+    }
+    crc
+}
+
+/// "IMGF", the first word of an [`ImageFooter`]
+pub const IMAGE_FOOTER_MAGIC: u32 = u32::from_le_bytes(*b"IMGF");
+
+/// Bytes in an encoded [`ImageFooter`]
+pub const IMAGE_FOOTER_SIZE: usize = 12;
+
+/// Image footers start on a multiple of this many bytes
+pub const IMAGE_FOOTER_ALIGN: u32 = 4;
+
+/// Trailer written after an image (`write --append-footer`) so the
+/// application can check its own flash contents at boot
+///
+/// Encoded as three little-endian u32s: [`IMAGE_FOOTER_MAGIC`], the image
+/// length and the CRC-32 of the image (same parameters as packet CRCs). It
+/// starts at [`ImageFooter::address`], the first [`IMAGE_FOOTER_ALIGN`]-byte
+/// boundary at or after the end of the image; any gap is left erased (0xFF).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ImageFooter {
+    pub length: u32,
+    pub crc32: u32,
+}
+
+impl ImageFooter {
+    pub fn for_image(image: &[u8]) -> Self {
+        Self {
+            length: image.len() as u32,
+            crc32: crc32::Crc32::checksum(image),
+        }
+    }
+
+    /// Where the footer of a `length`-byte image at `image_address` goes
+    ///
+    /// Fails when the footer wouldn't fit in the 32-bit address space (e.g.
+    /// a corrupt length read back from flash); such a footer is invalid.
+    pub fn address(image_address: u32, length: u32) -> Result<u32, &'static str> {
+        image_address
+            .checked_add(length)
+            .and_then(|end| end.checked_next_multiple_of(IMAGE_FOOTER_ALIGN))
+            .filter(|address| address.checked_add(IMAGE_FOOTER_SIZE as u32).is_some())
+            .ok_or("Image footer address overflows")
+    }
+
+    pub fn to_bytes(&self) -> [u8; IMAGE_FOOTER_SIZE] {
+        let mut bytes = [0u8; IMAGE_FOOTER_SIZE];
+        bytes[0..4].copy_from_slice(&IMAGE_FOOTER_MAGIC.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.length.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.crc32.to_le_bytes());
+        bytes
+    }
+
+    /// Decode a footer read from flash, failing if the magic is missing
+    /// (e.g. the image was written without one)
+    pub fn from_bytes(data: &[u8]) -> Result<Self, &'static str> {
+        if data.len() < IMAGE_FOOTER_SIZE {
+            return Err("Image footer too short");
+        }
+        let word = |offset: usize| {
+            u32::from_le_bytes([
+                data[offset],
+                data[offset + 1],
+                data[offset + 2],
+                data[offset + 3],
+            ])
+        };
+        if word(0) != IMAGE_FOOTER_MAGIC {
+            return Err("No image footer");
+        }
+        Ok(Self {
+            length: word(4),
+            crc32: word(8),
+        })
+    }
+
+    /// Whether `image` is the image this footer describes
+    pub fn matches(&self, image: &[u8]) -> bool {
+        *self == Self::for_image(image)
+    }
+}
+
 /// Command types for flash operations
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[repr(u8)]
 pub enum Command {
     /// Get flash information (size, page size, etc.)
@@ -61,10 +250,92 @@ pub enum Command {
     VerifyCRC = 0x09,
     /// Read flash status register
     Status = 0x0A,
+    /// Reconfigure SPI clock polarity/phase (data[0] = mode)
+    SetSpiMode = 0x0B,
+    /// Read `length` bytes as a series of tagged responses (see `read_stream`)
+    ReadStream = 0x0C,
+    /// Check the CRC of the flash range written by the preceding StreamWrite
+    /// batch (see `batch`)
+    BatchChecksum = 0x0D,
+    /// Change the SPI clock (data[0..4] = Hz, u32); responds with the clock in use
+    SetSpiFrequency = 0x0E,
+    /// Cancel the erase/write/read in progress; the firmware acts on it as
+    /// soon as it is received and the interrupted command fails with `Aborted`
+    Abort = 0x0F,
+    /// Describe the firmware's commands, limits and flash geometry (see
+    /// `capabilities`)
+    Capabilities = 0x10,
+    /// Read `length` bytes of the chip's SFDP space at `address`; like Read,
+    /// the packet carries no payload and at most `MAX_PAYLOAD_SIZE` bytes
+    ReadSfdp = 0x11,
+    /// Report the firmware's error counters since boot (see `error_counters`)
+    GetErrorCounters = 0x12,
+    /// Reset every error counter to zero
+    ClearErrorCounters = 0x13,
+    /// Report the current runtime settings (see `config`)
+    GetConfig = 0x1E,
+    /// Reboot into the MCU's system memory USB DFU bootloader once the
+    /// response has been sent
+    EnterBootloader = 0x1F,
+}
+
+impl TryFrom<u8> for Command {
+    type Error = &'static str;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0x01 => Command::Info,
+            0x02 => Command::Erase,
+            0x03 => Command::Write,
+            0x04 => Command::Read,
+            0x05 => Command::Verify,
+            0x06 => Command::BatchWrite,
+            0x07 => Command::BatchAck,
+            0x08 => Command::StreamWrite,
+            0x09 => Command::VerifyCRC,
+            0x0A => Command::Status,
+            0x0B => Command::SetSpiMode,
+            0x0C => Command::ReadStream,
+            0x0D => Command::BatchChecksum,
+            0x0E => Command::SetSpiFrequency,
+            0x0F => Command::Abort,
+            0x10 => Command::Capabilities,
+            0x11 => Command::ReadSfdp,
+            0x12 => Command::GetErrorCounters,
+            0x13 => Command::ClearErrorCounters,
+            0x1E => Command::GetConfig,
+            0x1F => Command::EnterBootloader,
+            _ => return Err("Invalid command"),
+        })
+    }
+}
+
+/// SPI clock polarity/phase supported by SPI NOR flash
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum SpiMode {
+    /// CPOL=0, CPHA=0 (default)
+    Mode0 = 0,
+    /// CPOL=1, CPHA=1
+    Mode3 = 3,
+}
+
+impl TryFrom<u8> for SpiMode {
+    type Error = &'static str;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(SpiMode::Mode0),
+            3 => Ok(SpiMode::Mode3),
+            _ => Err("Unsupported SPI mode (flash supports 0 and 3)"),
+        }
+    }
 }
 
 /// Status codes for responses
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[repr(u8)]
 pub enum Status {
     /// Operation completed successfully
@@ -83,10 +354,78 @@ pub enum Status {
     Timeout = 0x06,
     /// Data verification failed
     VerificationFailed = 0x07,
+    /// Program target not erased (would need to set bits)
+    NotErased = 0x08,
+    /// Write enable latch won't set: hardware write protection (WP#) is active
+    WriteProtected = 0x09,
+    /// Operation cancelled by an `Abort` command
+    Aborted = 0x0A,
     /// Unknown error
     Unknown = 0xFF,
 }
 
+/// Cause of a failed flash operation
+///
+/// Error responses produced from a backend failure carry this as their only
+/// data byte, so e.g. a `FlashError` can be told apart as a bus fault or a
+/// write that didn't stick. Responses without it (empty data) predate the
+/// detail byte or failed for a protocol-level reason.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum ErrorDetail {
+    /// Flash chip not detected or driver not set up
+    NotInitialized = 0x01,
+    /// SPI transfer failed
+    Bus = 0x02,
+    /// Operation did not complete in time
+    Timeout = 0x03,
+    /// Address or length outside the device
+    InvalidAddress = 0x04,
+    /// Write did not take effect
+    WriteFailed = 0x05,
+    /// Write enable latch won't set
+    WriteProtected = 0x06,
+    /// Operation not supported by the firmware's flash driver
+    Unsupported = 0x07,
+    /// Cancelled by an `Abort` command
+    Aborted = 0x08,
+}
+
+impl ErrorDetail {
+    /// Short explanation for the user
+    pub fn description(self) -> &'static str {
+        match self {
+            ErrorDetail::NotInitialized => "flash not detected - check wiring and power",
+            ErrorDetail::Bus => "SPI transfer failed",
+            ErrorDetail::Timeout => "flash did not finish in time",
+            ErrorDetail::InvalidAddress => "address outside the flash",
+            ErrorDetail::WriteFailed => "data did not program",
+            ErrorDetail::WriteProtected => "write enable failed - check WP# pin",
+            ErrorDetail::Unsupported => "not supported by the firmware's flash driver",
+            ErrorDetail::Aborted => "aborted",
+        }
+    }
+}
+
+impl TryFrom<u8> for ErrorDetail {
+    type Error = &'static str;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0x01 => ErrorDetail::NotInitialized,
+            0x02 => ErrorDetail::Bus,
+            0x03 => ErrorDetail::Timeout,
+            0x04 => ErrorDetail::InvalidAddress,
+            0x05 => ErrorDetail::WriteFailed,
+            0x06 => ErrorDetail::WriteProtected,
+            0x07 => ErrorDetail::Unsupported,
+            0x08 => ErrorDetail::Aborted,
+            _ => return Err("Unknown error detail"),
+        })
+    }
+}
+
 /// Command packet structure
 #[derive(Debug, Clone)]
 pub struct Packet {
@@ -102,7 +441,7 @@ pub struct Packet {
     pub sequence: u16,
     /// Data payload
     pub data: Vec<u8>,
-    /// CRC32 checksum
+    /// Trailer checksum (CRC-32, or CRC-16 in the low bits under [`CrcMode::Crc16`])
     pub crc: u32,
 }
 
@@ -113,11 +452,14 @@ pub struct Response {
     pub magic: u16,
     /// Status code
     pub status: Status,
+    /// Sequence number of the request this answers, so the host can drop
+    /// late replies to a request it already gave up on
+    pub sequence: u16,
     /// Response data length
     pub length: u32,
     /// Response data
     pub data: Vec<u8>,
-    /// CRC32 checksum
+    /// Trailer checksum (CRC-32, or CRC-16 in the low bits under [`CrcMode::Crc16`])
     pub crc: u32,
 }
 
@@ -143,7 +485,6 @@ impl Packet {
     }
 
     /// Calculate CRC for the packet
-    #[cfg(feature = "std")]
     pub fn calculate_crc(&self) -> u32 {
         let mut digest = CRC32.digest();
         digest.update(&self.magic.to_le_bytes());
@@ -155,45 +496,26 @@ impl Packet {
         digest.finalize()
     }
 
-    /// Calculate CRC for the packet (no-std version, temporary software fallback)
-    #[cfg(not(feature = "std"))]
-    pub fn calculate_crc(&self) -> u32 {
-        // Temporary software CRC implementation for compatibility
-        // TODO: Re-enable hardware CRC after debugging
-        let mut crc = 0xFFFFFFFFu32;
-
-        // Simple CRC-32 calculation (not optimized, but compatible)
-        let data = [
-            &self.magic.to_le_bytes()[..],
-            &[self.command as u8],
-            &self.length.to_le_bytes()[..],
-            &self.address.to_le_bytes()[..],
-            &self.sequence.to_le_bytes()[..],
-            &self.data[..],
-        ]
-        .concat();
-
-        for &byte in &data {
-            crc ^= byte as u32;
-            for _ in 0..8 {
-                if crc & 1 != 0 {
-                    crc = (crc >> 1) ^ 0xEDB88320;
-                } else {
-                    crc >>= 1;
-                }
-            }
+    /// Calculate the trailer checksum for `mode`
+    pub fn calculate_crc_with(&self, mode: CrcMode) -> u32 {
+        match mode {
+            CrcMode::Crc32 => self.calculate_crc(),
+            CrcMode::Crc16 => calculate_crc16(&self.body_bytes()) as u32,
         }
-
-        !crc
     }
 
     /// Verify packet integrity
     pub fn verify_crc(&self) -> bool {
-        self.crc == self.calculate_crc()
+        self.verify_crc_with(CrcMode::Crc32)
     }
 
-    /// Serialize packet to bytes
-    pub fn to_bytes(&self) -> Vec<u8> {
+    /// Verify packet integrity against a `mode` trailer
+    pub fn verify_crc_with(&self, mode: CrcMode) -> bool {
+        self.crc == self.calculate_crc_with(mode)
+    }
+
+    /// Header and data, everything the trailer covers
+    fn body_bytes(&self) -> Vec<u8> {
         let mut bytes = Vec::new();
         bytes.extend_from_slice(&self.magic.to_le_bytes());
         bytes.push(self.command as u8);
@@ -201,13 +523,31 @@ impl Packet {
         bytes.extend_from_slice(&self.address.to_le_bytes());
         bytes.extend_from_slice(&self.sequence.to_le_bytes());
         bytes.extend_from_slice(&self.data);
-        bytes.extend_from_slice(&self.crc.to_le_bytes());
+        bytes
+    }
+
+    /// Serialize packet to bytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.to_bytes_with(CrcMode::Crc32)
+    }
+
+    /// Serialize packet to bytes with a `mode` trailer (`crc` must already
+    /// hold a checksum of that kind)
+    pub fn to_bytes_with(&self, mode: CrcMode) -> Vec<u8> {
+        let mut bytes = self.body_bytes();
+        bytes.extend_from_slice(&self.crc.to_le_bytes()[..mode.trailer_size()]);
         bytes
     }
 
     /// Deserialize packet from bytes
     pub fn from_bytes(bytes: &[u8]) -> Result<Self, &'static str> {
-        if bytes.len() < 17 {
+        Self::from_bytes_with(bytes, CrcMode::Crc32)
+    }
+
+    /// Deserialize packet from bytes with a `mode` trailer
+    pub fn from_bytes_with(bytes: &[u8], mode: CrcMode) -> Result<Self, &'static str> {
+        let trailer = mode.trailer_size();
+        if bytes.len() < 13 + trailer {
             return Err("Packet too short");
         }
 
@@ -216,35 +556,19 @@ impl Packet {
             return Err("Invalid magic number");
         }
 
-        let command = match bytes[2] {
-            0x01 => Command::Info,
-            0x02 => Command::Erase,
-            0x03 => Command::Write,
-            0x04 => Command::Read,
-            0x05 => Command::Verify,
-            0x06 => Command::BatchWrite,
-            0x07 => Command::BatchAck,
-            0x08 => Command::StreamWrite,
-            0x09 => Command::VerifyCRC,
-            0x0A => Command::Status,
-            _ => return Err("Invalid command"),
-        };
+        let command = Command::try_from(bytes[2])?;
 
         let length = u32::from_le_bytes([bytes[3], bytes[4], bytes[5], bytes[6]]);
         let address = u32::from_le_bytes([bytes[7], bytes[8], bytes[9], bytes[10]]);
         let sequence = u16::from_le_bytes([bytes[11], bytes[12]]);
 
-        if bytes.len() < 17 + length as usize {
+        if bytes.len() < 13 + trailer + length as usize {
             return Err("Incomplete packet");
         }
 
-        let data = bytes[13..13 + length as usize].to_vec();
-        let crc = u32::from_le_bytes([
-            bytes[13 + length as usize],
-            bytes[14 + length as usize],
-            bytes[15 + length as usize],
-            bytes[16 + length as usize],
-        ]);
+        let data_end = 13 + length as usize;
+        let data = bytes[13..data_end].to_vec();
+        let crc = read_trailer(&bytes[data_end..data_end + trailer]);
 
         let packet = Self {
             magic,
@@ -256,7 +580,7 @@ impl Packet {
             crc,
         };
 
-        if !packet.verify_crc() {
+        if !packet.verify_crc_with(mode) {
             return Err("CRC mismatch");
         }
 
@@ -267,9 +591,24 @@ impl Packet {
 impl Response {
     /// Create a new response
     pub fn new(status: Status, data: Vec<u8>) -> Self {
+        Self::new_with_sequence(status, data, 0)
+    }
+
+    /// Cause attached to an error response, if it carries one
+    pub fn error_detail(&self) -> Option<ErrorDetail> {
+        match (self.status, self.data.as_slice()) {
+            (Status::Success, _) => None,
+            (_, &[detail]) => ErrorDetail::try_from(detail).ok(),
+            _ => None,
+        }
+    }
+
+    /// Create a new response echoing a request's sequence number
+    pub fn new_with_sequence(status: Status, data: Vec<u8>, sequence: u16) -> Self {
         let mut response = Self {
             magic: RESPONSE_MAGIC,
             status,
+            sequence,
             length: data.len() as u32,
             data,
             crc: 0,
@@ -278,66 +617,82 @@ impl Response {
         response
     }
 
+    /// Re-stamp the response with a request's sequence number
+    pub fn with_sequence(mut self, sequence: u16) -> Self {
+        self.sequence = sequence;
+        self.crc = self.calculate_crc();
+        self
+    }
+
+    /// Replace the checksum with a `mode` one, ready for [`to_bytes_with`](Self::to_bytes_with)
+    pub fn with_crc_mode(mut self, mode: CrcMode) -> Self {
+        self.crc = self.calculate_crc_with(mode);
+        self
+    }
+
     /// Calculate CRC for the response
-    #[cfg(feature = "std")]
     pub fn calculate_crc(&self) -> u32 {
         let mut digest = CRC32.digest();
         digest.update(&self.magic.to_le_bytes());
         digest.update(&[self.status as u8]);
+        digest.update(&self.sequence.to_le_bytes());
         digest.update(&self.length.to_le_bytes());
         digest.update(&self.data);
         digest.finalize()
     }
 
-    /// Calculate CRC for the response (no-std version, temporary software fallback)
-    #[cfg(not(feature = "std"))]
-    pub fn calculate_crc(&self) -> u32 {
-        // Temporary software CRC implementation for compatibility
-        // TODO: Re-enable hardware CRC after debugging
-        let mut crc = 0xFFFFFFFFu32;
-
-        // Simple CRC-32 calculation (not optimized, but compatible)
-        let data = [
-            &self.magic.to_le_bytes()[..],
-            &[self.status as u8],
-            &self.length.to_le_bytes()[..],
-            &self.data[..],
-        ]
-        .concat();
-
-        for &byte in &data {
-            crc ^= byte as u32;
-            for _ in 0..8 {
-                if crc & 1 != 0 {
-                    crc = (crc >> 1) ^ 0xEDB88320;
-                } else {
-                    crc >>= 1;
-                }
-            }
+    /// Calculate the trailer checksum for `mode`
+    pub fn calculate_crc_with(&self, mode: CrcMode) -> u32 {
+        match mode {
+            CrcMode::Crc32 => self.calculate_crc(),
+            CrcMode::Crc16 => calculate_crc16(&self.body_bytes()) as u32,
         }
-
-        !crc
     }
 
     /// Verify response integrity
     pub fn verify_crc(&self) -> bool {
-        self.crc == self.calculate_crc()
+        self.verify_crc_with(CrcMode::Crc32)
     }
 
-    /// Serialize response to bytes
-    pub fn to_bytes(&self) -> Vec<u8> {
+    /// Verify response integrity against a `mode` trailer
+    pub fn verify_crc_with(&self, mode: CrcMode) -> bool {
+        self.crc == self.calculate_crc_with(mode)
+    }
+
+    /// Header and data, everything the trailer covers
+    fn body_bytes(&self) -> Vec<u8> {
         let mut bytes = Vec::new();
         bytes.extend_from_slice(&self.magic.to_le_bytes());
         bytes.push(self.status as u8);
+        bytes.extend_from_slice(&self.sequence.to_le_bytes());
         bytes.extend_from_slice(&self.length.to_le_bytes());
         bytes.extend_from_slice(&self.data);
-        bytes.extend_from_slice(&self.crc.to_le_bytes());
+        bytes
+    }
+
+    /// Serialize response to bytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.to_bytes_with(CrcMode::Crc32)
+    }
+
+    /// Serialize response to bytes with a `mode` trailer (`crc` must already
+    /// hold a checksum of that kind, see [`with_crc_mode`](Self::with_crc_mode))
+    pub fn to_bytes_with(&self, mode: CrcMode) -> Vec<u8> {
+        let mut bytes = self.body_bytes();
+        bytes.extend_from_slice(&self.crc.to_le_bytes()[..mode.trailer_size()]);
         bytes
     }
 
     /// Deserialize response from bytes
     pub fn from_bytes(bytes: &[u8]) -> Result<Self, &'static str> {
-        if bytes.len() < 11 {
+        Self::from_bytes_with(bytes, CrcMode::Crc32)
+    }
+
+    /// Deserialize response from bytes with a `mode` trailer
+    pub fn from_bytes_with(bytes: &[u8], mode: CrcMode) -> Result<Self, &'static str> {
+        // RESPONSE_OVERHEAD counts a CRC-32 trailer
+        let overhead = RESPONSE_OVERHEAD - 4 + mode.trailer_size();
+        if bytes.len() < overhead {
             return Err("Response too short");
         }
 
@@ -354,32 +709,34 @@ impl Response {
             0x04 => Status::CrcError,
             0x05 => Status::BufferOverflow,
             0x06 => Status::Timeout,
+            0x07 => Status::VerificationFailed,
+            0x08 => Status::NotErased,
+            0x09 => Status::WriteProtected,
+            0x0A => Status::Aborted,
             _ => Status::Unknown,
         };
 
-        let length = u32::from_le_bytes([bytes[3], bytes[4], bytes[5], bytes[6]]);
+        let sequence = u16::from_le_bytes([bytes[3], bytes[4]]);
+        let length = u32::from_le_bytes([bytes[5], bytes[6], bytes[7], bytes[8]]);
 
-        if bytes.len() < 11 + length as usize {
+        if bytes.len() < overhead + length as usize {
             return Err("Incomplete response");
         }
 
-        let data = bytes[7..7 + length as usize].to_vec();
-        let crc = u32::from_le_bytes([
-            bytes[7 + length as usize],
-            bytes[8 + length as usize],
-            bytes[9 + length as usize],
-            bytes[10 + length as usize],
-        ]);
+        let data_end = 9 + length as usize;
+        let data = bytes[9..data_end].to_vec();
+        let crc = read_trailer(&bytes[data_end..data_end + mode.trailer_size()]);
 
         let response = Self {
             magic,
             status,
+            sequence,
             length,
             data,
             crc,
         };
 
-        if !response.verify_crc() {
+        if !response.verify_crc_with(mode) {
             return Err("CRC mismatch");
         }
 
@@ -387,6 +744,14 @@ impl Response {
     }
 }
 
+/// Little-endian trailer of 2 or 4 bytes
+pub(crate) fn read_trailer(bytes: &[u8]) -> u32 {
+    bytes
+        .iter()
+        .rev()
+        .fold(0, |value, &byte| (value << 8) | byte as u32)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -408,13 +773,62 @@ mod tests {
     #[test]
     fn test_response_serialization() {
         let data = vec![0xAA, 0xBB, 0xCC, 0xDD];
-        let response = Response::new(Status::Success, data.clone());
+        let response = Response::new_with_sequence(Status::Success, data.clone(), 0x1234);
 
         let bytes = response.to_bytes();
+        assert_eq!(bytes.len(), RESPONSE_OVERHEAD + data.len());
         let decoded = Response::from_bytes(&bytes).unwrap();
 
         assert_eq!(response.status, decoded.status);
+        assert_eq!(decoded.sequence, 0x1234);
         assert_eq!(response.data, decoded.data);
         assert!(decoded.verify_crc());
     }
+
+    #[test]
+    fn test_image_footer_round_trip() {
+        let image = b"123456789";
+        let footer = ImageFooter::for_image(image);
+        assert_eq!(footer.crc32, 0xCBF4_3926);
+        assert_eq!(ImageFooter::address(0x1000, image.len() as u32), Ok(0x100C));
+        assert_eq!(ImageFooter::address(0x1000, 8), Ok(0x1008));
+        assert!(ImageFooter::address(0xFFFF_FF00, 0x100).is_err());
+        assert!(ImageFooter::address(0xFFFF_FFF0, 0x0D).is_err());
+        assert!(ImageFooter::address(0x1000, u32::MAX).is_err());
+        assert_eq!(ImageFooter::address(0xFFFF_FFE0, 0x10), Ok(0xFFFF_FFF0));
+
+        let bytes = footer.to_bytes();
+        assert_eq!(&bytes[..4], b"IMGF");
+        let decoded = ImageFooter::from_bytes(&bytes).unwrap();
+        assert!(decoded.matches(image));
+        assert!(!decoded.matches(&image[..8]));
+        assert!(ImageFooter::from_bytes(&[0xFF; IMAGE_FOOTER_SIZE]).is_err());
+        assert!(ImageFooter::from_bytes(&bytes[..8]).is_err());
+    }
+
+    #[test]
+    fn test_crc16_framing() {
+        // CRC-16/CCITT-FALSE check value
+        assert_eq!(calculate_crc16(b"123456789"), 0x29B1);
+
+        let mut packet = Packet::new(Command::Write, 0x1000, vec![1, 2, 3]);
+        packet.crc = packet.calculate_crc_with(CrcMode::Crc16);
+        let bytes = packet.to_bytes_with(CrcMode::Crc16);
+        assert_eq!(bytes.len(), packet.to_bytes().len() - 2);
+        let decoded = Packet::from_bytes_with(&bytes, CrcMode::Crc16).unwrap();
+        assert_eq!(decoded.data, vec![1, 2, 3]);
+        assert!(Packet::from_bytes(&bytes).is_err());
+
+        let response = Response::new_with_sequence(Status::Success, vec![0xAA], 7)
+            .with_crc_mode(CrcMode::Crc16);
+        let mut bytes = response.to_bytes_with(CrcMode::Crc16);
+        assert_eq!(bytes.len(), RESPONSE_OVERHEAD - 2 + 1);
+        let decoded = Response::from_bytes_with(&bytes, CrcMode::Crc16).unwrap();
+        assert_eq!(decoded.sequence, 7);
+        bytes[9] ^= 0x01;
+        assert_eq!(
+            Response::from_bytes_with(&bytes, CrcMode::Crc16).unwrap_err(),
+            "CRC mismatch"
+        );
+    }
 }
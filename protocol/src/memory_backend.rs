@@ -0,0 +1,250 @@
+//! In-memory flash backend for host-side testing
+//!
+//! Behaves like SPI NOR flash: erase sets bytes to 0xFF and programming can
+//! only clear bits (new value = old value AND data), so writing over
+//! non-erased data produces the same corruption real hardware would.
+
+use crate::backend::{BackendError, FlashBackend};
+use crate::error_counters::ErrorCounters;
+use crate::geometry::{EraseUnit, SFDP_SIGNATURE};
+use crate::{SpiMode, FLASH_BLOCK_SIZE, FLASH_SECTOR_SIZE, FLASH_TOTAL_SIZE};
+
+/// Where the emulated SFDP space puts its Basic Flash Parameter Table
+const SFDP_BFPT_ADDRESS: usize = 0x30;
+const SFDP_BFPT_DWORDS: usize = 9;
+
+/// JEDEC ID reported by default (Winbond W25Q128)
+pub const DEFAULT_JEDEC_ID: u32 = 0xEF4018;
+
+/// RAM-backed NOR flash emulation
+pub struct MemoryBackend {
+    data: Vec<u8>,
+    jedec_id: u32,
+    status: u8,
+    spi_mode: SpiMode,
+    /// Last clock set through `set_spi_frequency`, 0 until then
+    spi_frequency_hz: u32,
+    write_protected: bool,
+    /// Smallest erase the emulated chip supports (and its SFDP reports)
+    erase_unit: EraseUnit,
+    /// Answer `read_sfdp`, like most current chips
+    sfdp: bool,
+    error_counters: ErrorCounters,
+    /// Total of every `pause`, which returns at once
+    paused_ms: u32,
+}
+
+impl MemoryBackend {
+    /// Create an erased 16MB device
+    pub fn new() -> Self {
+        Self::with_size(FLASH_TOTAL_SIZE)
+    }
+
+    /// Create an erased device of `size` bytes
+    pub fn with_size(size: usize) -> Self {
+        Self {
+            data: vec![0xFF; size],
+            jedec_id: DEFAULT_JEDEC_ID,
+            status: 0x00,
+            spi_mode: SpiMode::Mode0,
+            spi_frequency_hz: 0,
+            write_protected: false,
+            erase_unit: EraseUnit::Sector,
+            sfdp: false,
+            error_counters: ErrorCounters::default(),
+            paused_ms: 0,
+        }
+    }
+
+    /// Override the reported JEDEC ID
+    pub fn with_jedec_id(mut self, jedec_id: u32) -> Self {
+        self.jedec_id = jedec_id;
+        self
+    }
+
+    /// Emulate WP# held low: the write enable latch never sets, so writes
+    /// and erases fail with `BackendError::WriteProtected`
+    pub fn with_write_protected(mut self) -> Self {
+        self.write_protected = true;
+        self
+    }
+
+    /// Emulate a chip whose smallest erase is `erase_unit`: with
+    /// `EraseUnit::Block`, sector erases fail with `BackendError::Unsupported`
+    pub fn with_erase_unit(mut self, erase_unit: EraseUnit) -> Self {
+        self.erase_unit = erase_unit;
+        self
+    }
+
+    /// Serve SFDP tables describing the device's size and erase unit
+    pub fn with_sfdp(mut self) -> Self {
+        self.sfdp = true;
+        self
+    }
+
+    /// Milliseconds the handler has asked to pause for so far
+    pub fn paused_ms(&self) -> u32 {
+        self.paused_ms
+    }
+
+    /// Override the reported error counters
+    pub fn set_error_counters(&mut self, error_counters: ErrorCounters) {
+        self.error_counters = error_counters;
+    }
+
+    /// Override the reported status register value
+    pub fn set_status(&mut self, status: u8) {
+        self.status = status;
+    }
+
+    /// Raw device contents
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn check_writable(&self) -> Result<(), BackendError> {
+        if self.write_protected {
+            return Err(BackendError::WriteProtected);
+        }
+        Ok(())
+    }
+
+    fn range(&self, address: u32, length: usize) -> Result<core::ops::Range<usize>, BackendError> {
+        let start = address as usize;
+        let end = start
+            .checked_add(length)
+            .ok_or(BackendError::InvalidAddress)?;
+        if end > self.data.len() {
+            return Err(BackendError::InvalidAddress);
+        }
+        Ok(start..end)
+    }
+
+    /// SFDP space: the header, then a JESD216 BFPT giving the density and
+    /// the erase types (4KB only when sector erase is supported, and 64KB)
+    fn sfdp_space(&self) -> Vec<u8> {
+        let mut sfdp = vec![0xFF; SFDP_BFPT_ADDRESS + SFDP_BFPT_DWORDS * 4];
+        sfdp[..4].copy_from_slice(&SFDP_SIGNATURE.to_le_bytes());
+        sfdp[4..8].copy_from_slice(&[0, 1, 0, 0xFF]);
+        sfdp[8..16].copy_from_slice(&[
+            0x00,
+            0,
+            1,
+            SFDP_BFPT_DWORDS as u8,
+            SFDP_BFPT_ADDRESS as u8,
+            0,
+            0,
+            0xFF,
+        ]);
+
+        let bfpt = &mut sfdp[SFDP_BFPT_ADDRESS..];
+        let bits = self.data.len() as u32 * 8 - 1;
+        bfpt[4..8].copy_from_slice(&bits.to_le_bytes());
+        let erase_types = match self.erase_unit {
+            EraseUnit::Sector => [12, 0x20, 16, 0xD8, 0, 0, 0, 0],
+            EraseUnit::Block => [16, 0xD8, 0, 0, 0, 0, 0, 0],
+        };
+        bfpt[28..36].copy_from_slice(&erase_types);
+        sfdp
+    }
+
+    fn erase_aligned(&mut self, address: u32, size: usize) -> Result<(), BackendError> {
+        self.check_writable()?;
+        let start = address as usize / size * size;
+        let range = self.range(start as u32, size)?;
+        self.data[range].fill(0xFF);
+        Ok(())
+    }
+}
+
+impl Default for MemoryBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FlashBackend for MemoryBackend {
+    async fn read(&mut self, address: u32, length: u32) -> Result<Vec<u8>, BackendError> {
+        let range = self.range(address, length as usize)?;
+        Ok(self.data[range].to_vec())
+    }
+
+    async fn write(&mut self, address: u32, data: &[u8]) -> Result<(), BackendError> {
+        self.check_writable()?;
+        let range = self.range(address, data.len())?;
+        for (cell, byte) in self.data[range].iter_mut().zip(data) {
+            *cell &= *byte;
+        }
+        Ok(())
+    }
+
+    async fn erase_sector(&mut self, address: u32) -> Result<(), BackendError> {
+        if self.erase_unit != EraseUnit::Sector {
+            return Err(BackendError::Unsupported);
+        }
+        self.erase_aligned(address, FLASH_SECTOR_SIZE)
+    }
+
+    async fn erase_block(&mut self, address: u32) -> Result<(), BackendError> {
+        self.erase_aligned(address, FLASH_BLOCK_SIZE)
+    }
+
+    async fn chip_erase(&mut self) -> Result<(), BackendError> {
+        self.check_writable()?;
+        self.data.fill(0xFF);
+        Ok(())
+    }
+
+    async fn jedec_id(&mut self) -> Result<u32, BackendError> {
+        Ok(self.jedec_id)
+    }
+
+    async fn read_sfdp(&mut self, address: u32, length: u32) -> Result<Vec<u8>, BackendError> {
+        if !self.sfdp {
+            return Err(BackendError::Unsupported);
+        }
+        let sfdp = self.sfdp_space();
+        let start = (address as usize).min(sfdp.len());
+        let end = start.saturating_add(length as usize).min(sfdp.len());
+        Ok(sfdp[start..end].to_vec())
+    }
+
+    async fn status(&mut self) -> Result<u8, BackendError> {
+        Ok(self.status)
+    }
+
+    async fn write_enable_check(&mut self) -> Result<bool, BackendError> {
+        Ok(!self.write_protected)
+    }
+
+    async fn set_spi_mode(&mut self, mode: SpiMode) -> Result<(), BackendError> {
+        self.spi_mode = mode;
+        Ok(())
+    }
+
+    fn spi_mode(&self) -> SpiMode {
+        self.spi_mode
+    }
+
+    fn spi_frequency_hz(&self) -> u32 {
+        self.spi_frequency_hz
+    }
+
+    async fn set_spi_frequency(&mut self, hz: u32) -> Result<u32, BackendError> {
+        self.spi_frequency_hz = hz;
+        Ok(hz)
+    }
+
+    async fn pause(&mut self, ms: u32) {
+        self.paused_ms += ms;
+    }
+
+    async fn error_counters(&mut self) -> Result<ErrorCounters, BackendError> {
+        Ok(self.error_counters)
+    }
+
+    async fn clear_error_counters(&mut self) -> Result<(), BackendError> {
+        self.error_counters = ErrorCounters::default();
+        Ok(())
+    }
+}
@@ -0,0 +1,160 @@
+//! RGB565 test-pattern generation shared by the host tool and the display firmware
+//!
+//! Patterns are computed per pixel so they can be streamed to flash or to a
+//! display without holding the whole frame in memory. Pixels are emitted as
+//! little-endian RGB565, the same byte order as the boot screen assets.
+
+/// Common RGB565 colors
+pub mod colors {
+    pub const BLACK: u16 = 0x0000;
+    pub const WHITE: u16 = 0xFFFF;
+    pub const RED: u16 = 0xF800;
+    pub const GREEN: u16 = 0x07E0;
+    pub const BLUE: u16 = 0x001F;
+    pub const YELLOW: u16 = 0xFFE0;
+    pub const CYAN: u16 = 0x07FF;
+    pub const MAGENTA: u16 = 0xF81F;
+}
+
+/// Color bar sequence (standard SMPTE-like order, ending in black)
+pub const COLOR_BARS: [u16; 8] = [
+    colors::WHITE,
+    colors::YELLOW,
+    colors::CYAN,
+    colors::GREEN,
+    colors::MAGENTA,
+    colors::RED,
+    colors::BLUE,
+    colors::BLACK,
+];
+
+/// Decode one stored pixel; frames are little-endian, as written by the web tool
+pub fn rgb565_from_le_bytes(bytes: [u8; 2]) -> u16 {
+    u16::from_le_bytes(bytes)
+}
+
+/// Split an RGB565 value into its 5-bit red, 6-bit green and 5-bit blue channels
+pub fn rgb565_components(raw: u16) -> (u8, u8, u8) {
+    (
+        ((raw >> 11) & 0x1F) as u8,
+        ((raw >> 5) & 0x3F) as u8,
+        (raw & 0x1F) as u8,
+    )
+}
+
+/// Parameterized test pattern
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestPattern {
+    /// Alternating squares of `square` pixels, starting with `fg` at the top-left
+    Checkerboard { square: u16, fg: u16, bg: u16 },
+    /// Eight vertical bars spanning the frame width
+    ColorBars,
+    /// Horizontal gray ramp from black (left) to white (right)
+    Gradient,
+    /// Single color fill
+    Solid(u16),
+}
+
+impl TestPattern {
+    /// RGB565 color of the pixel at (x, y) in a `width` x `height` frame
+    pub fn pixel(&self, x: u16, y: u16, width: u16, _height: u16) -> u16 {
+        match *self {
+            TestPattern::Checkerboard { square, fg, bg } => {
+                let square = square.max(1);
+                if ((x / square) + (y / square)) & 1 == 0 {
+                    fg
+                } else {
+                    bg
+                }
+            }
+            TestPattern::ColorBars => {
+                let bar = (x as usize * COLOR_BARS.len()) / width.max(1) as usize;
+                COLOR_BARS[bar.min(COLOR_BARS.len() - 1)]
+            }
+            TestPattern::Gradient => {
+                // 0..=63 maps onto the 6-bit green channel; red/blue use the top 5 bits
+                let level = ((x as u32 * 64) / width.max(1) as u32).min(63) as u16;
+                ((level >> 1) << 11) | (level << 5) | (level >> 1)
+            }
+            TestPattern::Solid(color) => color,
+        }
+    }
+
+    /// Total size in bytes of a `width` x `height` RGB565 frame
+    pub fn frame_size(width: u16, height: u16) -> usize {
+        width as usize * height as usize * 2
+    }
+
+    /// Fill `buf` with frame bytes starting at byte `offset` into the frame
+    ///
+    /// Returns the number of bytes written, which is less than `buf.len()` once
+    /// the end of the frame is reached.
+    pub fn fill_bytes(&self, width: u16, height: u16, offset: usize, buf: &mut [u8]) -> usize {
+        let frame_size = Self::frame_size(width, height);
+        if offset >= frame_size {
+            return 0;
+        }
+
+        let count = buf.len().min(frame_size - offset);
+        for (i, byte) in buf[..count].iter_mut().enumerate() {
+            let position = offset + i;
+            let pixel_index = position / 2;
+            let x = (pixel_index % width as usize) as u16;
+            let y = (pixel_index / width as usize) as u16;
+            let color = self.pixel(x, y, width, height).to_le_bytes();
+            *byte = color[position % 2];
+        }
+
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checkerboard_alternates_squares() {
+        let pattern = TestPattern::Checkerboard {
+            square: 20,
+            fg: colors::WHITE,
+            bg: colors::BLACK,
+        };
+
+        assert_eq!(pattern.pixel(0, 0, 320, 172), colors::WHITE);
+        assert_eq!(pattern.pixel(19, 19, 320, 172), colors::WHITE);
+        assert_eq!(pattern.pixel(20, 0, 320, 172), colors::BLACK);
+        assert_eq!(pattern.pixel(20, 20, 320, 172), colors::WHITE);
+    }
+
+    #[test]
+    fn test_fill_bytes_is_little_endian_and_resumable() {
+        let pattern = TestPattern::Solid(colors::RED);
+        let mut buf = [0u8; 3];
+
+        assert_eq!(pattern.fill_bytes(2, 1, 0, &mut buf), 3);
+        assert_eq!(buf, [0x00, 0xF8, 0x00]);
+
+        // Resume mid-pixel; only one byte of the 4-byte frame remains
+        assert_eq!(pattern.fill_bytes(2, 1, 3, &mut buf), 1);
+        assert_eq!(buf[0], 0xF8);
+        assert_eq!(pattern.fill_bytes(2, 1, 4, &mut buf), 0);
+    }
+
+    #[test]
+    fn test_rgb565_decode_is_little_endian() {
+        // Pure red is stored low byte first; a swapped decode would give blue-ish 0x00F8
+        let raw = rgb565_from_le_bytes([0x00, 0xF8]);
+        assert_eq!(raw, colors::RED);
+        assert_eq!(rgb565_components(raw), (31, 0, 0));
+        assert_eq!(rgb565_components(colors::GREEN), (0, 63, 0));
+        assert_eq!(rgb565_components(colors::BLUE), (0, 0, 31));
+    }
+
+    #[test]
+    fn test_color_bars_span_width() {
+        let pattern = TestPattern::ColorBars;
+        assert_eq!(pattern.pixel(0, 0, 320, 172), COLOR_BARS[0]);
+        assert_eq!(pattern.pixel(319, 0, 320, 172), COLOR_BARS[7]);
+    }
+}
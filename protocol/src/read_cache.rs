@@ -0,0 +1,395 @@
+//! Line cache for reads from the external flash
+//!
+//! Used by the display firmware's `FlashManager`, whose font and image
+//! lookups read the same small ranges over and over. Entries need not be
+//! aligned: a read is served from whichever entries cover it, and only the
+//! gaps between them are fetched from the chip.
+//!
+//! Sequential readers (the boot screen) can also have the range after their
+//! last read loaded ahead of time with [`ReadCache::prefetch`], e.g. while
+//! the chunk just read goes out to the display on its own SPI bus.
+
+/// Bytes held by one cache entry (one read from the chip on a miss)
+pub const CACHE_LINE_SIZE: usize = 256;
+
+/// Where cache misses are read from
+#[allow(async_fn_in_trait)]
+pub trait ReadSource {
+    /// Fill `buf` with the flash contents starting at `address`
+    async fn read(&mut self, address: u32, buf: &mut [u8]) -> Result<(), &'static str>;
+}
+
+/// Cache of up to `N` flash ranges, evicting the least recently used entry
+/// when full
+pub struct ReadCache<const N: usize> {
+    entries: [CacheEntry; N],
+    used: usize,
+    /// Bumped on every access; entries keep the value of their last one
+    clock: u32,
+    /// End of the last `read`, to spot sequential access
+    last_read_end: Option<u32>,
+    /// Range `prefetch` should load next (set by sequential reads)
+    pending_prefetch: Option<(u32, usize)>,
+    /// Lines loaded by `prefetch`
+    prefetch_count: u32,
+}
+
+#[derive(Clone, Copy)]
+struct CacheEntry {
+    address: u32,
+    len: usize,
+    data: [u8; CACHE_LINE_SIZE],
+    access_count: u32,
+    last_used: u32,
+}
+
+impl CacheEntry {
+    const EMPTY: Self = Self {
+        address: 0,
+        len: 0,
+        data: [0; CACHE_LINE_SIZE],
+        access_count: 0,
+        last_used: 0,
+    };
+
+    fn end(&self) -> u32 {
+        self.address + self.len as u32
+    }
+}
+
+impl<const N: usize> ReadCache<N> {
+    pub const fn new() -> Self {
+        Self {
+            entries: [CacheEntry::EMPTY; N],
+            used: 0,
+            clock: 0,
+            last_read_end: None,
+            pending_prefetch: None,
+            prefetch_count: 0,
+        }
+    }
+
+    /// Fill `out` with the flash contents at `address`
+    ///
+    /// Cached ranges are copied; each gap is read from `source` up to the
+    /// next cached entry, at most one line at a time, and cached. A gap read
+    /// runs on to a whole line when nothing cached follows, so the next
+    /// sequential read is likely a hit.
+    ///
+    /// A read that starts where the previous one ended queues the same
+    /// amount after it for [`prefetch`](Self::prefetch).
+    pub async fn read<S: ReadSource>(
+        &mut self,
+        source: &mut S,
+        address: u32,
+        out: &mut [u8],
+    ) -> Result<(), &'static str> {
+        let mut filled = 0;
+        while filled < out.len() {
+            let current = address + filled as u32;
+            let remaining = out.len() - filled;
+
+            if let Some(cached) = self.get(current, remaining) {
+                out[filled..filled + cached.len()].copy_from_slice(cached);
+                filled += cached.len();
+                continue;
+            }
+
+            let (line, gap) = self.load_gap(source, current).await?;
+            let taken = core::cmp::min(remaining, gap);
+            out[filled..filled + taken].copy_from_slice(&line[..taken]);
+            filled += taken;
+        }
+
+        let end = address + out.len() as u32;
+        self.pending_prefetch = if self.last_read_end == Some(address) {
+            // Keep half the cache for the lines just read
+            Some((end, core::cmp::min(out.len(), N / 2 * CACHE_LINE_SIZE)))
+        } else {
+            None
+        };
+        self.last_read_end = Some(end);
+        Ok(())
+    }
+
+    /// Load the range a sequential reader will want next, returning whether
+    /// there was one
+    ///
+    /// Does nothing unless the last two reads were sequential. Call it while
+    /// the CPU would otherwise wait, so the next `read` is served from cache
+    /// instead of stalling on the chip.
+    pub async fn prefetch<S: ReadSource>(&mut self, source: &mut S) -> Result<bool, &'static str> {
+        let Some((address, length)) = self.pending_prefetch.take() else {
+            return Ok(false);
+        };
+        let mut loaded = 0;
+        while loaded < length {
+            let current = address + loaded as u32;
+            if let Some(cached) = self.get(current, length - loaded) {
+                loaded += cached.len();
+                continue;
+            }
+            let (_, gap) = self.load_gap(source, current).await?;
+            self.prefetch_count += 1;
+            loaded += gap;
+        }
+        Ok(true)
+    }
+
+    /// Lines loaded by `prefetch` since the cache was created
+    pub fn prefetch_count(&self) -> u32 {
+        self.prefetch_count
+    }
+
+    /// Read the uncached bytes at `address` from `source` up to the next
+    /// cached entry, at most one line, and cache them
+    async fn load_gap<S: ReadSource>(
+        &mut self,
+        source: &mut S,
+        address: u32,
+    ) -> Result<([u8; CACHE_LINE_SIZE], usize), &'static str> {
+        let gap = match self.next_entry_after(address) {
+            Some(next) => core::cmp::min((next - address) as usize, CACHE_LINE_SIZE),
+            None => CACHE_LINE_SIZE,
+        };
+        let mut line = [0u8; CACHE_LINE_SIZE];
+        source.read(address, &mut line[..gap]).await?;
+        self.put(address, &line[..gap])?;
+        Ok((line, gap))
+    }
+
+    /// Cached data starting at `address`, at most `length` bytes (fewer when
+    /// the entry holding `address` ends first)
+    pub fn get(&mut self, address: u32, length: usize) -> Option<&[u8]> {
+        let entry = self.entries[..self.used]
+            .iter_mut()
+            .find(|entry| address >= entry.address && address < entry.end())?;
+        self.clock += 1;
+        entry.access_count += 1;
+        entry.last_used = self.clock;
+        let offset = (address - entry.address) as usize;
+        let available = core::cmp::min(length, entry.len - offset);
+        Some(&entry.data[offset..offset + available])
+    }
+
+    /// Start address of the nearest cached entry beginning after `address`
+    pub fn next_entry_after(&self, address: u32) -> Option<u32> {
+        self.entries[..self.used]
+            .iter()
+            .map(|entry| entry.address)
+            .filter(|&start| start > address)
+            .min()
+    }
+
+    /// Cache `data` as the contents at `address`
+    pub fn put(&mut self, address: u32, data: &[u8]) -> Result<(), &'static str> {
+        if data.len() > CACHE_LINE_SIZE {
+            return Err("Data too large for cache entry");
+        }
+
+        let existing = self.entries[..self.used]
+            .iter()
+            .position(|entry| entry.address == address);
+        let index = match existing {
+            Some(index) => index,
+            None if self.used < N => {
+                self.used += 1;
+                self.used - 1
+            }
+            None => self.find_lru_index(),
+        };
+
+        self.clock += 1;
+        let entry = &mut self.entries[index];
+        entry.last_used = self.clock;
+        entry.address = address;
+        entry.len = data.len();
+        entry.data[..data.len()].copy_from_slice(data);
+        entry.access_count = if existing.is_some() {
+            entry.access_count + 1
+        } else {
+            1
+        };
+        Ok(())
+    }
+
+    /// Index of the least recently used entry
+    fn find_lru_index(&self) -> usize {
+        self.entries[..self.used]
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map_or(0, |(index, _)| index)
+    }
+
+    /// Drop every entry (after the flash contents changed)
+    pub fn clear(&mut self) {
+        self.used = 0;
+        self.last_read_end = None;
+        self.pending_prefetch = None;
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let entries = &self.entries[..self.used];
+        CacheStats {
+            entries: self.used,
+            max_entries: N,
+            total_access_count: entries.iter().map(|entry| entry.access_count).sum(),
+            total_size_bytes: entries.iter().map(|entry| entry.len).sum(),
+        }
+    }
+}
+
+impl<const N: usize> Default for ReadCache<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Cache statistics
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CacheStats {
+    pub entries: usize,
+    pub max_entries: usize,
+    pub total_access_count: u32,
+    pub total_size_bytes: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Vec;
+    use core::future::Future;
+    use core::pin::pin;
+    use core::task::{Context, Poll, Waker};
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    /// Flash image that records every (address, length) read from it
+    struct Flash {
+        data: Vec<u8>,
+        reads: Vec<(u32, usize)>,
+    }
+
+    impl Flash {
+        fn new() -> Self {
+            Self {
+                data: (0..0x1000u32).map(|i| (i * 7 + i / 256) as u8).collect(),
+                reads: Vec::new(),
+            }
+        }
+
+        fn at(&self, address: u32, length: usize) -> &[u8] {
+            &self.data[address as usize..address as usize + length]
+        }
+
+        fn read_through<const N: usize>(
+            &mut self,
+            cache: &mut ReadCache<N>,
+            address: u32,
+            length: usize,
+        ) -> Vec<u8> {
+            let mut out = vec![0u8; length];
+            block_on(cache.read(self, address, &mut out)).unwrap();
+            out
+        }
+    }
+
+    impl ReadSource for Flash {
+        async fn read(&mut self, address: u32, buf: &mut [u8]) -> Result<(), &'static str> {
+            self.reads.push((address, buf.len()));
+            buf.copy_from_slice(&self.data[address as usize..address as usize + buf.len()]);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_read_spanning_two_cached_entries() {
+        let mut flash = Flash::new();
+        let mut cache = ReadCache::<4>::new();
+        // Unaligned entries that meet at 0x1A0
+        cache.put(0x120, flash.at(0x120, 0x80)).unwrap();
+        cache.put(0x1A0, flash.at(0x1A0, 0x100)).unwrap();
+
+        let out = flash.read_through(&mut cache, 0x150, 0x100);
+        assert_eq!(out, flash.at(0x150, 0x100));
+        assert!(flash.reads.is_empty());
+    }
+
+    #[test]
+    fn test_read_partially_overlapping_an_entry() {
+        let mut flash = Flash::new();
+        let mut cache = ReadCache::<4>::new();
+        cache.put(0x100, flash.at(0x100, 0x100)).unwrap();
+
+        // Only the bytes before the cached entry come from flash
+        let out = flash.read_through(&mut cache, 0xC0, 0x80);
+        assert_eq!(out, flash.at(0xC0, 0x80));
+        assert_eq!(flash.reads, [(0xC0, 0x40)]);
+
+        // Past the end of the entry a whole line is read and cached
+        flash.reads.clear();
+        let out = flash.read_through(&mut cache, 0x1C0, 0x80);
+        assert_eq!(out, flash.at(0x1C0, 0x80));
+        assert_eq!(flash.reads, [(0x200, CACHE_LINE_SIZE)]);
+
+        flash.reads.clear();
+        let out = flash.read_through(&mut cache, 0x240, 0x40);
+        assert_eq!(out, flash.at(0x240, 0x40));
+        assert!(flash.reads.is_empty());
+    }
+
+    #[test]
+    fn test_full_cache_evicts_least_used_entry() {
+        let mut flash = Flash::new();
+        let mut cache = ReadCache::<2>::new();
+        flash.read_through(&mut cache, 0x000, 0x10);
+        flash.read_through(&mut cache, 0x400, 0x10);
+        flash.read_through(&mut cache, 0x000, 0x10);
+        assert_eq!(flash.reads.len(), 2);
+
+        // 0x400 was used least recently, so it makes room for 0x800
+        flash.read_through(&mut cache, 0x800, 0x10);
+        flash.read_through(&mut cache, 0x000, 0x10);
+        assert_eq!(flash.reads.len(), 3);
+        assert_eq!(cache.stats().entries, 2);
+
+        cache.clear();
+        flash.read_through(&mut cache, 0x000, 0x10);
+        assert_eq!(flash.reads.len(), 4);
+        assert!(cache.put(0, &[0; CACHE_LINE_SIZE + 1]).is_err());
+    }
+
+    #[test]
+    fn test_sequential_reads_are_prefetched() {
+        let mut flash = Flash::new();
+        let mut cache = ReadCache::<16>::new();
+        flash.read_through(&mut cache, 0x000, 0x200);
+        assert!(!block_on(cache.prefetch(&mut flash)).unwrap());
+
+        // From the second chunk on, each chunk is loaded before it is read,
+        // so the reads themselves never wait on the chip
+        flash.read_through(&mut cache, 0x200, 0x200);
+        for chunk in 2..8u32 {
+            assert!(block_on(cache.prefetch(&mut flash)).unwrap());
+            let reads = flash.reads.len();
+            let out = flash.read_through(&mut cache, chunk * 0x200, 0x200);
+            assert_eq!(out, flash.at(chunk * 0x200, 0x200));
+            assert_eq!(flash.reads.len(), reads);
+        }
+        assert_eq!(cache.prefetch_count(), 12);
+
+        // A jump elsewhere ends the sequence
+        flash.read_through(&mut cache, 0x100, 0x10);
+        assert!(!block_on(cache.prefetch(&mut flash)).unwrap());
+    }
+}
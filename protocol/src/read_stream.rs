@@ -0,0 +1,66 @@
+//! Framing for multi-response reads (`Command::ReadStream`)
+//!
+//! A ReadStream request (`length` = total bytes) is answered with one
+//! response per chunk, sent back-to-back. Each successful response's data
+//! starts with a 4-byte tag — chunk index (u16 LE) and chunk count (u16 LE)
+//! — followed by up to [`CHUNK_SIZE`] bytes of flash data. The chunk with
+//! `index == total - 1` is the last one. A non-success response ends the
+//! stream early.
+
+use super::Vec;
+use crate::MAX_PAYLOAD_SIZE;
+
+/// Flash bytes carried by each response
+pub const CHUNK_SIZE: usize = MAX_PAYLOAD_SIZE;
+
+/// Size of the index/total tag in front of each chunk
+pub const TAG_SIZE: usize = 4;
+
+/// Largest read a single ReadStream request may ask for (chunk count fits in u16)
+pub const MAX_STREAM_LENGTH: u32 = (u16::MAX as u32) * CHUNK_SIZE as u32;
+
+/// Number of responses a read of `length` bytes produces
+pub fn chunk_count(length: u32) -> u16 {
+    (length as usize)
+        .div_ceil(CHUNK_SIZE)
+        .min(u16::MAX as usize) as u16
+}
+
+/// Build the data of one tagged response
+pub fn encode_chunk(index: u16, total: u16, payload: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(TAG_SIZE + payload.len());
+    data.extend_from_slice(&index.to_le_bytes());
+    data.extend_from_slice(&total.to_le_bytes());
+    data.extend_from_slice(payload);
+    data
+}
+
+/// Split a tagged response into (index, total, payload)
+pub fn decode_chunk(data: &[u8]) -> Option<(u16, u16, &[u8])> {
+    if data.len() < TAG_SIZE {
+        return None;
+    }
+    let index = u16::from_le_bytes([data[0], data[1]]);
+    let total = u16::from_le_bytes([data[2], data[3]]);
+    Some((index, total, &data[TAG_SIZE..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunk_round_trip() {
+        let data = encode_chunk(2, 5, &[0xAA, 0xBB]);
+        assert_eq!(decode_chunk(&data), Some((2, 5, &[0xAA, 0xBB][..])));
+        assert_eq!(decode_chunk(&[0, 1]), None);
+    }
+
+    #[test]
+    fn test_chunk_count_rounds_up() {
+        assert_eq!(chunk_count(1), 1);
+        assert_eq!(chunk_count(CHUNK_SIZE as u32), 1);
+        assert_eq!(chunk_count(CHUNK_SIZE as u32 + 1), 2);
+        assert_eq!(chunk_count(16 * 1024 * 1024), 16384);
+    }
+}
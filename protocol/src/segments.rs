@@ -0,0 +1,98 @@
+//! Overlap checking for inputs that place several segments in flash
+//!
+//! Multi-segment inputs (asset packs, and any image format that carries
+//! its own load addresses) are checked with [`find_overlap`] before
+//! anything is erased or written, since overlapping segments would
+//! silently overwrite each other.
+
+use super::Vec;
+use core::fmt;
+
+/// A run of `length` bytes programmed at `address`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Segment {
+    pub address: u32,
+    pub length: u32,
+}
+
+impl Segment {
+    pub fn new(address: u32, length: u32) -> Self {
+        Self { address, length }
+    }
+
+    /// One past the last byte (u64 so a segment ending at 4GB doesn't wrap)
+    pub fn end(&self) -> u64 {
+        self.address as u64 + self.length as u64
+    }
+}
+
+/// Formats as the inclusive range, e.g. `0x00010000-0x00010FFF`
+impl fmt::Display for Segment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "0x{:08X}-0x{:08X}",
+            self.address,
+            self.end().saturating_sub(1)
+        )
+    }
+}
+
+/// The first pair of overlapping segments in address order, or `None`
+///
+/// Empty segments are ignored. Once sorted by address, segments before the
+/// first overlap are disjoint, so comparing neighbours is enough.
+pub fn find_overlap(segments: &[Segment]) -> Option<(Segment, Segment)> {
+    let mut sorted: Vec<Segment> = segments.iter().copied().filter(|s| s.length != 0).collect();
+    sorted.sort_unstable_by_key(|s| s.address);
+
+    sorted
+        .windows(2)
+        .find(|pair| (pair[1].address as u64) < pair[0].end())
+        .map(|pair| (pair[0], pair[1]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::string::ToString;
+
+    #[test]
+    fn test_find_overlap_sorts_and_reports_pair() {
+        let segments = [
+            Segment::new(0x2000, 0x1000),
+            Segment::new(0x0000, 0x1000),
+            Segment::new(0x1000, 0x1000),
+        ];
+        assert_eq!(find_overlap(&segments), None);
+
+        let segments = [
+            Segment::new(0x0000, 0x10000),
+            Segment::new(0x20000, 0x100),
+            Segment::new(0x8000, 0x100),
+        ];
+        let (first, second) = find_overlap(&segments).unwrap();
+        assert_eq!(first, Segment::new(0x0000, 0x10000));
+        assert_eq!(second, Segment::new(0x8000, 0x100));
+        assert_eq!(first.to_string(), "0x00000000-0x0000FFFF");
+    }
+
+    #[test]
+    fn test_find_overlap_edge_cases() {
+        // Same start address, and a segment nested in a longer one
+        let same_start = [Segment::new(0x100, 4), Segment::new(0x100, 1)];
+        assert!(find_overlap(&same_start).is_some());
+        let nested = [Segment::new(0x2000, 0x10), Segment::new(0x0000, 0x3000)];
+        assert!(find_overlap(&nested).is_some());
+
+        // Empty segments never conflict
+        assert_eq!(
+            find_overlap(&[Segment::new(0, 0), Segment::new(0, 4)]),
+            None
+        );
+        assert_eq!(find_overlap(&[]), None);
+
+        let top = Segment::new(0xFFFF_FF00, 0x100);
+        assert_eq!(top.to_string(), "0xFFFFFF00-0xFFFFFFFF");
+    }
+}
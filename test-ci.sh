@@ -25,9 +25,13 @@ cd host-tool && cargo test && cd ..
 echo "✅ host-tool 测试通过"
 
 echo "测试 protocol 测试..."
-cd protocol && cargo test && cd ..
+cd protocol && cargo test --features jpeg && cd ..
 echo "✅ protocol 测试通过"
 
+echo "测试 protocol no_std 测试..."
+cd protocol && cargo test --no-default-features && cd ..
+echo "✅ protocol no_std 测试通过"
+
 echo ""
 echo "=== 3. Lints 步骤 ==="
 echo "测试 host-tool fmt..."
@@ -51,7 +55,7 @@ cd firmware && cargo clippy --target thumbv7em-none-eabihf -- -D warnings && cd
 echo "✅ firmware Clippy 检查通过"
 
 echo "测试 protocol clippy..."
-cd protocol && cargo clippy -- -D warnings && cd ..
+cd protocol && cargo clippy --features jpeg -- -D warnings && cd ..
 echo "✅ protocol Clippy 检查通过"
 
 echo ""